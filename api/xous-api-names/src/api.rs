@@ -52,6 +52,13 @@ pub enum Opcode {
     /// }
     /// ```
     BlockingConnect = 6,
+
+    /// A process has terminated. This is sent by the kernel, because xous-names
+    /// registers itself as the process supervisor.
+    ///
+    /// arg1 is the PID, arg2 is the exit code or exception type, and arg3 is 1 if
+    /// the process faulted.
+    ProcessTerminated = 7,
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -30,4 +30,7 @@ pub enum Return {
 
     /// Operation requested was otherwise successful (currently only used by disconnect to ack the disconnect)
     Success,
+
+    /// The connection policy does not allow the caller to connect to this server
+    AccessDenied,
 }
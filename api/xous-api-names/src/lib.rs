@@ -99,6 +99,7 @@ impl XousNames {
         match buf.to_original().unwrap() {
             api::Return::CID((cid, token)) => Ok((cid, token)),
             // api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
+            api::Return::AccessDenied => Err(xous::Error::AccessDenied),
             _ => Err(xous::Error::ServerNotFound),
         }
     }
@@ -137,6 +138,7 @@ impl XousNames {
         match buf.to_original().unwrap() {
             api::Return::CID((cid, _)) => Ok(cid),
             // api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
+            api::Return::AccessDenied => Err(xous::Error::AccessDenied),
             _ => Err(xous::Error::ServerNotFound),
         }
     }
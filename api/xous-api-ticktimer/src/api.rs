@@ -48,6 +48,23 @@ pub enum Opcode {
     /// *arg1*: An integer of some sort, such as the address of the Condvar
     /// *arg2*: The number of conditions to notify
     NotifyCondition = 9,
+
+    /// Expire a kernel IPC timeout once the given number of milliseconds has passed.
+    /// This is sent by `xous::send_message_timeout()` and `xous::receive_message_timeout()`.
+    ///
+    /// # Arguments
+    ///
+    /// *arg1*: The timeout token returned by the kernel
+    /// *arg2*: The number of milliseconds to wait before expiring the token
+    IpcTimeout = 10,
+
+    /// Cancel an IPC timeout requested with `IpcTimeout`, because the call it was guarding
+    /// has returned. This is sent by the same calls, once they have disarmed the token.
+    ///
+    /// # Arguments
+    ///
+    /// *arg1*: The timeout token returned by the kernel
+    CancelIpcTimeout = 11,
 }
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -5,7 +5,9 @@ pub mod irq;
 pub mod mem;
 pub mod process;
 pub mod rand;
+pub mod replay;
 pub mod syscall;
+pub mod trace;
 
 use std::cell::RefCell;
 use std::convert::TryInto;
@@ -15,7 +17,7 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAdd
 use std::thread_local;
 
 use crate::arch::process::Process;
-use crate::services::SystemServices;
+use crate::services::{ProcessExit, SystemServices};
 
 use crossbeam_channel::{unbounded, Receiver, RecvError, RecvTimeoutError, Sender};
 
@@ -24,6 +26,7 @@ use xous_kernel::{ProcessInit, ProcessKey, Result, SysCall, ThreadInit, PID, TID
 enum ThreadMessage {
     SysCall(PID, TID, SysCall),
     NewConnection(TcpStream, ProcessKey),
+    Disconnected(PID),
 }
 
 #[derive(Debug)]
@@ -85,6 +88,16 @@ pub fn current_pid() -> PID {
     crate::arch::process::current_pid()
 }
 
+/// Derive a process name from the command used to launch it, which is the
+/// file name of the program without its extension.
+#[cfg(not(test))]
+fn command_name(command: &str) -> String {
+    std::path::Path::new(command.split(' ').next().unwrap_or(command))
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| command.to_owned())
+}
+
 /// Each client gets its own connection and its own thread, which is handled here.
 fn handle_connection(
     conn: TcpStream,
@@ -313,15 +326,10 @@ fn handle_connection(
     conn_thread.join().unwrap();
     #[cfg(not(test))]
     eprintln!(
-        "KERNEL({}): Finished the thread so sending TerminateProcess",
+        "KERNEL({}): Finished the thread so terminating the process",
         pid
     );
-    chn.send(ThreadMessage::SysCall(
-        pid,
-        1,
-        xous_kernel::SysCall::TerminateProcess(0),
-    ))
-    .unwrap();
+    chn.send(ThreadMessage::Disconnected(pid)).unwrap();
 }
 
 fn listen_thread(
@@ -475,6 +483,8 @@ fn listen_thread(
 /// that kmain can activate. In a hosted environment,this is the primary
 /// thread that handles network communications, and this function never returns.
 pub fn idle() -> bool {
+    trace::init();
+
     // Start listening.
     let (sender, message_receiver) = unbounded();
     let (new_pid_sender, new_pid_receiver) = unbounded();
@@ -484,6 +494,8 @@ pub fn idle() -> bool {
     let pid1_key = PID1_KEY.with(|p1k| *p1k.borrow());
     let pid1_init = ProcessInit {
         key: ProcessKey::new(pid1_key),
+        priority: xous_kernel::ThreadPriority::Normal,
+        memory_quota: usize::MAX,
     };
     let process_1 = SystemServices::with_mut(|ss| ss.create_process(pid1_init)).unwrap();
     assert_eq!(process_1.pid().get(), 1);
@@ -534,16 +546,21 @@ pub fn idle() -> bool {
             let process_key = generate_pid_key();
             let init = xous_kernel::ProcessInit {
                 key: ProcessKey::new(process_key),
+                priority: xous_kernel::ThreadPriority::Normal,
+                memory_quota: usize::MAX,
             };
             let new_process = SystemServices::with_mut(|ss| ss.create_process(init)).unwrap();
             println!(" {:^5} |  {}", new_process, arg);
+            trace::process_started(new_process.pid(), &arg);
+            SystemServices::with_mut(|ss| ss.set_process_name(new_process.pid(), &command_name(&arg)));
             let process_args = xous_kernel::ProcessArgs::new("program", arg);
             xous_kernel::arch::create_process_post(process_args, init, new_process)
                 .expect("couldn't spawn");
         }
     }
 
-    while let Ok(msg) = message_receiver.recv() {
+    let mut sequencer = replay::Sequencer::new();
+    while let Some(msg) = sequencer.next(&message_receiver) {
         match msg {
             ThreadMessage::NewConnection(conn, access_key) => {
                 // The new process should already have a PID registered. Convert its access key
@@ -574,17 +591,34 @@ pub fn idle() -> bool {
                     .unwrap();
                 }
             }
+            ThreadMessage::Disconnected(pid) => {
+                // A process that goes away without calling `TerminateProcess` has
+                // crashed, so report it to the supervisor as a fault.
+                if SystemServices::with(|ss| ss.get_process(pid).map(|p| !p.free()).unwrap_or(false)) {
+                    crate::arch::process::set_current_pid(pid);
+                    SystemServices::with_mut(|ss| ss.terminate_process(pid, ProcessExit::Faulted(0)))
+                        .ok();
+                }
+            }
             ThreadMessage::SysCall(pid, thread_id, call) => {
                 // let measurement_start = std::time::Instant::now();
                 // println!("KERNEL({}): Received syscall {:?}", pid, call);
                 crate::arch::process::set_current_pid(pid);
                 // println!("KERNEL({}): Now running as the new process", pid);
 
+                // The thread has been running since it last received a response.
+                let run_time = Process::current().take_run_time(thread_id);
+                SystemServices::with_mut(|ss| ss.charge_run_time(pid, thread_id, run_time));
+
                 // If the call being made is to terminate the current process, we need to know
                 // because we won't be able to send a response.
-                let is_terminate = call == SysCall::TerminateProcess(0);
+                let is_terminate = matches!(call, SysCall::TerminateProcess(_));
                 let is_shutdown = call == SysCall::Shutdown;
 
+                if let SysCall::SendMessage(cid, ref message) | SysCall::TrySendMessage(cid, ref message) = call {
+                    trace::message_sent(pid, thread_id, cid, message);
+                }
+
                 // For a "Shutdown" command, send the response before we issue the shutdown.
                 // This is because the "process" will be "terminated" (the network socket will be closed),
                 // and we won't be able to send the response after we're done.
@@ -647,6 +681,8 @@ pub fn idle() -> bool {
                         crate::syscall::handle(pid, thread_id, false, SysCall::TerminateProcess(0))
                             .ok();
                     });
+                    trace::thread_resumed(pid, thread_id, &response);
+                    Process::current().mark_resumed(thread_id);
                     crate::arch::process::set_current_pid(existing_pid);
                     // println!(
                     //     "KERNEL [{:2}:{:2}] Syscall took {:7} usec",
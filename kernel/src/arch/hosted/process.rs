@@ -7,6 +7,7 @@ use core::cell::RefCell;
 use std::io::Write;
 use std::net::TcpStream;
 use std::thread_local;
+use std::time::Instant;
 use xous_kernel::{ProcessInit, ProcessKey, ProcessStartup, ThreadInit, PID, TID};
 
 pub const INITIAL_TID: usize = 2;
@@ -38,6 +39,10 @@ struct ProcessImpl {
 
     /// The currently-active thread for this process
     current_thread: TID,
+
+    /// When each thread was last allowed to run, which is used to measure
+    /// how long a thread runs before making its next syscall
+    resumed_at: [Option<Instant>; MAX_THREAD + 1],
 }
 
 impl PartialEq for Process {
@@ -196,6 +201,7 @@ impl Process {
 
         assert!(!process.threads[thread - 1].allocated);
         process.threads[thread - 1].allocated = true;
+        process.resumed_at[thread - 1] = Some(Instant::now());
     }
 
     pub fn retry_instruction(&mut self, _tid: TID) -> Result<(), xous_kernel::Error> {
@@ -277,8 +283,21 @@ impl Process {
         false
     }
 
+    /// Return the number of threads that are currently allocated in this process.
+    pub fn thread_count(&self) -> usize {
+        PROCESS_TABLE.with(|pt| {
+            let process_table = pt.borrow();
+            let current_pid_idx = process_table.current.get() as usize - 1;
+            process_table.table[current_pid_idx]
+                .as_ref()
+                .map(|process| process.threads.iter().filter(|t| t.allocated).count())
+                .unwrap_or(0)
+        })
+    }
+
     pub fn set_thread_result(&mut self, tid: TID, result: xous_kernel::Result) {
         assert!(tid > 0);
+        super::trace::thread_resumed(self.pid, tid, &result);
         PROCESS_TABLE.with(|pt| {
             let mut process_table = pt.borrow_mut();
             let current_pid_idx = process_table.current.get() as usize - 1;
@@ -321,9 +340,37 @@ impl Process {
             let conn = process.conn.as_mut().unwrap();
             conn.write_all(&response).expect("Disconnection");
             conn.flush().expect("Disconnection");
+            process.resumed_at[tid - 1] = Some(Instant::now());
+        });
+    }
+
+    /// Note that the given thread has been handed the result of its syscall
+    /// and is running again.
+    pub fn mark_resumed(&mut self, tid: TID) {
+        PROCESS_TABLE.with(|pt| {
+            let mut process_table = pt.borrow_mut();
+            let current_pid_idx = process_table.current.get() as usize - 1;
+            if let Some(process) = process_table.table[current_pid_idx].as_mut() {
+                process.resumed_at[tid - 1] = Some(Instant::now());
+            }
         });
     }
 
+    /// Return the number of microseconds the given thread has been running
+    /// since it was last resumed. The thread is considered to have stopped
+    /// running until it is resumed again.
+    pub fn take_run_time(&mut self, tid: TID) -> u64 {
+        PROCESS_TABLE.with(|pt| {
+            let mut process_table = pt.borrow_mut();
+            let current_pid_idx = process_table.current.get() as usize - 1;
+            process_table.table[current_pid_idx]
+                .as_mut()
+                .and_then(|process| process.resumed_at[tid - 1].take())
+                .map(|resumed_at| resumed_at.elapsed().as_micros() as u64)
+                .unwrap_or(0)
+        })
+    }
+
     pub fn return_memory(&mut self, tid: TID, buf: &[u8]) {
         PROCESS_TABLE.with(|pt| {
             let mut process_table = pt.borrow_mut();
@@ -353,6 +400,7 @@ impl Process {
                 memory_to_return: filled_array![None; 32 /* MAX_THREAD */],
                 current_thread: INITIAL_TID,
                 threads: [Thread { allocated: false }; MAX_THREAD + 1],
+                resumed_at: [None; MAX_THREAD + 1],
             };
 
             process_table.total += 1;
@@ -0,0 +1,176 @@
+// SPDX-FileCopyrightText: 2020 Sean Cross <sean@xobs.io>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Record and replay of the order in which syscalls reach the hosted kernel.
+//!
+//! Hosted processes are real host processes, so the order in which their
+//! syscalls arrive at the kernel -- and therefore the order in which messages
+//! are delivered -- changes from run to run. Setting `XOUS_RECORD` to a path
+//! writes the order that was observed, one `pid tid syscall` line per call.
+//! Setting `XOUS_REPLAY` to such a file makes the kernel hold back any syscall
+//! that arrives early until every call recorded before it has been handled,
+//! reproducing the recorded interleaving. Both may be set at once, which is
+//! useful for checking that a replay did not diverge.
+//!
+//! Only the kernel's scheduling is reproduced. Processes must be started with
+//! the same arguments and the same `XOUS_SEED`, and anything a process reads
+//! from the host (such as the wall clock) can still make it take a different
+//! path. If the next recorded call does not arrive within `REPLAY_TIMEOUT`,
+//! the run has diverged: a warning is printed and the kernel resumes handling
+//! calls in arrival order.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use xous_kernel::{SysCall, PID, TID};
+
+use super::ThreadMessage;
+
+/// How long to wait for the next recorded syscall before giving up on the replay.
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(5);
+
+thread_local!(static PATHS: RefCell<Option<(Option<String>, Option<String>)>> = RefCell::new(None));
+
+/// Override `XOUS_RECORD` and `XOUS_REPLAY` for the kernel running on this thread.
+#[cfg(test)]
+pub fn set_record_replay_paths(record: Option<&str>, replay: Option<&str>) {
+    PATHS.with(|p| *p.borrow_mut() = Some((record.map(|s| s.to_owned()), replay.map(|s| s.to_owned()))));
+}
+
+/// One syscall in the schedule. Only the syscall number is compared, as the
+/// arguments contain addresses that differ between runs.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Step {
+    pid: u8,
+    tid: TID,
+    call: usize,
+}
+
+impl Step {
+    fn new(pid: PID, tid: TID, call: &SysCall) -> Step {
+        Step {
+            pid: pid.get(),
+            tid,
+            call: call.as_args()[0],
+        }
+    }
+
+    fn parse(line: &str) -> Option<Step> {
+        let mut fields = line.split_whitespace();
+        let step = Step {
+            pid: fields.next()?.parse().ok()?,
+            tid: fields.next()?.parse().ok()?,
+            call: fields.next()?.parse().ok()?,
+        };
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(step)
+    }
+}
+
+/// Sits between the network threads and the kernel's main loop, deciding which
+/// incoming syscall is handled next.
+pub(super) struct Sequencer {
+    record: Option<LineWriter<File>>,
+    schedule: VecDeque<Step>,
+    held: VecDeque<(PID, TID, SysCall)>,
+}
+
+impl Sequencer {
+    /// Create a sequencer configured from `XOUS_RECORD` and `XOUS_REPLAY`.
+    pub(super) fn new() -> Sequencer {
+        let (record_path, replay_path) = PATHS.with(|p| p.borrow().clone()).unwrap_or_else(|| {
+            (
+                std::env::var("XOUS_RECORD").ok(),
+                std::env::var("XOUS_REPLAY").ok(),
+            )
+        });
+
+        let mut schedule = VecDeque::new();
+        if let Some(path) = replay_path {
+            let file = File::open(&path)
+                .unwrap_or_else(|e| panic!("unable to open replay file {}: {}", path, e));
+            for (lineno, line) in BufReader::new(file).lines().enumerate() {
+                let line = line.expect("unable to read replay file");
+                schedule.push_back(Step::parse(&line).unwrap_or_else(|| {
+                    panic!("{}:{}: malformed replay entry {:?}", path, lineno + 1, line)
+                }));
+            }
+            println!("KERNEL: Replaying {} syscalls from {}", schedule.len(), path);
+        }
+
+        let record = record_path.map(|path| {
+            println!("KERNEL: Recording syscall order to {}", path);
+            LineWriter::new(
+                File::create(&path)
+                    .unwrap_or_else(|e| panic!("unable to create record file {}: {}", path, e)),
+            )
+        });
+
+        Sequencer {
+            record,
+            schedule,
+            held: VecDeque::new(),
+        }
+    }
+
+    /// Return the next message the kernel should handle, or `None` once every
+    /// sender has gone away.
+    pub(super) fn next(&mut self, receiver: &Receiver<ThreadMessage>) -> Option<ThreadMessage> {
+        let (pid, tid, call) = loop {
+            let expected = match self.schedule.front() {
+                Some(expected) => *expected,
+                None => match self.held.pop_front() {
+                    // Calls held back when the replay diverged go first, in arrival order.
+                    Some(held) => break held,
+                    None => match receiver.recv() {
+                        Ok(ThreadMessage::SysCall(pid, tid, call)) => break (pid, tid, call),
+                        Ok(msg) => return Some(msg),
+                        Err(_) => return None,
+                    },
+                },
+            };
+
+            if let Some(index) = self
+                .held
+                .iter()
+                .position(|(pid, tid, call)| Step::new(*pid, *tid, call) == expected)
+            {
+                self.schedule.pop_front();
+                break self.held.remove(index).unwrap();
+            }
+
+            match receiver.recv_timeout(REPLAY_TIMEOUT) {
+                Ok(ThreadMessage::SysCall(pid, tid, call)) => self.held.push_back((pid, tid, call)),
+                // Connections and disconnections aren't part of the schedule, and are handled immediately.
+                Ok(msg) => return Some(msg),
+                Err(RecvTimeoutError::Timeout) => {
+                    eprintln!(
+                        "KERNEL: replay diverged with {} steps left: PID {} TID {} never made syscall {} \
+                         -- resuming normal scheduling",
+                        self.schedule.len(),
+                        expected.pid,
+                        expected.tid,
+                        expected.call
+                    );
+                    self.schedule.clear();
+                }
+                Err(RecvTimeoutError::Disconnected) => self.schedule.clear(),
+            }
+        };
+
+        if let Some(record) = self.record.as_mut() {
+            let step = Step::new(pid, tid, &call);
+            if let Err(e) = writeln!(record, "{} {} {}", step.pid, step.tid, step.call) {
+                eprintln!("KERNEL: unable to write to record file ({}) -- recording stopped", e);
+                self.record = None;
+            }
+        }
+        Some(ThreadMessage::SysCall(pid, tid, call))
+    }
+}
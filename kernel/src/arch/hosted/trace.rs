@@ -0,0 +1,189 @@
+// SPDX-FileCopyrightText: 2020 Sean Cross <sean@xobs.io>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Optional trace of every message passed between processes in hosted mode.
+//!
+//! Tracing is enabled by setting `XOUS_TRACE_FILE` to a path before starting
+//! the kernel. Each line of that file is a self-contained JSON object. A
+//! `process` record is written as each initial process is launched, and a
+//! `message` record is written once each message has been delivered -- for
+//! blocking messages, that is when the reply reaches the sender. xous-names
+//! appends a `server` record to the same file for every name it registers.
+//! Use `tools/ipc_trace.py` to turn the file into a table or sequence diagram.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::Instant;
+
+use xous_kernel::{Message, Result, CID, PID, SID, TID};
+
+use crate::services::SystemServices;
+
+/// A blocking message that is waiting for its reply.
+struct Pending {
+    sent_us: u128,
+    sent_at: Instant,
+    record: String,
+}
+
+struct Tracer {
+    file: File,
+    start: Instant,
+    pending: HashMap<(PID, TID), Pending>,
+}
+
+impl Tracer {
+    /// Append one record to the trace. Other processes append to the same
+    /// file, so each line goes out in a single write.
+    fn record(&mut self, line: String) -> std::io::Result<()> {
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+thread_local!(static TRACER: RefCell<Option<Tracer>> = const { RefCell::new(None) });
+
+/// Run `f` against the tracer if tracing is enabled. Tracing is turned off
+/// if the trace file can no longer be written.
+fn with_tracer<F: FnOnce(&mut Tracer) -> std::io::Result<()>>(f: F) {
+    TRACER.with(|t| {
+        let mut t = t.borrow_mut();
+        if let Some(tracer) = t.as_mut() {
+            if let Err(e) = f(tracer) {
+                eprintln!("KERNEL: unable to write to trace file ({}) -- disabling trace", e);
+                *t = None;
+            }
+        }
+    });
+}
+
+/// Open the trace file named by `XOUS_TRACE_FILE`, if it is set.
+pub fn init() {
+    let path = match std::env::var("XOUS_TRACE_FILE") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    // Truncate the file, then reopen it for appending so that records written
+    // by other processes aren't overwritten
+    match File::create(&path).and_then(|_| OpenOptions::new().append(true).open(&path)) {
+        Ok(file) => {
+            println!("KERNEL: Writing IPC trace to {}", path);
+            TRACER.with(|t| {
+                *t.borrow_mut() = Some(Tracer {
+                    file,
+                    start: Instant::now(),
+                    pending: HashMap::new(),
+                })
+            });
+        }
+        Err(e) => eprintln!("KERNEL: unable to create trace file {}: {}", path, e),
+    }
+}
+
+/// Record the command that was used to launch `pid`, so the trace can
+/// refer to processes by name.
+#[cfg(not(test))]
+pub fn process_started(pid: PID, command: &str) {
+    with_tracer(|tracer| {
+        let name = super::command_name(command);
+        let line = format!(
+            "{{\"event\":\"process\",\"t_us\":{},\"pid\":{},\"name\":\"{}\"}}\n",
+            tracer.start.elapsed().as_micros(),
+            pid,
+            escape(&name)
+        );
+        tracer.record(line)
+    });
+}
+
+/// Record a message sent by `pid`:`tid` to connection `cid`. Must be called
+/// before the message is handed to the kernel, while `pid` is still current,
+/// so that `cid` can be resolved to a server.
+pub fn message_sent(pid: PID, tid: TID, cid: CID, message: &Message) {
+    with_tracer(|tracer| {
+        let (server_pid, server) = SystemServices::with(|ss| {
+            ss.sidx_from_cid(cid)
+                .and_then(|sidx| ss.server_from_sidx(sidx))
+                .map(|server| (server.pid.get() as usize, server_name(server.sid)))
+        })
+        .unwrap_or((0, format!("cid {}", cid)));
+        let (kind, size) = match message {
+            Message::Scalar(_) => ("scalar", 0),
+            Message::BlockingScalar(_) => ("blocking_scalar", 0),
+            Message::Move(msg) => ("move", msg.buf.len()),
+            Message::MutableBorrow(msg) => ("mutable_borrow", msg.buf.len()),
+            Message::Borrow(msg) => ("borrow", msg.buf.len()),
+        };
+        let sent_us = tracer.start.elapsed().as_micros();
+        let record = format!(
+            "\"pid\":{},\"tid\":{},\"server_pid\":{},\"server\":\"{}\",\"opcode\":{},\"kind\":\"{}\",\"size\":{}",
+            pid,
+            tid,
+            server_pid,
+            escape(&server),
+            message.id(),
+            kind,
+            size
+        );
+        if message.is_blocking() {
+            tracer.pending.insert(
+                (pid, tid),
+                Pending {
+                    sent_us,
+                    sent_at: Instant::now(),
+                    record,
+                },
+            );
+            Ok(())
+        } else {
+            let line = format!(
+                "{{\"event\":\"message\",\"t_us\":{},{},\"latency_us\":null,\"error\":null}}\n",
+                sent_us, record
+            );
+            tracer.record(line)
+        }
+    });
+}
+
+/// Record that `pid`:`tid` has been handed `result`. If the thread was waiting
+/// on a blocking message, this is the reply to that message.
+pub fn thread_resumed(pid: PID, tid: TID, result: &Result) {
+    with_tracer(|tracer| {
+        let pending = match tracer.pending.remove(&(pid, tid)) {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let error = match result {
+            Result::Error(e) => format!("\"{:?}\"", e),
+            _ => "null".to_owned(),
+        };
+        let line = format!(
+            "{{\"event\":\"message\",\"t_us\":{},{},\"latency_us\":{},\"error\":{}}}\n",
+            pending.sent_us,
+            pending.record,
+            pending.sent_at.elapsed().as_micros(),
+            error
+        );
+        tracer.record(line)
+    });
+}
+
+/// Servers created with a well-known address are named after that address.
+/// Everything else is named after its SID.
+fn server_name(sid: SID) -> String {
+    let mut bytes = Vec::with_capacity(16);
+    for word in sid.to_array().iter() {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    if bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        String::from_utf8(bytes).unwrap()
+    } else {
+        let (a, b, c, d) = sid.to_u32();
+        format!("{:08x}-{:08x}-{:08x}-{:08x}", a, b, c, d)
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
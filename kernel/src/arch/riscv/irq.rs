@@ -6,7 +6,7 @@ use crate::arch::exception::RiscvException;
 use crate::arch::mem::MemoryMapping;
 use crate::arch::process::{Process as ArchProcess, RETURN_FROM_EXCEPTION_HANDLER};
 use crate::arch::process::{Thread, EXIT_THREAD, RETURN_FROM_ISR};
-use crate::services::SystemServices;
+use crate::services::{ProcessExit, SystemServices};
 use riscv::register::{scause, sepc, sstatus, stval, vexriscv::sim, vexriscv::sip};
 use xous_kernel::{SysCall, PID, TID};
 
@@ -16,6 +16,43 @@ extern "Rust" {
 
 // use RAM-based backing so this variable is automatically saved on suspend
 static mut SIM_BACKING: usize = 0;
+
+/// Cycle counter value at the most recent trap
+static mut LAST_TRAP_TIME: u64 = 0;
+
+/// Read the 64-bit `cycle` counter, which counts at the CPU clock frequency. The
+/// VexRiscv has no `time` CSR, and `mcycle` traps in Supervisor mode, so this uses
+/// the user-level counter that the loader enables through `mcounteren`.
+fn read_cycle() -> u64 {
+    loop {
+        let (hi, lo, hi_again): (usize, usize, usize);
+        unsafe {
+            core::arch::asm!(
+                "csrr {0}, cycleh",
+                "csrr {1}, cycle",
+                "csrr {2}, cycleh",
+                out(reg) hi,
+                out(reg) lo,
+                out(reg) hi_again,
+            );
+        }
+        // retry if the low word wrapped between the reads
+        if hi == hi_again {
+            return ((hi as u64) << 32) | lo as u64;
+        }
+    }
+}
+
+/// Return the number of whole microseconds since the previous trap, as measured
+/// by the cycle counter. Any remainder is carried over to the next trap so that
+/// frequent short traps don't lose time.
+fn time_since_last_trap_us() -> u64 {
+    let ticks_per_us = utralib::generated::LITEX_CONFIG_CLOCK_FREQUENCY as u64 / 1_000_000;
+    let now = read_cycle();
+    let elapsed_us = now.wrapping_sub(unsafe { LAST_TRAP_TIME }) / ticks_per_us;
+    unsafe { LAST_TRAP_TIME = LAST_TRAP_TIME.wrapping_add(elapsed_us * ticks_per_us) };
+    elapsed_us
+}
 /// Disable external interrupts
 pub fn disable_all_irqs() {
     unsafe { SIM_BACKING = sim::read() };
@@ -137,6 +174,12 @@ pub extern "C" fn trap_handler(
 
     let pid = current_pid();
 
+    // Charge the time since the previous trap to the context that was just
+    // interrupted. This includes any time the kernel spent switching to it.
+    let elapsed = time_since_last_trap_us();
+    let interrupted_tid = ArchProcess::with_current(|p| p.current_tid());
+    SystemServices::with_mut(|ss| ss.charge_run_time(pid, interrupted_tid, elapsed));
+
     if (sc.bits() == 9) || (sc.bits() == 8) {
         // We got here because of an `ecall` instruction.  When we return, skip
         // past this instruction.  If this is a call such as `SwitchTo`, then we
@@ -328,7 +371,8 @@ pub extern "C" fn trap_handler(
 
         // If it's not a failure in the kernel, terminate or debug the current process.
         SystemServices::with_mut(|ss| {
-            ss.terminate_process(pid)
+            let exception = generate_exception_args(&ex).map(|args| args[0]).unwrap_or_default();
+            ss.terminate_process(pid, ProcessExit::Faulted(exception))
                 .expect("couldn't terminate current process");
             crate::syscall::reset_switchto_caller();
         });
@@ -197,6 +197,18 @@ impl Process {
         self.thread(tid).sepc != 0
     }
 
+    /// Return the number of threads that currently exist in this process,
+    /// not counting the ISR thread.
+    pub fn thread_count(&self) -> usize {
+        let process = unsafe { &*PROCESS };
+        process
+            .threads
+            .iter()
+            .enumerate()
+            .filter(|(idx, thread)| *idx != IRQ_TID && thread.sepc != 0)
+            .count()
+    }
+
     /// Set the current thread number.
     pub fn set_tid(&mut self, thread: TID) -> Result<(), xous_kernel::Error> {
         let mut process = unsafe { &mut *PROCESS };
@@ -105,6 +105,8 @@ pub unsafe extern "C" fn init(arg_offset: *const u32, init_offset: *const u32, r
 }
 
 /// Loop through the SystemServices list to determine the next PID to be run.
+/// The process with the highest-priority ready context wins, and processes
+/// that share the same priority are run in a round-robin fashion.
 /// If no process is ready, return `None`.
 fn next_pid_to_run(last_pid: Option<PID>) -> Option<PID> {
     // PIDs are 1-indexed but arrays are 0-indexed.  By not subtracting
@@ -113,33 +115,26 @@ fn next_pid_to_run(last_pid: Option<PID>) -> Option<PID> {
     let current_pid = last_pid.unwrap_or(unsafe { PID::new_unchecked(1) }).get() as usize;
 
     SystemServices::with(|system_services| {
-        for test_idx in current_pid..system_services.processes.len() {
-            if system_services.processes[test_idx].ppid.get() == 1 {
-                // print!("PID {} is owned by PID1... ", test_idx + 1);
-                if system_services.processes[test_idx].runnable() {
-                    // println!(" and is runnable");
-                    return match pid_from_usize(test_idx + 1) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    };
-                }
-                // println!(" and is NOT RUNNABLE");
+        let process_count = system_services.processes.len();
+        let mut best: Option<(usize, ThreadPriority)> = None;
+        for offset in 0..process_count {
+            let test_idx = (current_pid + offset) % process_count;
+            let process = &system_services.processes[test_idx];
+            if process.ppid.get() != 1 {
+                continue;
             }
-        }
-        for test_idx in 0..current_pid {
-            if system_services.processes[test_idx].ppid.get() == 1 {
-                // print!("PID {} is owned by PID1... ", test_idx + 1);
-                if system_services.processes[test_idx].runnable() {
-                    // println!(" and is runnable");
-                    return match pid_from_usize(test_idx + 1) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    };
+            // print!("PID {} is owned by PID1... ", test_idx + 1);
+            if let Some(priority) = process.ready_priority() {
+                // println!(" and is runnable");
+                // Only a strictly higher priority displaces an earlier candidate,
+                // which preserves round-robin ordering among equals.
+                match best {
+                    Some((_, best_priority)) if best_priority >= priority => (),
+                    _ => best = Some((test_idx, priority)),
                 }
-                // println!(" and is NOT RUNNABLE");
             }
         }
-        None
+        best.and_then(|(idx, _)| pid_from_usize(idx + 1).ok())
     })
 }
 
@@ -4,7 +4,7 @@
 use core::fmt;
 
 pub use crate::arch::mem::{MemoryMapping, PAGE_SIZE};
-use crate::arch::process::Process;
+use crate::arch::process::{Process, MAX_PROCESS_COUNT};
 
 use xous_kernel::{MemoryFlags, MemoryRange, PID};
 
@@ -47,6 +47,11 @@ pub struct MemoryManager {
     ram_name: u32,
     #[allow(dead_code)]
     last_ram_page: usize,
+    /// The number of RAM pages currently owned by each process
+    ram_pages: [usize; MAX_PROCESS_COUNT],
+    /// The number of RAM pages each process may be allocated before
+    /// `alloc_page()` starts returning `OutOfMemory`
+    ram_quota: [usize; MAX_PROCESS_COUNT],
 }
 
 impl Default for MemoryManager {
@@ -77,6 +82,8 @@ impl MemoryManager {
             ram_size: 0,
             ram_name: 0,
             last_ram_page: 0,
+            ram_pages: [0; MAX_PROCESS_COUNT],
+            ram_quota: [usize::MAX; MAX_PROCESS_COUNT],
         }
     }
 
@@ -173,23 +180,69 @@ impl MemoryManager {
         unsafe {
             MEMORY_ALLOCATIONS = slice::from_raw_parts_mut(base as *mut Option<PID>, mem_size)
         };
+
+        // The loader has already handed out pages to the initial processes
+        unsafe {
+            for owner in MEMORY_ALLOCATIONS[0..self.ram_size / PAGE_SIZE].iter() {
+                self.account_ram_page(None, *owner);
+            }
+        }
         Ok(())
     }
 
-    /// Print the number of RAM bytes used by the specified process.
-    /// This does not include memory such as peripherals and CSRs.
+    /// Update the per-process page counts after a RAM page has changed hands.
     #[cfg(baremetal)]
-    pub fn ram_used_by(&self, pid: PID) -> usize {
-        let mut owned_bytes = 0;
-        #[cfg(baremetal)]
-        unsafe {
-            for owner in &MEMORY_ALLOCATIONS[0..self.ram_size / PAGE_SIZE] {
-                if owner == &Some(pid) {
-                    owned_bytes += PAGE_SIZE;
+    fn account_ram_page(&mut self, previous: Option<PID>, next: Option<PID>) {
+        if let Some(pid) = previous {
+            if let Some(count) = self.ram_pages.get_mut(pid.get() as usize - 1) {
+                *count = count.saturating_sub(1);
+            }
+        }
+        if let Some(pid) = next {
+            if let Some(count) = self.ram_pages.get_mut(pid.get() as usize - 1) {
+                *count += 1;
+            }
+        }
+    }
+
+    /// Return the number of RAM pages currently owned by the given process.
+    pub fn ram_pages_used_by(&self, pid: PID) -> usize {
+        self.ram_pages
+            .get(pid.get() as usize - 1)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Return the number of RAM pages the given process may own.
+    pub fn ram_quota(&self, pid: PID) -> usize {
+        self.ram_quota
+            .get(pid.get() as usize - 1)
+            .copied()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Change the number of RAM pages the given process may own. The quota
+    /// is only replaced if `current` matches the existing quota, which
+    /// prevents two callers from racing each other. The quota may be set
+    /// below the number of pages already in use, in which case future
+    /// allocations fail until pages are freed. Returns the resulting quota.
+    pub fn adjust_ram_quota(&mut self, pid: PID, current: usize, new: usize) -> usize {
+        match self.ram_quota.get_mut(pid.get() as usize - 1) {
+            Some(quota) => {
+                if *quota == current {
+                    *quota = new;
                 }
+                *quota
             }
+            None => usize::MAX,
         }
-        owned_bytes
+    }
+
+    /// Print the number of RAM bytes used by the specified process.
+    /// This does not include memory such as peripherals and CSRs.
+    #[cfg(baremetal)]
+    pub fn ram_used_by(&self, pid: PID) -> usize {
+        self.ram_pages_used_by(pid) * PAGE_SIZE
     }
 
     #[cfg(all(baremetal, feature = "print-debug"))]
@@ -250,6 +303,10 @@ impl MemoryManager {
     /// This function CANNOT zero the page, as it hasn't been mapped yet.
     #[cfg(baremetal)]
     pub fn alloc_page(&mut self, pid: PID) -> Result<usize, xous_kernel::Error> {
+        if self.ram_pages_used_by(pid) >= self.ram_quota(pid) {
+            return Err(xous_kernel::Error::OutOfMemory);
+        }
+
         // Go through all RAM pages looking for a free page.
         // println!("Allocating page for PID {}", pid);
         unsafe {
@@ -266,6 +323,9 @@ impl MemoryManager {
                 // );
                 if allocation.is_none() {
                     *allocation = Some(pid);
+                    if let Some(count) = self.ram_pages.get_mut(pid.get() as usize - 1) {
+                        *count += 1;
+                    }
                     self.last_ram_page = index + 1;
                     // if self.last_ram_page >= end_point {
                     //     self.last_ram_page = 0;
@@ -553,6 +613,8 @@ impl MemoryManager {
         dest_addr: *mut u8,
     ) -> Result<(), xous_kernel::Error> {
         let phys_addr = crate::arch::mem::virt_to_phys(src_addr as usize)?;
+        // Check the quota before the page is remapped, since that can't be undone
+        self.check_ram_quota(phys_addr, dest_pid)?;
         crate::arch::mem::move_page_inner(
             self,
             src_mapping,
@@ -656,6 +718,26 @@ impl MemoryManager {
         crate::arch::mem::ensure_page_exists_inner(address).and(Ok(()))
     }
 
+    /// Ensure that taking ownership of the page at `addr` won't push `pid`
+    /// past its RAM quota. Pages that are lent remain owned by the lender, so
+    /// only claims and moves can change how many pages a process owns.
+    #[cfg(not(baremetal))]
+    fn check_ram_quota(&self, _addr: usize, _pid: PID) -> Result<(), xous_kernel::Error> {
+        Ok(())
+    }
+
+    #[cfg(baremetal)]
+    fn check_ram_quota(&self, addr: usize, pid: PID) -> Result<(), xous_kernel::Error> {
+        if addr < self.ram_start || addr >= self.ram_start + self.ram_size {
+            return Ok(());
+        }
+        let owner = unsafe { MEMORY_ALLOCATIONS[(addr - self.ram_start) / PAGE_SIZE] };
+        if owner != Some(pid) && self.ram_pages_used_by(pid) >= self.ram_quota(pid) {
+            return Err(xous_kernel::Error::OutOfMemory);
+        }
+        Ok(())
+    }
+
     /// Claim the given memory for the given process, or release the memory
     /// back to the free pool.
     #[cfg(not(baremetal))]
@@ -722,7 +804,13 @@ impl MemoryManager {
         // Happy path: The address is in main RAM
         if addr >= self.ram_start && addr < self.ram_start + self.ram_size {
             offset += (addr - self.ram_start) / PAGE_SIZE;
-            return unsafe { action_inner(&mut MEMORY_ALLOCATIONS[offset], pid, action) };
+            if !matches!(action, ClaimReleaseMove::Release) {
+                self.check_ram_quota(addr, pid)?;
+            }
+            let previous = unsafe { MEMORY_ALLOCATIONS[offset] };
+            unsafe { action_inner(&mut MEMORY_ALLOCATIONS[offset], pid, action)? };
+            self.account_ram_page(previous, unsafe { MEMORY_ALLOCATIONS[offset] });
+            return Ok(());
         }
 
         offset += self.ram_size / PAGE_SIZE;
@@ -801,12 +889,23 @@ impl MemoryManager {
                     // If the page is lent, reparent it to PID 1 so it will
                     // get freed when it is returned.
                     *owner = PID::new(1);
+                    if idx < self.ram_size / PAGE_SIZE {
+                        self.ram_pages[0] += 1;
+                    }
                 } else {
                     // Mark this page as free, which allows it to be re-allocated.
                     *owner = None;
                 }
             }
         }
+
+        // The next process to be given this PID starts out with no quota.
+        if let Some(count) = self.ram_pages.get_mut(_pid.get() as usize - 1) {
+            *count = 0;
+        }
+        if let Some(quota) = self.ram_quota.get_mut(_pid.get() as usize - 1) {
+            *quota = usize::MAX;
+        }
     }
 
     /// Adjust the flags on the given memory range. This allows for stripping flags from a memory
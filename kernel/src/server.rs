@@ -37,6 +37,12 @@ pub struct Server {
     /// this message. If there are no available contexts, then messages will
     /// need to be queued.
     ready_threads: usize,
+
+    /// Event flags that clients have set and the server has not yet consumed.
+    event_flags: usize,
+
+    /// The thread waiting on `event_flags`, along with the mask it is waiting on.
+    event_waiter: Option<(TID, usize)>,
 }
 
 pub struct SenderID {
@@ -100,6 +106,29 @@ pub enum WaitingMessage {
 
     /// This memory should be returned to the system.
     ForgetMemory(MemoryRange),
+
+    /// The sender gave up waiting for this scalar message, so the response
+    /// should be dropped.
+    DiscardedScalar,
+}
+
+/// A blocking message that was withdrawn because its sender timed out.
+#[derive(PartialEq, Debug)]
+pub enum CancelledMessage {
+    /// A scalar message. The sender only needs to be woken up.
+    Scalar,
+
+    /// Memory that was lent to the server before it received the message,
+    /// which must be returned to the sender.
+    BorrowedMemory(
+        usize, /* server memory address */
+        usize, /* client memory address */
+        usize, /* buf_size */
+    ),
+
+    /// Lent memory that the server is already working on. This can't be
+    /// withdrawn, so the sender must keep waiting for the server.
+    InServer,
 }
 
 /// Internal representation of a queued message for a server. This should be
@@ -238,6 +267,23 @@ enum QueuedMessage {
         u8,    /* message index */
         usize, /* server return address */
     ),
+
+    /// The client timed out while the server was handling its blocking scalar
+    /// message. The server's response will be dropped.
+    WaitingDiscardScalar(
+        u16,   /* client PID */
+        u8,    /* client TID */
+        u8,    /* message index */
+        usize, /* server return address */
+    ),
+
+    /// The client timed out before the server received this message. The slot
+    /// is skipped when its turn comes, which keeps later messages in order.
+    Cancelled(
+        u16, /* client PID */
+        u8,  /* client TID */
+        u8,  /* message index */
+    ),
 }
 
 impl QueuedMessage {
@@ -252,6 +298,7 @@ impl QueuedMessage {
             &QueuedMessage::WaitingForget(_, _, _, _, _, _)
                 | &QueuedMessage::WaitingReturnMemory(_, _, _, _, _, _)
                 | &QueuedMessage::WaitingReturnScalar(_, _, _, _)
+                | &QueuedMessage::WaitingDiscardScalar(_, _, _, _)
         )
     }
 }
@@ -301,6 +348,8 @@ impl Server {
             tail_generation: 0,
             queue,
             ready_threads: 0,
+            event_flags: 0,
+            event_waiter: None,
         });
         Ok(())
     }
@@ -326,12 +375,16 @@ impl Server {
                 // we already determined above that this wouldn't happen.
                 QueuedMessage::WaitingForget(_, _, _, _, _, _)
                 | QueuedMessage::WaitingReturnMemory(_, _, _, _, _, _)
-                | QueuedMessage::WaitingReturnScalar(_, _, _, _) => panic!("message was waiting"),
+                | QueuedMessage::WaitingReturnScalar(_, _, _, _)
+                | QueuedMessage::WaitingDiscardScalar(_, _, _, _) => panic!("message was waiting"),
 
                 // For `Empty` and `Scalar` messages, all we have to do is ignore them.
                 // The sending process will not be blocked. These messages will be dropped,
-                // and the server will never see them.
-                QueuedMessage::Empty | QueuedMessage::ScalarMessage(_, _, _, _, _, _, _, _, _) => {}
+                // and the server will never see them. `Cancelled` messages have already
+                // been answered.
+                QueuedMessage::Empty
+                | QueuedMessage::ScalarMessage(_, _, _, _, _, _, _, _, _)
+                | QueuedMessage::Cancelled(_, _, _) => {}
 
                 // For `Send` messages, the Server has not yet seen these messages. Simply
                 // prevent this memory from getting mapped into the Server and free it.
@@ -454,8 +507,12 @@ impl Server {
 
         let server_pid = ss.current_pid();
 
-        // Finally, wake up all threads that are waiting on this Server.
-        while let Some(server_tid) = self.take_available_thread() {
+        // Finally, wake up all threads that are waiting on this Server, either
+        // for a message or for event flags.
+        while let Some(server_tid) = self
+            .take_available_thread()
+            .or_else(|| self.event_waiter.take().map(|(tid, _mask)| tid))
+        {
             ss.ready_thread(server_pid, server_tid).unwrap();
             ss.set_thread_result(
                 server_pid,
@@ -479,6 +536,92 @@ impl Server {
         Ok(())
     }
 
+    /// Tear down a server whose process is terminating. Unlike `destroy()`, this
+    /// also handles messages the server has already received: every client that
+    /// is still waiting on the server is woken up with `ServerNotFound` and has
+    /// its lent memory returned. The server's own threads, including any
+    /// waiting on event flags, are not woken, since they are going away. The server's process must be active.
+    ///
+    /// Returns the first error encountered while waking a client. Remaining
+    /// queue entries are left untouched in that case.
+    pub fn terminate(mut self, ss: &mut SystemServices) -> Result<(), xous_kernel::Error> {
+        for entry in self.queue.iter_mut() {
+            let (client_pid, client_tid, lent) = match *entry {
+                QueuedMessage::BlockingScalarMessage(pid, tid, _, _, _, _, _, _, _)
+                | QueuedMessage::WaitingReturnScalar(pid, tid, _, _) => (pid, tid, None),
+
+                QueuedMessage::MemoryMessageROLend(
+                    pid,
+                    tid,
+                    _,
+                    client_addr,
+                    _,
+                    server_addr,
+                    len,
+                    _,
+                    _,
+                )
+                | QueuedMessage::MemoryMessageRWLend(
+                    pid,
+                    tid,
+                    _,
+                    client_addr,
+                    _,
+                    server_addr,
+                    len,
+                    _,
+                    _,
+                )
+                | QueuedMessage::WaitingReturnMemory(pid, tid, _, server_addr, client_addr, len) => {
+                    (pid, tid, Some((server_addr, client_addr, len)))
+                }
+
+                // Nobody is waiting on these. Clients of `Terminated` messages are
+                // already gone, and memory that was moved or lent to the server is
+                // released along with the rest of its process.
+                _ => {
+                    *entry = QueuedMessage::Empty;
+                    continue;
+                }
+            };
+            *entry = QueuedMessage::Empty;
+
+            let client_pid =
+                PID::new(client_pid as _).ok_or(xous_kernel::Error::ProcessNotFound)?;
+            let client_tid = client_tid as _;
+            if let Some((server_addr, client_addr, len)) = lent {
+                ss.return_memory(
+                    server_addr as *mut usize,
+                    client_pid,
+                    client_tid,
+                    client_addr as _,
+                    len,
+                )?;
+            }
+            ss.ready_thread(client_pid, client_tid)?;
+            if !cfg!(baremetal) {
+                ss.switch_to_thread(client_pid, Some(client_tid))?;
+            }
+            ss.set_thread_result(
+                client_pid,
+                client_tid,
+                xous_kernel::Result::Error(xous_kernel::Error::ServerNotFound),
+            )?;
+        }
+
+        // Release the backing memory
+        #[cfg(baremetal)]
+        MemoryManager::with_mut(|mm| {
+            let virt = self.queue.as_mut_ptr() as usize;
+            let size = self.queue.len();
+            for addr in (virt..(virt + size)).step_by(crate::arch::mem::PAGE_SIZE) {
+                mm.unmap_page(addr as *mut usize)?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
     // pub fn print_queue(&self) {
     //     println!("    Q Queue Head: {}", self.queue_head);
     //     println!("    Q Queue Tail: {}", self.queue_tail);
@@ -582,6 +725,9 @@ impl Server {
                 (pid, tid, idx, server_addr, client_addr, len, true, true)
             }
             QueuedMessage::WaitingReturnScalar(pid, tid, idx, return_address) => {
+                (pid, tid, idx, return_address, 0, 0, false, false)
+            }
+            QueuedMessage::WaitingDiscardScalar(pid, tid, idx, return_address) => {
                 (pid, tid, idx, return_address, 0, 0, true, false)
             }
             _ => return Ok(WaitingMessage::None),
@@ -620,6 +766,10 @@ impl Server {
         //     tid
         // );
 
+        if !is_memory && forget {
+            return Ok(WaitingMessage::DiscardedScalar);
+        }
+
         if !is_memory {
             return Ok(WaitingMessage::ScalarMessage(
                 PID::new(pid as _).unwrap(),
@@ -938,6 +1088,24 @@ impl Server {
                     self.head_generation = self.head_generation.wrapping_add(1);
                     return Some(msg);
                 }
+
+                // The sender of this message has already timed out, so skip over it
+                // and start looking for the next message.
+                QueuedMessage::Cancelled(_pid, _tid, idx) if idx == self.head_generation => {
+                    self.queue[queue_idx] = QueuedMessage::Empty;
+                    if queue_idx == self.queue_tail {
+                        self.queue_tail += 1;
+                        if self.queue_tail >= self.queue.len() {
+                            self.queue_tail = 0;
+                        }
+                    }
+                    self.head_generation = self.head_generation.wrapping_add(1);
+                    if self.tail_generation == self.head_generation {
+                        return None;
+                    }
+                    queue_idx = self.queue_tail;
+                    continue;
+                }
                 _ => {
                     queue_idx += 1;
                     if queue_idx >= self.queue.len() {
@@ -1202,4 +1370,109 @@ impl Server {
         self.ready_threads |= 1 << tid;
         klog!("ready threads now: {:08b}", self.ready_threads);
     }
+
+    /// Remove the given context from the list of ready and waiting contexts.
+    /// Returns `true` if it was waiting for a message.
+    pub fn unpark_thread(&mut self, tid: TID) -> bool {
+        if self.ready_threads & (1 << tid) == 0 {
+            return false;
+        }
+        self.ready_threads &= !(1 << tid);
+        true
+    }
+
+    /// Withdraw the blocking message that `pid`:`tid` is waiting on, if it
+    /// was sent to this server. Messages the server has not yet received are
+    /// skipped over when their turn comes, and the response to a scalar
+    /// message the server is working on is dropped.
+    pub fn cancel_message(&mut self, pid: PID, tid: TID) -> Option<CancelledMessage> {
+        let (pid, tid) = (pid.get() as u16, tid as u8);
+        for entry in self.queue.iter_mut() {
+            match *entry {
+                QueuedMessage::BlockingScalarMessage(msg_pid, msg_tid, idx, _, _, _, _, _, _)
+                    if msg_pid == pid && msg_tid == tid =>
+                {
+                    *entry = QueuedMessage::Cancelled(msg_pid, msg_tid, idx);
+                    return Some(CancelledMessage::Scalar);
+                }
+                QueuedMessage::MemoryMessageROLend(
+                    msg_pid,
+                    msg_tid,
+                    idx,
+                    client_addr,
+                    _id,
+                    server_addr,
+                    buf_size,
+                    _offset,
+                    _valid,
+                )
+                | QueuedMessage::MemoryMessageRWLend(
+                    msg_pid,
+                    msg_tid,
+                    idx,
+                    client_addr,
+                    _id,
+                    server_addr,
+                    buf_size,
+                    _offset,
+                    _valid,
+                ) if msg_pid == pid && msg_tid == tid => {
+                    *entry = QueuedMessage::Cancelled(msg_pid, msg_tid, idx);
+                    return Some(CancelledMessage::BorrowedMemory(
+                        server_addr,
+                        client_addr,
+                        buf_size,
+                    ));
+                }
+                QueuedMessage::WaitingReturnScalar(msg_pid, msg_tid, idx, return_address)
+                    if msg_pid == pid && msg_tid == tid =>
+                {
+                    *entry =
+                        QueuedMessage::WaitingDiscardScalar(msg_pid, msg_tid, idx, return_address);
+                    return Some(CancelledMessage::Scalar);
+                }
+                QueuedMessage::WaitingReturnMemory(msg_pid, msg_tid, _, _, _, _)
+                    if msg_pid == pid && msg_tid == tid =>
+                {
+                    return Some(CancelledMessage::InServer);
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// OR `flags` into the event flags. If a thread is waiting on any of the
+    /// flags that are now set, those flags are cleared and returned along with
+    /// the thread, which the caller must then wake.
+    pub fn set_event_flags(&mut self, flags: usize) -> Option<(TID, usize)> {
+        self.event_flags |= flags;
+        let (tid, mask) = self.event_waiter?;
+        if self.event_flags & mask == 0 {
+            return None;
+        }
+        self.event_waiter = None;
+        Some((tid, self.take_event_flags(mask)))
+    }
+
+    /// Clear and return any event flags that are set in `mask`.
+    pub fn take_event_flags(&mut self, mask: usize) -> usize {
+        let flags = self.event_flags & mask;
+        self.event_flags &= !mask;
+        flags
+    }
+
+    /// Record that `tid` is waiting for any of the event flags in `mask`.
+    ///
+    /// # Errors
+    ///
+    /// * **ThreadNotAvailable**: Another thread is already waiting
+    pub fn park_event_waiter(&mut self, tid: TID, mask: usize) -> Result<(), xous_kernel::Error> {
+        if self.event_waiter.is_some() {
+            return Err(xous_kernel::Error::ThreadNotAvailable);
+        }
+        klog!("parking thread {} on event flags {:08x}", tid, mask);
+        self.event_waiter = Some((tid, mask));
+        Ok(())
+    }
 }
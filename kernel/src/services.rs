@@ -11,15 +11,16 @@ use xous_kernel::MemoryRange;
 use core::num::NonZeroU8;
 
 use crate::filled_array;
-use crate::server::Server;
+use crate::server::{CancelledMessage, SenderID, Server};
 // use core::mem;
 use xous_kernel::{
-    pid_from_usize, Error, MemoryAddress, Message, ProcessInit, ThreadInit, CID, PID, SID, TID,
+    pid_from_usize, Error, MemoryAddress, Message, MessageEnvelope, ProcessInfo, ProcessInit,
+    ProcessStatus, ScalarMessage, ThreadInit, ThreadPriority, CID, PID, SID, TID,
 };
 
 const MAX_SERVER_COUNT: usize = 128;
 
-pub use crate::arch::process::{INITIAL_TID, MAX_PROCESS_COUNT};
+pub use crate::arch::process::{INITIAL_TID, MAX_PROCESS_COUNT, MAX_THREAD};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct ExceptionHandler {
@@ -30,6 +31,17 @@ pub struct ExceptionHandler {
     pub sp: usize,
 }
 
+/// The reason a process stopped running, as reported to the supervisor.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProcessExit {
+    /// The process called `TerminateProcess` with the given exit code
+    Exited(u32),
+
+    /// The process was terminated after an unhandled exception of the given
+    /// `ExceptionType`
+    Faulted(usize),
+}
+
 // fn log_process_update(f: &str, l: u32, process: &Process, old_state: ProcessState) {
 //     if process.pid.get() == 3 {
 //         println!("[{}:{}] Updated PID {:?} state: {:?} -> {:?}", f, l, process.pid, old_state, process.state);
@@ -44,6 +56,14 @@ pub struct SystemServices {
 
     /// A table of all servers in the system
     pub servers: [Option<Server>; MAX_SERVER_COUNT],
+
+    /// The server that is told when a process terminates, along with the
+    /// message ID to use
+    supervisor: Option<(SID, usize)>,
+
+    /// Names of the processes the hosted kernel launched itself
+    #[cfg(not(baremetal))]
+    process_names: [Option<String>; MAX_PROCESS_COUNT],
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -126,6 +146,11 @@ pub struct Process {
     /// manipulate this process.
     pub ppid: PID,
 
+    /// The process that asked for this process to be created. Every process
+    /// is parented to PID 1 so that the scheduler will run it, so this is what
+    /// determines who may adjust this process' priority and resource limits.
+    creator: PID,
+
     /// The current thread ID
     pub current_thread: TID,
 
@@ -135,18 +160,45 @@ pub struct Process {
 
     /// When an exception is hit, the kernel will switch to this Thread.
     exception_handler: Option<ExceptionHandler>,
+
+    /// The priority that newly-created threads in this process will receive.
+    priority: ThreadPriority,
+
+    /// The scheduling priority of each thread in this process.
+    thread_priorities: [ThreadPriority; MAX_THREAD + 1],
+
+    /// Cumulative time, in microseconds, that each thread has spent running.
+    run_time: [u64; MAX_THREAD + 1],
+
+    /// Run time of threads whose slots have since been reused, so that the
+    /// total for the process doesn't go backwards.
+    retired_run_time: u64,
+
+    /// The token of the IPC timeout that each thread has armed, if any.
+    ipc_timeouts: [Option<usize>; MAX_THREAD + 1],
+
+    /// Incremented each time a thread arms an IPC timeout, so that a stale
+    /// token can never match a newer timeout.
+    ipc_timeout_generation: usize,
 }
 
 impl Default for Process {
     fn default() -> Self {
         Process {
             ppid: unsafe { PID::new_unchecked(1) },
+            creator: unsafe { PID::new_unchecked(1) },
             state: ProcessState::Allocated,
             pid: unsafe { PID::new_unchecked(2) },
             current_thread: 0,
             previous_thread: 0,
             exception_handler: None,
             mapping: Default::default(),
+            priority: ThreadPriority::Normal,
+            thread_priorities: [ThreadPriority::Normal; MAX_THREAD + 1],
+            run_time: [0; MAX_THREAD + 1],
+            retired_run_time: 0,
+            ipc_timeouts: [None; MAX_THREAD + 1],
+            ipc_timeout_generation: 0,
         }
     }
 }
@@ -207,19 +259,51 @@ impl Default for ProcessInner {
 }
 
 impl Process {
-    /// This process has at least one context that may be run
-    pub fn runnable(&self) -> bool {
-        matches!(
-            self.state,
-            ProcessState::Setup(_) | ProcessState::Ready(_) | ProcessState::Exception(_)
-        )
-    }
-
     /// This process slot is unallocated and may be turn into a process
     pub fn free(&self) -> bool {
         matches!(self.state, ProcessState::Free)
     }
 
+    /// Return the priority of the given thread. Threads that are out of range
+    /// receive the process' default priority.
+    pub fn thread_priority(&self, tid: TID) -> ThreadPriority {
+        self.thread_priorities
+            .get(tid)
+            .copied()
+            .unwrap_or(self.priority)
+    }
+
+    /// Pick the highest-priority thread out of the `threads` bitmask. Threads
+    /// of equal priority are picked in a round-robin fashion, beginning with
+    /// `start`. Returns `None` if no threads are set in the mask.
+    pub fn highest_priority_thread(&self, threads: usize, start: TID) -> Option<TID> {
+        let mut best: Option<TID> = None;
+        for offset in 0..=MAX_THREAD {
+            let tid = (start + offset) % (MAX_THREAD + 1);
+            if threads & (1 << tid) == 0 {
+                continue;
+            }
+            match best {
+                Some(best_tid) if self.thread_priority(best_tid) >= self.thread_priority(tid) => {}
+                _ => best = Some(tid),
+            }
+        }
+        best
+    }
+
+    /// The priority of the most important context within this process that is
+    /// able to run, or `None` if this process has nothing to run.
+    pub fn ready_priority(&self) -> Option<ThreadPriority> {
+        match self.state {
+            ProcessState::Setup(_) => Some(self.thread_priority(INITIAL_TID)),
+            ProcessState::Exception(_) => Some(self.priority),
+            ProcessState::Ready(x) => self
+                .highest_priority_thread(x, 0)
+                .map(|tid| self.thread_priority(tid)),
+            _ => None,
+        }
+    }
+
     pub fn activate(&self) -> Result<(), xous_kernel::Error> {
         crate::arch::process::set_current_pid(self.pid);
         self.mapping.activate()?;
@@ -252,15 +336,24 @@ std::thread_local!(static SYSTEM_SERVICES: core::cell::RefCell<SystemServices> =
     processes: [Process {
         state: ProcessState::Free,
         ppid: unsafe { PID::new_unchecked(1) },
+        creator: unsafe { PID::new_unchecked(1) },
         pid: unsafe { PID::new_unchecked(1) },
         mapping: arch::mem::DEFAULT_MEMORY_MAPPING,
         current_thread: 0_usize,
         previous_thread: INITIAL_TID as TID,
         exception_handler: None,
+        priority: ThreadPriority::Normal,
+        thread_priorities: [ThreadPriority::Normal; MAX_THREAD + 1],
+        run_time: [0; MAX_THREAD + 1],
+        retired_run_time: 0,
+        ipc_timeouts: [None; MAX_THREAD + 1],
+        ipc_timeout_generation: 0,
     }; MAX_PROCESS_COUNT],
     // Note we can't use MAX_SERVER_COUNT here because of how Rust's
     // macro tokenization works
     servers: filled_array![None; 128],
+    supervisor: None,
+    process_names: Default::default(),
 }));
 
 #[cfg(baremetal)]
@@ -268,15 +361,23 @@ static mut SYSTEM_SERVICES: SystemServices = SystemServices {
     processes: [Process {
         state: ProcessState::Free,
         ppid: unsafe { PID::new_unchecked(1) },
+        creator: unsafe { PID::new_unchecked(1) },
         pid: unsafe { PID::new_unchecked(1) },
         mapping: arch::mem::DEFAULT_MEMORY_MAPPING,
         current_thread: 0_usize,
         previous_thread: INITIAL_TID as TID,
         exception_handler: None,
+        priority: ThreadPriority::Normal,
+        thread_priorities: [ThreadPriority::Normal; MAX_THREAD + 1],
+        run_time: [0; MAX_THREAD + 1],
+        retired_run_time: 0,
+        ipc_timeouts: [None; MAX_THREAD + 1],
+        ipc_timeout_generation: 0,
     }; MAX_PROCESS_COUNT],
     // Note we can't use MAX_SERVER_COUNT here because of how Rust's
     // macro tokenization works
     servers: filled_array![None; 128],
+    supervisor: None,
 };
 
 impl core::fmt::Debug for Process {
@@ -396,7 +497,13 @@ impl SystemServices {
     ) -> Result<ProcessStartup, xous_kernel::Error> {
         let mut entry_idx = None;
         let mut new_pid = None;
-        let _ppid = crate::arch::process::current_pid();
+        let creator = crate::arch::process::current_pid();
+        // A process may not start one that would run ahead of it
+        let priority = if creator.get() == 1 {
+            init_process.priority
+        } else {
+            init_process.priority.min(self.get_process(creator)?.priority)
+        };
 
         for (idx, entry) in self.processes.iter_mut().enumerate() {
             if entry.state != ProcessState::Free {
@@ -406,7 +513,13 @@ impl SystemServices {
             new_pid = Some(pid_from_usize(idx + 1)?);
             entry.pid = new_pid.unwrap();
             entry.ppid = PID::new(1).unwrap();
+            entry.creator = creator;
             entry.state = ProcessState::Allocated;
+            entry.priority = priority;
+            entry.thread_priorities = [priority; MAX_THREAD + 1];
+            entry.run_time = [0; MAX_THREAD + 1];
+            entry.retired_run_time = 0;
+            entry.ipc_timeouts = [None; MAX_THREAD + 1];
             unsafe {
                 entry
                     .mapping
@@ -419,6 +532,23 @@ impl SystemServices {
             return Err(xous_kernel::Error::ProcessNotFound);
         }
         let new_pid = new_pid.unwrap();
+        #[cfg(not(baremetal))]
+        {
+            self.process_names[entry_idx.unwrap()] = None;
+        }
+        // The quota must be in place before the process can allocate anything.
+        // A process may not give a new one more than it has left itself, or
+        // it could get around its own quota.
+        crate::mem::MemoryManager::with_mut(|mm| {
+            let quota = if creator.get() == 1 {
+                init_process.memory_quota
+            } else {
+                let left = mm.ram_quota(creator).saturating_sub(mm.ram_pages_used_by(creator));
+                init_process.memory_quota.min(left)
+            };
+            let current = mm.ram_quota(new_pid);
+            mm.adjust_ram_quota(new_pid, current, quota)
+        });
         let startup = arch::process::Process::create(new_pid, init_process, self).unwrap();
 
         #[cfg(baremetal)]
@@ -753,17 +883,9 @@ impl SystemServices {
             }
             ProcessState::Ready(x) => {
                 let new_thread = match tid {
-                    None => {
-                        let mut new_context = 0;
-
-                        while x & (1 << new_context) == 0 {
-                            new_context += 1;
-                            if new_context > arch::process::MAX_THREAD {
-                                new_context = 0;
-                            }
-                        }
-                        new_context
-                    }
+                    None => process
+                        .highest_priority_thread(x, process.current_thread + 1)
+                        .expect("ready process had no threads to run"),
                     Some(ctx) => {
                         // Ensure the specified context is ready to run
                         if x & (1 << ctx) == 0 {
@@ -794,17 +916,9 @@ impl SystemServices {
                 let mut p = ArchProcess::current();
                 // let current_thread = p.current_thread();
                 let new_thread = match tid {
-                    None => {
-                        let mut new_thread = 0;
-
-                        while ready_threads & (1 << new_thread) == 0 {
-                            new_thread += 1;
-                            if new_thread > arch::process::MAX_THREAD {
-                                new_thread = 0;
-                            }
-                        }
-                        new_thread
-                    }
+                    None => process
+                        .highest_priority_thread(ready_threads, process.current_thread + 1)
+                        .expect("running process had no threads to run"),
                     Some(tid) => {
                         // Ensure the specified context is ready to run, or is
                         // currently running.
@@ -967,26 +1081,18 @@ impl SystemServices {
                     // new.current_thread = new_tid;
                 }
                 ProcessState::Running(x) | ProcessState::Ready(x) => {
-                    // If no new context is specified, take the previous
-                    // context.  If that is not runnable, do a round-robin
-                    // search for the next available context.
+                    // If no new context is specified, pick the highest-priority
+                    // context that is ready, round-robining between contexts
+                    // of equal priority.
                     assert!(
                         x != 0,
                         "process was {:?} but had no free contexts",
                         new.state
                     );
                     if new_tid == 0 {
-                        new_tid = (new.current_thread + 1) as usize;
-                        while x & (1 << new_tid) == 0 {
-                            new_tid += 1;
-                            if new_tid > arch::process::MAX_THREAD {
-                                new_tid = 0;
-                            } else if new_tid == (new.current_thread + 1) as usize {
-                                // If we've looped around, return an error.
-                                // println!("Looked through all contexts and couldn't find one that was ready");
-                                return Err(xous_kernel::Error::ProcessNotFound);
-                            }
-                        }
+                        new_tid = new
+                            .highest_priority_thread(x, new.current_thread + 1)
+                            .ok_or(xous_kernel::Error::ProcessNotFound)?;
                         new.current_thread = new_tid as _;
                         klog!("picked thread ID {}", new_tid);
                     } else if x & (1 << new_tid) == 0 {
@@ -1117,20 +1223,13 @@ impl SystemServices {
             // let old_state = new.state;
             new.state = if let ProcessState::Running(x) = new.state {
                 let previous_tid = new.current_thread;
-                // If no new thread is specified, take the previous
-                // thread.  If that is not runnable, do a round-robin
-                // search for the next available thread.
+                // If no new thread is specified, pick the highest-priority
+                // thread that is ready, round-robining between threads of
+                // equal priority.
                 if new_tid == 0 {
-                    new_tid = (new.current_thread + 1) as usize;
-                    while x & (1 << new_tid) == 0 {
-                        new_tid += 1;
-                        if new_tid > arch::process::MAX_THREAD {
-                            new_tid = 0;
-                        } else if new_tid == (new.current_thread + 1) as usize {
-                            // If we've looped around, return an error.
-                            return Err(xous_kernel::Error::ProcessNotFound);
-                        }
-                    }
+                    new_tid = new
+                        .highest_priority_thread(x, new.current_thread + 1)
+                        .ok_or(xous_kernel::Error::ProcessNotFound)?;
                     new.current_thread = new_tid as _;
                 } else if x & (1 << new_tid) == 0 {
                     return Err(xous_kernel::Error::ProcessNotFound);
@@ -1554,6 +1653,18 @@ impl SystemServices {
 
         arch_process.setup_thread(new_tid, thread_init)?;
 
+        // New threads inherit the default priority of their process
+        if let Some(priority) = process.thread_priorities.get_mut(new_tid) {
+            *priority = process.priority;
+        }
+        if let Some(timeout) = process.ipc_timeouts.get_mut(new_tid) {
+            *timeout = None;
+        }
+        if let Some(run_time) = process.run_time.get_mut(new_tid) {
+            process.retired_run_time = process.retired_run_time.saturating_add(*run_time);
+            *run_time = 0;
+        }
+
         // println!("KERNEL({}): Created new thread {}", pid, new_tid);
 
         // let old_state = process.state;
@@ -1628,6 +1739,320 @@ impl SystemServices {
         Ok(new_pid != pid)
     }
 
+    /// Set the scheduling priority of a process, or of a single thread within
+    /// that process. If no thread is specified, every thread in the process
+    /// is adjusted along with the priority that new threads will inherit.
+    /// Returns the previous priority.
+    ///
+    /// # Errors
+    ///
+    /// * **ProcessNotFound**: The target process does not exist
+    /// * **ProcessNotChild**: The caller is neither the target nor its parent
+    /// * **AccessDenied**: The priority is above the caller's own
+    /// * **InvalidThread**: The thread ID is out of range
+    pub fn set_priority(
+        &mut self,
+        caller: PID,
+        pid: PID,
+        tid: Option<TID>,
+        priority: ThreadPriority,
+    ) -> Result<ThreadPriority, xous_kernel::Error> {
+        self.may_adjust(caller, pid)?;
+        self.may_grant_priority(caller, priority)?;
+        let process = self.get_process_mut(pid)?;
+        match tid {
+            Some(tid) => {
+                let slot = process
+                    .thread_priorities
+                    .get_mut(tid)
+                    .ok_or(xous_kernel::Error::InvalidThread)?;
+                Ok(core::mem::replace(slot, priority))
+            }
+            None => {
+                process.thread_priorities = [priority; MAX_THREAD + 1];
+                Ok(core::mem::replace(&mut process.priority, priority))
+            }
+        }
+    }
+
+    /// Look up a process that userspace is asking about, distinguishing
+    /// between PIDs that are out of range and PIDs that are not in use.
+    fn allocated_process(&self, pid: PID) -> Result<&Process, xous_kernel::Error> {
+        let process = self
+            .processes
+            .get(pid.get() as usize - 1)
+            .ok_or(xous_kernel::Error::InvalidPID)?;
+        if process.free() {
+            return Err(xous_kernel::Error::ProcessNotFound);
+        }
+        Ok(process)
+    }
+
+    /// Determine whether `caller` may adjust the priority or resource limits
+    /// of `pid`. This is permitted for the process itself, for the process
+    /// that created it, and for PID 1.
+    ///
+    /// # Errors
+    ///
+    /// * **InvalidPID**: The PID is beyond the end of the process table
+    /// * **ProcessNotFound**: No process is allocated to this PID
+    /// * **ProcessNotChild**: The caller may not adjust the given process
+    pub fn may_adjust(&self, caller: PID, pid: PID) -> Result<(), xous_kernel::Error> {
+        let process = self.allocated_process(pid)?;
+        if caller == pid || caller == process.creator || caller.get() == 1 {
+            Ok(())
+        } else {
+            Err(xous_kernel::Error::ProcessNotChild)
+        }
+    }
+
+    /// Determine whether `caller` may give a process or thread `priority`.
+    /// Priorities never age, so a process may not hand out, or take, a
+    /// priority above its own default. PID 1 is exempt.
+    fn may_grant_priority(
+        &self,
+        caller: PID,
+        priority: ThreadPriority,
+    ) -> Result<(), xous_kernel::Error> {
+        if caller.get() == 1 || priority <= self.get_process(caller)?.priority {
+            Ok(())
+        } else {
+            Err(xous_kernel::Error::AccessDenied)
+        }
+    }
+
+    /// Return a snapshot of the process table entry for the given PID.
+    ///
+    /// # Errors
+    ///
+    /// * **InvalidPID**: The PID is beyond the end of the process table
+    /// * **ProcessNotFound**: No process is allocated to this PID
+    pub fn process_info(&self, pid: PID) -> Result<ProcessInfo, xous_kernel::Error> {
+        let process = self.allocated_process(pid)?;
+        let status = match process.state {
+            ProcessState::Free => return Err(xous_kernel::Error::ProcessNotFound),
+            ProcessState::Allocated => ProcessStatus::Allocated,
+            ProcessState::Setup(_) => ProcessStatus::Setup,
+            ProcessState::Ready(x) => ProcessStatus::Ready(x),
+            ProcessState::Running(x) => ProcessStatus::Running(x),
+            ProcessState::Sleeping => ProcessStatus::Sleeping,
+            ProcessState::Debug(x) => ProcessStatus::Debug(x),
+            ProcessState::Exception(x) => ProcessStatus::Exception(x),
+            ProcessState::BlockedException(x) => ProcessStatus::BlockedException(x),
+        };
+
+        // The thread table is only reachable while the process is active, so
+        // briefly switch to it and then restore the current process.
+        let current_pid = self.current_pid();
+        process.activate()?;
+        let threads = ArchProcess::current().thread_count();
+        self.get_process(current_pid)?.activate()?;
+
+        let servers = self
+            .servers
+            .iter()
+            .filter(|server| matches!(server, Some(server) if server.pid == pid))
+            .count();
+
+        #[cfg(baremetal)]
+        let ram_bytes = crate::mem::MemoryManager::with(|mm| mm.ram_used_by(pid));
+        #[cfg(not(baremetal))]
+        let ram_bytes = 0;
+
+        Ok(ProcessInfo {
+            pid,
+            ppid: process.ppid,
+            status,
+            threads,
+            servers,
+            ram_bytes,
+        })
+    }
+
+    /// Return the total length of the name of the given process along with
+    /// the bytes of the name starting at `offset`, packed into words.
+    ///
+    /// # Errors
+    ///
+    /// * **InvalidPID**: The PID is beyond the end of the process table
+    /// * **ProcessNotFound**: No process is allocated to this PID
+    pub fn process_name_chunk(
+        &self,
+        pid: PID,
+        offset: usize,
+    ) -> Result<(usize, [usize; 4]), xous_kernel::Error> {
+        self.allocated_process(pid)?;
+        let name = self.process_name(pid).unwrap_or("").as_bytes();
+        let mut words = [0usize; 4];
+        let remaining = name.get(offset..).unwrap_or(&[]);
+        for (word, chunk) in words
+            .iter_mut()
+            .zip(remaining.chunks(core::mem::size_of::<usize>()))
+        {
+            let mut bytes = [0u8; core::mem::size_of::<usize>()];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *word = usize::from_le_bytes(bytes);
+        }
+        Ok((name.len(), words))
+    }
+
+    /// Add `elapsed` microseconds to the run time of the given thread.
+    pub fn charge_run_time(&mut self, pid: PID, tid: TID, elapsed: u64) {
+        if let Some(run_time) = self
+            .processes
+            .get_mut(pid.get() as usize - 1)
+            .and_then(|process| process.run_time.get_mut(tid))
+        {
+            *run_time = run_time.saturating_add(elapsed);
+        }
+    }
+
+    /// Return the cumulative run time, in microseconds, of the given thread.
+    /// If no thread is specified, return the total for the whole process.
+    ///
+    /// # Errors
+    ///
+    /// * **InvalidPID**: The PID is beyond the end of the process table
+    /// * **ProcessNotFound**: No process is allocated to this PID
+    /// * **InvalidThread**: The thread ID is out of range
+    pub fn run_time(&self, pid: PID, tid: Option<TID>) -> Result<u64, xous_kernel::Error> {
+        let process = self.allocated_process(pid)?;
+        match tid {
+            Some(tid) => process
+                .run_time
+                .get(tid)
+                .copied()
+                .ok_or(xous_kernel::Error::InvalidThread),
+            None => Ok(process.retired_run_time + process.run_time.iter().sum::<u64>()),
+        }
+    }
+
+    /// Arm an IPC timeout for the given thread, replacing any that is already
+    /// armed. Returns the token that identifies this timeout, which carries
+    /// the thread ID in its lowest byte.
+    pub fn arm_ipc_timeout(&mut self, pid: PID, tid: TID) -> Result<usize, xous_kernel::Error> {
+        let process = self.get_process_mut(pid)?;
+        let slot = process
+            .ipc_timeouts
+            .get_mut(tid)
+            .ok_or(xous_kernel::Error::InvalidThread)?;
+        process.ipc_timeout_generation = (process.ipc_timeout_generation + 1) & (usize::MAX >> 8);
+        if process.ipc_timeout_generation == 0 {
+            process.ipc_timeout_generation = 1;
+        }
+        let token = (process.ipc_timeout_generation << 8) | tid;
+        *slot = Some(token);
+        Ok(token)
+    }
+
+    /// Disarm the IPC timeout of the given thread, if it has one.
+    pub fn disarm_ipc_timeout(&mut self, pid: PID, tid: TID) -> Result<(), xous_kernel::Error> {
+        let process = self.get_process_mut(pid)?;
+        *process
+            .ipc_timeouts
+            .get_mut(tid)
+            .ok_or(xous_kernel::Error::InvalidThread)? = None;
+        Ok(())
+    }
+
+    /// Withdraw the blocking send or receive of the thread that armed the IPC
+    /// timeout `token` in `pid`. Returns the thread if its call was withdrawn,
+    /// in which case the caller must wake it up with an error. Returns `None`
+    /// if the token is stale, or if the thread lent memory that the server is
+    /// already working on and must therefore keep waiting.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: `caller` is not the ticktimer
+    /// * **ProcessNotFound**: `pid` does not exist
+    /// * **ThreadNotAvailable**: The thread has not started waiting yet
+    pub fn expire_ipc_timeout(
+        &mut self,
+        caller: PID,
+        pid: PID,
+        token: usize,
+    ) -> Result<Option<TID>, xous_kernel::Error> {
+        // Only the ticktimer knows when a timeout has elapsed
+        let ticktimer = SID::from_bytes(b"ticktimer-server").unwrap();
+        if !self
+            .servers
+            .iter()
+            .flatten()
+            .any(|server| server.pid == caller && server.sid == ticktimer)
+        {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+
+        let tid = token & 0xff;
+        if self.get_process(pid)?.ipc_timeouts.get(tid) != Some(&Some(token)) {
+            return Ok(None);
+        }
+
+        // A thread waiting to receive a message is parked on one of its own servers
+        let mut withdrawn = self
+            .servers
+            .iter_mut()
+            .flatten()
+            .any(|server| server.pid == pid && server.unpark_thread(tid));
+
+        // Otherwise, look for the message it is waiting to have answered
+        if !withdrawn {
+            let mut cancelled = None;
+            for server in self.servers.iter_mut().flatten() {
+                if let Some(message) = server.cancel_message(pid, tid) {
+                    cancelled = Some((server.pid, message));
+                    break;
+                }
+            }
+            withdrawn = match cancelled {
+                None => return Err(xous_kernel::Error::ThreadNotAvailable),
+                Some((_, CancelledMessage::InServer)) => false,
+                Some((_, CancelledMessage::Scalar)) => true,
+                Some((server_pid, CancelledMessage::BorrowedMemory(server_addr, client_addr, len))) => {
+                    // The memory is mapped into the server, so unlend it from
+                    // there before switching back to the caller.
+                    let current_pid = self.current_pid();
+                    self.get_process(server_pid)?.activate()?;
+                    let result = self.return_memory(
+                        server_addr as *mut usize,
+                        pid,
+                        tid,
+                        client_addr as *mut usize,
+                        len,
+                    );
+                    self.get_process(current_pid)?.activate()?;
+                    result?;
+                    true
+                }
+            };
+        }
+
+        self.disarm_ipc_timeout(pid, tid)?;
+        Ok(if withdrawn { Some(tid) } else { None })
+    }
+
+    /// Set the priority that new threads in a process will inherit, leaving
+    /// the priority of its existing threads alone. Returns the previous
+    /// default priority.
+    ///
+    /// # Errors
+    ///
+    /// * **InvalidPID**: The PID is beyond the end of the process table
+    /// * **ProcessNotFound**: The target process does not exist
+    /// * **ProcessNotChild**: The caller is neither the target nor its parent
+    /// * **AccessDenied**: The priority is above the caller's own
+    pub fn set_default_priority(
+        &mut self,
+        caller: PID,
+        pid: PID,
+        priority: ThreadPriority,
+    ) -> Result<ThreadPriority, xous_kernel::Error> {
+        self.may_adjust(caller, pid)?;
+        self.may_grant_priority(caller, priority)?;
+        let process = self.get_process_mut(pid)?;
+        Ok(core::mem::replace(&mut process.priority, priority))
+    }
+
     /// Park this thread if the target thread is currently running. Otherwise,
     /// return the value of the given thread.
     pub fn join_thread(
@@ -2059,8 +2484,13 @@ impl SystemServices {
     //     None
     // }
 
-    /// Terminate the given process. Returns the process' parent PID.
-    pub fn terminate_process(&mut self, target_pid: PID) -> Result<PID, xous_kernel::Error> {
+    /// Terminate the given process and tell the supervisor why it stopped.
+    /// Returns the process' parent PID.
+    pub fn terminate_process(
+        &mut self,
+        target_pid: PID,
+        exit: ProcessExit,
+    ) -> Result<PID, xous_kernel::Error> {
         // To terminate a process, we must perform the following:
         //
         // 1. If we have any client connections, remove them.
@@ -2102,14 +2532,15 @@ impl SystemServices {
             }
         }
 
-        // Now that the server has been "Disconnected", free the server entry.
-        #[allow(clippy::manual_flatten)]
-        for server in self.servers.iter_mut() {
-            if let Some(server_inner) = server {
-                if server_inner.pid == target_pid {
-                    *server = None;
-                }
+        // Now that the server has been "Disconnected", free the server entry and
+        // wake up any clients that are still waiting on it.
+        for sidx in 0..self.servers.len() {
+            if self.servers[sidx].as_ref().map(|server| server.pid) != Some(target_pid) {
+                continue;
             }
+            let server = self.servers[sidx].take().unwrap();
+            self.get_process(target_pid)?.activate()?;
+            server.terminate(self)?;
         }
 
         let process = self.get_process_mut(target_pid)?;
@@ -2119,9 +2550,79 @@ impl SystemServices {
 
         self.switch_to_thread(parent_pid, None).unwrap();
 
+        self.notify_supervisor(target_pid, exit);
+
         Ok(parent_pid)
     }
 
+    /// Make `sid`, which must be owned by `pid`, the server that is told when
+    /// a process terminates. A different supervisor may only take over once the
+    /// current one has gone away.
+    pub fn set_supervisor(&mut self, pid: PID, sid: SID, id: usize) -> Result<(), xous_kernel::Error> {
+        self.sidx_from_sid(sid, pid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        if let Some((current, _)) = self.supervisor {
+            if current != sid && self.servers.iter().flatten().any(|server| server.sid == current) {
+                return Err(xous_kernel::Error::AccessDenied);
+            }
+        }
+        self.supervisor = Some((sid, id));
+        Ok(())
+    }
+
+    /// Send the supervisor, if there is one, a message saying that `pid` has
+    /// terminated. The message comes from PID 1. If the supervisor's queue is
+    /// full the notification is dropped.
+    fn notify_supervisor(&mut self, pid: PID, exit: ProcessExit) {
+        let (sid, id) = match self.supervisor {
+            Some(supervisor) => supervisor,
+            None => return,
+        };
+        let sidx = match self
+            .servers
+            .iter()
+            .position(|server| server.as_ref().map(|server| server.sid) == Some(sid))
+        {
+            Some(sidx) => sidx,
+            None => return,
+        };
+        let (code, faulted) = match exit {
+            ProcessExit::Exited(code) => (code as usize, 0),
+            ProcessExit::Faulted(exception) => (exception, 1),
+        };
+        let message = Message::Scalar(ScalarMessage {
+            id,
+            arg1: pid.get() as usize,
+            arg2: code,
+            arg3: faulted,
+            arg4: 0,
+        });
+        let kernel_pid = PID::new(1).unwrap();
+
+        let server = self.servers[sidx].as_mut().unwrap();
+        let server_pid = server.pid;
+        if let Some(server_tid) = server.take_available_thread() {
+            let envelope = MessageEnvelope {
+                sender: SenderID::new(sidx, 0, Some(kernel_pid)).into(),
+                body: message,
+            };
+            let woken = self.ready_thread(server_pid, server_tid).and_then(|_| {
+                if !cfg!(baremetal) {
+                    self.switch_to_thread(server_pid, Some(server_tid))?;
+                }
+                self.set_thread_result(server_pid, server_tid, xous_kernel::Result::Message(envelope))
+            });
+            if woken.is_err() {
+                klog!("couldn't wake supervisor {}:{}", server_pid, server_tid);
+            }
+        } else if self
+            .queue_server_message(sidx, kernel_pid, 0, message, None)
+            .is_err()
+        {
+            klog!("supervisor queue is full -- dropping exit notice for {}", pid);
+        }
+    }
+
     #[cfg(feature="gdb-stub")]
     pub fn suspend_process(&mut self, pid: PID) -> Result<(), xous_kernel::Error> {
         let (process_state, parent_pid) = {
@@ -2315,4 +2816,21 @@ impl SystemServices {
         }
         None
     }
+
+    /// There is no loader in hosted mode, so only processes that the kernel
+    /// launched from its command line have names.
+    #[cfg(not(baremetal))]
+    pub fn process_name(&self, pid: PID) -> Option<&str> {
+        self.process_names
+            .get(pid.get() as usize - 1)
+            .and_then(|name| name.as_deref())
+    }
+
+    /// Record the name of a process launched by the hosted kernel.
+    #[cfg(all(not(baremetal), not(test)))]
+    pub fn set_process_name(&mut self, pid: PID, name: &str) {
+        if let Some(entry) = self.process_names.get_mut(pid.get() as usize - 1) {
+            *entry = Some(name.to_owned());
+        }
+    }
 }
@@ -6,7 +6,7 @@ use crate::arch::process::Process as ArchProcess;
 use crate::irq::interrupt_claim;
 use crate::mem::{MemoryManager, PAGE_SIZE};
 use crate::server::{SenderID, WaitingMessage};
-use crate::services::SystemServices;
+use crate::services::{ProcessExit, SystemServices};
 use core::mem;
 use xous_kernel::*;
 
@@ -328,7 +328,7 @@ fn return_memory(
                     result
                 })
             }
-            WaitingMessage::ScalarMessage(_pid, _tid) => {
+            WaitingMessage::ScalarMessage(_, _) | WaitingMessage::DiscardedScalar => {
                 println!("WARNING: Tried to wait on a message that was a scalar");
                 return Err(xous_kernel::Error::InternalError);
             }
@@ -411,6 +411,8 @@ fn return_scalar(
         let result = server.take_waiting_message(sender.idx, None)?;
         let (client_pid, client_tid) = match result {
             WaitingMessage::ScalarMessage(pid, tid) => (pid, tid),
+            // The client timed out, so there is nobody to respond to.
+            WaitingMessage::DiscardedScalar => return Ok(xous_kernel::Result::Ok),
             WaitingMessage::ForgetMemory(_) => {
                 println!(
                     "WARNING: Tried to wait on a scalar message that was actually forgettingmemory"
@@ -484,6 +486,8 @@ fn return_scalar2(
         let result = server.take_waiting_message(sender.idx, None)?;
         let (client_pid, client_tid) = match result {
             WaitingMessage::ScalarMessage(pid, tid) => (pid, tid),
+            // The client timed out, so there is nobody to respond to.
+            WaitingMessage::DiscardedScalar => return Ok(xous_kernel::Result::Ok),
             WaitingMessage::ForgetMemory(_) => {
                 println!("WARNING: Tried to wait on a scalar message that was actually forgetting memory");
                 return Err(xous_kernel::Error::ProcessNotFound);
@@ -597,6 +601,99 @@ fn receive_message(pid: PID, tid: TID, sid: SID, blocking: ExecutionType) -> Sys
     })
 }
 
+fn set_event_flags(cid: CID, flags: usize) -> SysCallResult {
+    SystemServices::with_mut(|ss| {
+        let sidx = ss
+            .sidx_from_cid(cid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        let server = ss
+            .server_from_sidx_mut(sidx)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        let server_pid = server.pid;
+
+        // If the server has a thread waiting on these flags, hand them over
+        // and wake it up. The caller keeps running either way.
+        if let Some((server_tid, flags)) = server.set_event_flags(flags) {
+            klog!(
+                "waking {}:{} with event flags {:08x}",
+                server_pid,
+                server_tid,
+                flags
+            );
+            ss.ready_thread(server_pid, server_tid)?;
+            if !cfg!(baremetal) {
+                ss.switch_to_thread(server_pid, Some(server_tid))?;
+            }
+            ss.set_thread_result(server_pid, server_tid, xous_kernel::Result::Scalar1(flags))?;
+        }
+        Ok(xous_kernel::Result::Ok)
+    })
+}
+
+fn wait_event_flags(
+    pid: PID,
+    tid: TID,
+    sid: SID,
+    mask: usize,
+    blocking: ExecutionType,
+) -> SysCallResult {
+    SystemServices::with_mut(|ss| {
+        let sidx = ss
+            .sidx_from_sid(sid, pid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        let server = ss
+            .server_from_sidx_mut(sidx)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        if server.pid != pid {
+            return Err(xous_kernel::Error::ServerNotFound);
+        }
+
+        let flags = server.take_event_flags(mask);
+        if flags != 0 || blocking == ExecutionType::NonBlocking {
+            return Ok(xous_kernel::Result::Scalar1(flags));
+        }
+
+        // Waiting on an empty mask would never return
+        if mask == 0 {
+            return Err(xous_kernel::Error::InvalidSyscall);
+        }
+        server.park_event_waiter(tid, mask)?;
+
+        // Block exactly as `ReceiveMessage` does. The result will be filled
+        // in by `SetEventFlags`.
+        if cfg!(baremetal) {
+            unsafe { SWITCHTO_CALLER = None };
+            let ppid = ss.get_process(pid).expect("Can't get current process").ppid;
+            ss.activate_process_thread(tid, ppid, 0, false)
+                .map(|_| Ok(xous_kernel::Result::ResumeProcess))
+                .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
+        } else {
+            ss.unschedule_thread(pid, tid)
+                .map(|_| xous_kernel::Result::BlockedProcess)
+        }
+    })
+}
+
+fn expire_ipc_timeout(caller: PID, pid: PID, token: usize) -> SysCallResult {
+    SystemServices::with_mut(|ss| {
+        // If the thread's call was withdrawn, wake it up with an error. The
+        // caller keeps running either way.
+        if let Some(tid) = ss.expire_ipc_timeout(caller, pid, token)? {
+            klog!("IPC timeout expired for {}:{}", pid, tid);
+            ss.ready_thread(pid, tid)?;
+            if !cfg!(baremetal) {
+                ss.switch_to_thread(pid, Some(tid))?;
+            }
+            ss.set_thread_result(
+                pid,
+                tid,
+                xous_kernel::Result::Error(xous_kernel::Error::Timeout),
+            )?;
+        }
+        Ok(xous_kernel::Result::Ok)
+    })
+}
+
 pub fn handle(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallResult {
     #[cfg(feature = "debug-print")]
     print!("KERNEL({}:{}): Syscall {:x?}", pid, tid, call);
@@ -856,9 +953,29 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
             return_scalar2(pid, tid, in_irq, sender, arg1, arg2)
         }
         SysCall::TrySendMessage(cid, message) => send_message(pid, tid, cid, message),
-        SysCall::TerminateProcess(_ret) => SystemServices::with_mut(|ss| {
+        SysCall::SetEventFlags(cid, flags) => set_event_flags(cid, flags),
+        SysCall::WaitEventFlags(sid, mask) => {
+            wait_event_flags(pid, tid, sid, mask, ExecutionType::Blocking)
+        }
+        SysCall::TryWaitEventFlags(sid, mask) => {
+            wait_event_flags(pid, tid, sid, mask, ExecutionType::NonBlocking)
+        }
+        SysCall::ArmIpcTimeout => SystemServices::with_mut(|ss| {
+            ss.arm_ipc_timeout(pid, tid)
+                .map(xous_kernel::Result::Scalar1)
+        }),
+        SysCall::DisarmIpcTimeout => SystemServices::with_mut(|ss| {
+            ss.disarm_ipc_timeout(pid, tid)
+                .map(|_| xous_kernel::Result::Ok)
+        }),
+        SysCall::ExpireIpcTimeout(target_pid, token) => expire_ipc_timeout(pid, target_pid, token),
+        SysCall::SetSupervisor(sid, id) => SystemServices::with_mut(|ss| {
+            ss.set_supervisor(pid, sid, id)
+                .map(|_| xous_kernel::Result::Ok)
+        }),
+        SysCall::TerminateProcess(exit_code) => SystemServices::with_mut(|ss| {
             ss.unschedule_thread(pid, tid)?;
-            ss.terminate_process(pid)?;
+            ss.terminate_process(pid, ProcessExit::Exited(exit_code))?;
             // Clear out `SWITCHTO_CALLER` since we're resuming the parent process.
             unsafe { SWITCHTO_CALLER = None };
             Ok(xous_kernel::Result::ResumeProcess)
@@ -921,21 +1038,53 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
             MemoryManager::with_mut(|mm| mm.update_memory_flags(range, flags))?;
             Ok(xous_kernel::Result::Ok)
         }
-        SysCall::AdjustProcessLimit(index, current, new) => match index {
-            1 => arch::process::Process::with_inner_mut(|p| {
-                if p.mem_heap_max == current {
-                    p.mem_heap_max = new;
-                }
-                Ok(xous_kernel::Result::Scalar2(index, p.mem_heap_max))
-            }),
-            2 => arch::process::Process::with_inner_mut(|p| {
-                if p.mem_heap_size == current && new < p.mem_heap_max {
-                    p.mem_heap_size = new;
+        SysCall::AdjustProcessLimit(index, current, new, target_pid) => {
+            let target_pid = target_pid.unwrap_or(pid);
+            if index == 1 || index == 2 {
+                // The heap lives in the process' own address space
+                if target_pid != pid {
+                    return Err(xous_kernel::Error::InvalidLimit);
                 }
-                Ok(xous_kernel::Result::Scalar2(index, p.mem_heap_size))
-            }),
-            _ => Err(xous_kernel::Error::InvalidLimit),
-        },
+            } else {
+                SystemServices::with(|ss| ss.may_adjust(pid, target_pid))?;
+            }
+            match index {
+                1 => arch::process::Process::with_inner_mut(|p| {
+                    if p.mem_heap_max == current {
+                        p.mem_heap_max = new;
+                    }
+                    Ok(xous_kernel::Result::Scalar2(index, p.mem_heap_max))
+                }),
+                2 => arch::process::Process::with_inner_mut(|p| {
+                    if p.mem_heap_size == current && new < p.mem_heap_max {
+                        p.mem_heap_size = new;
+                    }
+                    Ok(xous_kernel::Result::Scalar2(index, p.mem_heap_size))
+                }),
+                3 => MemoryManager::with_mut(|mm| {
+                    // A process may give up memory, but only its creator may grant more,
+                    // and no more than it has left itself
+                    let quota = mm.ram_quota(target_pid);
+                    if pid.get() != 1 && current == quota && new > quota {
+                        let left = mm.ram_quota(pid).saturating_sub(mm.ram_pages_used_by(pid));
+                        if target_pid == pid || new > left {
+                            return Err(xous_kernel::Error::AccessDenied);
+                        }
+                    }
+                    Ok(xous_kernel::Result::Scalar2(
+                        index,
+                        mm.adjust_ram_quota(target_pid, current, new),
+                    ))
+                }),
+                4 => MemoryManager::with_mut(|mm| {
+                    Ok(xous_kernel::Result::Scalar2(
+                        index,
+                        mm.ram_pages_used_by(target_pid),
+                    ))
+                }),
+                _ => Err(xous_kernel::Error::InvalidLimit),
+            }
+        }
         #[cfg(feature="v2p")]
         SysCall::VirtToPhys(vaddr) => {
             let phys_addr = crate::arch::mem::virt_to_phys(vaddr as usize);
@@ -944,6 +1093,27 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
                 Err(_) => Err(xous_kernel::Error::BadAddress),
             }
         }
+        SysCall::SetPriority(target_pid, target_tid, priority) => SystemServices::with_mut(|ss| {
+            ss.set_priority(pid, target_pid.unwrap_or(pid), target_tid, priority)
+                .map(|previous| xous_kernel::Result::Scalar1(previous.to_usize()))
+        }),
+        SysCall::SetDefaultPriority(target_pid, priority) => SystemServices::with_mut(|ss| {
+            ss.set_default_priority(pid, target_pid.unwrap_or(pid), priority)
+                .map(|previous| xous_kernel::Result::Scalar1(previous.to_usize()))
+        }),
+        SysCall::GetProcessInfo(target_pid) => SystemServices::with(|ss| {
+            ss.process_info(target_pid)
+                .map(xous_kernel::Result::ProcessInfo)
+        }),
+        SysCall::GetProcessName(target_pid, offset) => SystemServices::with(|ss| {
+            ss.process_name_chunk(target_pid, offset)
+                .map(|(len, w)| xous_kernel::Result::Scalar5(len, w[0], w[1], w[2], w[3]))
+        }),
+        SysCall::GetRunTime(target_pid, target_tid) => SystemServices::with(|ss| {
+            ss.run_time(target_pid, target_tid).map(|time| {
+                xous_kernel::Result::Scalar2(time as u32 as usize, (time >> 32) as usize)
+            })
+        }),
         /* https://github.com/betrusted-io/xous-core/issues/90
         SysCall::SetExceptionHandler(pc, sp) => SystemServices::with_mut(|ss| {
             ss.set_exception_handler(pid, pc, sp)
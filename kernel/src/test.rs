@@ -20,6 +20,16 @@ use core::sync::atomic::{AtomicU64, Ordering};
 static RNG_LOCAL_STATE: AtomicU64 = AtomicU64::new(1);
 
 fn start_kernel(server_spec: &str) -> JoinHandle<()> {
+    start_kernel_with_schedule(server_spec, None, None)
+}
+
+/// Start the kernel, optionally recording the order of syscalls to `record`
+/// and replaying the order stored in `replay`.
+fn start_kernel_with_schedule(
+    server_spec: &str,
+    record: Option<String>,
+    replay: Option<String>,
+) -> JoinHandle<()> {
     assert!(
         std::env::var("XOUS_LISTEN_ADDR").is_err(),
         "XOUS_LISTEN_ADDR environment variable must be unset to run tests"
@@ -62,6 +72,7 @@ fn start_kernel(server_spec: &str) -> JoinHandle<()> {
             crate::arch::set_pid1_key(pid1_key);
             crate::arch::set_send_addr(send_addr);
             crate::arch::set_listen_address(&server_spec_server);
+            crate::arch::replay::set_record_replay_paths(record.as_deref(), replay.as_deref());
             kmain()
         })
         .expect("couldn't start kernel thread");
@@ -1177,3 +1188,816 @@ fn process_restart_server() {
 
     main_thread.join().expect("couldn't join kernel process");
 }
+
+/// Test that thread and process priorities can be adjusted, that a process
+/// may not change the priority of a process it doesn't own, and that it may
+/// not raise itself above its own default
+#[test]
+fn set_priority() {
+    use xous_kernel::ThreadPriority;
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let (pid_send, pid_recv) = unbounded();
+    let (done_send, done_recv) = unbounded();
+
+    let idle_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("set_priority idle process", move || {
+            let previous = xous_kernel::set_process_priority(None, ThreadPriority::Idle)
+                .expect("couldn't set process priority");
+            assert_eq!(previous, ThreadPriority::Normal);
+            pid_send.send(xous_kernel::current_pid().unwrap()).unwrap();
+            done_recv.recv().unwrap();
+        }),
+    )
+    .expect("couldn't start idle process");
+
+    let other_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("set_priority other process", move || {
+            let idle_pid = pid_recv.recv().unwrap();
+            assert_eq!(
+                xous_kernel::set_process_priority(Some(idle_pid), ThreadPriority::Realtime),
+                Err(xous_kernel::Error::ProcessNotChild)
+            );
+
+            assert_eq!(
+                xous_kernel::set_thread_priority(ThreadPriority::High),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            assert_eq!(
+                xous_kernel::set_process_priority(None, ThreadPriority::Realtime),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            let previous = xous_kernel::set_thread_priority(ThreadPriority::Low)
+                .expect("couldn't set thread priority");
+            assert_eq!(previous, ThreadPriority::Normal);
+
+            // New threads inherit the process default rather than the priority
+            // of the thread that spawned them.
+            let (child_send, child_recv) = unbounded();
+            let child = xous_kernel::create_thread(move || {
+                let previous = xous_kernel::set_thread_priority(ThreadPriority::Normal)
+                    .expect("couldn't set child thread priority");
+                child_send.send(previous).unwrap();
+            })
+            .expect("couldn't spawn thread");
+            xous_kernel::wait_thread(child).expect("couldn't wait for thread");
+            assert_eq!(child_recv.recv().unwrap(), ThreadPriority::Normal);
+            done_send.send(()).unwrap();
+        }),
+    )
+    .expect("couldn't start other process");
+
+    xous_kernel::wait_process_as_thread(other_process).expect("couldn't join other process");
+    xous_kernel::wait_process_as_thread(idle_process).expect("couldn't join idle process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a process and its threads can be given a priority when they are
+/// created, rather than having to adjust it afterwards
+#[test]
+fn spawn_priority() {
+    use xous_kernel::ThreadPriority;
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let high_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("spawn_priority high process", move || {
+            // The initial thread starts out with the priority of the process
+            let previous = xous_kernel::set_thread_priority(ThreadPriority::High)
+                .expect("couldn't set thread priority");
+            assert_eq!(previous, ThreadPriority::High);
+
+            // Changing the default only affects threads created afterwards
+            let previous = xous_kernel::set_default_priority(None, ThreadPriority::Low)
+                .expect("couldn't set default priority");
+            assert_eq!(previous, ThreadPriority::High);
+            let (child_send, child_recv) = unbounded();
+            let child = xous_kernel::create_thread(move || {
+                let previous = xous_kernel::set_thread_priority(ThreadPriority::Idle)
+                    .expect("couldn't set child thread priority");
+                child_send.send(previous).unwrap();
+            })
+            .expect("couldn't spawn thread");
+            xous_kernel::wait_thread(child).expect("couldn't wait for thread");
+            assert_eq!(child_recv.recv().unwrap(), ThreadPriority::Low);
+
+            // Having lowered its default, it may not go back above it
+            assert_eq!(
+                xous_kernel::set_thread_priority(ThreadPriority::High),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+        })
+        .priority(ThreadPriority::High),
+    )
+    .expect("couldn't start high process");
+
+    xous_kernel::wait_process_as_thread(high_process).expect("couldn't join high process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that the scheduler picks a ready high-priority process ahead of
+/// low-priority processes that are always ready to run, such as ones that are
+/// spinning, and that it round-robins between processes of equal priority.
+/// Hosted processes run as host threads, so this drives the scheduler
+/// directly rather than through a running kernel.
+#[test]
+fn scheduling_order() {
+    use crate::services::SystemServices;
+    use xous_kernel::{ProcessInit, ProcessKey, ThreadInit, ThreadPriority, PID};
+
+    fn spawn(ss: &mut SystemServices, priority: ThreadPriority) -> PID {
+        let init = ProcessInit {
+            key: ProcessKey::new([0; 16]),
+            priority,
+            memory_quota: usize::MAX,
+        };
+        // Start each one from PID 1, as `create_thread` leaves the process
+        // it adds a thread to as the current one
+        crate::arch::process::set_current_pid(PID::new(1).unwrap());
+        let pid = ss.create_process(init).unwrap().pid();
+        ss.create_thread(pid, ThreadInit {}).unwrap();
+        pid
+    }
+
+    // The process table is per-thread in hosted mode, so this test has it
+    // to itself.
+    let (pid1, spinner, other_spinner, interactive) = SystemServices::with_mut(|ss| {
+        let init = ProcessInit {
+            key: ProcessKey::new([0; 16]),
+            priority: ThreadPriority::Normal,
+            memory_quota: usize::MAX,
+        };
+        let pid1 = ss.create_process(init).unwrap().pid();
+        (
+            pid1,
+            spawn(ss, ThreadPriority::Low),
+            spawn(ss, ThreadPriority::Low),
+            spawn(ss, ThreadPriority::High),
+        )
+    });
+
+    // The high-priority process is picked no matter which process ran last
+    assert_eq!(crate::next_pid_to_run(Some(spinner)), Some(interactive));
+    assert_eq!(crate::next_pid_to_run(Some(other_spinner)), Some(interactive));
+    assert_eq!(crate::next_pid_to_run(Some(interactive)), Some(interactive));
+
+    // Once it drops below the spinners, they take turns
+    SystemServices::with_mut(|ss| {
+        ss.set_priority(pid1, interactive, None, ThreadPriority::Idle)
+            .unwrap()
+    });
+    assert_eq!(crate::next_pid_to_run(Some(spinner)), Some(other_spinner));
+    assert_eq!(crate::next_pid_to_run(Some(other_spinner)), Some(spinner));
+
+    // Within a process, the highest-priority ready thread is picked
+    SystemServices::with_mut(|ss| {
+        use crate::services::{INITIAL_TID, MAX_THREAD};
+        ss.set_priority(pid1, spinner, Some(MAX_THREAD), ThreadPriority::Realtime)
+            .unwrap();
+        let process = ss.get_process(spinner).unwrap();
+        let ready = (1 << INITIAL_TID) | (1 << MAX_THREAD);
+        for start in 0..=MAX_THREAD {
+            assert_eq!(process.highest_priority_thread(ready, start), Some(MAX_THREAD));
+        }
+    });
+}
+
+/// Test that no process but PID 1 may raise a priority above its own, whether
+/// by setting it or by starting a process, and that a creator may adjust its
+/// child's priorities through either call
+#[test]
+fn priority_limits() {
+    use crate::arch::process::set_current_pid;
+    use crate::services::{SystemServices, INITIAL_TID};
+    use xous_kernel::{Error, ProcessInit, ProcessKey, ThreadPriority::*};
+
+    let init = |priority| ProcessInit {
+        key: ProcessKey::new([0; 16]),
+        priority,
+        memory_quota: usize::MAX,
+    };
+    SystemServices::with_mut(|ss| {
+        let pid1 = ss.create_process(init(Normal)).unwrap().pid();
+        set_current_pid(pid1);
+        let parent = ss.create_process(init(Normal)).unwrap().pid();
+        assert_eq!(ss.set_priority(parent, parent, None, Realtime), Err(Error::AccessDenied));
+        assert_eq!(
+            ss.set_priority(parent, parent, Some(INITIAL_TID), High),
+            Err(Error::AccessDenied)
+        );
+        assert_eq!(ss.set_default_priority(parent, parent, High), Err(Error::AccessDenied));
+
+        // A new process runs no higher than the process that started it
+        set_current_pid(parent);
+        let child = ss.create_process(init(Realtime)).unwrap().pid();
+        assert_eq!(ss.get_process(child).unwrap().thread_priority(INITIAL_TID), Normal);
+
+        assert_eq!(ss.set_default_priority(parent, child, Low), Ok(Normal));
+        assert_eq!(ss.set_priority(parent, child, None, Normal), Ok(Low));
+        assert_eq!(ss.set_default_priority(parent, child, High), Err(Error::AccessDenied));
+        assert_eq!(ss.set_default_priority(child, parent, Low), Err(Error::ProcessNotChild));
+
+        // PID 1 is exempt
+        assert_eq!(ss.set_priority(pid1, child, None, Realtime), Ok(Normal));
+        set_current_pid(pid1);
+        let realtime = ss.create_process(init(Realtime)).unwrap().pid();
+        assert_eq!(ss.get_process(realtime).unwrap().thread_priority(INITIAL_TID), Realtime);
+    });
+}
+
+/// Test that a `CreateProcess` call whose priority and quota slots are zeroed,
+/// as sent by callers that don't set them, starts a `Normal` process with no
+/// RAM quota
+#[test]
+fn process_init_defaults() {
+    use xous_kernel::{SysCall, SysCallNumber, ThreadPriority};
+
+    let call = SysCall::from_args(SysCallNumber::CreateProcess as usize, 1, 2, 3, 4, 0, 0, 0)
+        .expect("couldn't decode CreateProcess");
+    match call {
+        SysCall::CreateProcess(init) => {
+            assert_eq!(init.priority, ThreadPriority::Normal);
+            assert_eq!(init.memory_quota, usize::MAX);
+        }
+        other => panic!("decoded {:?}", other),
+    }
+}
+
+/// Test that the process table can be enumerated from userspace
+#[test]
+fn process_info() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let (done_send, done_recv) = unbounded();
+    let (server_pid_send, server_pid_recv) = unbounded();
+
+    let server_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("process_info server", move || {
+            let _server = xous_kernel::create_server().expect("couldn't create server");
+            server_pid_send
+                .send(xous_kernel::current_pid().unwrap())
+                .unwrap();
+            done_recv.recv().unwrap();
+        }),
+    )
+    .expect("couldn't start server process");
+
+    let client_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("process_info client", move || {
+            let server_pid = server_pid_recv.recv().unwrap();
+            let our_pid = xous_kernel::current_pid().unwrap();
+
+            let mut seen = vec![];
+            for idx in 1.. {
+                let pid = xous_kernel::pid_from_usize(idx).unwrap();
+                match xous_kernel::process_info(pid) {
+                    Ok(info) => {
+                        assert_eq!(info.pid, pid);
+                        seen.push(info);
+                    }
+                    Err(xous_kernel::Error::ProcessNotFound) => continue,
+                    Err(xous_kernel::Error::InvalidPID) => break,
+                    Err(e) => panic!("unexpected error {:?}", e),
+                }
+            }
+
+            let server_info = seen
+                .iter()
+                .find(|info| info.pid == server_pid)
+                .expect("server process wasn't listed");
+            assert_eq!(server_info.servers, 1);
+            assert!(server_info.threads >= 1);
+
+            let our_info = seen
+                .iter()
+                .find(|info| info.pid == our_pid)
+                .expect("our process wasn't listed");
+            assert_eq!(our_info.servers, 0);
+            assert!(matches!(
+                our_info.status,
+                xous_kernel::ProcessStatus::Running(_)
+            ));
+
+            // The loader doesn't exist in hosted mode, so processes have no names
+            let mut name = [0u8; 32];
+            assert_eq!(xous_kernel::process_name(server_pid, &mut name), Ok(0));
+            done_send.send(()).unwrap();
+        }),
+    )
+    .expect("couldn't start client process");
+
+    xous_kernel::wait_process_as_thread(client_process).expect("couldn't join client process");
+    xous_kernel::wait_process_as_thread(server_process).expect("couldn't join server process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that the kernel accounts for the time each thread spends running
+#[test]
+fn run_time_accounting() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let busy_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("run_time_accounting process", || {
+            let pid = xous_kernel::current_pid().unwrap();
+            let tid = xous_kernel::current_tid().unwrap();
+            let before = xous_kernel::thread_run_time(pid, tid).expect("couldn't get run time");
+
+            // Stay busy without making any syscalls
+            let start = std::time::Instant::now();
+            while start.elapsed() < std::time::Duration::from_millis(50) {
+                std::hint::spin_loop();
+            }
+
+            let after = xous_kernel::thread_run_time(pid, tid).expect("couldn't get run time");
+            assert!(
+                after - before >= 50_000,
+                "thread only ran for {} usec",
+                after - before
+            );
+            let total = xous_kernel::process_run_time(pid).expect("couldn't get process run time");
+            assert!(total >= after);
+        }),
+    )
+    .expect("couldn't start process");
+
+    xous_kernel::wait_process_as_thread(busy_process).expect("couldn't join process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that memory quotas may be lowered but not raised by a process,
+/// and may not be changed by an unrelated process
+#[test]
+fn memory_quota() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let (pid_send, pid_recv) = unbounded();
+    let (done_send, done_recv) = unbounded();
+
+    let limited_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("memory_quota limited process", move || {
+            assert_eq!(xous_kernel::memory_quota(None), Ok(usize::MAX));
+            xous_kernel::set_memory_quota(None, 64).expect("couldn't lower quota");
+            assert_eq!(xous_kernel::memory_quota(None), Ok(64));
+            assert_eq!(
+                xous_kernel::set_memory_quota(None, 128),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            assert_eq!(xous_kernel::memory_quota(None), Ok(64));
+            pid_send.send(xous_kernel::current_pid().unwrap()).unwrap();
+            done_recv.recv().unwrap();
+        }),
+    )
+    .expect("couldn't start limited process");
+
+    let other_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("memory_quota other process", move || {
+            let limited_pid = pid_recv.recv().unwrap();
+            assert_eq!(
+                xous_kernel::set_memory_quota(Some(limited_pid), 1024),
+                Err(xous_kernel::Error::ProcessNotChild)
+            );
+            assert_eq!(
+                xous_kernel::adjust_process_limit(
+                    Some(limited_pid),
+                    xous_kernel::Limits::HeapMaximum,
+                    0,
+                    0
+                ),
+                Err(xous_kernel::Error::InvalidLimit)
+            );
+            // The kernel does not manage memory in hosted mode
+            assert_eq!(xous_kernel::memory_usage(None), Ok(0));
+            done_send.send(()).unwrap();
+        }),
+    )
+    .expect("couldn't start other process");
+
+    xous_kernel::wait_process_as_thread(other_process).expect("couldn't join other process");
+    xous_kernel::wait_process_as_thread(limited_process).expect("couldn't join limited process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a memory quota can be given to a process when it is spawned
+#[test]
+fn spawn_memory_quota() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let limited_process = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("spawn_memory_quota limited process", move || {
+            assert_eq!(xous_kernel::memory_quota(None), Ok(32));
+            assert_eq!(
+                xous_kernel::set_memory_quota(None, 64),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+        })
+        .memory_quota(32),
+    )
+    .expect("couldn't start limited process");
+
+    xous_kernel::wait_process_as_thread(limited_process).expect("couldn't join limited process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a process can't give a process it starts more RAM than it has
+/// left itself, unless it is PID 1
+#[test]
+fn spawn_memory_quota_limit() {
+    use crate::arch::process::set_current_pid;
+    use crate::mem::MemoryManager;
+    use crate::services::SystemServices;
+    use xous_kernel::{ProcessInit, ProcessKey, ThreadPriority};
+
+    let init = |memory_quota| ProcessInit {
+        key: ProcessKey::new([0; 16]),
+        priority: ThreadPriority::Normal,
+        memory_quota,
+    };
+    SystemServices::with_mut(|ss| {
+        let pid1 = ss.create_process(init(usize::MAX)).unwrap().pid();
+        set_current_pid(pid1);
+        let parent = ss.create_process(init(32)).unwrap().pid();
+        let unlimited = ss.create_process(init(usize::MAX)).unwrap().pid();
+
+        set_current_pid(parent);
+        let child = ss.create_process(init(usize::MAX)).unwrap().pid();
+        let small_child = ss.create_process(init(8)).unwrap().pid();
+        set_current_pid(pid1);
+
+        MemoryManager::with_mut(|mm| {
+            assert_eq!(mm.ram_quota(unlimited), usize::MAX);
+            assert_eq!(mm.ram_quota(child), 32);
+            assert_eq!(mm.ram_quota(small_child), 8);
+        });
+    });
+}
+
+/// Run two clients that race to send messages to one server, once while
+/// recording the order of syscalls and once while replaying it. The replayed
+/// run must deliver the messages to the server in exactly the recorded order.
+#[test]
+fn record_replay() {
+    fn racing_clients(record: String, replay: Option<String>) -> Vec<usize> {
+        let main_thread = start_kernel_with_schedule(SERVER_SPEC, Some(record), replay);
+        const MESSAGES: usize = 20;
+
+        let (server_addr_send, server_addr_recv) = unbounded();
+        let (order_send, order_recv) = unbounded();
+        let server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+            "record_replay server",
+            move || {
+                let sid = xous_kernel::create_server().expect("couldn't create test server");
+                server_addr_send.send(sid).unwrap();
+                server_addr_send.send(sid).unwrap();
+                let mut order = vec![];
+                for _ in 0..MESSAGES * 2 {
+                    let envelope =
+                        xous_kernel::receive_message(sid).expect("couldn't receive message");
+                    order.push(envelope.body.id());
+                }
+                order_send.send(order).unwrap();
+            },
+        ))
+        .expect("couldn't spawn server process");
+
+        let mut clients = vec![];
+        for id in 1..=2 {
+            let server_addr_recv = server_addr_recv.clone();
+            clients.push(
+                xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+                    "record_replay client",
+                    move || {
+                        let sid = server_addr_recv.recv().unwrap();
+                        let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+                        for _ in 0..MESSAGES {
+                            xous_kernel::send_message(
+                                conn,
+                                xous_kernel::Message::new_scalar(id, 0, 0, 0, 0),
+                            )
+                            .expect("couldn't send message");
+                        }
+                    },
+                ))
+                .expect("couldn't spawn client process"),
+            );
+        }
+
+        for client in clients {
+            xous_kernel::wait_process_as_thread(client).expect("couldn't join client process");
+        }
+        xous_kernel::wait_process_as_thread(server).expect("couldn't join server process");
+        shutdown_kernel();
+        main_thread.join().expect("couldn't join kernel process");
+        order_recv.recv().unwrap()
+    }
+
+    let dir = std::env::temp_dir();
+    let first = dir.join(format!("xous-record-{}-1", std::process::id()));
+    let second = dir.join(format!("xous-record-{}-2", std::process::id()));
+    let first = first.to_str().unwrap().to_owned();
+    let second = second.to_str().unwrap().to_owned();
+
+    let recorded = racing_clients(first.clone(), None);
+    let replayed = racing_clients(second.clone(), Some(first.clone()));
+    std::fs::remove_file(&first).ok();
+    std::fs::remove_file(&second).ok();
+
+    assert_eq!(recorded.len(), 40);
+    assert_eq!(recorded, replayed);
+}
+
+/// Test that event flags coalesce, and that they wake a waiting server
+#[test]
+fn event_flags() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let (server_addr_send, server_addr_recv) = unbounded();
+    let (step_send, step_recv) = unbounded();
+
+    let server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "event_flags server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            assert_eq!(xous_kernel::try_wait_event_flags(sid, !0), Ok(0));
+            assert_eq!(
+                xous_kernel::wait_event_flags(sid, 0),
+                Err(xous_kernel::Error::InvalidSyscall)
+            );
+            server_addr_send.send(sid).unwrap();
+
+            // Wait for the client to set flags several times
+            step_recv.recv().unwrap();
+            assert_eq!(xous_kernel::wait_event_flags(sid, 0b0001), Ok(0b0001));
+            assert_eq!(xous_kernel::try_wait_event_flags(sid, !0), Ok(0b0110));
+            assert_eq!(xous_kernel::try_wait_event_flags(sid, !0), Ok(0));
+
+            // Block until the client sets a flag we're interested in
+            server_addr_send.send(sid).unwrap();
+            assert_eq!(xous_kernel::wait_event_flags(sid, 0b1000), Ok(0b1000));
+            assert_eq!(xous_kernel::try_wait_event_flags(sid, !0), Ok(0b0001));
+
+            // Destroying the server wakes a thread that is still waiting
+            let waiter = xous_kernel::create_thread(move || {
+                assert_eq!(
+                    xous_kernel::wait_event_flags(sid, 0b0001),
+                    Err(xous_kernel::Error::ServerNotFound)
+                );
+            })
+            .unwrap();
+            // Give the thread a chance to block
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            xous_kernel::destroy_server(sid).expect("couldn't destroy server");
+            xous_kernel::wait_thread(waiter).expect("waiter was not woken");
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "event_flags client",
+        move || {
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            xous_kernel::set_event_flags(conn, 0b0101).expect("couldn't set flags");
+            xous_kernel::set_event_flags(conn, 0b0101).expect("couldn't set flags");
+            xous_kernel::set_event_flags(conn, 0b0010).expect("couldn't set flags");
+            step_send.send(()).unwrap();
+
+            server_addr_recv.recv().unwrap();
+            // Give the server a chance to block
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            // This flag is not being waited on, so the server stays asleep
+            xous_kernel::set_event_flags(conn, 0b0001).expect("couldn't set flags");
+            xous_kernel::set_event_flags(conn, 0b1000).expect("couldn't set flags");
+        },
+    ))
+    .expect("couldn't spawn client process");
+
+    xous_kernel::wait_process_as_thread(client).expect("couldn't join client process");
+    xous_kernel::wait_process_as_thread(server).expect("couldn't join server process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn ipc_timeouts() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let (server_addr_send, server_addr_recv) = unbounded();
+    let (step_send, step_recv) = unbounded();
+
+    // Stand in for the ticktimer, which is the only process allowed to expire timeouts
+    let ticktimer = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "ipc_timeouts ticktimer",
+        move || {
+            let sid = xous_kernel::create_server_with_address(b"ticktimer-server")
+                .expect("couldn't create ticktimer server");
+            let mut timers = vec![];
+            loop {
+                let envelope = xous_kernel::receive_message(sid).expect("couldn't receive");
+                let scalar = match envelope.body {
+                    xous_kernel::Message::Scalar(scalar) if scalar.id == 10 => scalar,
+                    // expiring a disarmed token does nothing, so cancels can be ignored
+                    xous_kernel::Message::Scalar(scalar) if scalar.id == 11 => continue,
+                    _ => break,
+                };
+                let pid = envelope.sender.pid().unwrap();
+                timers.push(xous_kernel::create_thread(move || {
+                    std::thread::sleep(std::time::Duration::from_millis(scalar.arg2 as u64));
+                    while xous_kernel::expire_ipc_timeout(pid, scalar.arg1)
+                        == Err(xous_kernel::Error::ThreadNotAvailable)
+                    {
+                        std::thread::sleep(std::time::Duration::from_millis(1));
+                    }
+                }));
+            }
+            for timer in timers {
+                xous_kernel::wait_thread(timer.expect("couldn't start timer")).unwrap();
+            }
+        },
+    ))
+    .expect("couldn't spawn ticktimer process");
+
+    let server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "ipc_timeouts server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            server_addr_send.send(sid).unwrap();
+
+            // Don't receive anything until the client's first messages have timed out
+            step_recv.recv().unwrap();
+
+            // The withdrawn messages are skipped
+            let envelope = xous_kernel::receive_message(sid).unwrap();
+            assert_eq!(envelope.body.id(), 3);
+            xous_kernel::return_scalar(envelope.sender, 3).unwrap();
+
+            // Take too long to answer this one. The response is dropped.
+            let envelope = xous_kernel::receive_message(sid).unwrap();
+            assert_eq!(envelope.body.id(), 4);
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            xous_kernel::return_scalar(envelope.sender, 4).unwrap();
+
+            let envelope = xous_kernel::receive_message(sid).unwrap();
+            assert_eq!(envelope.body.id(), 5);
+            xous_kernel::return_scalar(envelope.sender, 5).unwrap();
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "ipc_timeouts client",
+        move || {
+            let own_sid = xous_kernel::create_server().expect("couldn't create client server");
+            assert_eq!(
+                xous_kernel::receive_message_timeout(own_sid, 50),
+                Err(xous_kernel::Error::Timeout)
+            );
+
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            assert_eq!(
+                xous_kernel::send_message_timeout(
+                    conn,
+                    xous_kernel::Message::new_blocking_scalar(1, 0, 0, 0, 0),
+                    50
+                ),
+                Err(xous_kernel::Error::Timeout)
+            );
+
+            // Lent memory comes back untouched
+            let test_bytes = b"Hello, world!";
+            let carton = xous_kernel::carton::Carton::from_bytes(test_bytes);
+            let range: &xous_kernel::MemoryRange = carton.as_ref();
+            assert_eq!(
+                xous_kernel::send_message_timeout(
+                    conn,
+                    xous_kernel::Message::MutableBorrow(xous_kernel::MemoryMessage {
+                        id: 2,
+                        buf: *range,
+                        offset: None,
+                        valid: None,
+                    }),
+                    50
+                ),
+                Err(xous_kernel::Error::Timeout)
+            );
+            let returned_bytes: &[u8] = carton.as_ref();
+            assert_eq!(returned_bytes, test_bytes);
+
+            step_send.send(()).unwrap();
+            assert_eq!(
+                xous_kernel::send_message_timeout(
+                    conn,
+                    xous_kernel::Message::new_blocking_scalar(3, 0, 0, 0, 0),
+                    1000
+                ),
+                Ok(xous_kernel::Result::Scalar1(3))
+            );
+            assert_eq!(
+                xous_kernel::send_message_timeout(
+                    conn,
+                    xous_kernel::Message::new_blocking_scalar(4, 0, 0, 0, 0),
+                    50
+                ),
+                Err(xous_kernel::Error::Timeout)
+            );
+            assert_eq!(
+                xous_kernel::send_message(
+                    conn,
+                    xous_kernel::Message::new_blocking_scalar(5, 0, 0, 0, 0)
+                ),
+                Ok(xous_kernel::Result::Scalar1(5))
+            );
+
+            // Only the ticktimer may expire a timeout
+            assert_eq!(
+                xous_kernel::expire_ipc_timeout(xous_kernel::current_pid().unwrap(), 1),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+
+            // Shut down the ticktimer
+            let ticktimer =
+                xous_kernel::connect(xous_kernel::SID::from_bytes(b"ticktimer-server").unwrap())
+                    .unwrap();
+            xous_kernel::send_message(ticktimer, xous_kernel::Message::new_scalar(0, 0, 0, 0, 0))
+                .unwrap();
+        },
+    ))
+    .expect("couldn't spawn client process");
+
+    xous_kernel::wait_process_as_thread(client).expect("couldn't join client process");
+    xous_kernel::wait_process_as_thread(server).expect("couldn't join server process");
+    xous_kernel::wait_process_as_thread(ticktimer).expect("couldn't join ticktimer process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that only one process may act as the supervisor at a time, and only
+/// through a server that it owns
+#[test]
+fn supervisor() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let (server_addr_send, server_addr_recv) = unbounded();
+    let (done_send, done_recv) = unbounded();
+
+    let supervisor = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "supervisor supervisor",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            assert_eq!(xous_kernel::set_supervisor(sid, 1), Ok(()));
+            // Setting the same server again updates the message ID
+            assert_eq!(xous_kernel::set_supervisor(sid, 2), Ok(()));
+            server_addr_send.send(sid).unwrap();
+
+            // Keep the server alive until the other process is done
+            done_recv.recv().unwrap();
+        },
+    ))
+    .expect("couldn't spawn supervisor process");
+
+    let other = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "supervisor other",
+        move || {
+            let supervisor_sid = server_addr_recv.recv().unwrap();
+            assert_eq!(
+                xous_kernel::set_supervisor(supervisor_sid, 1),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            assert_eq!(
+                xous_kernel::set_supervisor(sid, 1),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            done_send.send(()).unwrap();
+        },
+    ))
+    .expect("couldn't spawn other process");
+
+    xous_kernel::wait_process_as_thread(supervisor).expect("couldn't join supervisor process");
+    xous_kernel::wait_process_as_thread(other).expect("couldn't join other process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
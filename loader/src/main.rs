@@ -1199,6 +1199,16 @@ fn memtest() {
     }
 }
 
+/// Let Supervisor mode read the `cycle` counter, which the kernel uses to account CPU time.
+/// `mcounteren` resets to 0, which makes every such read an illegal instruction.
+fn enable_cycle_counter() {
+    #[cfg(target_arch = "riscv32")]
+    unsafe {
+        // mcounteren.CY
+        core::arch::asm!("csrs mcounteren, {0}", in(reg) 1usize);
+    }
+}
+
 fn boot_sequence(args: KernelArguments, _signature: u32) -> ! {
     // Store the initial boot config on the stack.  We don't know
     // where in heap this memory will go.
@@ -1208,6 +1218,8 @@ fn boot_sequence(args: KernelArguments, _signature: u32) -> ! {
         // use this mainly to trace down e.g. hardware issues with timing to the RAM.
         memtest();
     }
+    // on both a clean boot and a resume, as the counter enables don't survive a suspend
+    enable_cycle_counter();
     let mut cfg = BootConfig {
         base_addr: args.base as *const usize,
         args,
@@ -3,6 +3,7 @@
 pub(crate) const SERVER_NAME_DNS: &str = "_DNS Resolver Middleware_";
 use net::NetIpAddr;
 use rkyv::{Archive, Deserialize, Serialize};
+use std::convert::TryInto;
 
 #[allow(dead_code)]
 pub(crate) const DNS_NAME_LENGTH_LIMIT: usize = 256;
@@ -49,6 +50,22 @@ pub(crate) enum Opcode {
     ///     * 4: Ipv4 Address -- 4 octets follow, for a total of 5 bytes
     ///     * 6: Ipv6 Address -- 16 octets follow, for a total of 17 bytes
     RawLookup = 6,
+
+    /// Set the name this device answers to on the local network, without the `.local` suffix
+    MdnsSetHostname = 7,
+    /// Get the name this device answers to on the local network, without the `.local` suffix
+    MdnsGetHostname = 8,
+    /// Advertise a service over DNS-SD
+    MdnsRegister = 9,
+    /// Stop advertising a service
+    MdnsUnregister = 10,
+    /// Find the instances of a service type on the local network
+    MdnsBrowse = 11,
+    /// Look up the host, port and TXT strings of one service instance
+    MdnsResolve = 12,
+
+    /// Look up the TXT or SRV records of a name
+    LookupRecords = 13,
 }
 
 #[derive(
@@ -60,6 +77,8 @@ pub(crate) enum Opcode {
     Deserialize,
     Copy,
     Clone,
+    PartialEq,
+    Eq,
 )]
 #[repr(u16)]
 pub enum DnsResponseCode {
@@ -80,3 +99,222 @@ pub struct DnsResponse {
     pub addr: Option<NetIpAddr>,
     pub code: DnsResponseCode,
 }
+
+/// Space for the packed records returned by `LookupRecords`
+#[allow(dead_code)]
+pub(crate) const DNS_RECORDS_LEN: usize = 3584;
+
+/// The types of record that can be looked up with `Dns::lookup_records()`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub enum DnsRecordType {
+    Txt,
+    Srv,
+}
+
+/// Used by `LookupRecords`. On return, `data` holds `count` records, as packed by
+/// `DnsRecord::pack()`.
+#[derive(Debug, Copy, Clone, Archive, Serialize, Deserialize)]
+pub(crate) struct DnsRecordQuery {
+    pub name: xous_ipc::String<DNS_NAME_LENGTH_LIMIT>,
+    pub rtype: DnsRecordType,
+    pub data: [u8; DNS_RECORDS_LEN],
+    pub len: u32,
+    pub count: u32,
+    pub code: DnsResponseCode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsRecord {
+    /// The strings of one TXT record
+    Txt(Vec<std::string::String>),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: std::string::String,
+    },
+}
+#[allow(dead_code)]
+impl DnsRecord {
+    /// Appends this record to `out`, as a type byte (16 or 33) and a u16 LE length, followed by
+    /// the strings of a TXT record as they appear in the RDATA, or the priority, weight and port
+    /// of an SRV record as u16 LE and the target preceded by its length in one byte.
+    pub(crate) fn pack(&self, out: &mut Vec<u8>) {
+        let mut body = Vec::new();
+        let tag = match self {
+            DnsRecord::Txt(txt) => {
+                body = pack_txt(txt);
+                16
+            }
+            DnsRecord::Srv { priority, weight, port, target } => {
+                body.extend_from_slice(&priority.to_le_bytes());
+                body.extend_from_slice(&weight.to_le_bytes());
+                body.extend_from_slice(&port.to_le_bytes());
+                body.push(target.len().min(255) as u8);
+                body.extend_from_slice(&target.as_bytes()[..target.len().min(255)]);
+                33
+            }
+        };
+        out.push(tag);
+        out.extend_from_slice(&(body.len() as u16).to_le_bytes());
+        out.extend_from_slice(&body);
+    }
+    /// Reads a record packed by `pack()`, advancing `index` past it.
+    pub(crate) fn unpack(data: &[u8], index: &mut usize) -> Option<DnsRecord> {
+        let tag = *data.get(*index)?;
+        let len = u16::from_le_bytes(data.get(*index + 1..*index + 3)?.try_into().ok()?) as usize;
+        let body = data.get(*index + 3..*index + 3 + len)?;
+        *index += 3 + len;
+        match tag {
+            16 => Some(DnsRecord::Txt(unpack_txt(body))),
+            33 => {
+                let target_len = *body.get(6)? as usize;
+                Some(DnsRecord::Srv {
+                    priority: u16::from_le_bytes(body.get(0..2)?.try_into().ok()?),
+                    weight: u16::from_le_bytes(body.get(2..4)?.try_into().ok()?),
+                    port: u16::from_le_bytes(body.get(4..6)?.try_into().ok()?),
+                    target: std::string::String::from_utf8_lossy(body.get(7..7 + target_len)?).to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Longest label in a DNS name
+#[allow(dead_code)]
+pub(crate) const MDNS_LABEL_LEN: usize = 63;
+/// Space for the TXT strings of a service, each preceded by its length in one byte
+#[allow(dead_code)]
+pub(crate) const MDNS_TXT_LEN: usize = 256;
+/// Space for the packed results of a browse
+#[allow(dead_code)]
+pub(crate) const MDNS_LIST_LEN: usize = 3584;
+
+/// Used by `MdnsRegister` and `MdnsUnregister`. `service` is the service type and protocol,
+/// for example `_http._tcp`. `MdnsSetHostname` and `MdnsGetHostname` use it too, with the
+/// host name in `instance`.
+#[derive(Debug, Copy, Clone, Archive, Serialize, Deserialize)]
+pub(crate) struct MdnsServiceIpc {
+    pub instance: xous_ipc::String<MDNS_LABEL_LEN>,
+    pub service: xous_ipc::String<MDNS_LABEL_LEN>,
+    pub port: u16,
+    pub txt: [u8; MDNS_TXT_LEN],
+    pub txt_len: u16,
+    pub code: DnsResponseCode,
+}
+
+/// Used by `MdnsBrowse`, which leaves `instance` empty, and `MdnsResolve`. On return, `data`
+/// holds `count` service instances, as packed by `ServiceInstance::pack()`.
+#[derive(Debug, Copy, Clone, Archive, Serialize, Deserialize)]
+pub(crate) struct MdnsQueryIpc {
+    pub instance: xous_ipc::String<MDNS_LABEL_LEN>,
+    pub service: xous_ipc::String<MDNS_LABEL_LEN>,
+    pub timeout_ms: u32,
+    pub data: [u8; MDNS_LIST_LEN],
+    pub len: u32,
+    pub count: u32,
+    pub code: DnsResponseCode,
+}
+
+/// A service found on the local network
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceInstance {
+    /// The name of this instance, for example `Living Room Printer`
+    pub instance: std::string::String,
+    /// The service type and protocol, for example `_ipp._tcp`
+    pub service: std::string::String,
+    /// The host the service runs on, including the `.local` suffix
+    pub host: std::string::String,
+    pub port: u16,
+    /// The address of `host`, if the responder gave one
+    pub addr: Option<std::net::IpAddr>,
+    pub txt: Vec<std::string::String>,
+}
+#[allow(dead_code)]
+impl ServiceInstance {
+    /// Appends this instance to `out`. Each name is preceded by its length in one byte, the
+    /// address by a tag of 0, 4 or 6, and the port is u16 LE, followed by the TXT strings as
+    /// they appear in a TXT record, preceded by their total length as a u16 LE.
+    pub(crate) fn pack(&self, out: &mut Vec<u8>) {
+        for name in [&self.instance, &self.service, &self.host].iter() {
+            out.push(name.len().min(255) as u8);
+            out.extend_from_slice(&name.as_bytes()[..name.len().min(255)]);
+        }
+        match self.addr {
+            None => out.push(0),
+            Some(std::net::IpAddr::V4(addr)) => {
+                out.push(4);
+                out.extend_from_slice(&addr.octets());
+            }
+            Some(std::net::IpAddr::V6(addr)) => {
+                out.push(6);
+                out.extend_from_slice(&addr.octets());
+            }
+        }
+        out.extend_from_slice(&self.port.to_le_bytes());
+        let txt = pack_txt(&self.txt);
+        out.extend_from_slice(&(txt.len() as u16).to_le_bytes());
+        out.extend_from_slice(&txt);
+    }
+    /// Reads an instance packed by `pack()`, advancing `index` past it.
+    pub(crate) fn unpack(data: &[u8], index: &mut usize) -> Option<ServiceInstance> {
+        let mut name = || -> Option<std::string::String> {
+            let len = *data.get(*index)? as usize;
+            let bytes = data.get(*index + 1..*index + 1 + len)?;
+            *index += 1 + len;
+            Some(std::string::String::from_utf8_lossy(bytes).to_string())
+        };
+        let instance = name()?;
+        let service = name()?;
+        let host = name()?;
+        let addr = match *data.get(*index)? {
+            4 => {
+                let octets: [u8; 4] = data.get(*index + 1..*index + 5)?.try_into().ok()?;
+                *index += 5;
+                Some(std::net::IpAddr::from(octets))
+            }
+            6 => {
+                let octets: [u8; 16] = data.get(*index + 1..*index + 17)?.try_into().ok()?;
+                *index += 17;
+                Some(std::net::IpAddr::from(octets))
+            }
+            _ => {
+                *index += 1;
+                None
+            }
+        };
+        let port = u16::from_le_bytes(data.get(*index..*index + 2)?.try_into().ok()?);
+        let txt_len = u16::from_le_bytes(data.get(*index + 2..*index + 4)?.try_into().ok()?) as usize;
+        let txt = unpack_txt(data.get(*index + 4..*index + 4 + txt_len)?);
+        *index += 4 + txt_len;
+        Some(ServiceInstance { instance, service, host, port, addr, txt })
+    }
+}
+
+/// Encodes strings as the RDATA of a TXT record: each one preceded by its length in one byte.
+#[allow(dead_code)]
+pub(crate) fn pack_txt(txt: &[std::string::String]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for s in txt.iter() {
+        let len = s.len().min(255);
+        out.push(len as u8);
+        out.extend_from_slice(&s.as_bytes()[..len]);
+    }
+    out
+}
+/// Decodes the RDATA of a TXT record. Empty strings are dropped.
+#[allow(dead_code)]
+pub(crate) fn unpack_txt(data: &[u8]) -> Vec<std::string::String> {
+    let mut txt = Vec::new();
+    let mut index = 0;
+    while let Some(&len) = data.get(index) {
+        if let Some(bytes) = data.get(index + 1..index + 1 + len as usize) {
+            if len > 0 {
+                txt.push(std::string::String::from_utf8_lossy(bytes).to_string());
+            }
+        }
+        index += 1 + len as usize;
+    }
+    txt
+}
@@ -0,0 +1,127 @@
+use xous::CID;
+use xous_ipc::{Buffer, String};
+use num_traits::ToPrimitive;
+
+use crate::api::*;
+
+/// Multicast DNS and DNS-SD: the name this device answers to on the local network, the services
+/// it advertises, and lookups of the services offered by others.
+#[derive(Debug)]
+pub struct Mdns {
+    conn: CID,
+}
+impl Mdns {
+    pub fn new(xns: &xous_names::XousNames) -> Result<Self, xous::Error> {
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(crate::api::SERVER_NAME_DNS).expect("Can't connect to Dns server");
+        Ok(Mdns {
+            conn
+        })
+    }
+
+    /// Sets the name this device answers to, without the `.local` suffix. If another device on
+    /// the network already answers to it, a `-2` suffix (or `-3`, and so on) is added.
+    pub fn set_hostname(&self, hostname: &str) -> Result<(), DnsResponseCode> {
+        let request = Self::service_ipc(hostname, "", 0, &[])?;
+        self.service_op(Opcode::MdnsSetHostname, request).map(|_| ())
+    }
+    /// The name this device answers to, without the `.local` suffix
+    pub fn hostname(&self) -> Result<std::string::String, DnsResponseCode> {
+        let request = Self::service_ipc("", "", 0, &[])?;
+        let response = self.service_op(Opcode::MdnsGetHostname, request)?;
+        Ok(response.instance.as_str().or(Err(DnsResponseCode::UnknownError))?.to_string())
+    }
+    /// Advertises `instance` of `service`, for example `_http._tcp`, on `port`. Registering an
+    /// instance again replaces it. As with the host name, an instance name that's already in use
+    /// on the network gets a numbered suffix.
+    pub fn register(&self, instance: &str, service: &str, port: u16, txt: &[&str]) -> Result<(), DnsResponseCode> {
+        let request = Self::service_ipc(instance, service, port, txt)?;
+        self.service_op(Opcode::MdnsRegister, request).map(|_| ())
+    }
+    /// Stops advertising `instance` of `service`, and tells the network it's gone.
+    pub fn unregister(&self, instance: &str, service: &str) -> Result<(), DnsResponseCode> {
+        let request = Self::service_ipc(instance, service, 0, &[])?;
+        self.service_op(Opcode::MdnsUnregister, request).map(|_| ())
+    }
+
+    /// Lists the instances of `service` that answer within `timeout_ms`. Blocks for the whole of
+    /// `timeout_ms`, as there's no telling when the last one has answered.
+    pub fn browse(&self, service: &str, timeout_ms: u32) -> Result<Vec<ServiceInstance>, DnsResponseCode> {
+        self.query(Opcode::MdnsBrowse, "", service, timeout_ms)
+    }
+    /// Looks up the host, port and TXT strings of `instance` of `service`.
+    pub fn resolve(&self, instance: &str, service: &str, timeout_ms: u32) -> Result<ServiceInstance, DnsResponseCode> {
+        self.query(Opcode::MdnsResolve, instance, service, timeout_ms)?
+            .pop()
+            .ok_or(DnsResponseCode::NameError)
+    }
+
+    fn service_ipc(instance: &str, service: &str, port: u16, txt: &[&str]) -> Result<MdnsServiceIpc, DnsResponseCode> {
+        if instance.len() > MDNS_LABEL_LEN || service.len() > MDNS_LABEL_LEN {
+            return Err(DnsResponseCode::FormatError);
+        }
+        let packed = pack_txt(&txt.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        if packed.len() > MDNS_TXT_LEN {
+            return Err(DnsResponseCode::FormatError);
+        }
+        let mut request = MdnsServiceIpc {
+            instance: String::<MDNS_LABEL_LEN>::from_str(instance),
+            service: String::<MDNS_LABEL_LEN>::from_str(service),
+            port,
+            txt: [0u8; MDNS_TXT_LEN],
+            txt_len: packed.len() as u16,
+            code: DnsResponseCode::UnknownError,
+        };
+        request.txt[..packed.len()].copy_from_slice(&packed);
+        Ok(request)
+    }
+    fn service_op(&self, op: Opcode, request: MdnsServiceIpc) -> Result<MdnsServiceIpc, DnsResponseCode> {
+        let mut buf = Buffer::into_buf(request).or(Err(DnsResponseCode::UnknownError))?;
+        buf.lend_mut(self.conn, op.to_u32().unwrap()).or(Err(DnsResponseCode::UnknownError))?;
+        let response = buf.to_original::<MdnsServiceIpc, _>().or(Err(DnsResponseCode::UnknownError))?;
+        match response.code {
+            DnsResponseCode::NoError => Ok(response),
+            code => Err(code),
+        }
+    }
+    fn query(&self, op: Opcode, instance: &str, service: &str, timeout_ms: u32) -> Result<Vec<ServiceInstance>, DnsResponseCode> {
+        if instance.len() > MDNS_LABEL_LEN || service.len() > MDNS_LABEL_LEN {
+            return Err(DnsResponseCode::FormatError);
+        }
+        let request = MdnsQueryIpc {
+            instance: String::<MDNS_LABEL_LEN>::from_str(instance),
+            service: String::<MDNS_LABEL_LEN>::from_str(service),
+            timeout_ms,
+            data: [0u8; MDNS_LIST_LEN],
+            len: 0,
+            count: 0,
+            code: DnsResponseCode::UnknownError,
+        };
+        let mut buf = Buffer::into_buf(request).or(Err(DnsResponseCode::UnknownError))?;
+        buf.lend_mut(self.conn, op.to_u32().unwrap()).or(Err(DnsResponseCode::UnknownError))?;
+        let response = buf.to_original::<MdnsQueryIpc, _>().or(Err(DnsResponseCode::UnknownError))?;
+        if let DnsResponseCode::NoError = response.code {
+            let data = &response.data[..(response.len as usize).min(MDNS_LIST_LEN)];
+            let mut index = 0;
+            let mut found = Vec::new();
+            for _ in 0..response.count {
+                found.push(ServiceInstance::unpack(data, &mut index).ok_or(DnsResponseCode::FormatError)?);
+            }
+            Ok(found)
+        } else {
+            Err(response.code)
+        }
+    }
+}
+
+use core::sync::atomic::{AtomicU32, Ordering};
+static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl Drop for Mdns {
+    fn drop(&mut self) {
+        // the connection to the server side must be reference counted, so that multiple instances of this object within
+        // a single process do not end up de-allocating the CID on other threads before they go out of scope.
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use net::NetIpAddr;
 use std::net::ToSocketAddrs;
-use crate::DnsResponseCode;
+use crate::{DnsRecord, DnsRecordType, DnsResponseCode};
 
 #[derive(Debug)]
 pub struct Dns {
@@ -34,6 +34,10 @@ impl Dns {
             }
         }
     }
+    pub fn lookup_records(&self, _name: &str, _rtype: DnsRecordType) -> Result<Vec<DnsRecord>, DnsResponseCode> {
+        log::warn!("DNS record lookups not implemented in hosted mode!");
+        Err(DnsResponseCode::NotImplemented)
+    }
     pub fn flush_cache(&self) -> Result<(), xous::Error> {
         log::warn!("DNS cache flush not implemented in hosted mode!");
         Ok(())
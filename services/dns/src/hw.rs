@@ -39,6 +39,34 @@ impl Dns {
             }
         }
     }
+    /// Looks up the TXT or SRV records of `name`, following CNAMEs.
+    pub fn lookup_records(&self, name: &str, rtype: DnsRecordType) -> Result<Vec<DnsRecord>, DnsResponseCode> {
+        if name.len() > DNS_NAME_LENGTH_LIMIT {
+            return Err(DnsResponseCode::FormatError);
+        }
+        let request = DnsRecordQuery {
+            name: String::<DNS_NAME_LENGTH_LIMIT>::from_str(name),
+            rtype,
+            data: [0u8; DNS_RECORDS_LEN],
+            len: 0,
+            count: 0,
+            code: DnsResponseCode::UnknownError,
+        };
+        let mut buf = Buffer::into_buf(request).or(Err(DnsResponseCode::UnknownError))?;
+        buf.lend_mut(self.conn, Opcode::LookupRecords.to_u32().unwrap())
+            .or(Err(DnsResponseCode::UnknownError))?;
+        let response = buf.to_original::<DnsRecordQuery, _>().or(Err(DnsResponseCode::UnknownError))?;
+        if response.code != DnsResponseCode::NoError {
+            return Err(response.code);
+        }
+        let data = &response.data[..(response.len as usize).min(DNS_RECORDS_LEN)];
+        let mut index = 0;
+        let mut records = Vec::new();
+        for _ in 0..response.count {
+            records.push(DnsRecord::unpack(data, &mut index).ok_or(DnsResponseCode::FormatError)?);
+        }
+        Ok(records)
+    }
     pub fn flush_cache(&self) -> Result<(), xous::Error> {
         xous::send_message(
             self.conn,
@@ -11,3 +11,6 @@ pub use hw::*;
 mod hosted;
 #[cfg(any(feature="hosted"))]
 pub use hosted::*;
+
+mod discovery;
+pub use discovery::*;
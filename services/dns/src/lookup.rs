@@ -0,0 +1,158 @@
+//! # Lookups
+//!
+//! Sends queries to a server, and follows the responses to an answer. A query goes out over UDP,
+//! and is sent again over TCP if the response comes back truncated (RFC 7766). CNAMEs are
+//! followed through the answer section, and with further queries if the server left the end of
+//! the chain out. A name that doesn't exist, or has no records of the type asked for, comes back
+//! with how long that may be cached, if the server said (RFC 2308).
+
+use crate::api::*;
+use crate::message::*;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// CNAMEs followed before giving up on a name
+pub(crate) const MAX_CNAME_HOPS: usize = 8;
+/// Negative answers are never cached for longer than this, whatever the server says
+pub(crate) const MAX_NEGATIVE_TTL: u32 = 3600;
+
+#[derive(Debug)]
+pub(crate) enum Outcome {
+    /// The records of the type asked for, each with how long it may be cached. This is the
+    /// shortest TTL along the CNAME chain that led to it.
+    Found(Vec<(RData, u32)>),
+    /// `NameError` if the name doesn't exist, or `NoError` if it has no records of the type
+    /// asked for, with how long that may be cached. If there's no TTL, it must not be cached.
+    Negative(DnsResponseCode, Option<u32>),
+}
+
+/// Sends `query` over UDP and waits for the response to it. Datagrams from anyone but `server`,
+/// or that don't answer `id`, are dropped.
+pub(crate) fn exchange(socket: &UdpSocket, server: SocketAddr, query: &[u8], id: u16, timeout: Duration)
+-> Result<Message, DnsResponseCode> {
+    socket.send_to(query, server).or(Err(DnsResponseCode::NetworkError))?;
+    let start = Instant::now();
+    let mut buf = [0u8; DNS_PKT_MAX_LEN];
+    loop {
+        let remaining = match timeout.checked_sub(start.elapsed()) {
+            Some(remaining) if remaining.as_millis() > 0 => remaining,
+            _ => return Err(DnsResponseCode::NetworkError),
+        };
+        socket.set_read_timeout(Some(remaining)).ok();
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                if from != server {
+                    log::warn!("dropping DNS response from {:?}, which wasn't asked", from);
+                    continue;
+                }
+                let (response_id, flags) = match Message::header(&buf[..len]) {
+                    Some(header) => header,
+                    None => continue,
+                };
+                if response_id != id {
+                    // a late response to an earlier query, or a spoofing attempt
+                    log::debug!("dropping DNS response with id {:x}, expected {:x}", response_id, id);
+                    continue;
+                }
+                if Message::is_truncated(flags) {
+                    log::debug!("DNS response was truncated, retrying over TCP");
+                    return exchange_tcp(server, query, id, remaining);
+                }
+                let message = Message::parse(&buf[..len])?;
+                return if message.is_response() { Ok(message) } else { Err(DnsResponseCode::FormatError) };
+            }
+            Err(e) => match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => return Err(DnsResponseCode::NetworkError),
+                _ => return Err(DnsResponseCode::UnknownError),
+            },
+        }
+    }
+}
+
+/// Sends `query` over TCP, where each message is preceded by its length as a u16 BE.
+fn exchange_tcp(server: SocketAddr, query: &[u8], id: u16, timeout: Duration) -> Result<Message, DnsResponseCode> {
+    let mut stream = TcpStream::connect_timeout(&server, timeout).or(Err(DnsResponseCode::NetworkError))?;
+    stream.set_read_timeout(Some(timeout)).ok();
+    stream.set_write_timeout(Some(timeout)).ok();
+    let mut framed = Vec::with_capacity(query.len() + 2);
+    framed.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    framed.extend_from_slice(query);
+    stream.write_all(&framed).or(Err(DnsResponseCode::NetworkError))?;
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).or(Err(DnsResponseCode::NetworkError))?;
+    let mut response = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response).or(Err(DnsResponseCode::NetworkError))?;
+    let message = Message::parse(&response)?;
+    if message.id != id || !message.is_response() {
+        return Err(DnsResponseCode::FormatError);
+    }
+    Ok(message)
+}
+
+/// Looks up the records of `qtype` for `name` on `server`, following CNAMEs. `next_id` gives the
+/// ID of each query sent, which should be random.
+pub(crate) fn lookup(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    name: &str,
+    qtype: u16,
+    next_id: &mut dyn FnMut() -> u16,
+    timeout: Duration,
+) -> Result<Outcome, DnsResponseCode> {
+    let mut current = name.trim_end_matches('.').to_string();
+    let mut chain_ttl = u32::MAX;
+    let mut hops = 0;
+    loop {
+        let asked = current.clone();
+        let id = next_id();
+        let message = exchange(socket, server, &Message::query(&asked, qtype, id), id, timeout)?;
+        if !message.questions.iter().any(|(q, t)| same_name(q, &asked) && *t == qtype) {
+            // the ID matched, but the question didn't; don't trust the answers
+            log::warn!("DNS response for {} didn't echo the question", asked);
+            return Err(DnsResponseCode::FormatError);
+        }
+        let rcode = message.rcode();
+        match rcode {
+            DnsResponseCode::NoError | DnsResponseCode::NameError => (),
+            _ => return Err(rcode),
+        }
+        // follow the chain as far as this response goes
+        loop {
+            let found: Vec<(RData, u32)> = message.answers.iter()
+                .filter(|r| r.rtype == qtype && same_name(&r.name, &current))
+                .map(|r| (r.data.clone(), r.ttl.min(chain_ttl)))
+                .collect();
+            if !found.is_empty() {
+                return Ok(Outcome::Found(found));
+            }
+            let cname = message.answers.iter().find_map(|r| match &r.data {
+                RData::Cname(target) if same_name(&r.name, &current) => Some((target.clone(), r.ttl)),
+                _ => None,
+            });
+            match cname {
+                Some((target, ttl)) => {
+                    hops += 1;
+                    if hops > MAX_CNAME_HOPS {
+                        log::warn!("gave up on {} after {} CNAMEs", name, MAX_CNAME_HOPS);
+                        return Err(DnsResponseCode::ServerFailure);
+                    }
+                    log::debug!("{} is an alias for {}", current, target);
+                    chain_ttl = chain_ttl.min(ttl);
+                    current = target;
+                }
+                None => break,
+            }
+        }
+        let negative_ttl = message.negative_ttl().map(|ttl| ttl.min(chain_ttl).min(MAX_NEGATIVE_TTL));
+        // a server that doesn't recurse may hand back a CNAME and nothing else; the target has to
+        // be asked about separately
+        let follow = !same_name(&asked, &current)
+            && matches!(rcode, DnsResponseCode::NoError)
+            && negative_ttl.is_none();
+        if !follow {
+            return Ok(Outcome::Negative(rcode, negative_ttl));
+        }
+    }
+}
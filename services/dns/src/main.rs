@@ -3,6 +3,12 @@
 
 mod api;
 use api::*;
+mod mdns;
+mod message;
+use message::{RData, TYPE_A, TYPE_SRV, TYPE_TXT};
+mod lookup;
+#[cfg(test)]
+mod tests;
 
 use net::NetIpAddr;
 use num_traits::*;
@@ -10,8 +16,9 @@ use xous::msg_scalar_unpack;
 
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::io::ErrorKind;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::thread;
 use xous_ipc::{Buffer, String};
@@ -22,305 +29,37 @@ use xous_ipc::{Buffer, String};
 // MOROS is MIT licensed.
 // See RFC 1035 for implementation details
 
-#[repr(u16)]
-enum QueryType {
-    A = 1,
-    // NS = 2,
-    // MD = 3,
-    // MF = 4,
-    // CNAME = 5,
-    // SOA = 6,
-    // MX = 15,
-    // TXT = 16,
-}
-
-#[repr(u16)]
-enum QueryClass {
-    IN = 1,
-}
-
-struct Message {
-    pub datagram: Vec<u8>,
-}
-
-const FLAG_RD: u16 = 0x0100; // Recursion desired
-
-impl Message {
-    pub fn from(datagram: &[u8]) -> Self {
-        Self {
-            datagram: Vec::from(datagram),
-        }
-    }
-
-    pub fn query(qname: &str, qtype: QueryType, qclass: QueryClass, id: u16) -> Self {
-        let mut datagram = Vec::new();
-
-        for b in id.to_be_bytes().iter() {
-            datagram.push(*b); // Transaction ID
-        }
-        for b in FLAG_RD.to_be_bytes().iter() {
-            datagram.push(*b); // Flags
-        }
-        for b in (1 as u16).to_be_bytes().iter() {
-            datagram.push(*b); // Questions
-        }
-        for _ in 0..6 {
-            datagram.push(0); // Answer + Authority + Additional
-        }
-        for label in qname.split('.') {
-            datagram.push(label.len() as u8); // QNAME label length
-            for b in label.bytes() {
-                datagram.push(b); // QNAME label bytes
-            }
-        }
-        datagram.push(0); // Root null label
-        for b in (qtype as u16).to_be_bytes().iter() {
-            datagram.push(*b); // QTYPE
-        }
-        for b in (qclass as u16).to_be_bytes().iter() {
-            datagram.push(*b); // QCLASS
-        }
-
-        Self { datagram }
-    }
-
-    pub fn id(&self) -> u16 {
-        u16::from_be_bytes(self.datagram[0..2].try_into().unwrap())
-    }
-
-    pub fn header(&self) -> u16 {
-        u16::from_be_bytes(self.datagram[2..4].try_into().unwrap())
-    }
-
-    pub fn is_response(&self) -> bool {
-        if (self.header() & (1 << 15)) == 0 {
-            false
-        } else {
-            true
-        }
-    }
-
-    fn fast_foward_name(&self, start: usize) -> Result<usize, DnsResponseCode> {
-        use DnsResponseCode::FormatError;
-        let mut index = start;
-        loop {
-            log::trace!("cname index: {}", index);
-            if *(self.datagram.get(index).ok_or(FormatError)?) == 0 {
-                index += 1;
-                break;
-            } else {
-                index += *(self.datagram.get(index).ok_or(FormatError)?) as usize;
-                index += 1;
-            }
-        }
-        Ok(index)
-    }
-
-    pub fn parse_response(&self) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
-        use DnsResponseCode::FormatError;
-        log::trace!("parsing packet: {:?}", self.datagram);
-
-        let mut map = HashMap::<IpAddr, u32>::new();
-        // ASSUME: the query ID and response bit fields have already been checked
-        // and that the rcode is valid
-        let qdcount = u16::from_be_bytes(self.datagram[4..6].try_into().unwrap());
-        let ancount = u16::from_be_bytes(self.datagram[6..8].try_into().unwrap());
-
-        let mut index = 12;
-        // fast forward past the qname
-        for queries in 0..qdcount {
-            log::trace!("parsing query{}, index {}", queries, index);
-            index = self.fast_foward_name(index)?;
-            log::trace!("fast forward through qname to {}", index);
-            // index is now at qtype
-            let qtype = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            // A = 1, AAAA = 28
-            if qtype != 1 && qtype != 28 {
-                log::error!("Problem parsing qname, qtype is not 1 or 28: {}", qtype);
-                return Err(FormatError);
-            }
-            index += 2;
-            let qclass = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            if qclass != 1 {
-                log::error!("Problem parsing qname, qclass is not 1: {}", qclass);
-                return Err(FormatError);
-            }
-            index += 2;
-        }
-        // index is now at the aname section
-        for aname in 0..ancount {
-            log::trace!("parsing aname{}, index {}", aname, index);
-            // first check to see if we're dealing with a pointer or a name
-            if self.datagram[index] >= 0xc0 {
-                // pointer
-                index += 1;
-                if self.datagram[index] != 0xc {
-                    log::error!(
-                        "Found aname pointer, but value does not conform to length of aname header"
-                    );
-                    return Err(FormatError);
-                }
-                index += 1;
-            } else {
-                // name, fast forward past the name
-                index = self.fast_foward_name(index)?;
-                log::trace!("fast forward aname to {}", index);
-            }
-            // index is now at type
-            let atype = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            // A = 1, AAAA = 28
-            if atype != 1 && atype != 28 {
-                log::error!("Problem parsing aname, type is not 1 or 28: {}", atype);
-                return Err(FormatError);
-            }
-            index += 2;
-            let aclass = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            if aclass != 1 {
-                log::error!("Problem parsing aname, aclass is not 1: {}", aclass);
-                return Err(FormatError);
-            }
-            index += 2;
-            // this is our TTL
-            let ttl = u32::from_be_bytes(self.datagram[index..index + 4].try_into().unwrap());
-            log::trace!("got ttl: {}", ttl);
-            index += 4;
-            // this is the payload length
-            let addr_len = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            index += 2;
-            match addr_len {
-                // ipv4
-                4 => {
-                    if atype != 1 {
-                        log::error!("Got a 4-byte address, but ATYPE != A (1)");
-                        return Err(FormatError);
-                    }
-                    // this copy happens because I can't figure out how to get Ipv4Addr::from() to realize it's casting from a [u8;4]
-                    let mut rdata: [u8; 4] = [0; 4];
-                    for (&src, dst) in self.datagram[index..index + 4].iter().zip(rdata.iter_mut())
-                    {
-                        *dst = src;
-                    }
-                    let addr = IpAddr::V4(Ipv4Addr::from(rdata));
-                    index += 4;
-                    map.insert(addr, ttl);
-                }
-                // ipv6
-                16 => {
-                    if atype != 28 {
-                        log::error!("Got a 16-byte address, but ATYPE != AAAA (28)");
-                        return Err(FormatError);
-                    }
-                    // this copy happens because I can't figure out how to get Ipv6Addr::from() to realize it's casting from a [u8;4]
-                    let mut rdata: [u8; 16] = [0; 16];
-                    for (&src, dst) in self.datagram[index..index + 16]
-                        .iter()
-                        .zip(rdata.iter_mut())
-                    {
-                        *dst = src;
-                    }
-                    let addr = IpAddr::V6(Ipv6Addr::from(rdata));
-                    index += 16;
-                    map.insert(addr, ttl);
-                }
-                _ => {
-                    log::error!("Length field does not match a known record type");
-                    return Err(FormatError);
-                }
-            }
-        }
-
-        Ok(map)
-    }
-
-    /*
-         example response for: betrusted.io->185.199.111.153
-    Header:
-          61, ca,   id
-          81, 80,   header
-          0, 1,     qdcount
-          0, 4,     ancount
-          0, 0,     nscount
-          0, 0,     arcount
-    qname:
-          9,        length 9
-          62, 65, 74, 72, 75, 73, 74, 65, 64,    "betrusted"
-          2,        length 2
-          69, 6f,   "io"
-          0,        end of name
-    qtype:
-          0, 1,     type A
-    qclass:
-          0, 1,     type IN
-    aname0:
-          c0,       name is a pointer (any value > 192 is a pointer)
-          c,        offset of 12 from start of aname0
-          0, 1,     type A
-          0, 1,     class IN
-          0, 0, e, 10,   0xe10 = 3600 seconds TTL
-          0, 4,     4 bytes address
-          b9, c7, 6c, 99,  address
-    aname1:
-          c0,       name is a pointer
-          c,
-          0, 1,     type A
-          0, 1,     class IN
-          0, 0, e, 10,  TTL
-          0, 4,     4 byte address
-          b9, c7, 6d, 99,  address
-    aname2:
-          c0,
-          c,
-          0, 1,
-          0, 1,
-          0, 0, e, 10,
-          0, 4,
-          b9, c7, 6e, 99,
-    aname3:
-          c0,
-          c,
-          0, 1,
-          0, 1,
-          0, 0, e, 10,
-          0, 4,
-          b9, c7, 6f, 99
-         */
-
-    /*
-    pub fn is_query(&self) -> bool {
-        !self.is_response()
-    }
-    */
-
-    pub fn rcode(&self) -> DnsResponseCode {
-        match (self.header() >> 11) & 0xF {
-            0 => DnsResponseCode::NoError,
-            1 => DnsResponseCode::FormatError,
-            2 => DnsResponseCode::ServerFailure,
-            3 => DnsResponseCode::NameError,
-            4 => DnsResponseCode::NotImplemented,
-            5 => DnsResponseCode::Refused,
-            _ => DnsResponseCode::UnknownError,
-        }
-    }
-}
+/// How long to wait for each response from a DNS server
+const DNS_TIMEOUT_MS: u64 = 10_000;
+/// How long to wait for an answer to a `.local` name
+const MDNS_LOOKUP_TIMEOUT_MS: u64 = 2_000;
+/// Browses and resolves are served one at a time, so their timeouts are capped
+const MDNS_QUERY_TIMEOUT_MAX_MS: u32 = 10_000;
+/// Most names the negative cache holds; past this the entry closest to expiring makes room
+const NEGATIVE_CACHE_MAX_ENTRIES: usize = 64;
 
 pub struct Resolver {
     /// DnsServerManager is a service of the Net crate that automatically updates the DNS server list
     mgr: net::DnsServerManager,
     socket: UdpSocket,
-    buf: [u8; DNS_PKT_MAX_LEN],
     trng: trng::Trng,
     freeze: bool,
+    /// Names that don't exist, or have no records of a type, keyed by the lowercased name and the
+    /// type. Each holds the error to return, and its TTL.
+    negative_cache: HashMap<(std::string::String, u16), (DnsResponseCode, u32)>,
+    /// `.local` names are looked up with mDNS, and this device's own is answered from here
+    mdns: Arc<Mutex<mdns::MdnsState>>,
+    netmgr: net::NetManager,
 }
 impl Resolver {
-    pub fn new(xns: &xous_names::XousNames) -> Resolver {
+    pub fn new(xns: &xous_names::XousNames, mdns: Arc<Mutex<mdns::MdnsState>>) -> Resolver {
         let trng = trng::Trng::new(&xns).unwrap();
         let local_port = (49152 + trng.get_u32().unwrap() % 16384) as u16;
         let socket = UdpSocket::bind(
             format!("0.0.0.0:{}", local_port),
         )
         .expect("couldn't create socket for DNS resolver");
-        let timeout = Duration::from_millis(10_000); // 10 seconds for DNS to resolve by default
+        let timeout = Duration::from_millis(DNS_TIMEOUT_MS);
         socket.set_read_timeout(Some(timeout)).unwrap();
         socket.set_nonblocking(false).unwrap(); // we want this to block.
                                                 // we /could/ do a non-blocking DNS resolver, but...what would you do in the meantime??
@@ -330,9 +69,11 @@ impl Resolver {
             mgr: net::DnsServerManager::register(&xns)
                 .expect("Couldn't register the DNS server list auto-manager"),
             socket,
-            buf: [0; DNS_PKT_MAX_LEN],
             trng,
             freeze: false,
+            negative_cache: HashMap::new(),
+            mdns,
+            netmgr: net::NetManager::new(),
         }
     }
     pub fn add_server(&mut self, addr: IpAddr) {
@@ -356,39 +97,79 @@ impl Resolver {
         self.trng.get_u32().unwrap()
     }
     pub fn resolve(&mut self, name: &str) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
-        if let Some(dns_address) = self.mgr.get_random() {
-            let dns_port = 53;
-            let server = SocketAddr::new(dns_address, dns_port);
-
-            let qname = name;
-            let qtype = QueryType::A;
-            let qclass = QueryClass::IN;
-            let query = Message::query(qname, qtype, qclass, self.trng.get_u32().unwrap() as u16);
-
-            self.socket
-                .send_to(&query.datagram, &server)
-                .map_err(|_| DnsResponseCode::NetworkError)?;
-
-            match self.socket.recv(&mut self.buf) {
-                Ok(len) => {
-                    let message = Message::from(&self.buf[..len]);
-                    if message.id() == query.id() && message.is_response() {
-                        return match message.rcode() {
-                            DnsResponseCode::NoError => message.parse_response(),
-                            rcode => Err(rcode),
-                        };
-                    } else {
-                        Err(DnsResponseCode::NetworkError)
+        if mdns::is_local(name) {
+            let own = self.mdns.lock().unwrap().lookup_self(name, mdns::local_addr(&self.netmgr));
+            return match own {
+                Some(map) if map.len() > 0 => Ok(map),
+                Some(_) => Err(DnsResponseCode::NameError),
+                None => mdns::resolve_host(name, Duration::from_millis(MDNS_LOOKUP_TIMEOUT_MS)),
+            };
+        }
+        let map: HashMap<IpAddr, u32> = self.lookup(name, TYPE_A)?
+            .into_iter()
+            .filter_map(|(data, ttl)| match data {
+                RData::A(addr) => Some((IpAddr::V4(addr), ttl)),
+                RData::Aaaa(addr) => Some((IpAddr::V6(addr), ttl)),
+                _ => None,
+            })
+            .collect();
+        if map.len() > 0 {
+            Ok(map)
+        } else {
+            Err(DnsResponseCode::NameError)
+        }
+    }
+    /// Looks up the records of `qtype` for `name`, following CNAMEs. A name that doesn't exist, or
+    /// has no records of `qtype`, is a `NameError`; this is cached if the server says for how long.
+    pub fn lookup(&mut self, name: &str, qtype: u16) -> Result<Vec<(RData, u32)>, DnsResponseCode> {
+        let key = (name.trim_end_matches('.').to_ascii_lowercase(), qtype);
+        if let Some((code, _)) = self.negative_cache.get(&key) {
+            log::debug!("DNS negative cache: {}", name);
+            return Err(*code);
+        }
+        let dns_address = self.mgr.get_random().ok_or(DnsResponseCode::NoServerSpecified)?;
+        let server = SocketAddr::new(dns_address, 53);
+        let trng = &self.trng;
+        let outcome = lookup::lookup(
+            &self.socket,
+            server,
+            name,
+            qtype,
+            &mut || trng.get_u32().unwrap() as u16,
+            Duration::from_millis(DNS_TIMEOUT_MS),
+        )?;
+        match outcome {
+            lookup::Outcome::Found(records) => Ok(records),
+            lookup::Outcome::Negative(code, ttl) => {
+                log::debug!("{} has no records of type {}: {:?}", name, qtype, code);
+                // whether the name is missing or just has no records of this type, the caller gets nothing
+                if let Some(ttl) = ttl {
+                    if ttl > 0 {
+                        self.cache_negative(key, ttl);
                     }
                 }
-                Err(e) => match e.kind() {
-                    ErrorKind::WouldBlock => Err(DnsResponseCode::NetworkError),
-                    _ => Err(DnsResponseCode::UnknownError),
-                },
+                Err(DnsResponseCode::NameError)
+            }
+        }
+    }
+    fn cache_negative(&mut self, key: (std::string::String, u16), ttl: u32) {
+        if !self.negative_cache.contains_key(&key) && self.negative_cache.len() >= NEGATIVE_CACHE_MAX_ENTRIES {
+            let soonest = self.negative_cache.iter().min_by_key(|(_, (_, ttl))| *ttl).map(|(k, _)| k.clone());
+            if let Some(soonest) = soonest {
+                self.negative_cache.remove(&soonest);
             }
-        } else {
-            Err(DnsResponseCode::NoServerSpecified)
         }
+        self.negative_cache.insert(key, (DnsResponseCode::NameError, ttl));
+    }
+    /// Ages the negative cache by `secs`, dropping the entries that run out.
+    pub fn age_negative_cache(&mut self, secs: u32) {
+        self.negative_cache.retain(|_, (_, ttl)| {
+            *ttl = ttl.saturating_sub(secs);
+            *ttl > 0
+        });
+    }
+    pub fn clear_negative_cache(&mut self) {
+        self.negative_cache.clear();
     }
 }
 
@@ -479,6 +260,60 @@ fn fill_error(mut env: xous::MessageEnvelope, code: DnsResponseCode) -> Option<(
     None
 }
 
+/// Whether the rule for the sender of `msg` lets it use the network. Queries go out from the
+/// sockets of this service, so `net` checks those against the rule for `dns` only.
+fn may_query(netmgr: &net::NetManager, msg: &xous::MessageEnvelope) -> bool {
+    match msg.sender.pid() {
+        Some(pid) => netmgr.policy_check(pid, None).is_ok(),
+        None => false,
+    }
+}
+
+/// Handles `MdnsBrowse` and `MdnsResolve`, on the mDNS query thread.
+fn mdns_query(mut msg: xous::MessageEnvelope) {
+    let browse = msg.body.id() == Opcode::MdnsBrowse.to_usize().unwrap();
+    let mut buf = unsafe {
+        Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+    };
+    let mut request = buf.to_original::<MdnsQueryIpc, _>().unwrap();
+    let service = request.service.as_str().unwrap_or("").to_owned();
+    let instance = request.instance.as_str().unwrap_or("").to_owned();
+    let timeout = Duration::from_millis(request.timeout_ms.min(MDNS_QUERY_TIMEOUT_MAX_MS) as u64);
+    let result = if !mdns::valid_service_type(&service) || (!browse && !mdns::valid_label(&instance)) {
+        Err(DnsResponseCode::FormatError)
+    } else if browse {
+        mdns::browse(&service, timeout)
+    } else {
+        mdns::resolve(&instance, &service, timeout).map(|found| vec![found])
+    };
+    match result {
+        Ok(found) => {
+            let mut packed = Vec::new();
+            let mut count = 0;
+            for instance in found.iter() {
+                let mut entry = Vec::new();
+                instance.pack(&mut entry);
+                if packed.len() + entry.len() > MDNS_LIST_LEN {
+                    log::warn!("only {} of {} service instances fit in the response", count, found.len());
+                    break;
+                }
+                packed.extend_from_slice(&entry);
+                count += 1;
+            }
+            request.data[..packed.len()].copy_from_slice(&packed);
+            request.len = packed.len() as u32;
+            request.count = count;
+            request.code = DnsResponseCode::NoError;
+        }
+        Err(e) => {
+            request.len = 0;
+            request.count = 0;
+            request.code = e;
+        }
+    }
+    buf.replace(request).unwrap();
+}
+
 fn main() -> ! {
     log_server::init_wait().unwrap();
     log::set_max_level(log::LevelFilter::Info);
@@ -490,8 +325,26 @@ fn main() -> ! {
         .expect("can't register server");
     log::trace!("registered with NS -- {:?}", dns_sid);
 
+    // the MAC usually isn't known yet, in which case the responder gives the default host name
+    // its suffix once DHCP hands out an address
+    let netmgr = net::NetManager::new();
+    let hostname = mdns::default_hostname(netmgr.get_ipv4_config().map(|config| config.mac));
+    let mdns_state = Arc::new(Mutex::new(mdns::MdnsState::new(hostname)));
+    thread::spawn({
+        let mdns_state = mdns_state.clone();
+        move || mdns::responder(mdns_state)
+    });
+    // browses and resolves wait on other devices for seconds at a time, so they are handed to a
+    // thread of their own, which replies to each when it's done
+    let (mdns_query_tx, mdns_query_rx) = channel::<xous::MessageEnvelope>();
+    thread::spawn(move || {
+        while let Ok(msg) = mdns_query_rx.recv() {
+            mdns_query(msg);
+        }
+    });
+
     // this will magically populate a list of DNS servers when they become available
-    let mut resolver = Resolver::new(&xns);
+    let mut resolver = Resolver::new(&xns, mdns_state.clone());
     // if you wanted to force a server into the initial config, you can do it here, for example:
     // resolver.add_server(IpAddr::V4(Ipv4Addr::new(1,1,1,1)));
 
@@ -535,6 +388,10 @@ fn main() -> ! {
                             fill_response(msg, &local);
                             continue;
                         }
+                        if !may_query(&netmgr, &msg) {
+                            fill_error(msg, DnsResponseCode::Refused);
+                            continue;
+                        }
                         log::trace!("performing a lookup of {}", owned_name);
                         // Try to get the result out of the DNS cache
                         if let Some(entries) = dns_cache.get(&owned_name) {
@@ -563,9 +420,14 @@ fn main() -> ! {
                 };
             }
             Some(Opcode::Lookup) => {
+                let allowed = may_query(&netmgr, &msg);
                 let mut buf = unsafe {
                     Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
                 };
+                if !allowed {
+                    buf.replace(DnsResponse { addr: None, code: DnsResponseCode::Refused }).unwrap();
+                    continue;
+                }
                 let name = buf
                     .to_original::<String<DNS_NAME_LENGTH_LIMIT>, _>()
                     .unwrap();
@@ -663,10 +525,12 @@ fn main() -> ! {
                         log::debug!("DNS cache removing {}", &name);
                         dns_cache.remove(&name);
                     }
+                    resolver.age_negative_cache(increment);
                 }
             }),
             Some(Opcode::Flush) => {
                 dns_cache.clear();
+                resolver.clear_negative_cache();
             }
             Some(Opcode::FreezeConfig) => {
                 resolver.set_freeze_config(true);
@@ -674,6 +538,149 @@ fn main() -> ! {
             Some(Opcode::ThawConfig) => {
                 resolver.set_freeze_config(false);
             }
+            Some(Opcode::MdnsSetHostname) => {
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut request = buf.to_original::<MdnsServiceIpc, _>().unwrap();
+                let hostname = request.instance.as_str().unwrap_or("").to_owned();
+                if mdns::valid_label(&hostname) {
+                    let addr = mdns::local_addr(&netmgr);
+                    let mut state = mdns_state.lock().unwrap();
+                    // say goodbye under the old name, then claim everything under the new one
+                    state.announce_host(addr, 0);
+                    state.hostname = hostname;
+                    state.named = true;
+                    state.claim_all();
+                    // lookups of the old name must not be answered from the cache
+                    dns_cache.retain(|name, _| !mdns::is_local(name));
+                    request.code = DnsResponseCode::NoError;
+                } else {
+                    request.code = DnsResponseCode::FormatError;
+                }
+                buf.replace(request).unwrap();
+            }
+            Some(Opcode::MdnsGetHostname) => {
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut request = buf.to_original::<MdnsServiceIpc, _>().unwrap();
+                request.instance = String::<MDNS_LABEL_LEN>::from_str(&mdns_state.lock().unwrap().hostname);
+                request.code = DnsResponseCode::NoError;
+                buf.replace(request).unwrap();
+            }
+            Some(Opcode::MdnsRegister) | Some(Opcode::MdnsUnregister) => {
+                let register = msg.body.id() == Opcode::MdnsRegister.to_usize().unwrap();
+                let allowed = !register || may_query(&netmgr, &msg);
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut request = buf.to_original::<MdnsServiceIpc, _>().unwrap();
+                if !allowed {
+                    request.code = DnsResponseCode::Refused;
+                    buf.replace(request).unwrap();
+                    continue;
+                }
+                let instance = request.instance.as_str().unwrap_or("").to_owned();
+                let service = request.service.as_str().unwrap_or("").to_owned();
+                if !mdns::valid_label(&instance) || !mdns::valid_service_type(&service) {
+                    request.code = DnsResponseCode::FormatError;
+                    buf.replace(request).unwrap();
+                    continue;
+                }
+                let addr = mdns::local_addr(&netmgr);
+                let mut state = mdns_state.lock().unwrap();
+                let existing = state.services.iter().position(|s|
+                    s.instance.eq_ignore_ascii_case(&instance) && s.service.eq_ignore_ascii_case(&service)
+                );
+                if let Some(index) = existing {
+                    let old = state.services.remove(index);
+                    state.announce_service(&old, addr, 0);
+                }
+                request.code = if register {
+                    let txt_len = (request.txt_len as usize).min(MDNS_TXT_LEN);
+                    let service = mdns::Service {
+                        instance,
+                        service,
+                        port: request.port,
+                        txt: unpack_txt(&request.txt[..txt_len]),
+                    };
+                    log::info!("advertising {}.{} on port {}", service.instance, service.service, service.port);
+                    state.add_service(service);
+                    DnsResponseCode::NoError
+                } else if existing.is_some() {
+                    DnsResponseCode::NoError
+                } else {
+                    DnsResponseCode::NameError
+                };
+                buf.replace(request).unwrap();
+            }
+            Some(Opcode::MdnsBrowse) | Some(Opcode::MdnsResolve) => {
+                if may_query(&netmgr, &msg) {
+                    mdns_query_tx.send(msg).unwrap();
+                    continue;
+                }
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut request = buf.to_original::<MdnsQueryIpc, _>().unwrap();
+                request.len = 0;
+                request.count = 0;
+                request.code = DnsResponseCode::Refused;
+                buf.replace(request).unwrap();
+            }
+            Some(Opcode::LookupRecords) => {
+                let allowed = may_query(&netmgr, &msg);
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut request = buf.to_original::<DnsRecordQuery, _>().unwrap();
+                if !allowed {
+                    request.len = 0;
+                    request.count = 0;
+                    request.code = DnsResponseCode::Refused;
+                    buf.replace(request).unwrap();
+                    continue;
+                }
+                let name = std::string::String::from(request.name.as_str().unwrap_or(""));
+                let qtype = match request.rtype {
+                    DnsRecordType::Txt => TYPE_TXT,
+                    DnsRecordType::Srv => TYPE_SRV,
+                };
+                // records other than addresses aren't cached, only their absence
+                match resolver.lookup(&name, qtype) {
+                    Ok(records) => {
+                        let mut packed = Vec::new();
+                        let mut count = 0;
+                        for (data, _ttl) in records {
+                            let record = match data {
+                                RData::Txt(txt) => DnsRecord::Txt(txt),
+                                RData::Srv { priority, weight, port, target } => DnsRecord::Srv { priority, weight, port, target },
+                                _ => continue,
+                            };
+                            let mut entry = Vec::new();
+                            record.pack(&mut entry);
+                            if packed.len() + entry.len() > DNS_RECORDS_LEN {
+                                log::warn!("not all the records of {} fit in the response", name);
+                                break;
+                            }
+                            packed.extend_from_slice(&entry);
+                            count += 1;
+                        }
+                        request.data[..packed.len()].copy_from_slice(&packed);
+                        request.len = packed.len() as u32;
+                        request.count = count;
+                        request.code = DnsResponseCode::NoError;
+                    }
+                    Err(e) => {
+                        log::debug!("DNS record query failed: {}->{:?}", name, e);
+                        request.len = 0;
+                        request.count = 0;
+                        request.code = e;
+                    }
+                }
+                buf.replace(request).unwrap();
+            }
             Some(Opcode::Quit) => {
                 log::warn!("got quit!");
                 break;
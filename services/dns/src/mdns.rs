@@ -0,0 +1,982 @@
+//! # Multicast DNS
+//!
+//! A responder for multicast DNS (RFC 6762) and DNS-based service discovery (RFC 6763). The
+//! responder runs in its own thread, bound to port 5353 and joined to the mDNS group, and
+//! answers for the device's `.local` host name and the services registered with it.
+//!
+//! Lookups go out as one-shot queries (RFC 6762 section 5.1): each is sent from an ephemeral
+//! port, so responders answer it directly, and the answers are collected until a timeout. This
+//! keeps lookups out of the responder's way, at the cost of not seeing announcements made while
+//! no lookup is running; there is no cache of other hosts' records.
+//!
+//! Before the host name or a service instance is announced, it is probed for (RFC 6762 section
+//! 8): three queries for it go out 250 ms apart, and if another device answers for it, it is
+//! renamed with a `-2` suffix (or `-3`, and so on) and probed for again. The same happens if
+//! another device later answers for a name that has been claimed, with different records. A
+//! client that registered a service which was renamed will see the new name when browsing.
+
+use crate::api::*;
+use crate::message::{read_name, read_u16, read_u32, same_name, write_name};
+use crate::message::{CLASS_IN, TYPE_A, TYPE_AAAA, TYPE_SRV, TYPE_TXT};
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub(crate) const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub(crate) const MDNS_PORT: u16 = 5353;
+/// mDNS packets can be larger than unicast DNS ones, but must fit in one Ethernet frame
+const MDNS_PKT_MAX_LEN: usize = 1500;
+/// How many probes are sent for a name before it is claimed, and how far apart
+const PROBE_COUNT: u32 = 3;
+const PROBE_INTERVAL_MS: u64 = 250;
+/// How long to wait before probing again, after losing a tie-break with another device probing
+/// for the same name
+const PROBE_DEFER_MS: u64 = 1000;
+/// How often the responder checks for probes and announcements to send while it waits for queries
+const RESPONDER_POLL_MS: u64 = PROBE_INTERVAL_MS;
+
+const TYPE_PTR: u16 = 12;
+const TYPE_ANY: u16 = 255;
+/// Set in the class of a record that replaces all others of its name and type
+const CACHE_FLUSH: u16 = 0x8000;
+/// Set in the class of a question that asks for a unicast response
+const UNICAST_RESPONSE: u16 = 0x8000;
+/// QR and AA: an authoritative response
+const FLAGS_RESPONSE: u16 = 0x8400;
+
+/// TTL of records that name a host, per RFC 6762 section 10
+const HOST_TTL: u32 = 120;
+/// TTL of all other records
+pub(crate) const SERVICE_TTL: u32 = 4500;
+/// Responses to queries that don't come from port 5353 are capped to this TTL
+const LEGACY_TTL: u32 = 10;
+
+/// The name that lists the types of the services on a host
+const SERVICES_META: &str = "_services._dns-sd._udp.local";
+
+#[derive(Debug, Clone, PartialEq)]
+enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+    Srv { port: u16, target: String },
+    Txt(Vec<String>),
+    Other,
+}
+
+#[derive(Debug, Clone)]
+struct Record {
+    name: String,
+    rtype: u16,
+    ttl: u32,
+    flush: bool,
+    data: RData,
+}
+impl Record {
+    fn new(name: &str, ttl: u32, flush: bool, data: RData) -> Record {
+        let rtype = match data {
+            RData::A(_) => TYPE_A,
+            RData::Aaaa(_) => TYPE_AAAA,
+            RData::Ptr(_) => TYPE_PTR,
+            RData::Srv { .. } => TYPE_SRV,
+            RData::Txt(_) => TYPE_TXT,
+            RData::Other => 0,
+        };
+        Record { name: name.to_string(), rtype, ttl, flush, data }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Question {
+    name: String,
+    rtype: u16,
+    unicast: bool,
+}
+
+struct Packet {
+    id: u16,
+    flags: u16,
+    questions: Vec<Question>,
+    /// The answer, authority and additional sections, in that order
+    records: Vec<Record>,
+}
+
+impl Packet {
+    fn parse(d: &[u8]) -> Option<Packet> {
+        let id = read_u16(d, 0)?;
+        let flags = read_u16(d, 2)?;
+        let qdcount = read_u16(d, 4)?;
+        let rrcount = read_u16(d, 6)? as usize + read_u16(d, 8)? as usize + read_u16(d, 10)? as usize;
+        let mut index = 12;
+        let mut questions = Vec::new();
+        for _ in 0..qdcount {
+            let (name, next) = read_name(d, index)?;
+            let rtype = read_u16(d, next)?;
+            let class = read_u16(d, next + 2)?;
+            questions.push(Question { name, rtype, unicast: class & UNICAST_RESPONSE != 0 });
+            index = next + 4;
+        }
+        let mut records = Vec::new();
+        for _ in 0..rrcount {
+            let (name, next) = read_name(d, index)?;
+            let rtype = read_u16(d, next)?;
+            let class = read_u16(d, next + 2)?;
+            let ttl = read_u32(d, next + 4)?;
+            let rdlen = read_u16(d, next + 8)? as usize;
+            let start = next + 10;
+            let rdata = d.get(start..start + rdlen)?;
+            let data = match rtype {
+                TYPE_A if rdlen == 4 => RData::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+                TYPE_AAAA if rdlen == 16 => {
+                    let octets: [u8; 16] = rdata.try_into().ok()?;
+                    RData::Aaaa(Ipv6Addr::from(octets))
+                }
+                TYPE_PTR => RData::Ptr(read_name(d, start)?.0),
+                TYPE_SRV if rdlen > 6 => RData::Srv {
+                    port: read_u16(d, start + 4)?,
+                    target: read_name(d, start + 6)?.0,
+                },
+                TYPE_TXT => RData::Txt(unpack_txt(rdata)),
+                _ => RData::Other,
+            };
+            records.push(Record { name, rtype, ttl, flush: class & CACHE_FLUSH != 0, data });
+            index = start + rdlen;
+        }
+        Some(Packet { id, flags, questions, records })
+    }
+    fn is_response(&self) -> bool {
+        self.flags & 0x8000 != 0
+    }
+}
+
+fn write_record(out: &mut Vec<u8>, record: &Record) {
+    write_name(out, &record.name);
+    out.extend_from_slice(&record.rtype.to_be_bytes());
+    let class = if record.flush { CLASS_IN | CACHE_FLUSH } else { CLASS_IN };
+    out.extend_from_slice(&class.to_be_bytes());
+    out.extend_from_slice(&record.ttl.to_be_bytes());
+    let rdata = write_rdata(&record.data);
+    out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    out.extend_from_slice(&rdata);
+}
+
+fn write_rdata(data: &RData) -> Vec<u8> {
+    let mut rdata = Vec::new();
+    match data {
+        RData::A(addr) => rdata.extend_from_slice(&addr.octets()),
+        RData::Aaaa(addr) => rdata.extend_from_slice(&addr.octets()),
+        RData::Ptr(name) => write_name(&mut rdata, name),
+        RData::Srv { port, target } => {
+            // priority and weight
+            rdata.extend_from_slice(&[0, 0, 0, 0]);
+            rdata.extend_from_slice(&port.to_be_bytes());
+            write_name(&mut rdata, target);
+        }
+        RData::Txt(txt) => {
+            rdata = pack_txt(txt);
+            // a TXT record can't be empty
+            if rdata.is_empty() {
+                rdata.push(0);
+            }
+        }
+        RData::Other => (),
+    }
+    rdata
+}
+
+fn encode(id: u16, flags: u16, questions: &[Question], answers: &[Record], authority: &[Record], additional: &[Record])
+-> Vec<u8> {
+    let mut out = Vec::with_capacity(MDNS_PKT_MAX_LEN);
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&flags.to_be_bytes());
+    out.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(authority.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(additional.len() as u16).to_be_bytes());
+    for q in questions.iter() {
+        write_name(&mut out, &q.name);
+        out.extend_from_slice(&q.rtype.to_be_bytes());
+        let class = if q.unicast { CLASS_IN | UNICAST_RESPONSE } else { CLASS_IN };
+        out.extend_from_slice(&class.to_be_bytes());
+    }
+    for record in answers.iter().chain(authority.iter()).chain(additional.iter()) {
+        write_record(&mut out, record);
+    }
+    out
+}
+
+/// A service advertised by this device
+#[derive(Debug, Clone)]
+pub(crate) struct Service {
+    pub instance: String,
+    pub service: String,
+    pub port: u16,
+    pub txt: Vec<String>,
+}
+impl Service {
+    fn type_name(&self) -> String {
+        format!("{}.local", self.service)
+    }
+    fn instance_name(&self) -> String {
+        format!("{}.{}.local", self.instance, self.service)
+    }
+}
+
+/// Checks that `service` is a service type and protocol, such as `_http._tcp`.
+pub(crate) fn valid_service_type(service: &str) -> bool {
+    match service.split_once('.') {
+        Some((name, proto)) => {
+            name.len() > 1 && name.len() <= MDNS_LABEL_LEN && name.starts_with('_')
+                && (proto == "_tcp" || proto == "_udp")
+        }
+        None => false,
+    }
+}
+
+/// The default host name. It ends with the last two bytes of the MAC, if that is known yet, so
+/// that several devices on one network don't all start out probing for the same name.
+pub(crate) fn default_hostname(mac: Option<[u8; 6]>) -> String {
+    match mac {
+        Some(mac) => format!("precursor-{:02x}{:02x}", mac[4], mac[5]),
+        None => String::from("precursor"),
+    }
+}
+
+/// The label to try after `label` turns out to be taken: `name` becomes `name-2`, and `name-2`
+/// becomes `name-3`.
+pub(crate) fn next_label(label: &str) -> String {
+    let (base, n) = match label.rsplit_once('-') {
+        Some((base, n)) if !base.is_empty() && n.len() <= 3 && n.bytes().all(|b| b.is_ascii_digit()) => {
+            (base, n.parse::<u32>().unwrap_or(1))
+        }
+        _ => (label, 1),
+    };
+    let suffix = format!("-{}", n + 1);
+    let mut base = base.to_string();
+    while base.len() + suffix.len() > MDNS_LABEL_LEN {
+        base.pop();
+    }
+    base + &suffix
+}
+
+/// A name this device wants to answer for, which is being probed for
+#[derive(Debug, Clone)]
+struct Probe {
+    name: String,
+    sent: u32,
+}
+
+pub(crate) struct MdnsState {
+    /// without the `.local` suffix
+    pub hostname: String,
+    /// Set once a client has chosen the host name, so it isn't replaced by the default one
+    pub named: bool,
+    pub services: Vec<Service>,
+    /// Unsolicited responses for the responder thread to send
+    pending: Vec<Vec<u8>>,
+    /// Names being probed for. They aren't answered for, or announced, until probing is done.
+    probes: Vec<Probe>,
+    /// When the next round of probes can be sent
+    probe_at: Instant,
+}
+impl MdnsState {
+    pub(crate) fn new(hostname: String) -> MdnsState {
+        MdnsState {
+            hostname,
+            named: false,
+            services: Vec::new(),
+            pending: Vec::new(),
+            probes: Vec::new(),
+            probe_at: Instant::now(),
+        }
+    }
+    fn host_name(&self) -> String {
+        format!("{}.local", self.hostname)
+    }
+    /// The address record of the host, unless the host name is still being probed for
+    fn host_records(&self, addr: Option<Ipv4Addr>, ttl: u32) -> Vec<Record> {
+        if self.probing(&self.host_name()) {
+            return Vec::new();
+        }
+        addr.map(|addr| Record::new(&self.host_name(), ttl, true, RData::A(addr))).into_iter().collect()
+    }
+    fn srv_txt_records(&self, service: &Service, ttl: u32) -> Vec<Record> {
+        vec![
+            Record::new(&service.instance_name(), ttl.min(HOST_TTL), true,
+                RData::Srv { port: service.port, target: self.host_name() }),
+            Record::new(&service.instance_name(), ttl, true, RData::Txt(service.txt.clone())),
+        ]
+    }
+
+    fn probing(&self, name: &str) -> bool {
+        self.probes.iter().any(|p| same_name(&p.name, name))
+    }
+    /// The records this device means to answer with for `name`
+    fn proposed(&self, name: &str, addr: Option<Ipv4Addr>) -> Vec<Record> {
+        if same_name(name, &self.host_name()) {
+            return addr.map(|addr| Record::new(name, HOST_TTL, true, RData::A(addr))).into_iter().collect();
+        }
+        self.services.iter()
+            .filter(|s| same_name(name, &s.instance_name()))
+            .flat_map(|s| self.srv_txt_records(s, SERVICE_TTL))
+            .collect()
+    }
+
+    /// Probes for the host name and every service, and announces each once it is claimed.
+    pub(crate) fn claim_all(&mut self) {
+        self.probes.clear();
+        self.claim(self.host_name());
+        for name in self.services.iter().map(|s| s.instance_name()).collect::<Vec<String>>() {
+            self.claim(name);
+        }
+    }
+    /// Adds a service, which is announced once its instance name is claimed.
+    pub(crate) fn add_service(&mut self, service: Service) {
+        let name = service.instance_name();
+        self.services.push(service);
+        self.claim(name);
+    }
+    /// Probes for one name, and announces it once it is claimed.
+    pub(crate) fn claim(&mut self, name: String) {
+        self.probes.retain(|p| !same_name(&p.name, &name));
+        self.probes.push(Probe { name, sent: 0 });
+    }
+    /// Announces the names that have been probed for `PROBE_COUNT` times without a conflict, and
+    /// returns the next round of probes for the rest, if it's time to send one. Probing waits
+    /// until there is an address to claim the host name with.
+    fn probe_step(&mut self, addr: Option<Ipv4Addr>, now: Instant) -> Option<Vec<u8>> {
+        if addr.is_none() || self.probes.is_empty() || now < self.probe_at {
+            return None;
+        }
+        self.probe_at = now + Duration::from_millis(PROBE_INTERVAL_MS);
+        let (done, waiting): (Vec<Probe>, Vec<Probe>) = std::mem::take(&mut self.probes)
+            .into_iter()
+            .partition(|p| p.sent >= PROBE_COUNT);
+        self.probes = waiting;
+        for probe in done {
+            if same_name(&probe.name, &self.host_name()) {
+                log::info!("claimed {}", probe.name);
+                self.announce_host(addr, HOST_TTL);
+            } else if let Some(service) = self.services.iter().find(|s| same_name(&probe.name, &s.instance_name())).cloned() {
+                log::info!("claimed {}", probe.name);
+                self.announce_service(&service, addr, SERVICE_TTL);
+            }
+        }
+        if self.probes.is_empty() {
+            return None;
+        }
+        let mut questions = Vec::new();
+        let mut authority = Vec::new();
+        for probe in self.probes.iter_mut() {
+            probe.sent += 1;
+        }
+        for probe in self.probes.iter() {
+            questions.push(Question { name: probe.name.clone(), rtype: TYPE_ANY, unicast: true });
+            authority.extend(self.proposed(&probe.name, addr));
+        }
+        Some(encode(0, 0, &questions, &[], &authority, &[]))
+    }
+
+    /// Looks for records in a response from another device that answer for this device's names:
+    /// any record for a name being probed for, or a record of the same type with different data
+    /// for a name already claimed. Those names are renamed and probed for again.
+    fn check_conflicts(&mut self, response: &Packet, addr: Option<Ipv4Addr>) {
+        let mut lost = Vec::<String>::new();
+        for record in response.records.iter().filter(|r| r.ttl > 0) {
+            let conflict = self.probing(&record.name)
+                || self.proposed(&record.name, addr).iter().any(|r| r.rtype == record.rtype && r.data != record.data);
+            if conflict && !lost.iter().any(|name| same_name(name, &record.name)) {
+                lost.push(record.name.clone());
+            }
+        }
+        for name in lost {
+            self.rename(&name);
+        }
+    }
+    fn rename(&mut self, name: &str) {
+        if same_name(name, &self.host_name()) {
+            let hostname = next_label(&self.hostname);
+            log::warn!("{} is in use by another device, renaming to {}.local", name, hostname);
+            self.hostname = hostname;
+            // the SRV records of the services name the host, so they are claimed again along with it
+            self.claim_all();
+        } else if let Some(service) = self.services.iter_mut().find(|s| same_name(name, &s.instance_name())) {
+            service.instance = next_label(&service.instance);
+            let renamed = service.instance_name();
+            log::warn!("{} is in use by another device, renaming to {}", name, renamed);
+            self.claim(renamed);
+        }
+    }
+    /// Breaks a tie with another device probing for a name this device is probing for too
+    /// (RFC 6762 section 8.2). The device whose proposed records compare lower defers, and
+    /// probes again a second later; the name isn't changed, as the other device may yet lose
+    /// out to a third.
+    fn check_tiebreak(&mut self, query: &Packet, addr: Option<Ipv4Addr>, now: Instant) {
+        let key = |r: &Record| (r.rtype, write_rdata(&r.data));
+        let lost = self.probes.iter().find(|probe| {
+            if !query.questions.iter().any(|q| same_name(&q.name, &probe.name)) {
+                return false;
+            }
+            let mut theirs: Vec<(u16, Vec<u8>)> = query.records.iter()
+                .filter(|r| same_name(&r.name, &probe.name))
+                .map(key)
+                .collect();
+            let mut ours: Vec<(u16, Vec<u8>)> = self.proposed(&probe.name, addr).iter().map(key).collect();
+            theirs.sort();
+            ours.sort();
+            !theirs.is_empty() && ours < theirs
+        });
+        if let Some(probe) = lost {
+            log::info!("another device is probing for {}, deferring", probe.name);
+            for probe in self.probes.iter_mut() {
+                probe.sent = 0;
+            }
+            self.probe_at = now + Duration::from_millis(PROBE_DEFER_MS);
+        }
+    }
+
+    /// Builds the answers to `query`, or None if none of its questions are about this device.
+    /// `legacy` is set for queries that didn't come from port 5353, which are answered as a
+    /// unicast DNS server would.
+    fn respond(&self, query: &Packet, addr: Option<Ipv4Addr>, legacy: bool) -> Option<Vec<u8>> {
+        let ttl = |ttl: u32| if legacy { ttl.min(LEGACY_TTL) } else { ttl };
+        let mut answers = Vec::<Record>::new();
+        let mut additional = Vec::<Record>::new();
+        let wants = |q: &Question, rtype: u16| q.rtype == rtype || q.rtype == TYPE_ANY;
+        for q in query.questions.iter() {
+            if same_name(&q.name, &self.host_name()) && wants(q, TYPE_A) {
+                answers.extend(self.host_records(addr, ttl(HOST_TTL)));
+            }
+            if same_name(&q.name, SERVICES_META) && wants(q, TYPE_PTR) {
+                let mut types: Vec<String> = self.services.iter()
+                    .filter(|s| !self.probing(&s.instance_name()))
+                    .map(|s| s.type_name())
+                    .collect();
+                types.sort();
+                types.dedup();
+                for t in types {
+                    answers.push(Record::new(SERVICES_META, ttl(SERVICE_TTL), false, RData::Ptr(t)));
+                }
+            }
+            for service in self.services.iter().filter(|s| !self.probing(&s.instance_name())) {
+                if same_name(&q.name, &service.type_name()) && wants(q, TYPE_PTR) {
+                    answers.push(Record::new(&service.type_name(), ttl(SERVICE_TTL), false,
+                        RData::Ptr(service.instance_name())));
+                    additional.extend(self.srv_txt_records(service, ttl(SERVICE_TTL)));
+                    additional.extend(self.host_records(addr, ttl(HOST_TTL)));
+                }
+                if same_name(&q.name, &service.instance_name()) {
+                    let records = self.srv_txt_records(service, ttl(SERVICE_TTL));
+                    answers.extend(records.into_iter().filter(|r| wants(q, r.rtype)));
+                    additional.extend(self.host_records(addr, ttl(HOST_TTL)));
+                }
+            }
+        }
+        // known-answer suppression: leave out what the querier already has, with at least
+        // half its TTL to go
+        answers.retain(|a| !query.records.iter().any(|k|
+            same_name(&k.name, &a.name) && k.data == a.data && k.ttl >= a.ttl / 2
+        ));
+        if answers.is_empty() {
+            return None;
+        }
+        additional.retain(|r| !answers.iter().any(|a| same_name(&a.name, &r.name) && a.data == r.data));
+        additional.dedup_by(|a, b| same_name(&a.name, &b.name) && a.data == b.data);
+        if legacy {
+            // legacy responses repeat the question, and don't use the cache flush bit
+            for r in answers.iter_mut().chain(additional.iter_mut()) {
+                r.flush = false;
+            }
+            Some(encode(query.id, FLAGS_RESPONSE, &query.questions, &answers, &[], &additional))
+        } else {
+            Some(encode(0, FLAGS_RESPONSE, &[], &answers, &[], &additional))
+        }
+    }
+
+    /// Queues an announcement of a service, or a goodbye if `ttl` is 0.
+    pub(crate) fn announce_service(&mut self, service: &Service, addr: Option<Ipv4Addr>, ttl: u32) {
+        let mut answers = vec![Record::new(&service.type_name(), ttl, false, RData::Ptr(service.instance_name()))];
+        answers.extend(self.srv_txt_records(service, ttl));
+        if ttl != 0 {
+            answers.push(Record::new(SERVICES_META, ttl, false, RData::Ptr(service.type_name())));
+            answers.extend(self.host_records(addr, HOST_TTL));
+        }
+        self.pending.push(encode(0, FLAGS_RESPONSE, &[], &answers, &[], &[]));
+    }
+    /// Queues an announcement of the host name, or a goodbye if `ttl` is 0.
+    pub(crate) fn announce_host(&mut self, addr: Option<Ipv4Addr>, ttl: u32) {
+        let answers = self.host_records(addr, ttl);
+        if !answers.is_empty() {
+            self.pending.push(encode(0, FLAGS_RESPONSE, &[], &answers, &[], &[]));
+        }
+    }
+    /// Answers a lookup of this device's own name, which the responder won't see as a query.
+    pub(crate) fn lookup_self(&self, name: &str, addr: Option<Ipv4Addr>) -> Option<HashMap<IpAddr, u32>> {
+        if !same_name(name, &self.host_name()) {
+            return None;
+        }
+        Some(addr.map(|addr| (IpAddr::V4(addr), HOST_TTL)).into_iter().collect())
+    }
+}
+
+/// Names under `.local` are looked up with mDNS, instead of from the DNS servers.
+pub(crate) fn is_local(name: &str) -> bool {
+    name.trim_end_matches('.').to_ascii_lowercase().ends_with(".local")
+}
+
+/// Checks that `label` can be used as a host or instance name.
+pub(crate) fn valid_label(label: &str) -> bool {
+    !label.is_empty() && label.len() <= MDNS_LABEL_LEN && !label.contains('.')
+}
+
+/// The address the device answers for, if it has one
+pub(crate) fn local_addr(netmgr: &net::NetManager) -> Option<Ipv4Addr> {
+    let config = netmgr.get_ipv4_config()?;
+    let addr = Ipv4Addr::from(config.addr);
+    if addr.is_unspecified() {
+        None
+    } else {
+        Some(addr)
+    }
+}
+
+#[cfg(any(feature="precursor", feature="renode"))]
+fn join_group(_socket: &UdpSocket, netmgr: &net::NetManager) -> Result<(), xous::Error> {
+    netmgr.join_multicast_v4(MDNS_ADDR)
+}
+#[cfg(not(any(feature="precursor", feature="renode")))]
+fn join_group(socket: &UdpSocket, _netmgr: &net::NetManager) -> Result<(), xous::Error> {
+    socket.join_multicast_v4(&MDNS_ADDR, &Ipv4Addr::UNSPECIFIED).or(Err(xous::Error::InternalError))
+}
+
+/// Answers queries for this device's names and services. Never returns.
+pub(crate) fn responder(state: Arc<Mutex<MdnsState>>) {
+    let netmgr = net::NetManager::new();
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), MDNS_PORT))
+        .expect("couldn't bind the mDNS responder");
+    join_group(&socket, &netmgr).expect("couldn't join the mDNS multicast group");
+    socket.set_read_timeout(Some(Duration::from_millis(RESPONDER_POLL_MS))).unwrap();
+    let group = SocketAddr::new(IpAddr::V4(MDNS_ADDR), MDNS_PORT);
+    let mut buf = [0u8; MDNS_PKT_MAX_LEN];
+    log::info!("mDNS responder started as {}", state.lock().unwrap().host_name());
+    let mut last_addr = None;
+    loop {
+        // claim everything again whenever the address changes, including when DHCP first hands
+        // one out. That is also when the MAC is first known, so the default host name can be
+        // given its suffix.
+        let config = netmgr.get_ipv4_config();
+        let addr = config.as_ref().map(|config| Ipv4Addr::from(config.addr)).filter(|addr| !addr.is_unspecified());
+        if addr != last_addr {
+            if let Some(config) = config.as_ref().filter(|_| addr.is_some()) {
+                let mut state = state.lock().unwrap();
+                if !state.named {
+                    state.hostname = default_hostname(Some(config.mac));
+                }
+                state.claim_all();
+            }
+            last_addr = addr;
+        }
+        let probe = state.lock().unwrap().probe_step(addr, Instant::now());
+        if let Some(probe) = probe {
+            socket.send_to(&probe, group).ok();
+        }
+        let pending = std::mem::take(&mut state.lock().unwrap().pending);
+        for packet in pending {
+            socket.send_to(&packet, group).ok();
+        }
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                let packet = match Packet::parse(&buf[..len]) {
+                    Some(packet) => packet,
+                    None => continue,
+                };
+                // this device's own probes and announcements come back to it
+                let own = addr.map_or(false, |addr| from.ip() == IpAddr::V4(addr)) && from.port() == MDNS_PORT;
+                let mut state = state.lock().unwrap();
+                if packet.is_response() {
+                    if !own {
+                        state.check_conflicts(&packet, addr);
+                    }
+                    continue;
+                }
+                if !own {
+                    state.check_tiebreak(&packet, addr, Instant::now());
+                }
+                let legacy = from.port() != MDNS_PORT;
+                let response = state.respond(&packet, addr, legacy);
+                drop(state);
+                if let Some(response) = response {
+                    let unicast = legacy || packet.questions.iter().all(|q| q.unicast);
+                    socket.send_to(&response, if unicast { from } else { group }).ok();
+                }
+            }
+            Err(e) => match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => (),
+                _ => log::warn!("mDNS responder receive failed: {:?}", e),
+            },
+        }
+    }
+}
+
+/// Sends a one-shot query, and collects the records in the responses until `timeout`, or
+/// until `done` is satisfied with them.
+fn query(questions: &[(String, u16)], timeout: Duration, done: impl Fn(&[Record]) -> bool)
+-> Result<Vec<Record>, DnsResponseCode> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))
+        .or(Err(DnsResponseCode::NetworkError))?;
+    let questions: Vec<Question> = questions.iter()
+        .map(|(name, rtype)| Question { name: name.to_string(), rtype: *rtype, unicast: true })
+        .collect();
+    socket.send_to(&encode(0, 0, &questions, &[], &[], &[]), SocketAddr::new(IpAddr::V4(MDNS_ADDR), MDNS_PORT))
+        .or(Err(DnsResponseCode::NetworkError))?;
+    let start = Instant::now();
+    let mut records = Vec::new();
+    let mut buf = [0u8; MDNS_PKT_MAX_LEN];
+    while !done(&records) {
+        let remaining = match timeout.checked_sub(start.elapsed()) {
+            Some(remaining) if remaining.as_millis() > 0 => remaining,
+            _ => break,
+        };
+        socket.set_read_timeout(Some(remaining)).ok();
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) => {
+                if let Some(packet) = Packet::parse(&buf[..len]) {
+                    if packet.is_response() {
+                        records.extend(packet.records);
+                    }
+                }
+            }
+            Err(e) => match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => break,
+                _ => return Err(DnsResponseCode::NetworkError),
+            },
+        }
+    }
+    Ok(records)
+}
+
+/// Fills in a service instance from the records gathered so far. Returns None if there is
+/// no SRV record for it yet.
+fn assemble(records: &[Record], instance: &str, service: &str) -> Option<ServiceInstance> {
+    let fqdn = format!("{}.{}.local", instance, service);
+    let (host, port) = records.iter().find_map(|r| match &r.data {
+        RData::Srv { port, target } if same_name(&r.name, &fqdn) => Some((target.clone(), *port)),
+        _ => None,
+    })?;
+    let txt = records.iter().find_map(|r| match &r.data {
+        RData::Txt(txt) if same_name(&r.name, &fqdn) => Some(txt.clone()),
+        _ => None,
+    }).unwrap_or_default();
+    let addr = records.iter().find_map(|r| match r.data {
+        RData::A(addr) if same_name(&r.name, &host) => Some(IpAddr::V4(addr)),
+        RData::Aaaa(addr) if same_name(&r.name, &host) => Some(IpAddr::V6(addr)),
+        _ => None,
+    });
+    Some(ServiceInstance {
+        instance: instance.to_string(),
+        service: service.to_string(),
+        host,
+        port,
+        addr,
+        txt,
+    })
+}
+
+/// Looks up the host, port and TXT strings of one instance of a service.
+pub(crate) fn resolve(instance: &str, service: &str, timeout: Duration) -> Result<ServiceInstance, DnsResponseCode> {
+    let fqdn = format!("{}.{}.local", instance, service);
+    let mut records = query(&[(fqdn.clone(), TYPE_SRV), (fqdn, TYPE_TXT)], timeout,
+        |records| assemble(records, instance, service).map_or(false, |i| i.addr.is_some()))?;
+    let mut found = assemble(&records, instance, service).ok_or(DnsResponseCode::NameError)?;
+    if found.addr.is_none() {
+        // the responder didn't volunteer the address of the host
+        records.extend(query(&[(found.host.clone(), TYPE_A)], timeout, |r| !r.is_empty())?);
+        found = assemble(&records, instance, service).ok_or(DnsResponseCode::NameError)?;
+    }
+    Ok(found)
+}
+
+/// Finds the instances of `service` on the local network, for example `_http._tcp`.
+pub(crate) fn browse(service: &str, timeout: Duration) -> Result<Vec<ServiceInstance>, DnsResponseCode> {
+    let type_name = format!("{}.local", service);
+    let records = query(&[(type_name.clone(), TYPE_PTR)], timeout, |_| false)?;
+    let suffix = format!(".{}", type_name);
+    let mut instances: Vec<String> = records.iter().filter_map(|r| match &r.data {
+        RData::Ptr(target) if same_name(&r.name, &type_name) && r.ttl > 0 => {
+            let lower = target.to_ascii_lowercase();
+            if lower.ends_with(&suffix.to_ascii_lowercase()) {
+                Some(target[..target.len() - suffix.len()].to_string())
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }).collect();
+    instances.sort();
+    instances.dedup();
+    let mut found = Vec::new();
+    for instance in instances {
+        match assemble(&records, &instance, service) {
+            Some(complete) if complete.addr.is_some() => found.push(complete),
+            // ask for whatever the PTR response left out
+            _ => match resolve(&instance, service, timeout) {
+                Ok(complete) => found.push(complete),
+                Err(e) => log::info!("couldn't resolve {}.{}: {:?}", instance, service, e),
+            },
+        }
+    }
+    Ok(found)
+}
+
+/// Looks up a `.local` host name, returning its addresses and their TTLs.
+pub(crate) fn resolve_host(name: &str, timeout: Duration) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
+    let records = query(&[(name.to_string(), TYPE_A)], timeout,
+        |records| records.iter().any(|r| same_name(&r.name, name) && r.rtype == TYPE_A))?;
+    let map: HashMap<IpAddr, u32> = records.iter().filter_map(|r| match r.data {
+        RData::A(addr) if same_name(&r.name, name) => Some((IpAddr::V4(addr), r.ttl)),
+        RData::Aaaa(addr) if same_name(&r.name, name) => Some((IpAddr::V6(addr), r.ttl)),
+        _ => None,
+    }).collect();
+    if map.is_empty() {
+        Err(DnsResponseCode::NameError)
+    } else {
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 23);
+    const OTHER: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 99);
+
+    fn printer() -> Service {
+        Service {
+            instance: "Printer".to_string(),
+            service: "_ipp._tcp".to_string(),
+            port: 631,
+            txt: vec!["rp=ipp".to_string()],
+        }
+    }
+    /// A state with everything already claimed
+    fn claimed() -> MdnsState {
+        let mut state = MdnsState::new("precursor-ab12".to_string());
+        state.services.push(printer());
+        state
+    }
+    fn ask(name: &str, rtype: u16, known: &[Record]) -> Packet {
+        let question = Question { name: name.to_string(), rtype, unicast: false };
+        Packet::parse(&encode(0x1234, 0, &[question], known, &[], &[])).unwrap()
+    }
+    fn answer(state: &MdnsState, query: &Packet, legacy: bool) -> Option<Packet> {
+        state.respond(query, Some(ADDR), legacy).map(|response| Packet::parse(&response).unwrap())
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let records = vec![
+            Record::new("host.local", 120, true, RData::A(ADDR)),
+            Record::new("host.local", 120, true, RData::Aaaa(Ipv6Addr::LOCALHOST)),
+            Record::new("_ipp._tcp.local", 4500, false, RData::Ptr("Printer._ipp._tcp.local".to_string())),
+            Record::new("Printer._ipp._tcp.local", 120, true, RData::Srv { port: 631, target: "host.local".to_string() }),
+            Record::new("Printer._ipp._tcp.local", 4500, true, RData::Txt(vec!["rp=ipp".to_string(), "note=x".to_string()])),
+        ];
+        let questions = vec![Question { name: "host.local".to_string(), rtype: TYPE_ANY, unicast: true }];
+        let packet = Packet::parse(&encode(7, FLAGS_RESPONSE, &questions, &records[..2], &records[2..3], &records[3..])).unwrap();
+        assert_eq!(packet.id, 7);
+        assert!(packet.is_response());
+        assert_eq!(packet.questions.len(), 1);
+        assert_eq!(packet.questions[0].name, "host.local");
+        assert_eq!(packet.questions[0].rtype, TYPE_ANY);
+        assert!(packet.questions[0].unicast);
+        assert_eq!(packet.records.len(), records.len());
+        for (parsed, record) in packet.records.iter().zip(records.iter()) {
+            assert_eq!(parsed.name, record.name);
+            assert_eq!(parsed.rtype, record.rtype);
+            assert_eq!(parsed.ttl, record.ttl);
+            assert_eq!(parsed.flush, record.flush);
+            assert_eq!(parsed.data, record.data);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_truncated() {
+        let record = Record::new("host.local", 120, true, RData::A(ADDR));
+        let packet = encode(0, FLAGS_RESPONSE, &[], &[record], &[], &[]);
+        for len in 0..packet.len() {
+            assert!(Packet::parse(&packet[..len]).is_none(), "parsed a packet cut to {} bytes", len);
+        }
+        // a record claiming more RDATA than there is
+        let mut long = packet.clone();
+        let rdlen_at = packet.len() - 6;
+        long[rdlen_at..rdlen_at + 2].copy_from_slice(&8u16.to_be_bytes());
+        assert!(Packet::parse(&long).is_none());
+    }
+
+    #[test]
+    fn respond_to_host_query() {
+        let state = claimed();
+        let response = answer(&state, &ask("PRECURSOR-AB12.local", TYPE_A, &[]), false).unwrap();
+        assert!(response.is_response());
+        assert_eq!(response.id, 0);
+        assert!(response.questions.is_empty());
+        assert_eq!(response.records.len(), 1);
+        assert_eq!(response.records[0].data, RData::A(ADDR));
+        assert_eq!(response.records[0].ttl, HOST_TTL);
+        assert!(response.records[0].flush);
+        // nothing to say about other names, or without an address
+        assert!(answer(&state, &ask("someone-else.local", TYPE_A, &[]), false).is_none());
+        assert!(state.respond(&ask("precursor-ab12.local", TYPE_A, &[]), None, false).is_none());
+    }
+
+    #[test]
+    fn respond_to_service_query() {
+        let state = claimed();
+        let response = answer(&state, &ask("_ipp._tcp.local", TYPE_PTR, &[]), false).unwrap();
+        assert_eq!(response.records[0].data, RData::Ptr("Printer._ipp._tcp.local".to_string()));
+        let additional: Vec<&RData> = response.records[1..].iter().map(|r| &r.data).collect();
+        assert!(additional.contains(&&RData::Srv { port: 631, target: "precursor-ab12.local".to_string() }));
+        assert!(additional.contains(&&RData::Txt(vec!["rp=ipp".to_string()])));
+        assert!(additional.contains(&&RData::A(ADDR)));
+
+        let response = answer(&state, &ask(SERVICES_META, TYPE_PTR, &[]), false).unwrap();
+        assert_eq!(response.records.len(), 1);
+        assert_eq!(response.records[0].data, RData::Ptr("_ipp._tcp.local".to_string()));
+
+        let response = answer(&state, &ask("printer._ipp._tcp.local", TYPE_SRV, &[]), false).unwrap();
+        assert_eq!(response.records[0].rtype, TYPE_SRV);
+        assert!(response.records[1..].iter().all(|r| r.rtype != TYPE_TXT));
+    }
+
+    #[test]
+    fn respond_suppresses_known_answers() {
+        let state = claimed();
+        let ptr = |ttl| Record::new("_ipp._tcp.local", ttl, false, RData::Ptr("Printer._ipp._tcp.local".to_string()));
+        assert!(answer(&state, &ask("_ipp._tcp.local", TYPE_PTR, &[ptr(SERVICE_TTL)]), false).is_none());
+        // a known answer with less than half its TTL left is answered anyway
+        assert!(answer(&state, &ask("_ipp._tcp.local", TYPE_PTR, &[ptr(SERVICE_TTL / 2 - 1)]), false).is_some());
+    }
+
+    #[test]
+    fn respond_to_legacy_query() {
+        let state = claimed();
+        let response = answer(&state, &ask("_ipp._tcp.local", TYPE_PTR, &[]), true).unwrap();
+        assert_eq!(response.id, 0x1234);
+        assert_eq!(response.questions.len(), 1);
+        assert!(response.records.iter().all(|r| r.ttl <= LEGACY_TTL && !r.flush));
+    }
+
+    #[test]
+    fn probe_then_announce() {
+        let mut state = MdnsState::new("precursor-ab12".to_string());
+        state.add_service(printer());
+        state.claim_all();
+        let start = Instant::now();
+        // nothing is sent until there's an address
+        assert!(state.probe_step(None, start).is_none());
+        // nor answered while probing
+        assert!(answer(&state, &ask("precursor-ab12.local", TYPE_A, &[]), false).is_none());
+        assert!(answer(&state, &ask("_ipp._tcp.local", TYPE_PTR, &[]), false).is_none());
+        let mut now = start;
+        for _ in 0..PROBE_COUNT {
+            let probe = Packet::parse(&state.probe_step(Some(ADDR), now).unwrap()).unwrap();
+            assert!(!probe.is_response());
+            assert_eq!(probe.questions.len(), 2);
+            assert!(probe.questions.iter().all(|q| q.rtype == TYPE_ANY && q.unicast));
+            assert!(probe.records.iter().any(|r| r.data == RData::A(ADDR)));
+            assert!(probe.records.iter().any(|r| r.rtype == TYPE_SRV));
+            // not before the interval is up
+            assert!(state.probe_step(Some(ADDR), now).is_none());
+            now += Duration::from_millis(PROBE_INTERVAL_MS);
+        }
+        assert!(state.pending.is_empty());
+        assert!(state.probe_step(Some(ADDR), now).is_none());
+        assert_eq!(state.pending.len(), 2);
+        assert!(answer(&state, &ask("precursor-ab12.local", TYPE_A, &[]), false).is_some());
+        assert!(answer(&state, &ask("_ipp._tcp.local", TYPE_PTR, &[]), false).is_some());
+    }
+
+    #[test]
+    fn conflict_while_probing_renames() {
+        let mut state = MdnsState::new("precursor-ab12".to_string());
+        state.add_service(printer());
+        state.claim_all();
+        let now = Instant::now();
+        state.probe_step(Some(ADDR), now).unwrap();
+        let theirs = Record::new("precursor-ab12.local", HOST_TTL, true, RData::A(OTHER));
+        let response = Packet::parse(&encode(0, FLAGS_RESPONSE, &[], &[theirs], &[], &[])).unwrap();
+        state.check_conflicts(&response, Some(ADDR));
+        assert_eq!(state.hostname, "precursor-ab12-2");
+        // probing starts over under the new name, for the services too
+        assert!(state.probes.iter().all(|p| p.sent == 0));
+        assert!(state.probing("precursor-ab12-2.local"));
+        assert!(state.probing("Printer._ipp._tcp.local"));
+        assert!(!state.probing("precursor-ab12.local"));
+    }
+
+    #[test]
+    fn conflict_after_claiming_renames() {
+        let mut state = claimed();
+        let srv = |port| Record::new("Printer._ipp._tcp.local", 120, true,
+            RData::Srv { port, target: "precursor-ab12.local".to_string() });
+        // the same records aren't a conflict, nor are goodbyes
+        let same = Packet::parse(&encode(0, FLAGS_RESPONSE, &[], &[srv(631)], &[], &[])).unwrap();
+        state.check_conflicts(&same, Some(ADDR));
+        let mut goodbye = srv(80);
+        goodbye.ttl = 0;
+        let goodbye = Packet::parse(&encode(0, FLAGS_RESPONSE, &[], &[goodbye], &[], &[])).unwrap();
+        state.check_conflicts(&goodbye, Some(ADDR));
+        assert!(state.probes.is_empty());
+        let other = Packet::parse(&encode(0, FLAGS_RESPONSE, &[], &[srv(80)], &[], &[])).unwrap();
+        state.check_conflicts(&other, Some(ADDR));
+        assert_eq!(state.services[0].instance, "Printer-2");
+        assert!(state.probing("Printer-2._ipp._tcp.local"));
+        assert_eq!(state.hostname, "precursor-ab12");
+    }
+
+    #[test]
+    fn simultaneous_probe_tiebreak() {
+        let probe_from = |addr| {
+            let question = Question { name: "precursor-ab12.local".to_string(), rtype: TYPE_ANY, unicast: true };
+            let record = Record::new("precursor-ab12.local", HOST_TTL, true, RData::A(addr));
+            Packet::parse(&encode(0, 0, &[question], &[], &[record], &[])).unwrap()
+        };
+        let mut state = MdnsState::new("precursor-ab12".to_string());
+        state.claim_all();
+        let now = Instant::now();
+        state.probe_step(Some(ADDR), now).unwrap();
+        // a lower address loses to us, so probing carries on
+        state.check_tiebreak(&probe_from(Ipv4Addr::new(192, 168, 1, 2)), Some(ADDR), now);
+        assert_eq!(state.probes[0].sent, 1);
+        // a higher one wins, so we wait a second and probe again from the start, under the same name
+        state.check_tiebreak(&probe_from(OTHER), Some(ADDR), now);
+        assert_eq!(state.probes[0].sent, 0);
+        assert_eq!(state.hostname, "precursor-ab12");
+        let resume = now + Duration::from_millis(PROBE_DEFER_MS);
+        assert!(state.probe_step(Some(ADDR), resume - Duration::from_millis(1)).is_none());
+        assert!(state.probe_step(Some(ADDR), resume).is_some());
+    }
+
+    #[test]
+    fn next_labels() {
+        assert_eq!(next_label("precursor"), "precursor-2");
+        assert_eq!(next_label("precursor-2"), "precursor-3");
+        assert_eq!(next_label("precursor-ab12"), "precursor-ab12-2");
+        assert_eq!(next_label("precursor-1234"), "precursor-1234-2");
+        assert_eq!(next_label("-9"), "-9-2");
+        let long = "x".repeat(MDNS_LABEL_LEN);
+        assert_eq!(next_label(&long).len(), MDNS_LABEL_LEN);
+        assert!(next_label(&long).ends_with("-2"));
+    }
+
+    #[test]
+    fn default_hostnames() {
+        assert_eq!(default_hostname(None), "precursor");
+        assert_eq!(default_hostname(Some([0x02, 0, 0, 0, 0xab, 0x12])), "precursor-ab12");
+    }
+}
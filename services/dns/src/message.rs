@@ -0,0 +1,206 @@
+//! # DNS messages
+//!
+//! Building queries and parsing responses, per RFC 1035. Names in responses may be compressed;
+//! names written here never are.
+
+use crate::api::*;
+
+use std::convert::TryInto;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub(crate) const TYPE_A: u16 = 1;
+pub(crate) const TYPE_CNAME: u16 = 5;
+pub(crate) const TYPE_SOA: u16 = 6;
+pub(crate) const TYPE_TXT: u16 = 16;
+pub(crate) const TYPE_AAAA: u16 = 28;
+pub(crate) const TYPE_SRV: u16 = 33;
+pub(crate) const CLASS_IN: u16 = 1;
+
+const FLAG_QR: u16 = 0x8000; // Response
+const FLAG_TC: u16 = 0x0200; // Truncated
+const FLAG_RD: u16 = 0x0100; // Recursion desired
+
+pub(crate) fn read_u16(d: &[u8], index: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(d.get(index..index + 2)?.try_into().ok()?))
+}
+pub(crate) fn read_u32(d: &[u8], index: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(d.get(index..index + 4)?.try_into().ok()?))
+}
+
+/// Reads a name, following compression pointers, and returns it along with the index just past it.
+pub(crate) fn read_name(d: &[u8], start: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::<String>::new();
+    let mut index = start;
+    let mut end = None;
+    let mut jumps = 0;
+    loop {
+        let len = *d.get(index)? as usize;
+        if len == 0 {
+            index += 1;
+            break;
+        }
+        if len & 0xc0 == 0xc0 {
+            if end.is_none() {
+                end = Some(index + 2);
+            }
+            // a loop of pointers would otherwise never end
+            jumps += 1;
+            if jumps > 32 {
+                return None;
+            }
+            index = (read_u16(d, index)? & 0x3fff) as usize;
+            continue;
+        }
+        let label = d.get(index + 1..index + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        index += 1 + len;
+    }
+    Some((labels.join("."), end.unwrap_or(index)))
+}
+
+pub(crate) fn write_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let len = label.len().min(MDNS_LABEL_LEN);
+        out.push(len as u8);
+        out.extend_from_slice(&label.as_bytes()[..len]);
+    }
+    out.push(0);
+}
+
+pub(crate) fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Txt(Vec<String>),
+    Srv { priority: u16, weight: u16, port: u16, target: String },
+    /// Only the field needed for negative caching is kept
+    Soa { minimum: u32 },
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Record {
+    pub name: String,
+    pub rtype: u16,
+    pub ttl: u32,
+    pub data: RData,
+}
+
+pub(crate) struct Message {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: Vec<(String, u16)>,
+    pub answers: Vec<Record>,
+    pub authority: Vec<Record>,
+}
+
+impl Message {
+    pub fn query(qname: &str, qtype: u16, id: u16) -> Vec<u8> {
+        let mut datagram = Vec::new();
+        datagram.extend_from_slice(&id.to_be_bytes()); // Transaction ID
+        datagram.extend_from_slice(&FLAG_RD.to_be_bytes()); // Flags
+        datagram.extend_from_slice(&1u16.to_be_bytes()); // Questions
+        datagram.extend_from_slice(&[0; 6]); // Answer + Authority + Additional
+        write_name(&mut datagram, qname);
+        datagram.extend_from_slice(&qtype.to_be_bytes());
+        datagram.extend_from_slice(&CLASS_IN.to_be_bytes());
+        datagram
+    }
+
+    /// The ID and flags of a message, which can be read even when the rest was truncated
+    pub fn header(datagram: &[u8]) -> Option<(u16, u16)> {
+        Some((read_u16(datagram, 0)?, read_u16(datagram, 2)?))
+    }
+
+    pub fn parse(d: &[u8]) -> Result<Message, DnsResponseCode> {
+        Self::parse_inner(d).ok_or(DnsResponseCode::FormatError)
+    }
+    fn parse_inner(d: &[u8]) -> Option<Message> {
+        let (id, flags) = Self::header(d)?;
+        let qdcount = read_u16(d, 4)?;
+        let ancount = read_u16(d, 6)?;
+        let nscount = read_u16(d, 8)?;
+        let mut index = 12;
+        let mut questions = Vec::new();
+        for _ in 0..qdcount {
+            let (name, next) = read_name(d, index)?;
+            questions.push((name, read_u16(d, next)?));
+            index = next + 4;
+        }
+        let mut answers = Vec::new();
+        let mut authority = Vec::new();
+        // the additional section isn't needed, so parsing stops before it
+        for n in 0..ancount as usize + nscount as usize {
+            let (name, next) = read_name(d, index)?;
+            let rtype = read_u16(d, next)?;
+            let ttl = read_u32(d, next + 4)?;
+            let rdlen = read_u16(d, next + 8)? as usize;
+            let start = next + 10;
+            let rdata = d.get(start..start + rdlen)?;
+            let data = match rtype {
+                TYPE_A if rdlen == 4 => RData::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+                TYPE_AAAA if rdlen == 16 => {
+                    let octets: [u8; 16] = rdata.try_into().ok()?;
+                    RData::Aaaa(Ipv6Addr::from(octets))
+                }
+                TYPE_CNAME => RData::Cname(read_name(d, start)?.0),
+                TYPE_TXT => RData::Txt(unpack_txt(rdata)),
+                TYPE_SRV => RData::Srv {
+                    priority: read_u16(d, start)?,
+                    weight: read_u16(d, start + 2)?,
+                    port: read_u16(d, start + 4)?,
+                    target: read_name(d, start + 6)?.0,
+                },
+                TYPE_SOA => {
+                    // MNAME and RNAME, then SERIAL, REFRESH, RETRY and EXPIRE come before MINIMUM
+                    let (_, rname) = read_name(d, start)?;
+                    let (_, fields) = read_name(d, rname)?;
+                    RData::Soa { minimum: read_u32(d, fields + 16)? }
+                }
+                _ => RData::Other,
+            };
+            let record = Record { name, rtype, ttl, data };
+            if n < ancount as usize {
+                answers.push(record);
+            } else {
+                authority.push(record);
+            }
+            index = start + rdlen;
+        }
+        Some(Message { id, flags, questions, answers, authority })
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags & FLAG_QR != 0
+    }
+
+    pub fn is_truncated(flags: u16) -> bool {
+        flags & FLAG_TC != 0
+    }
+
+    pub fn rcode(&self) -> DnsResponseCode {
+        match self.flags & 0xF {
+            0 => DnsResponseCode::NoError,
+            1 => DnsResponseCode::FormatError,
+            2 => DnsResponseCode::ServerFailure,
+            3 => DnsResponseCode::NameError,
+            4 => DnsResponseCode::NotImplemented,
+            5 => DnsResponseCode::Refused,
+            _ => DnsResponseCode::UnknownError,
+        }
+    }
+
+    /// How long a negative answer may be cached, per RFC 2308: the lesser of the TTL of the SOA in
+    /// the authority section, and its MINIMUM field. None if there is no SOA.
+    pub fn negative_ttl(&self) -> Option<u32> {
+        self.authority.iter().find_map(|r| match r.data {
+            RData::Soa { minimum } => Some(r.ttl.min(minimum)),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,311 @@
+//! Lookups against a stub DNS server on the loopback interface. Each stub answers UDP and TCP
+//! queries on the same port with canned responses, so these run in the hosted build.
+
+use crate::api::*;
+use crate::lookup::{lookup, Outcome};
+use crate::message::*;
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const FLAG_TC: u16 = 0x0200;
+const RCODE_NXDOMAIN: u16 = 3;
+
+struct Rr {
+    name: &'static str,
+    rtype: u16,
+    ttl: u32,
+    rdata: Vec<u8>,
+}
+
+fn name(name: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_name(&mut out, name);
+    out
+}
+fn a(name: &'static str, ttl: u32, addr: [u8; 4]) -> Rr {
+    Rr { name, rtype: TYPE_A, ttl, rdata: addr.to_vec() }
+}
+fn cname(alias: &'static str, ttl: u32, target: &str) -> Rr {
+    Rr { name: alias, rtype: TYPE_CNAME, ttl, rdata: self::name(target) }
+}
+fn soa(zone: &'static str, ttl: u32, minimum: u32) -> Rr {
+    let mut rdata = name("ns1.example.com");
+    rdata.extend_from_slice(&name("hostmaster.example.com"));
+    for field in [2022070100u32, 7200, 3600, 1209600, minimum].iter() {
+        rdata.extend_from_slice(&field.to_be_bytes());
+    }
+    Rr { name: zone, rtype: TYPE_SOA, ttl, rdata }
+}
+
+/// Builds a response to `query` with the given RCODE and sections, echoing its question
+fn response(query: &Message, flags: u16, answers: &[Rr], authority: &[Rr]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&query.id.to_be_bytes());
+    out.extend_from_slice(&(0x8180 | flags).to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(authority.len() as u16).to_be_bytes());
+    out.extend_from_slice(&0u16.to_be_bytes());
+    let (qname, qtype) = &query.questions[0];
+    out.extend_from_slice(&name(qname));
+    out.extend_from_slice(&qtype.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    for rr in answers.iter().chain(authority.iter()) {
+        out.extend_from_slice(&name(rr.name));
+        out.extend_from_slice(&rr.rtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&rr.ttl.to_be_bytes());
+        out.extend_from_slice(&(rr.rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(&rr.rdata);
+    }
+    out
+}
+
+/// Starts a stub server, which answers queries over UDP with `udp`, and over TCP with `tcp`.
+fn stub<U, T>(udp: U, tcp: T) -> SocketAddr
+where
+    U: Fn(&Message) -> Vec<u8> + Send + 'static,
+    T: Fn(&Message) -> Vec<u8> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let socket = UdpSocket::bind(addr).unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; DNS_PKT_MAX_LEN];
+        while let Ok((len, from)) = socket.recv_from(&mut buf) {
+            let query = Message::parse(&buf[..len]).unwrap();
+            socket.send_to(&udp(&query), from).unwrap();
+        }
+    });
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut query = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut query).unwrap();
+            let response = tcp(&Message::parse(&query).unwrap());
+            stream.write_all(&(response.len() as u16).to_be_bytes()).unwrap();
+            stream.write_all(&response).unwrap();
+        }
+    });
+    addr
+}
+fn no_tcp(_: &Message) -> Vec<u8> {
+    panic!("unexpected TCP query");
+}
+
+fn run(server: SocketAddr, name: &str, qtype: u16) -> Result<Outcome, DnsResponseCode> {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let id = AtomicU16::new(0x1234);
+    lookup(&socket, server, name, qtype, &mut || id.fetch_add(1, Ordering::SeqCst), Duration::from_millis(2_000))
+}
+fn found(outcome: Result<Outcome, DnsResponseCode>) -> Vec<(RData, u32)> {
+    match outcome {
+        Ok(Outcome::Found(records)) => records,
+        other => panic!("expected records, got {:?}", other),
+    }
+}
+
+#[test]
+fn plain_a_record() {
+    let server = stub(|q| response(q, 0, &[a("betrusted.io", 3600, [185, 199, 111, 153])], &[]), no_tcp);
+    let records = found(run(server, "betrusted.io", TYPE_A));
+    assert_eq!(records, vec![(RData::A(Ipv4Addr::new(185, 199, 111, 153)), 3600)]);
+}
+
+#[test]
+fn compressed_answer_names() {
+    // the answer's name is a pointer back to the question, as most servers send it
+    let server = stub(|q| {
+        let mut out = response(q, 0, &[], &[]);
+        out[7] = 1; // ANCOUNT
+        out.extend_from_slice(&[0xc0, 0x0c]);
+        out.extend_from_slice(&TYPE_A.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&300u32.to_be_bytes());
+        out.extend_from_slice(&4u16.to_be_bytes());
+        out.extend_from_slice(&[10, 0, 0, 1]);
+        out
+    }, no_tcp);
+    let records = found(run(server, "example.com", TYPE_A));
+    assert_eq!(records, vec![(RData::A(Ipv4Addr::new(10, 0, 0, 1)), 300)]);
+}
+
+#[test]
+fn truncated_response_retries_over_tcp() {
+    let tcp_queries = Arc::new(AtomicUsize::new(0));
+    let server = stub(|q| response(q, FLAG_TC, &[], &[]), {
+        let tcp_queries = tcp_queries.clone();
+        move |q| {
+            tcp_queries.fetch_add(1, Ordering::SeqCst);
+            response(q, 0, &[a("big.example.com", 60, [10, 0, 0, 1]), a("big.example.com", 60, [10, 0, 0, 2])], &[])
+        }
+    });
+    let records = found(run(server, "big.example.com", TYPE_A));
+    assert_eq!(records.len(), 2);
+    assert_eq!(tcp_queries.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn cname_chain_in_one_response() {
+    let server = stub(|q| response(q, 0, &[
+        cname("www.example.com", 600, "cdn.example.net"),
+        cname("cdn.example.net", 120, "edge.example.net"),
+        a("edge.example.net", 300, [10, 1, 2, 3]),
+    ], &[]), no_tcp);
+    let records = found(run(server, "www.example.com", TYPE_A));
+    // the answer can't be cached for longer than any link in the chain
+    assert_eq!(records, vec![(RData::A(Ipv4Addr::new(10, 1, 2, 3)), 120)]);
+}
+
+#[test]
+fn cname_chain_across_queries() {
+    let server = stub(|q| {
+        if same_name(&q.questions[0].0, "www.example.com") {
+            response(q, 0, &[cname("www.example.com", 600, "host.example.org")], &[])
+        } else {
+            response(q, 0, &[a("host.example.org", 900, [10, 9, 8, 7])], &[])
+        }
+    }, no_tcp);
+    let records = found(run(server, "www.example.com", TYPE_A));
+    assert_eq!(records, vec![(RData::A(Ipv4Addr::new(10, 9, 8, 7)), 600)]);
+}
+
+#[test]
+fn cname_loop_gives_up() {
+    let server = stub(|q| {
+        if same_name(&q.questions[0].0, "a.example.com") {
+            response(q, 0, &[cname("a.example.com", 60, "b.example.com")], &[])
+        } else {
+            response(q, 0, &[cname("b.example.com", 60, "a.example.com")], &[])
+        }
+    }, no_tcp);
+    assert_eq!(run(server, "a.example.com", TYPE_A).unwrap_err(), DnsResponseCode::ServerFailure);
+}
+
+#[test]
+fn nxdomain_is_cacheable_for_soa_minimum() {
+    let server = stub(|q| response(q, RCODE_NXDOMAIN, &[], &[soa("example.com", 3600, 300)]), no_tcp);
+    match run(server, "missing.example.com", TYPE_A) {
+        Ok(Outcome::Negative(DnsResponseCode::NameError, Some(300))) => (),
+        other => panic!("expected a negative answer with a TTL of 300, got {:?}", other),
+    }
+}
+
+#[test]
+fn nodata_is_cacheable_for_soa_ttl() {
+    let server = stub(|q| response(q, 0, &[], &[soa("example.com", 60, 86400)]), no_tcp);
+    match run(server, "example.com", TYPE_TXT) {
+        Ok(Outcome::Negative(DnsResponseCode::NoError, Some(60))) => (),
+        other => panic!("expected a negative answer with a TTL of 60, got {:?}", other),
+    }
+}
+
+#[test]
+fn nxdomain_without_soa_is_not_cacheable() {
+    let server = stub(|q| response(q, RCODE_NXDOMAIN, &[], &[]), no_tcp);
+    match run(server, "missing.example.com", TYPE_A) {
+        Ok(Outcome::Negative(DnsResponseCode::NameError, None)) => (),
+        other => panic!("expected an uncacheable negative answer, got {:?}", other),
+    }
+}
+
+#[test]
+fn txt_and_srv_records() {
+    let server = stub(|q| {
+        if q.questions[0].1 == TYPE_TXT {
+            response(q, 0, &[Rr {
+                name: "example.com",
+                rtype: TYPE_TXT,
+                ttl: 60,
+                rdata: pack_txt(&["v=spf1 -all".to_string(), "hello".to_string()]),
+            }], &[])
+        } else {
+            let mut rdata = vec![0, 10, 0, 5, 0x14, 0x66];
+            rdata.extend_from_slice(&name("xmpp.example.com"));
+            response(q, 0, &[Rr { name: "_xmpp-client._tcp.example.com", rtype: TYPE_SRV, ttl: 60, rdata }], &[])
+        }
+    }, no_tcp);
+    let txt = found(run(server, "example.com", TYPE_TXT));
+    assert_eq!(txt, vec![(RData::Txt(vec!["v=spf1 -all".to_string(), "hello".to_string()]), 60)]);
+    let srv = found(run(server, "_xmpp-client._tcp.example.com", TYPE_SRV));
+    assert_eq!(srv, vec![(RData::Srv { priority: 10, weight: 5, port: 5222, target: "xmpp.example.com".to_string() }, 60)]);
+}
+
+#[test]
+fn mismatched_ids_are_ignored() {
+    // a response that doesn't answer the query sent must not be taken as the answer
+    let server = stub(|q| {
+        let mut stale = response(q, 0, &[a("example.com", 60, [6, 6, 6, 6])], &[]);
+        stale[0] ^= 0xff;
+        stale
+    }, no_tcp);
+    assert_eq!(run(server, "example.com", TYPE_A).unwrap_err(), DnsResponseCode::NetworkError);
+}
+
+#[test]
+fn records_pack_round_trip() {
+    let records = vec![
+        DnsRecord::Txt(vec!["a=1".to_string(), "b=2".to_string()]),
+        DnsRecord::Srv { priority: 1, weight: 2, port: 443, target: "example.com".to_string() },
+    ];
+    let mut packed = Vec::new();
+    for record in records.iter() {
+        record.pack(&mut packed);
+    }
+    let mut index = 0;
+    let unpacked: Vec<DnsRecord> = (0..records.len()).map(|_| DnsRecord::unpack(&packed, &mut index).unwrap()).collect();
+    assert_eq!(unpacked, records);
+    assert_eq!(index, packed.len());
+}
+
+#[test]
+fn service_instances_pack_round_trip() {
+    let instances = vec![
+        ServiceInstance {
+            instance: "Printer".to_string(),
+            service: "_ipp._tcp".to_string(),
+            host: "printer.local".to_string(),
+            port: 631,
+            addr: Some(Ipv4Addr::new(192, 168, 1, 23).into()),
+            txt: vec!["rp=ipp".to_string(), "note=upstairs".to_string()],
+        },
+        ServiceInstance {
+            instance: "Kitchen".to_string(),
+            service: "_http._tcp".to_string(),
+            host: "kitchen.local".to_string(),
+            port: 80,
+            addr: Some(std::net::Ipv6Addr::LOCALHOST.into()),
+            txt: vec![],
+        },
+        ServiceInstance {
+            instance: "Unresolved".to_string(),
+            service: "_http._tcp".to_string(),
+            host: "nowhere.local".to_string(),
+            port: 8080,
+            addr: None,
+            txt: vec!["path=/".to_string()],
+        },
+    ];
+    let mut packed = Vec::new();
+    for instance in instances.iter() {
+        instance.pack(&mut packed);
+    }
+    let mut index = 0;
+    let unpacked: Vec<ServiceInstance> =
+        (0..instances.len()).map(|_| ServiceInstance::unpack(&packed, &mut index).unwrap()).collect();
+    assert_eq!(unpacked, instances);
+    assert_eq!(index, packed.len());
+    // running out part way through an instance is caught
+    for len in 0..packed.len() {
+        let mut index = 0;
+        let complete = std::iter::from_fn(|| ServiceInstance::unpack(&packed[..len], &mut index)).count();
+        assert!(complete < instances.len());
+    }
+}
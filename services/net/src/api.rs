@@ -61,7 +61,7 @@ pub(crate) enum Opcode {
     DnsHookAllClear = 13,
     DnsUnhookAll = 14,
 
-    /// Ping stack
+    /// Ping stack. Checked against the caller's rule as a connection to port 0 of the host.
     Ping = 15,
     PingSetTtl = 16,
     PingGetTtl = 17,
@@ -364,11 +364,316 @@ pub(crate) enum Opcode {
 
     LoopbackRx = 47,
 
+    /// BlockingScalar call to join an IPv4 multicast group on the interface, so that UDP
+    /// sockets bound to an unspecified address receive datagrams sent to the group.
+    /// arg1: the group address, as a big-endian u32. Returns 0 on success, or a `NetError`;
+    /// `AccessDenied` if the caller's rule doesn't let it open sockets.
+    JoinMulticastV4 = 48,
+
+    /// BlockingScalar call to leave an IPv4 multicast group. Memberships are counted, so the
+    /// interface stays in the group until every join has been matched by a leave.
+    /// arg1: the group address, as a big-endian u32. Returns 0 on success, or a `NetError`.
+    LeaveMulticastV4 = 49,
+
+    /// Starts capturing the frames that pass through the device, with the filter and limits in a
+    /// `CaptureConfig`. Any earlier capture is discarded. Only the processes in `POLICY_ADMINS`
+    /// may use this or any of the other capture opcodes.
+    CaptureStart = 50,
+
+    /// BlockingScalar call to stop capturing. What was captured is kept until the next start.
+    /// Returns the number of frames held, and 0 or a `NetError`.
+    CaptureStop = 51,
+
+    /// Fills in a `CaptureStatus`
+    CaptureStatus = 52,
+
+    /// Writes the capture as a pcap file to a key in `CAPTURE_DICT`, named in a `CaptureSave`
+    CaptureSave = 53,
+
+    /// BlockingScalar call to write the capture as a pcap file to the debug log, in hex. Returns
+    /// the length of the file, and 0 or a `NetError`, once it's all been logged. The logging is
+    /// done by a thread of its own, so the network carries on meanwhile. Each line is
+    /// `pcap <offset> <hex>`; on the host,
+    /// `grep -o 'pcap [0-9a-f]* [0-9a-f]*' log.txt | cut -d' ' -f3 | xxd -r -p > capture.pcap`
+    /// turns the log back into the file.
+    CaptureDump = 54,
+
+    /// Sets the rule for a process, given in a `PolicyUpdate`. Only the processes in
+    /// `POLICY_ADMINS` may change rules.
+    PolicySet = 55,
+
+    /// Removes the rule for a process, named in a `PolicyUpdate`, so the default applies to it
+    PolicyRemove = 56,
+
+    /// Fills in a `PolicyList`, starting from its `start`th rule
+    PolicyList = 57,
+
+    /// Fills in a `UsageList`, starting from its `start`th process
+    UsageList = 58,
+
+    /// BlockingScalar call to zero the usage counters. Returns 0, or a `NetError`.
+    UsageReset = 59,
+
+    /// Asks whether a process may use the network, or reach a host, given in a `PolicyCheck`.
+    /// Only the processes in `POLICY_PROXIES`, which use the network on behalf of others, may ask.
+    PolicyCheck = 60,
+
     // do not use any numbers higher than 0x8000 as that is reserved for the nonblocking flag
 }
 #[allow(dead_code)]
 pub(crate) const NONBLOCKING_FLAG:usize = 0x8000; // when set, modulates a Peek or Read to be nonblocking
 
+/// Captures saved to the PDDB go in this dictionary, as pcap files
+pub const CAPTURE_DICT: &str = "net.pcap";
+/// Default and upper bound on the frame data a capture holds
+pub const CAPTURE_MAX_BYTES: u32 = 256 * 1024;
+/// Longest key name a capture can be saved under, the same as the PDDB's limit
+pub(crate) const CAPTURE_KEY_LEN: usize = 95;
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum CaptureProtocol {
+    Any,
+    Arp,
+    /// ICMP and ICMPv6
+    Icmp,
+    Tcp,
+    Udp,
+}
+
+/// Which frames a capture keeps. A `port` matches TCP and UDP packets with that source or
+/// destination port.
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub struct CaptureFilter {
+    pub protocol: CaptureProtocol,
+    pub port: Option<u16>,
+}
+impl Default for CaptureFilter {
+    fn default() -> Self {
+        CaptureFilter { protocol: CaptureProtocol::Any, port: None }
+    }
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct CaptureConfig {
+    pub filter: CaptureFilter,
+    /// Bytes kept of each frame; longer frames are cut short
+    pub snaplen: u32,
+    /// Bytes of frame data held before the oldest frames are dropped
+    pub max_bytes: u32,
+    /// On return, `Ok`, or `AccessDenied`
+    pub result: NetMemResponse,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default)]
+pub struct CaptureStatus {
+    pub capturing: bool,
+    /// Frames held
+    pub frames: u32,
+    /// Bytes of frame data held
+    pub bytes: u32,
+    /// Frames dropped to make room for newer ones
+    pub dropped: u32,
+    /// Set if the caller may not see the capture, in which case the rest is empty
+    pub(crate) denied: bool,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct CaptureSave {
+    pub key: xous_ipc::String<CAPTURE_KEY_LEN>,
+    /// Length of the pcap file written, on return
+    pub len: u32,
+    /// False on return if the PDDB isn't mounted, or couldn't be written
+    pub ok: bool,
+    /// Set on return if the caller may not save captures
+    pub denied: bool,
+}
+
+/// Per-process network rules are kept in this dictionary, one key per process name
+pub const POLICY_DICT: &str = "net.policy";
+/// The rule under this name applies to every process that doesn't have one of its own
+pub const POLICY_DEFAULT: &str = "*";
+/// Longest process name the kernel reports
+pub const POLICY_NAME_LEN: usize = 64;
+/// Hosts a single rule can list
+pub const POLICY_MAX_HOSTS: usize = 8;
+pub(crate) const POLICY_LIST_LEN: usize = 16;
+pub(crate) const USAGE_LIST_LEN: usize = 32;
+
+/// What a process may do on the network
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum NetAccess {
+    /// No sockets at all
+    Deny,
+    /// Only the hosts listed in the rule
+    Listed,
+    /// Hosts on the local network, and the hosts listed in the rule
+    Lan,
+    /// Any host
+    Any,
+}
+
+/// A range of addresses, and a port on them. A `prefix` of 0 matches every address, IPv4 or
+/// IPv6, and a `port` of 0 matches every port.
+#[derive(Archive, Serialize, Deserialize, Copy, Clone)]
+pub struct HostRule {
+    pub addr: NetIpAddr,
+    pub prefix: u8,
+    pub port: u16,
+}
+impl fmt::Display for HostRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefix == 0 {
+            write!(f, "*")?;
+        } else {
+            match IpAddr::from(self.addr) {
+                IpAddr::V4(addr) => write!(f, "{}", addr)?,
+                IpAddr::V6(addr) => write!(f, "[{}]", addr)?,
+            }
+            if self.prefix < self.max_prefix() {
+                write!(f, "/{}", self.prefix)?;
+            }
+        }
+        if self.port != 0 {
+            write!(f, ":{}", self.port)?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Debug for HostRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+impl HostRule {
+    pub(crate) fn max_prefix(&self) -> u8 {
+        match self.addr {
+            NetIpAddr::Ipv4(_) => 32,
+            NetIpAddr::Ipv6(_) => 128,
+        }
+    }
+}
+/// Parses the form that `Display` writes: `addr[/prefix][:port]`, with IPv6 addresses in
+/// brackets if there's a port, and `*` for any address.
+impl std::str::FromStr for HostRule {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+            let (addr, rest) = rest.split_once(']').ok_or(())?;
+            match rest.split_once(':') {
+                Some((prefix, port)) => (format!("{}{}", addr, prefix), Some(port)),
+                None => (format!("{}{}", addr, rest), None),
+            }
+        } else if s.matches(':').count() > 1 {
+            // a bare IPv6 address, which can't be followed by a port
+            (s.to_string(), None)
+        } else {
+            match s.split_once(':') {
+                Some((host, port)) => (host.to_string(), Some(port)),
+                None => (s.to_string(), None),
+            }
+        };
+        let port = match port {
+            Some(port) => port.parse::<u16>().or(Err(()))?,
+            None => 0,
+        };
+        if host == "*" {
+            return Ok(HostRule { addr: NetIpAddr::Ipv4([0; 4]), prefix: 0, port });
+        }
+        let (addr, prefix) = match host.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().or(Err(()))?)),
+            None => (host.as_str(), None),
+        };
+        let addr = NetIpAddr::from(addr.parse::<IpAddr>().or(Err(()))?);
+        let mut rule = HostRule { addr, prefix: 0, port };
+        rule.prefix = prefix.unwrap_or(rule.max_prefix());
+        if rule.prefix > rule.max_prefix() {
+            return Err(());
+        }
+        Ok(rule)
+    }
+}
+
+/// The rule for one process
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub struct NetPolicy {
+    /// The name of the process, or `POLICY_DEFAULT`
+    pub process: xous_ipc::String<POLICY_NAME_LEN>,
+    pub access: NetAccess,
+    pub hosts: [Option<HostRule>; POLICY_MAX_HOSTS],
+}
+impl NetPolicy {
+    pub fn new(process: &str, access: NetAccess, hosts: &[HostRule]) -> Option<Self> {
+        if process.is_empty() || process.len() > POLICY_NAME_LEN || hosts.len() > POLICY_MAX_HOSTS {
+            return None;
+        }
+        let mut policy = NetPolicy {
+            process: xous_ipc::String::<POLICY_NAME_LEN>::from_str(process),
+            access,
+            hosts: [None; POLICY_MAX_HOSTS],
+        };
+        for (dest, host) in policy.hosts.iter_mut().zip(hosts.iter()) {
+            *dest = Some(*host);
+        }
+        Some(policy)
+    }
+    pub fn hosts(&self) -> impl Iterator<Item = &HostRule> {
+        self.hosts.iter().filter_map(|h| h.as_ref())
+    }
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct PolicyUpdate {
+    pub policy: NetPolicy,
+    /// Filled in on return
+    pub result: NetMemResponse,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct PolicyCheck {
+    /// The process the check is for
+    pub pid: u8,
+    /// The host it would reach, or None to ask whether it may use the network at all
+    pub remote: Option<NetSocketAddr>,
+    /// Filled in on return
+    pub result: NetMemResponse,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default)]
+pub(crate) struct PolicyList {
+    /// Index of the first rule to return
+    pub start: u16,
+    /// Rules there are in all, on return
+    pub total: u16,
+    pub list: [Option<NetPolicy>; POLICY_LIST_LEN],
+}
+
+/// What one process has done on the network since boot, or since the counters were reset
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub struct NetUsage {
+    pub process: xous_ipc::String<POLICY_NAME_LEN>,
+    /// The PID it was last seen with
+    pub pid: u8,
+    /// Sockets it has open now
+    pub sockets: u32,
+    /// TCP connections made and accepted, and UDP sockets bound
+    pub connections: u32,
+    /// Requests refused by its rule
+    pub denied: u32,
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+    /// Milliseconds since it last opened a socket or moved data
+    pub idle_ms: u64,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default)]
+pub(crate) struct UsageList {
+    /// Index of the first process to return
+    pub start: u16,
+    /// Processes there are in all, on return
+    pub total: u16,
+    pub list: [Option<NetUsage>; USAGE_LIST_LEN],
+}
+
 #[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default)]
 pub(crate) struct SsidList {
     /// IPC memory structures have to pre-allocate all their memory, but are always allocated in 4096-byte chunks.
@@ -412,7 +717,7 @@ pub enum NetError {
     // Ok = 0,
     Unaddressable = 1,
     SocketInUse = 2,
-    // AccessDenied = 3,
+    AccessDenied = 3,
     Invalid = 4,
     // Finished = 5,
     LibraryError = 6,
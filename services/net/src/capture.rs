@@ -0,0 +1,239 @@
+//! # Packet capture
+//!
+//! An opt-in record of the Ethernet frames that pass through `NetPhy`, in both directions, so we
+//! can see what smoltcp actually sends and receives. Frames that pass the filter are kept in a ring
+//! buffer with a bound on the bytes it holds; once it's full, the oldest frames make room for the
+//! newest. Capture is off until started, and costs one uncontended lock per frame while it's off.
+//! A capture holds every process's traffic, so only the processes in `POLICY_ADMINS` may start,
+//! stop, read or save one.
+//!
+//! A capture is exported as a classic pcap file (link type Ethernet), which Wireshark and tcpdump
+//! read directly. Timestamps count from boot, as that's the only clock the net service has.
+
+use crate::api::*;
+
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetFrame, EthernetProtocol, IpProtocol, Ipv4Packet, Ipv6Packet, TcpPacket, UdpPacket};
+use std::collections::VecDeque;
+
+/// pcap global header: magic (microsecond timestamps), version 2.4, GMT offset, timestamp accuracy,
+/// snap length, and link type 1 (Ethernet). The snap length is filled in when exporting.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+
+struct Frame {
+    timestamp: Instant,
+    /// Length of the frame on the wire, which may be more than was kept
+    orig_len: u32,
+    data: Vec<u8>,
+}
+
+pub(crate) struct Capture {
+    capturing: bool,
+    filter: CaptureFilter,
+    snaplen: usize,
+    max_bytes: usize,
+    frames: VecDeque<Frame>,
+    /// Bytes of frame data held, not counting pcap headers
+    bytes: usize,
+    /// Frames pushed out of the ring to make room for newer ones
+    dropped: u32,
+}
+
+impl Capture {
+    pub(crate) fn new() -> Self {
+        Capture {
+            capturing: false,
+            filter: CaptureFilter::default(),
+            snaplen: 0,
+            max_bytes: 0,
+            frames: VecDeque::new(),
+            bytes: 0,
+            dropped: 0,
+        }
+    }
+    /// Starts a new capture, discarding the previous one.
+    pub(crate) fn start(&mut self, filter: CaptureFilter, snaplen: usize, max_bytes: usize) {
+        self.frames.clear();
+        self.bytes = 0;
+        self.dropped = 0;
+        self.filter = filter;
+        self.snaplen = snaplen;
+        self.max_bytes = max_bytes;
+        self.capturing = true;
+    }
+    /// Stops capturing. What was captured is kept until the next `start()`.
+    pub(crate) fn stop(&mut self) {
+        self.capturing = false;
+    }
+    pub(crate) fn status(&self) -> CaptureStatus {
+        CaptureStatus {
+            capturing: self.capturing,
+            frames: self.frames.len() as u32,
+            bytes: self.bytes as u32,
+            dropped: self.dropped,
+            denied: false,
+        }
+    }
+
+    pub(crate) fn record(&mut self, timestamp: Instant, frame: &[u8]) {
+        if !self.capturing || !self.filter.matches(frame) {
+            return;
+        }
+        let keep = frame.len().min(self.snaplen);
+        if keep > self.max_bytes {
+            self.dropped += 1;
+            return;
+        }
+        while self.bytes + keep > self.max_bytes {
+            match self.frames.pop_front() {
+                Some(oldest) => {
+                    self.bytes -= oldest.data.len();
+                    self.dropped += 1;
+                }
+                None => break,
+            }
+        }
+        self.bytes += keep;
+        self.frames.push_back(Frame {
+            timestamp,
+            orig_len: frame.len() as u32,
+            data: frame[..keep].to_vec(),
+        });
+    }
+
+    /// The capture as a pcap file
+    pub(crate) fn to_pcap(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(PCAP_HEADER_LEN + self.bytes + self.frames.len() * PCAP_RECORD_HEADER_LEN);
+        out.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&4u16.to_le_bytes());
+        out.extend_from_slice(&0i32.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&(self.snaplen as u32).to_le_bytes());
+        out.extend_from_slice(&PCAP_LINKTYPE_ETHERNET.to_le_bytes());
+        for frame in self.frames.iter() {
+            let micros = frame.timestamp.total_micros().max(0) as u64;
+            out.extend_from_slice(&((micros / 1_000_000) as u32).to_le_bytes());
+            out.extend_from_slice(&((micros % 1_000_000) as u32).to_le_bytes());
+            out.extend_from_slice(&(frame.data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&frame.orig_len.to_le_bytes());
+            out.extend_from_slice(&frame.data);
+        }
+        out
+    }
+}
+
+impl CaptureFilter {
+    /// Checks an Ethernet frame against the filter. Frames that can't be parsed as far as the
+    /// filter needs are only kept by a filter that doesn't look at them.
+    pub(crate) fn matches(&self, frame: &[u8]) -> bool {
+        if self.protocol == CaptureProtocol::Any && self.port.is_none() {
+            return true;
+        }
+        let frame = match EthernetFrame::new_checked(frame) {
+            Ok(frame) => frame,
+            Err(_) => return false,
+        };
+        let (protocol, payload) = match frame.ethertype() {
+            EthernetProtocol::Arp => return self.protocol == CaptureProtocol::Arp && self.port.is_none(),
+            EthernetProtocol::Ipv4 => match Ipv4Packet::new_checked(frame.payload()) {
+                Ok(packet) => (packet.next_header(), packet.payload()),
+                Err(_) => return false,
+            },
+            // extension headers aren't followed, so packets that have them won't match
+            EthernetProtocol::Ipv6 => match Ipv6Packet::new_checked(frame.payload()) {
+                Ok(packet) => (packet.next_header(), packet.payload()),
+                Err(_) => return false,
+            },
+            _ => return false,
+        };
+        let ports = match protocol {
+            IpProtocol::Tcp => TcpPacket::new_checked(payload).ok().map(|p| (p.src_port(), p.dst_port())),
+            IpProtocol::Udp => UdpPacket::new_checked(payload).ok().map(|p| (p.src_port(), p.dst_port())),
+            _ => None,
+        };
+        let protocol_matches = match self.protocol {
+            CaptureProtocol::Any => true,
+            CaptureProtocol::Arp => false,
+            CaptureProtocol::Icmp => protocol == IpProtocol::Icmp || protocol == IpProtocol::Icmpv6,
+            CaptureProtocol::Tcp => protocol == IpProtocol::Tcp,
+            CaptureProtocol::Udp => protocol == IpProtocol::Udp,
+        };
+        let port_matches = match self.port {
+            None => true,
+            Some(port) => ports.map_or(false, |(src, dst)| src == port || dst == port),
+        };
+        protocol_matches && port_matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An Ethernet frame holding an IPv4 UDP datagram with `payload` bytes of zeroes
+    fn udp_frame(src_port: u16, dst_port: u16, payload: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; 14 + 20 + 8 + payload];
+        frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        frame[14] = 0x45;
+        frame[16..18].copy_from_slice(&((20 + 8 + payload) as u16).to_be_bytes());
+        frame[22] = 64;
+        frame[23] = 17;
+        frame[34..36].copy_from_slice(&src_port.to_be_bytes());
+        frame[36..38].copy_from_slice(&dst_port.to_be_bytes());
+        frame[38..40].copy_from_slice(&((8 + payload) as u16).to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn filter_by_protocol_and_port() {
+        let dns = udp_frame(49152, 53, 10);
+        let any = CaptureFilter::default();
+        assert!(any.matches(&dns));
+        assert!(CaptureFilter { protocol: CaptureProtocol::Udp, port: Some(53) }.matches(&dns));
+        assert!(CaptureFilter { protocol: CaptureProtocol::Any, port: Some(49152) }.matches(&dns));
+        assert!(!CaptureFilter { protocol: CaptureProtocol::Tcp, port: None }.matches(&dns));
+        assert!(!CaptureFilter { protocol: CaptureProtocol::Udp, port: Some(5353) }.matches(&dns));
+        assert!(!CaptureFilter { protocol: CaptureProtocol::Udp, port: None }.matches(&[0u8; 10]));
+    }
+
+    #[test]
+    fn ring_drops_oldest() {
+        let mut capture = Capture::new();
+        let frame = udp_frame(1, 2, 58); // 100 bytes
+        capture.record(Instant::from_millis(0), &frame);
+        assert_eq!(capture.status().frames, 0, "nothing is kept before the capture starts");
+
+        capture.start(CaptureFilter::default(), 1514, 250);
+        for ms in 0..4 {
+            capture.record(Instant::from_millis(ms), &frame);
+        }
+        let status = capture.status();
+        assert_eq!((status.frames, status.bytes, status.dropped), (2, 200, 2));
+        assert_eq!(capture.frames[0].timestamp, Instant::from_millis(2));
+
+        capture.stop();
+        capture.record(Instant::from_millis(5), &frame);
+        assert_eq!(capture.status().frames, 2);
+    }
+
+    #[test]
+    fn pcap_layout() {
+        let mut capture = Capture::new();
+        capture.start(CaptureFilter::default(), 64, 1024);
+        capture.record(Instant::from_millis(1_500), &udp_frame(1, 2, 58));
+        let pcap = capture.to_pcap();
+        assert_eq!(pcap.len(), PCAP_HEADER_LEN + PCAP_RECORD_HEADER_LEN + 64);
+        assert_eq!(&pcap[0..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(&pcap[16..20], &64u32.to_le_bytes());
+        assert_eq!(&pcap[20..24], &1u32.to_le_bytes());
+        let record = &pcap[PCAP_HEADER_LEN..];
+        assert_eq!(&record[0..4], &1u32.to_le_bytes());
+        assert_eq!(&record[4..8], &500_000u32.to_le_bytes());
+        assert_eq!(&record[8..12], &64u32.to_le_bytes());
+        assert_eq!(&record[12..16], &100u32.to_le_bytes());
+    }
+}
@@ -12,6 +12,7 @@ use smoltcp::{
 };
 
 use crate::{MAC_ADDRESS_LSB, MAC_ADDRESS_MSB};
+use crate::capture::Capture;
 use core::sync::atomic::Ordering;
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
@@ -24,10 +25,12 @@ pub struct NetPhy {
     loopback_conn: xous::CID,
     // tracks the length (and count) of the loopback packets pending
     loopback_pending: Arc::<Mutex::<VecDeque<u16>>>,
+    // frames in both directions are offered to the capture, which keeps them only while it's running
+    capture: Arc::<Mutex::<Capture>>,
 }
 
 impl<'a> NetPhy {
-    pub fn new(xns: &xous_names::XousNames, loopback_conn: xous::CID) -> NetPhy {
+    pub fn new(xns: &xous_names::XousNames, loopback_conn: xous::CID, capture: Arc::<Mutex::<Capture>>) -> NetPhy {
         NetPhy {
             rx_buffer: [0; NET_MTU],
             tx_buffer: [0; NET_MTU],
@@ -35,6 +38,7 @@ impl<'a> NetPhy {
             rx_avail: None,
             loopback_conn,
             loopback_pending: Arc::new(Mutex::new(VecDeque::new())),
+            capture,
         }
     }
     // returns None if there was a slot to put the availability into
@@ -58,14 +62,14 @@ impl<'a> phy::Device<'a> for NetPhy {
             // loopback takes precedence
             self.com.wlan_fetch_loopback_packet(&mut self.rx_buffer[..rx_len as usize]).expect("Couldn't call wlan_fetch_packet in device adapter");
 
-            Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len as usize]},
-            NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com, loopback_conn: self.loopback_conn, loopback_count: self.loopback_pending.clone()}))
+            Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len as usize], capture: &self.capture},
+            NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com, loopback_conn: self.loopback_conn, loopback_count: self.loopback_pending.clone(), capture: &self.capture}))
         } else {
             if let Some(rx_len) = self.rx_avail.take() {
                 self.com.wlan_fetch_packet(&mut self.rx_buffer[..rx_len as usize]).expect("Couldn't call wlan_fetch_packet in device adapter");
 
-                Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len as usize]},
-                NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com, loopback_conn: self.loopback_conn, loopback_count: self.loopback_pending.clone()}))
+                Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len as usize], capture: &self.capture},
+                NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com, loopback_conn: self.loopback_conn, loopback_count: self.loopback_pending.clone(), capture: &self.capture}))
             } else {
                 None
             }
@@ -73,7 +77,7 @@ impl<'a> phy::Device<'a> for NetPhy {
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        Some(NetPhyTxToken{buf: &mut self.tx_buffer[..], com: &self.com, loopback_conn: self.loopback_conn, loopback_count: self.loopback_pending.clone()})
+        Some(NetPhyTxToken{buf: &mut self.tx_buffer[..], com: &self.com, loopback_conn: self.loopback_conn, loopback_count: self.loopback_pending.clone(), capture: &self.capture})
     }
 
     fn capabilities(&self) -> DeviceCapabilities {
@@ -87,12 +91,14 @@ impl<'a> phy::Device<'a> for NetPhy {
 
 pub struct NetPhyRxToken<'a> {
     buf: &'a mut [u8],
+    capture: &'a Mutex<Capture>,
 }
 
 impl<'a, 'c> phy::RxToken for NetPhyRxToken<'a> {
-    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> Result<R>
+    fn consume<R, F>(mut self, timestamp: Instant, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
+        self.capture.lock().unwrap().record(timestamp, &self.buf);
         let result = f(&mut self.buf);
         //log::info!("rx: {:x?}", self.buf);
         result
@@ -104,6 +110,7 @@ pub struct NetPhyTxToken<'a> {
     com: &'a Com,
     loopback_conn: xous::CID,
     loopback_count: Arc::<Mutex::<VecDeque<u16>>>,
+    capture: &'a Mutex<Capture>,
 }
 impl <'a> NetPhyTxToken<'a> {
     /// Initiates the Rx side of things to read out the loopback packet that was queued
@@ -152,11 +159,14 @@ impl <'a> NetPhyTxToken<'a> {
 }
 
 impl<'a> phy::TxToken for NetPhyTxToken<'a> {
-    fn consume<R, F>(mut self, _timestamp: Instant, len: usize, f: F) -> Result<R>
+    fn consume<R, F>(mut self, timestamp: Instant, len: usize, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
         let result = f(&mut self.buf[..len]);
         //log::info!("txlen: {}", len);
+        if result.is_ok() {
+            self.capture.lock().unwrap().record(timestamp, &self.buf[..len]);
+        }
 
         {
             // this is a hack to make loopbacks work on smoltcp. Work-around taken from Redox, but tracking this issue as well:
@@ -72,6 +72,169 @@ impl NetManager {
             None
         }
     }
+    /// Joins an IPv4 multicast group, so that UDP sockets bound to an unspecified address
+    /// receive datagrams sent to it. Each join should be matched by a `leave_multicast_v4`.
+    /// Fails with `AccessDenied` if the caller's rule doesn't let it open sockets.
+    pub fn join_multicast_v4(&self, group: std::net::Ipv4Addr) -> Result<(), xous::Error> {
+        self.multicast_op(Opcode::JoinMulticastV4, group)
+    }
+    pub fn leave_multicast_v4(&self, group: std::net::Ipv4Addr) -> Result<(), xous::Error> {
+        self.multicast_op(Opcode::LeaveMulticastV4, group)
+    }
+    fn multicast_op(&self, op: Opcode, group: std::net::Ipv4Addr) -> Result<(), xous::Error> {
+        match send_message(self.netconn.conn(),
+            Message::new_blocking_scalar(op.to_usize().unwrap(), u32::from(group) as usize, 0, 0, 0)
+        )? {
+            xous::Result::Scalar1(0) => Ok(()),
+            xous::Result::Scalar1(code) if code == NetError::AccessDenied as usize => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Starts capturing the frames that pass through the device, keeping those that match
+    /// `filter`. Each frame is cut to `snaplen` bytes, and once `max_bytes` of frames are held,
+    /// the oldest are dropped to make room; `max_bytes` is capped at `CAPTURE_MAX_BYTES`. Any
+    /// earlier capture is discarded.
+    ///
+    /// Only the processes in `POLICY_ADMINS` may capture; the capture calls return
+    /// `AccessDenied` to anyone else.
+    pub fn capture_start(&self, filter: CaptureFilter, snaplen: u32, max_bytes: u32) -> Result<(), xous::Error> {
+        let config = CaptureConfig { filter, snaplen, max_bytes, result: NetMemResponse::LibraryError };
+        let mut buf = Buffer::into_buf(config).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::CaptureStart.to_u32().unwrap())?;
+        match buf.to_original::<CaptureConfig, _>().or(Err(xous::Error::InternalError))?.result {
+            NetMemResponse::Ok => Ok(()),
+            NetMemResponse::AccessDenied => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Stops capturing, and returns the number of frames held. They're kept until the next start.
+    pub fn capture_stop(&self) -> Result<u32, xous::Error> {
+        match send_message(self.netconn.conn(),
+            Message::new_blocking_scalar(Opcode::CaptureStop.to_usize().unwrap(), 0, 0, 0, 0)
+        )? {
+            xous::Result::Scalar2(frames, 0) => Ok(frames as u32),
+            xous::Result::Scalar2(_, _) => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    pub fn capture_status(&self) -> Result<CaptureStatus, xous::Error> {
+        let mut buf = Buffer::into_buf(CaptureStatus::default()).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::CaptureStatus.to_u32().unwrap())?;
+        let status = buf.to_original::<CaptureStatus, _>().or(Err(xous::Error::InternalError))?;
+        if status.denied {
+            Err(xous::Error::AccessDenied)
+        } else {
+            Ok(status)
+        }
+    }
+    /// Saves the capture as a pcap file to `key` in `CAPTURE_DICT`, and returns its length.
+    /// Fails with `UseBeforeInit` if the PDDB isn't mounted, or couldn't be written.
+    pub fn capture_save(&self, key: &str) -> Result<usize, xous::Error> {
+        if key.len() > CAPTURE_KEY_LEN {
+            return Err(xous::Error::InvalidString);
+        }
+        let save = CaptureSave {
+            key: xous_ipc::String::<CAPTURE_KEY_LEN>::from_str(key),
+            len: 0,
+            ok: false,
+            denied: false,
+        };
+        let mut buf = Buffer::into_buf(save).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::CaptureSave.to_u32().unwrap())?;
+        let result = buf.to_original::<CaptureSave, _>().or(Err(xous::Error::InternalError))?;
+        if result.denied {
+            Err(xous::Error::AccessDenied)
+        } else if result.ok {
+            Ok(result.len as usize)
+        } else {
+            Err(xous::Error::UseBeforeInit)
+        }
+    }
+    /// Writes the capture as a pcap file to the debug log, in hex, and returns its length. See
+    /// the `CaptureDump` opcode for turning the log back into a file.
+    pub fn capture_dump(&self) -> Result<usize, xous::Error> {
+        match send_message(self.netconn.conn(),
+            Message::new_blocking_scalar(Opcode::CaptureDump.to_usize().unwrap(), 0, 0, 0, 0)
+        )? {
+            xous::Result::Scalar2(len, 0) => Ok(len),
+            xous::Result::Scalar2(_, _) => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Sets the rule for `process`, by its name as `ps` shows it, or for `POLICY_DEFAULT`.
+    /// `hosts` are the hosts it may reach in addition to what `access` allows. Lookups and TLS
+    /// sessions go through `dns` and `tls`, which check the caller's rule as well as their own.
+    pub fn policy_set(&self, process: &str, access: NetAccess, hosts: &[HostRule]) -> Result<(), xous::Error> {
+        let policy = NetPolicy::new(process, access, hosts).ok_or(xous::Error::InvalidString)?;
+        self.policy_update(Opcode::PolicySet, policy)
+    }
+    /// Removes the rule for `process`, so the default rule applies to it.
+    pub fn policy_remove(&self, process: &str) -> Result<(), xous::Error> {
+        let policy = NetPolicy::new(process, NetAccess::Any, &[]).ok_or(xous::Error::InvalidString)?;
+        self.policy_update(Opcode::PolicyRemove, policy)
+    }
+    fn policy_update(&self, op: Opcode, policy: NetPolicy) -> Result<(), xous::Error> {
+        let update = PolicyUpdate { policy, result: NetMemResponse::LibraryError };
+        let mut buf = Buffer::into_buf(update).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), op.to_u32().unwrap())?;
+        match buf.to_original::<PolicyUpdate, _>().or(Err(xous::Error::InternalError))?.result {
+            NetMemResponse::Ok => Ok(()),
+            NetMemResponse::AccessDenied => Err(xous::Error::AccessDenied),
+            NetMemResponse::Invalid => Err(xous::Error::InvalidString),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Checks whether the rule for `pid` lets it reach `remote`, or if `remote` is None, use the
+    /// network at all. Services that use the network on behalf of `pid` call this first, as their
+    /// own sockets are checked against their own rules; only those in `POLICY_PROXIES` may.
+    pub fn policy_check(&self, pid: xous::PID, remote: Option<std::net::SocketAddr>) -> Result<(), xous::Error> {
+        let check = PolicyCheck { pid: pid.get(), remote: remote.map(NetSocketAddr::from), result: NetMemResponse::LibraryError };
+        let mut buf = Buffer::into_buf(check).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::PolicyCheck.to_u32().unwrap())?;
+        match buf.to_original::<PolicyCheck, _>().or(Err(xous::Error::InternalError))?.result {
+            NetMemResponse::Ok => Ok(()),
+            NetMemResponse::AccessDenied => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    pub fn policy_list(&self) -> Result<Vec<NetPolicy>, xous::Error> {
+        let mut rules = Vec::new();
+        loop {
+            let list = PolicyList { start: rules.len() as u16, ..Default::default() };
+            let mut buf = Buffer::into_buf(list).or(Err(xous::Error::InternalError))?;
+            buf.lend_mut(self.netconn.conn(), Opcode::PolicyList.to_u32().unwrap())?;
+            let list = buf.to_original::<PolicyList, _>().or(Err(xous::Error::InternalError))?;
+            let before = rules.len();
+            rules.extend(list.list.iter().filter_map(|r| *r));
+            if rules.len() >= list.total as usize || rules.len() == before {
+                return Ok(rules);
+            }
+        }
+    }
+    /// What each process has done on the network since boot, most recently active first
+    pub fn usage_list(&self) -> Result<Vec<NetUsage>, xous::Error> {
+        let mut usage = Vec::new();
+        loop {
+            let list = UsageList { start: usage.len() as u16, ..Default::default() };
+            let mut buf = Buffer::into_buf(list).or(Err(xous::Error::InternalError))?;
+            buf.lend_mut(self.netconn.conn(), Opcode::UsageList.to_u32().unwrap())?;
+            let list = buf.to_original::<UsageList, _>().or(Err(xous::Error::InternalError))?;
+            let before = usage.len();
+            usage.extend(list.list.iter().filter_map(|u| *u));
+            if usage.len() >= list.total as usize || usage.len() == before {
+                return Ok(usage);
+            }
+        }
+    }
+    pub fn usage_reset(&self) -> Result<(), xous::Error> {
+        match send_message(self.netconn.conn(),
+            Message::new_blocking_scalar(Opcode::UsageReset.to_usize().unwrap(), 0, 0, 0, 0)
+        )? {
+            xous::Result::Scalar1(0) => Ok(()),
+            xous::Result::Scalar1(_) => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
     pub fn reset(&self) {
         send_message(
             self.netconn.conn(),
@@ -17,6 +17,8 @@ use num_traits::*;
 
 mod connection_manager;
 mod device;
+mod capture;
+mod policy;
 
 #[cfg(test)]
 mod tests;
@@ -43,7 +45,7 @@ use smoltcp::socket::{
 };
 use smoltcp::iface::SocketHandle;
 use smoltcp::time::{Duration, Instant};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{channel, Sender};
 use std::thread;
 use std::cmp::Ordering as CmpOrdering;
@@ -231,6 +233,12 @@ fn main() -> ! {
     // Each process keeps track of its own sockets. These are kept in a Vec. When a handle
     // is destroyed, it is turned into a `None`.
     let mut process_sockets: HashMap<Option<xous::PID>, Vec<Option<SocketHandle>>> = HashMap::new();
+    // number of outstanding joins for each multicast group the interface is in
+    let mut multicast_groups = HashMap::<Ipv4Address, usize>::new();
+    // frames seen by the device, while a capture is running
+    let capture = Arc::new(Mutex::new(capture::Capture::new()));
+    // which processes may use the network, and what they've done with it
+    let mut policy = policy::Policy::new();
 
     // When a TCP client issues a Receive request, it will get placed here while the packet data
     // is being accumulated.
@@ -296,13 +304,14 @@ fn main() -> ! {
     log::debug!("My MAC address is: {:x?}", hw_config.mac);
     MAC_ADDRESS_LSB.store(u32::from_be_bytes(hw_config.mac[2..6].try_into().unwrap()), Ordering::SeqCst);
     MAC_ADDRESS_MSB.store(u16::from_be_bytes(hw_config.mac[0..2].try_into().unwrap()), Ordering::SeqCst);
-    let device = device::NetPhy::new(&xns, net_cid);
+    let device = device::NetPhy::new(&xns, net_cid, capture.clone());
     // needed by ICMP to determine if we should compute checksums
     let device_caps = device.capabilities();
     let medium = device.capabilities().medium;
     let mut builder = InterfaceBuilder::new(device, vec![])
         .ip_addrs(ip_addrs)
-        .routes(routes);
+        .routes(routes)
+        .ipv4_multicast_groups(BTreeMap::new());
     if medium == Medium::Ethernet {
         builder = builder
             .hardware_addr(EthernetAddress::from_bytes(&hw_config.mac).into())
@@ -422,10 +431,19 @@ fn main() -> ! {
         match op {
             Some(Opcode::Ping) => {
                 log::debug!("Ping");
+                let pid = msg.sender.pid();
                 let mut buf = unsafe {
                     Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
                 };
                 let mut pkt = buf.to_original::<NetPingPacket, _>().unwrap();
+                // ICMP has no ports, so a ping is checked as a connection to port 0
+                let local_addrs = iface.ip_addrs().to_vec();
+                let target = IpEndpoint::new(IpAddress::from(pkt.endpoint), 0);
+                if !policy.may_connect(pid, target, &local_addrs) {
+                    pkt.sent_ok = Some(false);
+                    buf.replace(pkt).expect("Xous couldn't issue response to Ping request");
+                    continue;
+                }
                 let socket = iface.get_socket::<IcmpSocket>(icmp_handle);
                 if socket.can_send() {
                     log::debug!("sending ping to {:?}", pkt.endpoint);
@@ -607,6 +625,7 @@ fn main() -> ! {
                     &mut iface,
                     &mut tcp_connect_waiting,
                     process_sockets.entry(pid).or_default(),
+                    &mut policy,
                 );
                 xous::try_send_message(
                     net_conn,
@@ -626,6 +645,7 @@ fn main() -> ! {
                     &mut iface,
                     &mut tcp_tx_waiting,
                     process_sockets.entry(pid).or_default(),
+                    &mut policy,
                 );
                 xous::try_send_message(
                     net_conn,
@@ -666,6 +686,7 @@ fn main() -> ! {
                     &mut tcp_rx_waiting,
                     process_sockets.entry(pid).or_default(),
                     nonblocking,
+                    &mut policy,
                 );
                 xous::try_send_message(
                     net_conn,
@@ -847,6 +868,7 @@ fn main() -> ! {
                     &mut iface,
                     process_sockets.entry(pid).or_default(),
                     &trng,
+                    &mut policy,
                 );
                 xous::try_send_message(
                     net_conn,
@@ -866,6 +888,7 @@ fn main() -> ! {
                     &mut tcp_accept_waiting,
                     &mut tcp_server_remote_close_poll,
                     process_sockets.entry(pid).or_default(),
+                    &mut policy,
                 );
                 xous::try_send_message(
                     net_conn,
@@ -1028,6 +1051,229 @@ fn main() -> ! {
                 };
             }
 
+            Some(Opcode::JoinMulticastV4) => {
+                if !msg.body.is_blocking() || msg.body.has_memory() {
+                    respond_with_error(msg, NetError::LibraryError);
+                    continue;
+                }
+                if !policy.may_open(msg.sender.pid()) {
+                    respond_with_error(msg, NetError::AccessDenied);
+                    continue;
+                }
+                let args = msg.body.scalar_message().unwrap();
+                let group = Ipv4Address::from_bytes(&(args.arg1 as u32).to_be_bytes());
+                if !group.is_multicast() {
+                    respond_with_error(msg, NetError::Invalid);
+                    continue;
+                }
+                let members = multicast_groups.entry(group).or_insert(0);
+                if *members == 0 {
+                    let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                    if let Err(e) = iface.join_multicast_group(group, timestamp) {
+                        log::warn!("couldn't join multicast group {}: {:?}", group, e);
+                        multicast_groups.remove(&group);
+                        respond_with_error(msg, NetError::LibraryError);
+                        continue;
+                    }
+                    log::info!("joined multicast group {}", group);
+                }
+                *members += 1;
+                xous::return_scalar(msg.sender, 0).ok();
+            }
+            Some(Opcode::LeaveMulticastV4) => {
+                if !msg.body.is_blocking() || msg.body.has_memory() {
+                    respond_with_error(msg, NetError::LibraryError);
+                    continue;
+                }
+                let args = msg.body.scalar_message().unwrap();
+                let group = Ipv4Address::from_bytes(&(args.arg1 as u32).to_be_bytes());
+                match multicast_groups.get_mut(&group) {
+                    Some(members) if *members > 1 => *members -= 1,
+                    Some(_) => {
+                        multicast_groups.remove(&group);
+                        let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                        iface.leave_multicast_group(group, timestamp).ok();
+                        log::info!("left multicast group {}", group);
+                    }
+                    None => {
+                        respond_with_error(msg, NetError::Invalid);
+                        continue;
+                    }
+                }
+                xous::return_scalar(msg.sender, 0).ok();
+            }
+
+            // captures hold everyone's traffic, so only the processes in `POLICY_ADMINS` may
+            // work with them
+            Some(Opcode::CaptureStart) => {
+                let pid = msg.sender.pid();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut config = buffer.to_original::<CaptureConfig, _>().unwrap();
+                if policy.is_admin(pid, "capture packets") {
+                    let snaplen = (config.snaplen as usize).min(NET_MTU).max(1);
+                    let max_bytes = (config.max_bytes.min(CAPTURE_MAX_BYTES) as usize).max(snaplen);
+                    log::info!("starting capture of {:?}, {} bytes per frame, {} bytes total", config.filter, snaplen, max_bytes);
+                    capture.lock().unwrap().start(config.filter, snaplen, max_bytes);
+                    config.result = NetMemResponse::Ok;
+                } else {
+                    config.result = NetMemResponse::AccessDenied;
+                }
+                buffer.replace(config).expect("couldn't return capture start result");
+            }
+            Some(Opcode::CaptureStop) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                if policy.is_admin(msg.sender.pid(), "capture packets") {
+                    let mut capture = capture.lock().unwrap();
+                    capture.stop();
+                    let status = capture.status();
+                    log::info!("capture stopped: {:?}", status);
+                    xous::return_scalar2(msg.sender, status.frames as usize, 0).ok();
+                } else {
+                    xous::return_scalar2(msg.sender, 0, NetError::AccessDenied as usize).ok();
+                }
+            }),
+            Some(Opcode::CaptureStatus) => {
+                let pid = msg.sender.pid();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let status = if policy.is_admin(pid, "capture packets") {
+                    capture.lock().unwrap().status()
+                } else {
+                    CaptureStatus { denied: true, ..Default::default() }
+                };
+                buffer.replace(status).expect("couldn't return capture status");
+            }
+            Some(Opcode::CaptureSave) => {
+                let pid = msg.sender.pid();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut save = buffer.to_original::<CaptureSave, _>().unwrap();
+                let key = save.key.as_str().unwrap_or("").to_owned();
+                save.ok = false;
+                save.len = 0;
+                save.denied = !policy.is_admin(pid, "capture packets");
+                if save.denied {
+                    buffer.replace(save).expect("couldn't return capture save result");
+                    continue;
+                }
+                // the network stalls while the file is written, so the PDDB is only touched on request
+                let pcap = capture.lock().unwrap().to_pcap();
+                save.len = pcap.len() as u32;
+                if key.is_empty() {
+                    log::warn!("capture can't be saved without a key name");
+                } else if !pddb::PddbMountPoller::new().is_mounted_nonblocking() {
+                    log::warn!("capture can't be saved, the PDDB isn't mounted");
+                } else {
+                    let pddb = pddb::Pddb::new();
+                    // remove any previous capture first, so a shorter one doesn't leave a tail behind
+                    pddb.delete_key(CAPTURE_DICT, &key, None).ok();
+                    match pddb.get(CAPTURE_DICT, &key, None, true, true, Some(pcap.len()), None::<fn()>) {
+                        Ok(mut file) => match std::io::Write::write_all(&mut file, &pcap) {
+                            Ok(_) => {
+                                pddb.sync().ok();
+                                log::info!("saved capture to {}:{}, {} bytes", CAPTURE_DICT, key, pcap.len());
+                                save.ok = true;
+                            }
+                            Err(e) => log::error!("couldn't write capture to {}:{}: {:?}", CAPTURE_DICT, key, e),
+                        },
+                        Err(e) => log::error!("couldn't open {}:{}: {:?}", CAPTURE_DICT, key, e),
+                    }
+                }
+                buffer.replace(save).expect("couldn't return capture save result");
+            }
+            Some(Opcode::CaptureDump) => {
+                if !msg.body.is_blocking() || msg.body.has_memory() {
+                    respond_with_error(msg, NetError::LibraryError);
+                    continue;
+                }
+                if !policy.is_admin(msg.sender.pid(), "capture packets") {
+                    xous::return_scalar2(msg.sender, 0, NetError::AccessDenied as usize).ok();
+                    continue;
+                }
+                // a full capture is thousands of lines, so they are logged from a thread of their own
+                // rather than holding up the network while the log server takes them
+                let pcap = capture.lock().unwrap().to_pcap();
+                thread::spawn(move || {
+                    // one line per 32 bytes, in the format described at the opcode
+                    log::info!("pcap begin, {} bytes", pcap.len());
+                    for (index, chunk) in pcap.chunks(32).enumerate() {
+                        let mut line = std::string::String::with_capacity(64);
+                        for b in chunk.iter() {
+                            line.push_str(&format!("{:02x}", b));
+                        }
+                        log::info!("pcap {:06x} {}", index * 32, line);
+                    }
+                    log::info!("pcap end");
+                    xous::return_scalar2(msg.sender, pcap.len(), 0).ok();
+                });
+            }
+
+            Some(Opcode::PolicySet) => {
+                let pid = msg.sender.pid();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut update = buffer.to_original::<PolicyUpdate, _>().unwrap();
+                update.result = if policy.is_admin(pid, "change network rules") {
+                    policy.set(&update.policy)
+                } else {
+                    NetMemResponse::AccessDenied
+                };
+                buffer.replace(update).expect("couldn't return policy update result");
+            }
+            Some(Opcode::PolicyRemove) => {
+                let pid = msg.sender.pid();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut update = buffer.to_original::<PolicyUpdate, _>().unwrap();
+                update.result = if policy.is_admin(pid, "change network rules") {
+                    policy.remove(update.policy.process.as_str().unwrap_or(""))
+                } else {
+                    NetMemResponse::AccessDenied
+                };
+                buffer.replace(update).expect("couldn't return policy update result");
+            }
+            Some(Opcode::PolicyCheck) => {
+                let pid = msg.sender.pid();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut check = buffer.to_original::<PolicyCheck, _>().unwrap();
+                let client = xous::PID::new(check.pid);
+                let allowed = if !policy.is_proxy(pid) || client.is_none() {
+                    false
+                } else if let Some(remote) = check.remote {
+                    let local_addrs = iface.ip_addrs().to_vec();
+                    policy.may_connect(client, IpEndpoint::new(IpAddress::from(remote.addr), remote.port), &local_addrs)
+                } else {
+                    policy.may_open(client)
+                };
+                check.result = if allowed { NetMemResponse::Ok } else { NetMemResponse::AccessDenied };
+                buffer.replace(check).expect("couldn't return policy check result");
+            }
+            Some(Opcode::PolicyList) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut list = buffer.to_original::<PolicyList, _>().unwrap();
+                let rules = policy.list();
+                list.total = rules.len() as u16;
+                list.list = Default::default();
+                for (dest, rule) in list.list.iter_mut().zip(rules.into_iter().skip(list.start as usize)) {
+                    *dest = Some(rule);
+                }
+                buffer.replace(list).expect("couldn't return policy list");
+            }
+            Some(Opcode::UsageList) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut list = buffer.to_original::<UsageList, _>().unwrap();
+                let usage = policy.usage(&process_sockets);
+                list.total = usage.len() as u16;
+                list.list = Default::default();
+                for (dest, usage) in list.list.iter_mut().zip(usage.into_iter().skip(list.start as usize)) {
+                    *dest = Some(usage);
+                }
+                buffer.replace(list).expect("couldn't return usage list");
+            }
+            Some(Opcode::UsageReset) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                if policy.is_admin(msg.sender.pid(), "reset usage counters") {
+                    policy.reset_usage();
+                    xous::return_scalar(msg.sender, 0).ok();
+                } else {
+                    xous::return_scalar(msg.sender, NetError::AccessDenied as usize).ok();
+                }
+            }),
+
             Some(Opcode::StdUdpBind) => {
                 log::debug!("StdUdpBind");
                 let pid = msg.sender.pid();
@@ -1035,6 +1281,7 @@ fn main() -> ! {
                     msg,
                     &mut iface,
                     process_sockets.entry(pid).or_default(),
+                    &mut policy,
                 );
             }
 
@@ -1047,6 +1294,7 @@ fn main() -> ! {
                     &mut iface,
                     &mut udp_rx_waiting,
                     process_sockets.entry(pid).or_default(),
+                    &mut policy,
                 );
             }
 
@@ -1057,6 +1305,7 @@ fn main() -> ! {
                     msg,
                     &mut iface,
                     process_sockets.entry(pid).or_default(),
+                    &mut policy,
                 );
                 xous::try_send_message(
                     net_conn,
@@ -1346,6 +1595,7 @@ fn main() -> ! {
 
                     log::debug!("tcp state is {:?}", socket.state());
                     if socket.state() == TcpState::Established {
+                        policy.count_connection(env.sender.pid());
                         respond_with_connected(env, fd, local_port, remote_port);
                     } else {
                         respond_with_error(env, NetError::TimedOut);
@@ -1416,6 +1666,7 @@ fn main() -> ! {
                             log::debug!("rxrcv of {}", count);
                             body.valid = xous::MemorySize::new(count);
                             body.offset = xous::MemoryAddress::new(1);
+                            policy.count_rx(env.sender.pid(), count);
                         }
                         Err(e) => {
                             log::debug!("unable to receive: {:?}", e);
@@ -1561,6 +1812,7 @@ fn main() -> ! {
                     };
 
                     log::trace!("sent {}", sent_octets);
+                    policy.count_tx(env.sender.pid(), sent_octets);
                     let response_data = body.buf.as_slice_mut::<u32>();
                     response_data[0] = 0;
                     response_data[1] = sent_octets as u32;
@@ -1568,6 +1820,7 @@ fn main() -> ! {
 
                 // this handles TCP std listeners
                 log::trace!("pump: tcp listen");
+                let local_addrs = iface.ip_addrs().to_vec();
                 for connection in tcp_accept_waiting.iter_mut() {
                     let ep: IpEndpoint;
                     let AcceptingSocket {
@@ -1579,6 +1832,11 @@ fn main() -> ! {
                         Some(s) => {
                             let socket = iface.get_socket::<TcpSocket>(s.handle);
                             if socket.is_active() {
+                                if !policy.may_reach(s.env.sender.pid(), socket.remote_endpoint(), &local_addrs) {
+                                    tcp_refuse(socket);
+                                    continue;
+                                }
+                                policy.count_connection(s.env.sender.pid());
                                 tcp_server_remote_close_poll.push(s.handle);
                                 ep = socket.remote_endpoint();
                                 connection.take().unwrap()
@@ -1650,6 +1908,7 @@ fn main() -> ! {
                         match socket.recv() {
                             Ok((data, endpoint)) => {
                                 log::debug!("netpump udp rx");
+                                policy.count_rx(msg.sender.pid(), data.len());
                                 udp_rx_success(
                                     // unwrap is safe here because the message was type-checked prior to insertion into the waiting queue
                                     msg.body.memory_message_mut().unwrap().buf.as_slice_mut(),
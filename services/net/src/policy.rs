@@ -0,0 +1,466 @@
+//! # Network policy
+//!
+//! Rules for what each process may do on the network, and counters of what each one has done.
+//! Rules are looked up by the name the kernel has for the process, so they follow the program
+//! rather than whatever PID it happens to get. A process without a rule of its own gets the
+//! `POLICY_DEFAULT` rule, and if there is none of those, may reach any host.
+//!
+//! A rule is checked when a socket is bound or listened on, when a multicast group is joined,
+//! when a TCP connection is made or accepted, for every UDP datagram sent, and for every ping.
+//! A ping is checked as a connection to port 0 of the host, so only host entries that allow any
+//! port match it. A bound UDP socket hears from any host, but can only answer the ones its rule
+//! allows. Changing a rule doesn't close connections already made.
+//!
+//! Services that use the network on behalf of other processes, `dns` for lookups and `tls` for
+//! TLS sessions, open their sockets under their own names, so their own rules are checked for
+//! those. Before doing anything for another process, they ask whether its rule allows it too:
+//! `dns` whether it may use the network at all, and `tls` whether it may reach the host. Only the
+//! processes in `POLICY_PROXIES` may ask about others.
+//!
+//! Rules are kept in the PDDB, in `POLICY_DICT`, and are read the first time a socket is opened
+//! after it is mounted. Until then, every process but those in `POLICY_BOOT` is denied, as
+//! there's no telling what the rules would allow; the credentials for joining a network are kept
+//! in the PDDB too, so there is seldom a network to use before then anyway. Counters are kept in
+//! memory, and start over at boot.
+
+use crate::api::*;
+
+use smoltcp::iface::SocketHandle;
+use smoltcp::wire::{IpAddress, IpCidr, IpEndpoint};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Instant;
+
+/// Processes that may change the rules, reset the counters, and capture packets
+const POLICY_ADMINS: [&str; 1] = ["shellchat"];
+/// Processes that may use the network before the rules have been read in. The resolver opens
+/// its sockets at boot, well before the PDDB is mounted.
+const POLICY_BOOT: [&str; 1] = ["dns"];
+/// Processes that use the network on behalf of others, and check their rules before doing so
+const POLICY_PROXIES: [&str; 2] = ["dns", "tls"];
+/// Applies to every other process until the rules have been read in
+static UNLOADED: Rule = Rule { access: NetAccess::Deny, hosts: Vec::new() };
+
+#[derive(Debug, Clone)]
+pub(crate) struct Rule {
+    pub access: NetAccess,
+    pub hosts: Vec<HostRule>,
+}
+impl Rule {
+    pub(crate) fn permits(&self, remote: &IpEndpoint, local: &[IpCidr]) -> bool {
+        match self.access {
+            NetAccess::Deny => false,
+            NetAccess::Listed => self.lists(remote),
+            NetAccess::Lan => is_lan(&remote.addr, local) || self.lists(remote),
+            NetAccess::Any => true,
+        }
+    }
+    fn lists(&self, remote: &IpEndpoint) -> bool {
+        self.hosts.iter().any(|host| host_matches(host, remote))
+    }
+
+    /// The access as a byte, then each host as its address (preceded by 4 or 6), prefix, and
+    /// port as a u16 LE
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![self.access as u8];
+        for host in self.hosts.iter() {
+            match host.addr {
+                NetIpAddr::Ipv4(octets) => {
+                    out.push(4);
+                    out.extend_from_slice(&octets);
+                }
+                NetIpAddr::Ipv6(octets) => {
+                    out.push(6);
+                    out.extend_from_slice(&octets);
+                }
+            }
+            out.push(host.prefix);
+            out.extend_from_slice(&host.port.to_le_bytes());
+        }
+        out
+    }
+    pub(crate) fn from_bytes(d: &[u8]) -> Option<Rule> {
+        let access = match *d.get(0)? {
+            0 => NetAccess::Deny,
+            1 => NetAccess::Listed,
+            2 => NetAccess::Lan,
+            3 => NetAccess::Any,
+            _ => return None,
+        };
+        let mut hosts = Vec::new();
+        let mut index = 1;
+        while index < d.len() {
+            let (addr, len) = match d[index] {
+                4 => (NetIpAddr::Ipv4(d.get(index + 1..index + 5)?.try_into().ok()?), 4),
+                6 => (NetIpAddr::Ipv6(d.get(index + 1..index + 17)?.try_into().ok()?), 16),
+                _ => return None,
+            };
+            index += 1 + len;
+            let prefix = *d.get(index)?;
+            let port = u16::from_le_bytes(d.get(index + 1..index + 3)?.try_into().ok()?);
+            index += 3;
+            let host = HostRule { addr, prefix, port };
+            if prefix > host.max_prefix() {
+                return None;
+            }
+            hosts.push(host);
+        }
+        Some(Rule { access, hosts })
+    }
+}
+
+fn host_matches(host: &HostRule, remote: &IpEndpoint) -> bool {
+    if host.port != 0 && host.port != remote.port {
+        return false;
+    }
+    if host.prefix == 0 {
+        return true;
+    }
+    let (network, addr): (&[u8], &[u8]) = match (&host.addr, &remote.addr) {
+        (NetIpAddr::Ipv4(network), IpAddress::Ipv4(addr)) => (network, addr.as_bytes()),
+        (NetIpAddr::Ipv6(network), IpAddress::Ipv6(addr)) => (network, addr.as_bytes()),
+        _ => return false,
+    };
+    let whole = host.prefix as usize / 8;
+    let bits = host.prefix % 8;
+    if network[..whole] != addr[..whole] {
+        return false;
+    }
+    bits == 0 || (network[whole] ^ addr[whole]) & (0xff << (8 - bits)) == 0
+}
+
+/// Whether `addr` is on the local network: on one of the interface's subnets, in a private,
+/// link-local or loopback range, or a multicast group that routers don't forward.
+pub(crate) fn is_lan(addr: &IpAddress, local: &[IpCidr]) -> bool {
+    // the interface holds an unspecified /0 until DHCP completes, which would match everything
+    if local.iter().any(|cidr| cidr.prefix_len() > 0 && cidr.contains_addr(addr)) {
+        return true;
+    }
+    match addr {
+        IpAddress::Ipv4(addr) => {
+            let addr = Ipv4Addr::from(addr.0);
+            addr.is_private()
+                || addr.is_loopback()
+                || addr.is_link_local()
+                || addr.is_broadcast()
+                || addr.octets()[..3] == [224, 0, 0]
+                || addr.octets()[0] == 239
+        }
+        IpAddress::Ipv6(addr) => {
+            let first = Ipv6Addr::from(addr.0).segments()[0];
+            addr.is_loopback()
+                || first & 0xffc0 == 0xfe80 // link-local
+                || first & 0xfe00 == 0xfc00 // unique local
+                || first & 0xff0f == 0xff02 // link-local multicast
+        }
+        _ => false,
+    }
+}
+
+/// The rule for `name` in `rules`, or the default one; None if the process may reach any host.
+fn rule_in<'a>(rules: Option<&'a BTreeMap<String, Rule>>, name: &str) -> Option<&'a Rule> {
+    match rules {
+        Some(rules) => rules.get(name).or_else(|| rules.get(POLICY_DEFAULT)),
+        None if POLICY_BOOT.contains(&name) => None,
+        None => Some(&UNLOADED),
+    }
+}
+
+#[derive(Default)]
+struct Usage {
+    pid: Option<xous::PID>,
+    connections: u32,
+    denied: u32,
+    tx_bytes: u64,
+    rx_bytes: u64,
+    last_used: Option<Instant>,
+}
+
+pub(crate) struct Policy {
+    pddb: pddb::Pddb,
+    /// None until the rules have been read from the PDDB
+    rules: Option<BTreeMap<String, Rule>>,
+    usage: BTreeMap<String, Usage>,
+    /// The name of each process seen opening a socket
+    names: HashMap<xous::PID, String>,
+}
+impl Policy {
+    pub(crate) fn new() -> Self {
+        Policy {
+            pddb: pddb::Pddb::new(),
+            rules: None,
+            usage: BTreeMap::new(),
+            names: HashMap::new(),
+        }
+    }
+
+    /// Reads the rules in, if they haven't been and the PDDB is mounted.
+    fn ensure_loaded(&mut self) -> bool {
+        if self.rules.is_some() {
+            return true;
+        }
+        if !pddb::PddbMountPoller::new().is_mounted_nonblocking() {
+            return false;
+        }
+        let mut rules = BTreeMap::new();
+        for name in self.pddb.list_keys(POLICY_DICT, None).unwrap_or_default() {
+            if let Ok(mut key) = self.pddb.get(POLICY_DICT, &name, None, false, false, None, None::<fn()>) {
+                let mut data = Vec::new();
+                match key.read_to_end(&mut data).ok().and_then(|_| Rule::from_bytes(&data)) {
+                    Some(rule) => {
+                        rules.insert(name, rule);
+                    }
+                    None => log::warn!("rule for {} in {} can't be read, ignoring it", name, POLICY_DICT),
+                }
+            }
+        }
+        log::info!("loaded {} network rules", rules.len());
+        self.rules = Some(rules);
+        true
+    }
+
+    /// Looks up the name of `pid`, and remembers it so what the process does with the socket it's
+    /// opening can be counted. The rules are read in at this point, if they can be.
+    fn identify(&mut self, pid: Option<xous::PID>) -> String {
+        self.ensure_loaded();
+        let pid = match pid {
+            Some(pid) => pid,
+            None => return "unknown".to_string(),
+        };
+        let mut name = [0u8; POLICY_NAME_LEN];
+        let name = match xous::process_name(pid, &mut name) {
+            Ok(len) if len > 0 && len <= name.len() => core::str::from_utf8(&name[..len]).ok().map(|s| s.to_owned()),
+            _ => None,
+        }.unwrap_or_else(|| format!("PID {}", pid));
+        self.names.insert(pid, name.clone());
+        name
+    }
+    fn name_of(&self, pid: Option<xous::PID>) -> String {
+        match pid {
+            Some(pid) => self.names.get(&pid).cloned().unwrap_or_else(|| format!("PID {}", pid)),
+            None => "unknown".to_string(),
+        }
+    }
+    fn rule_for(&self, name: &str) -> Option<&Rule> {
+        rule_in(self.rules.as_ref(), name)
+    }
+    fn used(&mut self, name: String, pid: Option<xous::PID>) -> &mut Usage {
+        let usage = self.usage.entry(name).or_default();
+        usage.pid = pid;
+        usage.last_used = Some(Instant::now());
+        usage
+    }
+    fn decide(&mut self, name: String, pid: Option<xous::PID>, allowed: bool, what: &dyn core::fmt::Display) -> bool {
+        if !allowed {
+            log::warn!("{} (PID {:?}) may not {}", name, pid, what);
+            self.used(name, pid).denied += 1;
+        }
+        allowed
+    }
+
+    /// Whether `pid` may open a socket to listen on, or to send UDP datagrams from
+    pub(crate) fn may_open(&mut self, pid: Option<xous::PID>) -> bool {
+        let name = self.identify(pid);
+        let allowed = self.rule_for(&name).map_or(true, |rule| rule.access != NetAccess::Deny);
+        self.decide(name, pid, allowed, &"use the network")
+    }
+    /// Whether `pid` may make a connection to `remote`
+    pub(crate) fn may_connect(&mut self, pid: Option<xous::PID>, remote: IpEndpoint, local: &[IpCidr]) -> bool {
+        let name = self.identify(pid);
+        let allowed = self.rule_for(&name).map_or(true, |rule| rule.permits(&remote, local));
+        self.decide(name, pid, allowed, &format_args!("reach {}", remote))
+    }
+    /// Whether `pid` may send to, or accept a connection from, `remote` on a socket it already has
+    pub(crate) fn may_reach(&mut self, pid: Option<xous::PID>, remote: IpEndpoint, local: &[IpCidr]) -> bool {
+        let name = self.name_of(pid);
+        let allowed = self.rule_for(&name).map_or(true, |rule| rule.permits(&remote, local));
+        self.decide(name, pid, allowed, &format_args!("reach {}", remote))
+    }
+
+    pub(crate) fn count_connection(&mut self, pid: Option<xous::PID>) {
+        self.used(self.name_of(pid), pid).connections += 1;
+    }
+    pub(crate) fn count_tx(&mut self, pid: Option<xous::PID>, bytes: usize) {
+        self.used(self.name_of(pid), pid).tx_bytes += bytes as u64;
+    }
+    pub(crate) fn count_rx(&mut self, pid: Option<xous::PID>, bytes: usize) {
+        self.used(self.name_of(pid), pid).rx_bytes += bytes as u64;
+    }
+
+    /// Whether `pid` may change the rules, or capture packets. `what` names what it's trying to
+    /// do, for the log.
+    pub(crate) fn is_admin(&mut self, pid: Option<xous::PID>, what: &str) -> bool {
+        self.is_listed(&POLICY_ADMINS, pid, what)
+    }
+    /// Whether `pid` may ask what the rules allow other processes to do
+    pub(crate) fn is_proxy(&mut self, pid: Option<xous::PID>) -> bool {
+        self.is_listed(&POLICY_PROXIES, pid, "check the rules for other processes")
+    }
+    fn is_listed(&mut self, list: &[&str], pid: Option<xous::PID>, what: &str) -> bool {
+        let name = self.identify(pid);
+        if list.contains(&name.as_str()) {
+            true
+        } else {
+            log::warn!("{} (PID {:?}) may not {}", name, pid, what);
+            false
+        }
+    }
+
+    pub(crate) fn set(&mut self, policy: &NetPolicy) -> NetMemResponse {
+        let process = match policy.process.as_str() {
+            Ok(process) if !process.is_empty() => process.to_string(),
+            _ => return NetMemResponse::Invalid,
+        };
+        let rule = Rule { access: policy.access, hosts: policy.hosts().copied().collect() };
+        if rule.hosts.iter().any(|host| host.prefix > host.max_prefix()) {
+            return NetMemResponse::Invalid;
+        }
+        if !self.ensure_loaded() {
+            return NetMemResponse::LibraryError;
+        }
+        let data = rule.to_bytes();
+        // remove any previous rule first, so a shorter one doesn't leave a tail behind
+        self.pddb.delete_key(POLICY_DICT, &process, None).ok();
+        match self.pddb.get(POLICY_DICT, &process, None, true, true, Some(data.len()), None::<fn()>) {
+            Ok(mut key) => {
+                if let Err(e) = key.write_all(&data) {
+                    log::error!("couldn't write {}:{}: {:?}", POLICY_DICT, process, e);
+                    return NetMemResponse::LibraryError;
+                }
+                self.pddb.sync().ok();
+            }
+            Err(e) => {
+                log::error!("couldn't open {}:{}: {:?}", POLICY_DICT, process, e);
+                return NetMemResponse::LibraryError;
+            }
+        }
+        log::info!("network rule for {}: {:?} {:?}", process, rule.access, rule.hosts);
+        self.rules.as_mut().unwrap().insert(process, rule);
+        NetMemResponse::Ok
+    }
+    pub(crate) fn remove(&mut self, process: &str) -> NetMemResponse {
+        if !self.ensure_loaded() {
+            return NetMemResponse::LibraryError;
+        }
+        if self.rules.as_mut().unwrap().remove(process).is_none() {
+            return NetMemResponse::Invalid;
+        }
+        match self.pddb.delete_key(POLICY_DICT, process, None) {
+            Ok(_) => {
+                self.pddb.sync().ok();
+                log::info!("removed network rule for {}", process);
+                NetMemResponse::Ok
+            }
+            Err(e) => {
+                log::error!("couldn't remove {}:{}: {:?}", POLICY_DICT, process, e);
+                NetMemResponse::LibraryError
+            }
+        }
+    }
+    /// The rules, or none if they haven't been read in yet and can't be
+    pub(crate) fn list(&mut self) -> Vec<NetPolicy> {
+        self.ensure_loaded();
+        self.rules.iter()
+            .flat_map(|rules| rules.iter())
+            .filter_map(|(process, rule)| NetPolicy::new(process, rule.access, &rule.hosts))
+            .collect()
+    }
+
+    /// What each process has done, most recently active first. `sockets` is the table of the
+    /// sockets each PID has open.
+    pub(crate) fn usage(&self, sockets: &HashMap<Option<xous::PID>, Vec<Option<SocketHandle>>>) -> Vec<NetUsage> {
+        let mut usage: Vec<NetUsage> = self.usage.iter().map(|(process, usage)| NetUsage {
+            process: xous_ipc::String::<POLICY_NAME_LEN>::from_str(process),
+            pid: usage.pid.map_or(0, |pid| pid.get()),
+            sockets: sockets.get(&usage.pid).map_or(0, |s| s.iter().filter(|h| h.is_some()).count() as u32),
+            connections: usage.connections,
+            denied: usage.denied,
+            tx_bytes: usage.tx_bytes,
+            rx_bytes: usage.rx_bytes,
+            idle_ms: usage.last_used.map_or(u64::MAX, |t| t.elapsed().as_millis() as u64),
+        }).collect();
+        usage.sort_by_key(|u| u.idle_ms);
+        usage
+    }
+    pub(crate) fn reset_usage(&mut self) {
+        self.usage.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn ep(addr: &str, port: u16) -> IpEndpoint {
+        let addr: std::net::IpAddr = addr.parse().unwrap();
+        IpEndpoint::new(addr.into(), port)
+    }
+    fn rule(access: NetAccess, hosts: &[&str]) -> Rule {
+        Rule { access, hosts: hosts.iter().map(|h| HostRule::from_str(h).unwrap()).collect() }
+    }
+
+    #[test]
+    fn host_rules() {
+        let local = [IpCidr::new(IpAddress::v4(0, 0, 0, 0), 0), IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8)];
+        let listed = rule(NetAccess::Listed, &["93.184.216.34:443", "1.1.1.0/24", "*:123", "[2606:4700::]/32:443"]);
+        assert!(listed.permits(&ep("93.184.216.34", 443), &local));
+        assert!(!listed.permits(&ep("93.184.216.34", 80), &local));
+        assert!(listed.permits(&ep("1.1.1.1", 53), &local));
+        assert!(!listed.permits(&ep("1.1.2.1", 53), &local));
+        assert!(listed.permits(&ep("203.0.113.9", 123), &local));
+        assert!(listed.permits(&ep("2606:4700::1111", 443), &local));
+        assert!(!listed.permits(&ep("2606:4701::1111", 443), &local));
+        assert!(!listed.permits(&ep("192.168.1.1", 80), &local), "listed hosts only");
+        assert!(!rule(NetAccess::Deny, &["*"]).permits(&ep("1.1.1.1", 53), &local));
+        assert!(rule(NetAccess::Any, &[]).permits(&ep("1.1.1.1", 53), &local));
+    }
+
+    #[test]
+    fn lan_only() {
+        // before DHCP the interface has an unspecified address, which mustn't make everything local
+        let unconfigured = [IpCidr::new(IpAddress::v4(0, 0, 0, 0), 0)];
+        let configured = [IpCidr::new(IpAddress::v4(100, 64, 3, 7), 24)];
+        let lan = rule(NetAccess::Lan, &["9.9.9.9:53"]);
+        for addr in ["192.168.1.10", "10.1.2.3", "172.20.0.1", "169.254.3.3", "224.0.0.251", "fe80::1", "fd00::5"].iter() {
+            assert!(lan.permits(&ep(addr, 80), &unconfigured), "{} is local", addr);
+        }
+        assert!(!lan.permits(&ep("100.64.3.20", 80), &unconfigured));
+        assert!(lan.permits(&ep("100.64.3.20", 80), &configured));
+        assert!(!lan.permits(&ep("8.8.8.8", 53), &unconfigured));
+        assert!(!lan.permits(&ep("2001:db8::1", 443), &unconfigured));
+        assert!(lan.permits(&ep("9.9.9.9", 53), &unconfigured));
+    }
+
+    #[test]
+    fn rule_lookup() {
+        let anywhere = ep("93.184.216.34", 443);
+        let permits = |rule: Option<&Rule>| rule.map_or(true, |rule| rule.permits(&anywhere, &[]));
+        // until the rules are read in, only the boot services get anywhere
+        assert!(!permits(rule_in(None, "shellchat")));
+        assert!(!permits(rule_in(None, "tls")));
+        assert!(permits(rule_in(None, "dns")));
+
+        let mut rules = BTreeMap::new();
+        assert!(permits(rule_in(Some(&rules), "shellchat")), "no rules at all allows everything");
+        rules.insert(POLICY_DEFAULT.to_string(), rule(NetAccess::Deny, &[]));
+        rules.insert("tls".to_string(), rule(NetAccess::Listed, &["93.184.216.34:443"]));
+        assert!(!permits(rule_in(Some(&rules), "shellchat")), "the default applies");
+        assert!(!permits(rule_in(Some(&rules), "dns")), "boot services follow the rules once read");
+        assert!(permits(rule_in(Some(&rules), "tls")), "a process's own rule beats the default");
+    }
+
+    #[test]
+    fn rule_round_trip() {
+        let original = rule(NetAccess::Lan, &["10.0.0.0/8", "[::1]:8080", "*:53"]);
+        let decoded = Rule::from_bytes(&original.to_bytes()).unwrap();
+        assert_eq!(decoded.access, NetAccess::Lan);
+        let hosts: Vec<String> = decoded.hosts.iter().map(|h| h.to_string()).collect();
+        assert_eq!(hosts, vec!["10.0.0.0/8", "[::1]:8080", "*:53"]);
+        assert!(Rule::from_bytes(&[9]).is_none());
+        assert!(Rule::from_bytes(&[1, 4, 10, 0, 0]).is_none());
+        assert!(Rule::from_bytes(&[1, 4, 10, 0, 0, 0, 33, 0, 0]).is_none(), "prefix too long");
+    }
+}
@@ -1,5 +1,6 @@
 use crate::*;
 use crate::device::NetPhy;
+use crate::policy::Policy;
 use smoltcp::wire::{IpEndpoint, IpAddress};
 
 pub(crate) fn std_tcp_listen(
@@ -7,7 +8,9 @@ pub(crate) fn std_tcp_listen(
     iface: &mut Interface::<NetPhy>,
     our_sockets: &mut Vec<Option<SocketHandle>>,
     trng: &trng::Trng,
+    policy: &mut Policy,
     ) {
+    let pid = msg.sender.pid();
     // Ignore nonblocking and scalar messages
     let body = match msg.body.memory_message_mut() {
         Some(b) => b,
@@ -39,6 +42,10 @@ pub(crate) fn std_tcp_listen(
         std_failure(msg, NetError::Invalid);
         return;
     }
+    if !policy.may_open(pid) {
+        std_failure(msg, NetError::AccessDenied);
+        return;
+    }
 
     let tcp_rx_buffer = TcpSocketBuffer::new(vec![0; TCP_BUFFER_SIZE]);
     let tcp_tx_buffer = TcpSocketBuffer::new(vec![0; TCP_BUFFER_SIZE]);
@@ -98,7 +105,9 @@ pub(crate) fn std_tcp_accept(
     tcp_accept_waiting: &mut Vec<Option<AcceptingSocket>>,
     tcp_server_remote_close_poll: &mut Vec<SocketHandle>,
     our_sockets: &Vec<Option<SocketHandle>>,
+    policy: &mut Policy,
 ) {
+    let pid = msg.sender.pid();
     let fd = (msg.body.id() >> 16) & 0xffff;
     let body = match msg.body.memory_message_mut() {
         Some(body) => body,
@@ -122,14 +131,19 @@ pub(crate) fn std_tcp_accept(
     let args = body.buf.as_slice::<u8>();
     let nonblocking = args[0] == 0;
 
+    let local_addrs = iface.ip_addrs().to_vec();
     let socket = iface.get_socket::<TcpSocket>(*handle);
 
     if socket.is_active() {
-        log::debug!("accept did not block; immediately returning TcpSocket");
-        let buf = body.buf.as_slice_mut::<u8>();
-        tcp_server_remote_close_poll.push(*handle);
-        tcp_accept_success(buf, fd as u16, socket.remote_endpoint());
-        return;
+        if policy.may_reach(pid, socket.remote_endpoint(), &local_addrs) {
+            log::debug!("accept did not block; immediately returning TcpSocket");
+            let buf = body.buf.as_slice_mut::<u8>();
+            tcp_server_remote_close_poll.push(*handle);
+            policy.count_connection(pid);
+            tcp_accept_success(buf, fd as u16, socket.remote_endpoint());
+            return;
+        }
+        tcp_refuse(socket);
     }
 
     if nonblocking {
@@ -151,6 +165,17 @@ pub(crate) fn std_tcp_accept(
     );
 }
 
+/// Drops a connection from a host the listener's owner may not accept, and goes back to
+/// listening on the same port.
+pub(crate) fn tcp_refuse(socket: &mut TcpSocket) {
+    let port = socket.local_endpoint().port;
+    log::info!("refusing connection from {:?} on port {}", socket.remote_endpoint(), port);
+    socket.abort();
+    if let Err(e) = socket.listen(port) {
+        log::warn!("couldn't listen on port {} again: {:?}", port, e);
+    }
+}
+
 pub(crate) fn tcp_accept_success(buf: &mut [u8], fd: u16, ep: IpEndpoint) {
     log::debug!("tcp accept: remote {:?}", ep);
     buf[0] = 0;
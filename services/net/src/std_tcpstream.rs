@@ -9,6 +9,8 @@ use smoltcp::socket::{
 use smoltcp::iface::{Interface, SocketHandle};
 use crate::*;
 use crate::device::NetPhy;
+use crate::policy::Policy;
+use smoltcp::wire::IpEndpoint;
 
 pub(crate) fn std_tcp_connect(
     mut msg: xous::MessageEnvelope,
@@ -16,7 +18,9 @@ pub(crate) fn std_tcp_connect(
     iface: &mut Interface::<NetPhy>,
     tcp_connect_waiting: &mut Vec<Option<(xous::MessageEnvelope, SocketHandle, u16, u16, u16)>>,
     our_sockets: &mut Vec<Option<SocketHandle>>,
+    policy: &mut Policy,
 ) {
+    let pid = msg.sender.pid();
     // Ignore nonblocking and scalar messages
     let body = match msg.body.memory_message_mut() {
         Some(b) => b,
@@ -38,6 +42,10 @@ pub(crate) fn std_tcp_connect(
             return;
         }
     };
+    if !policy.may_connect(pid, IpEndpoint::new(address, remote_port), iface.ip_addrs()) {
+        respond_with_error(msg, NetError::AccessDenied);
+        return;
+    }
 
     // initiates a new connection to a remote server consisting of an (Address:Port) tuple.
     // multiple connections can exist to a server, and they are further differentiated by the return port
@@ -80,7 +88,9 @@ pub(crate) fn std_tcp_tx(
     iface: &mut Interface::<NetPhy>,
     tcp_tx_waiting: &mut Vec<Option<WaitingSocket>>,
     our_sockets: &Vec<Option<SocketHandle>>,
+    policy: &mut Policy,
 ) {
+    let pid = msg.sender.pid();
     let connection_handle_index = (msg.body.id() >> 16) & 0xffff;
     let body = match msg.body.memory_message_mut() {
         Some(body) => body,
@@ -142,6 +152,7 @@ pub(crate) fn std_tcp_tx(
     };
 
     log::trace!("sent {}", sent_octets);
+    policy.count_tx(pid, sent_octets);
     let response_data = body.buf.as_slice_mut::<u32>();
     response_data[0] = 0;
     response_data[1] = sent_octets as u32;
@@ -154,7 +165,9 @@ pub(crate) fn std_tcp_rx(
     tcp_rx_waiting: &mut Vec<Option<WaitingSocket>>,
     our_sockets: &Vec<Option<SocketHandle>>,
     nonblocking: bool,
+    policy: &mut Policy,
 ) {
+    let pid = msg.sender.pid();
     let connection_handle_index = (msg.body.id() >> 16) & 0xffff;
     let body = match msg.body.memory_message_mut() {
         Some(body) => body,
@@ -194,6 +207,7 @@ pub(crate) fn std_tcp_rx(
                 body.valid = xous::MemorySize::new(bytes);
                 body.offset = xous::MemoryAddress::new(1);
                 log::debug!("set body.valid = {:?}", body.valid);
+                policy.count_rx(pid, bytes);
             }
             Err(e) => {
                 log::error!("unable to receive: {:?}", e);
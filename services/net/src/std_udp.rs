@@ -2,6 +2,7 @@ use crate::*;
 use crate::device::NetPhy;
 use smoltcp::wire::{IpEndpoint, IpAddress};
 use ticktimer_server::Ticktimer;
+use crate::policy::Policy;
 
 /// Overall architecture for libstd UDP implementation.
 ///
@@ -14,7 +15,9 @@ pub(crate) fn std_udp_bind(
     mut msg: xous::MessageEnvelope,
     iface: &mut Interface::<NetPhy>,
     our_sockets: &mut Vec<Option<SocketHandle>>,
+    policy: &mut Policy,
     ) {
+    let pid = msg.sender.pid();
     // Ignore nonblocking and scalar messages
     let body = match msg.body.memory_message_mut() {
         Some(b) => b,
@@ -35,6 +38,10 @@ pub(crate) fn std_udp_bind(
             return;
         }
     };
+    if !policy.may_open(pid) {
+        std_failure(msg, NetError::AccessDenied);
+        return;
+    }
 
     let udp_rx_buffer =
     UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; BUFLEN]);
@@ -60,6 +67,7 @@ pub(crate) fn std_udp_bind(
 
     // Add the socket into our process' list of sockets, and pass the index back as the `fd` parameter for future reference.
     let idx = insert_or_append(our_sockets, handle) as u8;
+    policy.count_connection(pid);
 
     let body = msg.body.memory_message_mut().unwrap();
     let bfr = body.buf.as_slice_mut::<u8>();
@@ -74,7 +82,9 @@ pub(crate) fn std_udp_rx(
     iface: &mut Interface::<NetPhy>,
     udp_rx_waiting: &mut Vec<Option<UdpStdState>>,
     our_sockets: &Vec<Option<SocketHandle>>,
+    policy: &mut Policy,
 ) {
+    let pid = msg.sender.pid();
     let connection_handle_index = (msg.body.id() >> 16) & 0xffff;
     let body = match msg.body.memory_message_mut() {
         Some(body) => body,
@@ -154,6 +164,7 @@ pub(crate) fn std_udp_rx(
             match socket.recv() {
                 Ok((data, endpoint)) => {
                     log::debug!("immediate udp rx");
+                    policy.count_rx(pid, data.len());
                     udp_rx_success(body.buf.as_slice_mut(), data, endpoint);
                 }
                 Err(e) => {
@@ -187,7 +198,9 @@ pub(crate) fn std_udp_tx(
     mut msg: xous::MessageEnvelope,
     iface: &mut Interface::<NetPhy>,
     our_sockets: &Vec<Option<SocketHandle>>,
+    policy: &mut Policy,
 ) {
+    let pid = msg.sender.pid();
     // unpack meta
     let connection_handle_index = (msg.body.id() >> 16) & 0xffff;
     let body = match msg.body.memory_message_mut() {
@@ -217,6 +230,10 @@ pub(crate) fn std_udp_tx(
         }
     };
     let len = u16::from_le_bytes([bytes[19], bytes[20]]);
+    if !policy.may_reach(pid, IpEndpoint::new(address, remote_port), iface.ip_addrs()) {
+        std_failure(msg, NetError::AccessDenied);
+        return;
+    }
     // attempt the tx
     log::debug!("udp tx to fd {} -> {:?}:{} {:?}", connection_handle_index, address, remote_port, &bytes[21..21 + len as usize]);
     let local_addr = match iface.ipv4_addr() {
@@ -249,6 +266,7 @@ pub(crate) fn std_udp_tx(
     match socket.send_slice(&bytes[21..21 + len as usize], IpEndpoint::new(address, remote_port)) {
         Ok(_) => {
             body.buf.as_slice_mut()[0] = 0;
+            policy.count_tx(pid, len as usize);
         }
         Err(_e) => {
             // the only type of error returned from smoltcp in this case is if the destination is not addressible.
@@ -232,6 +232,53 @@ pub(crate) enum Opcode {
     /// in prepration for a system shutdown or backup event.
     PddbHalt = 51,
 
+    /// Start a transaction on a basis. Nothing is written to disk until it is committed.
+    TxnBegin = 52,
+    /// Stage a write or delete in an open transaction
+    TxnStage = 53,
+    /// Commit all of the staged operations, atomically
+    TxnCommit = 54,
+    /// Discard a transaction and everything staged in it
+    TxnAbort = 55,
+
+    /// Register a callback for changes to a dictionary or key
+    Subscribe = 56,
+    /// Remove a callback registered with `Subscribe`
+    Unsubscribe = 57,
+
+    /// Check the unlocked bases for inconsistencies, and optionally repair them
+    Fsck = 58,
+
+    /// Report the space used by a basis or dictionary, and by the PDDB as a whole
+    Usage = 59,
+
+    /// Read several pages of a key at once, for `PddbKeyStream`
+    ReadKeyStream = 60,
+    /// Write several pages of a key at once, deferring the descriptor update to the next flush
+    WriteKeyStream = 61,
+
+    /// Set or clear the time after which a key is deleted
+    KeySetExpiry = 62,
+
+    /// Build an encrypted archive of a basis, to be read out with `ArchiveRead`
+    ExportBasis = 63,
+    /// Write the keys of an archive sent with `ArchiveWrite` into a basis
+    ImportBasis = 64,
+    /// Read a chunk of an exported archive
+    ArchiveRead = 65,
+    /// Send a chunk of an archive to be imported
+    ArchiveWrite = 66,
+    /// Free an archive held by the server
+    ArchiveClose = 67,
+
+    /// List a page of the key names in a dictionary that match a prefix or range
+    QueryKeys = 68,
+
+    /// Remove a dict along with every dict and key below it
+    DeleteDictAllStd = 69,
+    /// Move a key or a dict, and everything below it, to a new path
+    RenamePathStd = 70,
+
     /// This key type could not be decoded
     InvalidOpcode = u32::MAX as _,
 }
@@ -242,6 +289,25 @@ pub(crate) enum PollOp {
     Quit = 1,
 }
 
+/// Delivered to a subscriber as the first argument of its callback scalar message; the second
+/// argument is the ID that `Pddb::subscribe()` returned.
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PddbChangeEvent {
+    /// A key was created
+    KeyCreated = 0,
+    /// A key's contents were written or truncated. Writes through a stream are reported once, when
+    /// the stream is flushed or the key is closed.
+    KeyUpdated = 1,
+    /// A key was deleted
+    KeyDeleted = 2,
+    /// The whole dictionary was deleted
+    DictDeleted = 3,
+    /// A basis that the subscription covers was unlocked
+    BasisMounted = 4,
+    /// A basis that the subscription covers was locked
+    BasisUnmounted = 5,
+}
+
 pub type ApiToken = [u32; 3];
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 #[repr(C)]
@@ -282,6 +348,8 @@ pub struct PddbBasisRequest {
     pub name: xous_ipc::String::<BASIS_NAME_LEN>,
     pub code: PddbRequestCode,
     pub policy: Option<BasisRetentionPolicy>,
+    /// limit in bytes on the space a basis being created may occupy
+    pub quota: Option<u64>,
 }
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct PddbDictRequest {
@@ -305,6 +373,8 @@ pub struct PddbKeyRequest {
     pub create_dict: bool,
     pub create_key: bool,
     pub alloc_hint: Option<u64>, // this is a usize but for IPC we must have defined memory sizes, so we pick the big option.
+    /// seconds from now until the key expires; applied whether the key is created or opened
+    pub expiry_secs: Option<u64>,
     pub cb_sid: Option<[u32; 4]>,
     pub result: PddbRequestCode,
 }
@@ -319,6 +389,195 @@ pub (crate) struct PddbKeyList {
     pub end: bool,
 }
 
+/// Selects a page of the key names in a dictionary; see `Pddb::query_keys()`. Names are compared
+/// byte by byte, so the order is the same as that of `str`.
+#[derive(Debug, Clone, Default)]
+pub struct KeyQuery {
+    /// only names that start with this
+    pub prefix: Option<String>,
+    /// only names that sort at or after this
+    pub start: Option<String>,
+    /// only names that sort before this
+    pub end: Option<String>,
+    /// the most names to return in a page; 0 returns as many as fit in one message
+    pub page_size: usize,
+    /// continues a query from where a previous page stopped. Set from `KeyPage::cursor`.
+    pub cursor: Option<String>,
+}
+impl KeyQuery {
+    pub(crate) fn matches(&self, key: &str) -> bool {
+        self.prefix.as_ref().map_or(true, |p| key.starts_with(p.as_str()))
+        && self.start.as_ref().map_or(true, |s| key >= s.as_str())
+        && self.end.as_ref().map_or(true, |e| key < e.as_str())
+        && self.cursor.as_ref().map_or(true, |c| key > c.as_str())
+    }
+}
+/// One page of the results of a `KeyQuery`
+#[derive(Debug)]
+pub struct KeyPage {
+    pub keys: Vec<String>,
+    /// set if there are more names to fetch: pass it in `KeyQuery::cursor` to get the next page
+    pub cursor: Option<String>,
+}
+
+/// Carries a `KeyQuery` to the server, and a page of key names back. The names are packed into
+/// `data` the same way as in `PddbKeyList`.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PddbKeyQuery {
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub prefix: Option<xous_ipc::String::<KEY_NAME_LEN>>,
+    pub start: Option<xous_ipc::String::<KEY_NAME_LEN>>,
+    pub end: Option<xous_ipc::String::<KEY_NAME_LEN>>,
+    pub cursor: Option<xous_ipc::String::<KEY_NAME_LEN>>,
+    pub page_size: u32,
+    pub data: [u8; MAX_PDDBKLISTLEN],
+    /// number of names packed into `data`
+    pub count: u32,
+    /// set if there are names after the ones returned
+    pub more: bool,
+    pub code: PddbRequestCode,
+}
+impl PddbKeyQuery {
+    pub(crate) fn to_query(&self) -> KeyQuery {
+        let convert = |s: &Option<xous_ipc::String::<KEY_NAME_LEN>>| s.as_ref().map(|s| String::from(s.as_str().unwrap_or("")));
+        KeyQuery {
+            prefix: convert(&self.prefix),
+            start: convert(&self.start),
+            end: convert(&self.end),
+            page_size: self.page_size as usize,
+            cursor: convert(&self.cursor),
+        }
+    }
+}
+
+/// Used to begin, commit, and abort transactions. `id` is filled in by the server on `TxnBegin`,
+/// and must be presented on every subsequent call.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbTxnRequest {
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub id: u32,
+    pub code: PddbRequestCode,
+}
+
+pub(crate) const PDDB_TXN_DATA_LEN: usize = 3072;
+/// Stages one operation in a transaction. Values larger than `PDDB_TXN_DATA_LEN` are sent as a
+/// `Write` followed by as many `Append` chunks as needed.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PddbTxnStage {
+    pub id: u32,
+    pub op: TxnStageOp,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+    pub data: [u8; PDDB_TXN_DATA_LEN],
+    pub len: u32,
+    pub code: PddbRequestCode,
+}
+
+/// bcrypt only uses the first 72 bytes of a passphrase
+pub(crate) const ARCHIVE_PASSPHRASE_LEN: usize = 73;
+/// Used to export a basis to an archive, and to import one. `id` names the archive held by the
+/// server: it is filled in by `ExportBasis` and by the first `ArchiveWrite`. `len` is the length
+/// of the archive after an export, and the number of keys written after an import.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PddbArchiveRequest {
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub passphrase: xous_ipc::String::<ARCHIVE_PASSPHRASE_LEN>,
+    pub id: u32,
+    pub len: u64,
+    pub code: PddbRequestCode,
+}
+
+pub(crate) const PDDB_ARCHIVE_DATA_LEN: usize = 3072;
+/// Largest archive that can be exported or imported. Archives are held in the server's RAM while
+/// they are moved, and this also bounds the total held for all clients at once.
+pub const PDDB_ARCHIVE_MAX_LEN: usize = 1024 * 1024;
+/// Moves one chunk of an archive between the client and the server. An `ArchiveWrite` with an
+/// `id` of 0 starts a new archive.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PddbArchiveChunk {
+    pub id: u32,
+    pub position: u64,
+    pub data: [u8; PDDB_ARCHIVE_DATA_LEN],
+    pub len: u32,
+    pub code: PddbRequestCode,
+}
+
+/// Registers a callback for changes to a dictionary, or to a single key in it. `id` is filled in
+/// by the server.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbSubscribeRequest {
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub key_specified: bool,
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+    pub cb_sid: [u32; 4],
+    pub cb_opcode: u32,
+    pub id: u32,
+    pub code: PddbRequestCode,
+}
+
+/// Results of a consistency check, totalled over every unlocked basis. `repair` is set by the caller.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbFsckReport {
+    /// return orphaned and superseded pages to FastSpace
+    pub repair: bool,
+    pub bases_checked: u32,
+    /// pages mapped by the page tables of the checked bases
+    pub pages_checked: u32,
+    /// pages mapped by a basis at an address that none of its dictionaries or keys use
+    pub orphaned_pages: u32,
+    /// virtual pages that more than one physical page is mapped to
+    pub duplicate_mappings: u32,
+    /// keys whose data isn't mapped
+    pub dangling_keys: u32,
+    /// duplicate mappings with the same journal revision, so the right one had to be guessed
+    pub journal_conflicts: u32,
+    pub pages_reclaimed: u32,
+    pub code: PddbRequestCode,
+}
+impl PddbFsckReport {
+    pub fn new(repair: bool) -> Self {
+        PddbFsckReport {
+            repair,
+            bases_checked: 0,
+            pages_checked: 0,
+            orphaned_pages: 0,
+            duplicate_mappings: 0,
+            dangling_keys: 0,
+            journal_conflicts: 0,
+            pages_reclaimed: 0,
+            code: PddbRequestCode::Uninit,
+        }
+    }
+}
+
+/// Space used by a basis, or by one dictionary in it. Space is `allocated` to a basis in whole
+/// pages, and counts against its quota; `used` is only the bytes of key data in them.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbUsageReport {
+    pub basis_specified: bool,
+    /// if not specified, the most recently unlocked basis is reported
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict_specified: bool,
+    /// if not specified, the whole basis is reported
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    /// the basis' quota in bytes, if it has one
+    pub quota: Option<u64>,
+    pub allocated: u64,
+    pub used: u64,
+    pub keys: u32,
+    /// size of the PDDB's data area
+    pub device_total: u64,
+    /// free space in the PDDB, as of the last free space scan. `None` if there hasn't been one
+    /// since boot. Locked bases are counted as free space.
+    pub device_free: Option<u64>,
+    pub code: PddbRequestCode,
+}
+
 /// Return codes for Read/Write API calls to the main server
 #[repr(u8)]
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -364,6 +623,31 @@ impl PddbBuf {
     }
 }
 
+/// Bytes of key data held by one page on disk. Streams move data in multiples of this, aligned to
+/// it, so that the server can write whole pages without reading them back first.
+pub const PDDB_STREAM_CHUNK: usize = 4064;
+/// Number of chunks a `PddbKeyStream` reads ahead or holds back for writing. This bounds the RAM
+/// used by a stream, regardless of the size of the key.
+pub const PDDB_STREAM_CHUNKS: usize = 8;
+pub(crate) const PDDB_STREAM_DATA_LEN: usize = PDDB_STREAM_CHUNK * PDDB_STREAM_CHUNKS;
+/// The multi-page equivalent of `PddbBuf`, used by `PddbKeyStream`.
+#[repr(C, align(4096))]
+pub(crate) struct PddbStreamBuf {
+    pub(crate) token: ApiToken,
+    pub(crate) retcode: PddbRetcode,
+    reserved: u8,
+    reserved2: u16,
+    pub(crate) len: u32,
+    pub(crate) position: u64,
+    pub(crate) data: [u8; PDDB_STREAM_DATA_LEN],
+}
+impl PddbStreamBuf {
+    pub(crate) fn from_slice_mut(slice: &mut [u8]) -> &mut PddbStreamBuf {
+        assert!(slice.len() >= core::mem::size_of::<PddbStreamBuf>(), "stream buffer is too small");
+        unsafe {core::mem::transmute::<*mut u8, &mut PddbStreamBuf>(slice.as_mut_ptr()) }
+    }
+}
+
 bitfield! {
     #[derive(Copy, Clone, PartialEq, Eq)]
     pub struct KeyFlags(u32);
@@ -391,6 +675,8 @@ pub struct KeyAttributes {
     pub flags: KeyFlags,
     /// descriptor index
     pub index: NonZeroU32,
+    /// seconds left until the key expires, if it has an expiry time
+    pub expires_in: Option<u64>,
 }
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -403,6 +689,7 @@ pub struct PddbKeyAttrIpc {
     pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
     pub flags: u32,
     pub index: u32,
+    pub expires_in: Option<u64>,
     pub token: ApiToken,
     pub code: PddbRequestCode,
 }
@@ -416,6 +703,7 @@ impl PddbKeyAttrIpc {
             basis: xous_ipc::String::<BASIS_NAME_LEN>::new(),
             flags: 0,
             index: 0,
+            expires_in: None,
             token,
             code: PddbRequestCode::Uninit,
         }
@@ -430,6 +718,7 @@ impl PddbKeyAttrIpc {
             basis: String::from(self.basis.as_str().unwrap()),
             flags: KeyFlags(self.flags),
             index: NonZeroU32::new(self.index).unwrap(),
+            expires_in: self.expires_in,
         }
     }
     pub fn from_attributes(attr: KeyAttributes, token: ApiToken) -> PddbKeyAttrIpc {
@@ -441,6 +730,7 @@ impl PddbKeyAttrIpc {
             basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(&attr.basis),
             flags: attr.flags.0,
             index: attr.index.get(),
+            expires_in: attr.expires_in,
             token,
             code: PddbRequestCode::NoErr,
         }
@@ -58,4 +58,15 @@ pub enum PddbRekeyOp {
     UserAbort,
     VerifyFail,
     InternalError,
-}
\ No newline at end of file
+}
+
+/// Operations that can be staged in a PDDB transaction
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub(crate) enum TxnStageOp {
+    /// Replace the contents of a key, creating the key and its dictionary if needed
+    Write,
+    /// Add more data to the end of the most recently staged `Write` of the same key
+    Append,
+    /// Remove a key
+    Delete,
+}
@@ -12,6 +12,13 @@ mod types;
 pub use types::*;
 mod bcrypt;
 pub use bcrypt::*;
+mod txn;
+pub(crate) use txn::*;
+mod fsck;
+mod usage;
+mod expiry;
+pub(crate) use expiry::*;
+mod archive;
 
 // local to the backend
 mod murmur3;
@@ -27,6 +34,11 @@ pub(crate) use hw::*;
 mod hosted;
 #[cfg(any(feature="hosted"))]
 pub(crate) use hosted::*;
+// stand-ins for other services, for the offline image tool
+#[cfg(feature="offline")]
+mod offline;
+#[cfg(feature="offline")]
+pub(crate) use offline::*;
 
 #[cfg(feature="migration1")]
 mod migration1to2;
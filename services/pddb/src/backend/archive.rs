@@ -0,0 +1,190 @@
+//! # Basis archives
+//!
+//! A single basis can be exported as an archive that holds all of its dictionaries and keys,
+//! encrypted under a passphrase rather than under any key held by the device, so that it can be
+//! imported into the PDDB of another device. The archive is:
+//!
+//!   - a header: the magic number, a version, a random salt and a random nonce
+//!   - the records, encrypted with AES-256-GCM-SIV, using the header as the AAD
+//!
+//! The key is derived by running the passphrase and salt through bcrypt, and expanding the result
+//! with HKDF. Nothing device-specific goes into it; the static crypto data that salts basis
+//! passwords stays on the device.
+//!
+//! Each record is a dictionary name and a key name, each preceded by its length in one byte, then
+//! the key's data, preceded by its length as a u32 LE. Expiry times are not exported, as they are
+//! counted on the exporting device's RTC. The whole archive is built and checked in RAM, so a
+//! basis can only be moved this way if its archive fits in `PDDB_ARCHIVE_MAX_LEN`.
+//!
+//! Importing into a basis that already has some of the keys overwrites them. The import is not a
+//! transaction: if it runs out of space part way, the keys written up to that point remain.
+
+use crate::*;
+
+use aes_gcm_siv::{Nonce, Aes256GcmSiv};
+use aes_gcm_siv::aead::{Aead, KeyInit, Payload};
+use core::mem::size_of;
+use std::convert::TryInto;
+use std::io::{Result, Error, ErrorKind};
+
+/// "PBXA", identifies a basis archive
+const ARCHIVE_MAGIC: [u8; 4] = [0x50, 0x42, 0x58, 0x41];
+const ARCHIVE_VERSION: u32 = 1;
+const ARCHIVE_SALT_LEN: usize = 16;
+/// magic, version, salt, nonce
+const ARCHIVE_HEADER_LEN: usize = 4 + 4 + ARCHIVE_SALT_LEN + 12;
+
+/// Derives the cipher for an archive from its passphrase and salt.
+fn archive_cipher(hw: &PddbOs, passphrase: &str, salt: &[u8]) -> Aes256GcmSiv {
+    let mut hashed_password: [u8; 24] = [0; 24];
+    let start_time = hw.timestamp_now();
+    bcrypt(BCRYPT_COST, salt, passphrase, &mut hashed_password);
+    log::info!("derived archive key in {}ms", hw.timestamp_now() - start_time);
+    let hk = hkdf::Hkdf::<sha2::Sha256>::new(Some(salt), &hashed_password);
+    let mut okm = [0u8; 32];
+    hk.expand(b"pddb archive key", &mut okm).expect("invalid length specified for HKDF");
+    let cipher = Aes256GcmSiv::new(&okm.into());
+    let okm_ptr = okm.as_mut_ptr();
+    for i in 0..okm.len() {
+        unsafe{okm_ptr.add(i).write_volatile(core::mem::zeroed());}
+    }
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    cipher
+}
+
+impl BasisCache {
+    /// Builds an encrypted archive of every dictionary and key in `basis_name`.
+    pub(crate) fn basis_export(&mut self, hw: &mut PddbOs, basis_name: &str, passphrase: &str) -> Result<Vec<u8>> {
+        if !self.basis_list().iter().any(|name| name == basis_name) {
+            return Err(Error::new(ErrorKind::NotFound, "Basis not found"));
+        }
+        let mut records = Vec::<u8>::new();
+        let mut count = 0;
+        for dict in self.dict_list(hw, Some(basis_name)).iter() {
+            for key in self.key_list(hw, dict, Some(basis_name))?.iter() {
+                let attr = self.key_attributes(hw, dict, key, Some(basis_name))?;
+                let mut data = vec![0u8; attr.len];
+                let len = self.key_read(hw, dict, key, &mut data, None, Some(basis_name))?;
+                records.push(dict.len() as u8);
+                records.extend_from_slice(dict.as_bytes());
+                records.push(key.len() as u8);
+                records.extend_from_slice(key.as_bytes());
+                records.extend_from_slice(&(len as u32).to_le_bytes());
+                records.extend_from_slice(&data[..len]);
+                for b in data.iter_mut() {
+                    *b = 0;
+                }
+                count += 1;
+                // the header and the MAC have to fit, too
+                if ARCHIVE_HEADER_LEN + records.len() + 16 > PDDB_ARCHIVE_MAX_LEN {
+                    for b in records.iter_mut() {
+                        *b = 0;
+                    }
+                    return Err(Error::new(ErrorKind::OutOfMemory, "Basis is too large to archive"));
+                }
+            }
+        }
+        log::info!("exporting {} keys from {}", count, basis_name);
+
+        let mut archive = Vec::<u8>::with_capacity(ARCHIVE_HEADER_LEN + records.len() + 16);
+        archive.extend_from_slice(&ARCHIVE_MAGIC);
+        archive.extend_from_slice(&ARCHIVE_VERSION.to_le_bytes());
+        let mut salt_nonce = [0u8; ARCHIVE_SALT_LEN + 12];
+        hw.trng_slice(&mut salt_nonce);
+        archive.extend_from_slice(&salt_nonce);
+        let cipher = archive_cipher(hw, passphrase, &salt_nonce[..ARCHIVE_SALT_LEN]);
+        let ciphertext = cipher.encrypt(
+            Nonce::from_slice(&salt_nonce[ARCHIVE_SALT_LEN..]),
+            Payload {
+                aad: &archive[..ARCHIVE_HEADER_LEN],
+                msg: &records,
+            }
+        ).expect("couldn't encrypt basis archive");
+        for b in records.iter_mut() {
+            *b = 0;
+        }
+        archive.extend_from_slice(&ciphertext);
+        Ok(archive)
+    }
+
+    /// Writes every key in `archive` into `basis_name`, creating dictionaries as needed. Returns
+    /// the dictionary and key names written, and whether each key was created or overwritten.
+    /// Fails with `PermissionDenied` if the passphrase is wrong, or the archive was altered.
+    pub(crate) fn basis_import(&mut self, hw: &mut PddbOs, basis_name: &str, passphrase: &str, archive: &[u8])
+    -> Result<Vec<(String, String, PddbChangeEvent)>> {
+        if !self.basis_list().iter().any(|name| name == basis_name) {
+            return Err(Error::new(ErrorKind::NotFound, "Basis not found"));
+        }
+        if archive.len() < ARCHIVE_HEADER_LEN || archive[..4] != ARCHIVE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a basis archive"));
+        }
+        if u32::from_le_bytes(archive[4..8].try_into().unwrap()) != ARCHIVE_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "Unsupported basis archive version"));
+        }
+        let salt = &archive[8..8 + ARCHIVE_SALT_LEN];
+        let cipher = archive_cipher(hw, passphrase, salt);
+        let mut records = cipher.decrypt(
+            Nonce::from_slice(&archive[8 + ARCHIVE_SALT_LEN..ARCHIVE_HEADER_LEN]),
+            Payload {
+                aad: &archive[..ARCHIVE_HEADER_LEN],
+                msg: &archive[ARCHIVE_HEADER_LEN..],
+            }
+        ).or(Err(Error::new(ErrorKind::PermissionDenied, "Wrong passphrase, or the archive is corrupted")))?;
+
+        // check the whole archive parses before anything is written
+        let mut entries = Vec::<(String, String, core::ops::Range<usize>)>::new();
+        let mut index = 0;
+        let name = |index: &mut usize, max: usize| -> Option<String> {
+            let len = *records.get(*index)? as usize;
+            let bytes = records.get(*index + 1..*index + 1 + len)?;
+            *index += 1 + len;
+            if len == 0 || len > max - 1 {
+                return None;
+            }
+            String::from_utf8(bytes.to_vec()).ok()
+        };
+        while index < records.len() {
+            let dict = name(&mut index, DICT_NAME_LEN);
+            let key = name(&mut index, KEY_NAME_LEN);
+            let len = records.get(index..index + size_of::<u32>())
+                .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize);
+            match (dict, key, len) {
+                (Some(dict), Some(key), Some(len)) if index + size_of::<u32>() + len <= records.len() => {
+                    index += size_of::<u32>();
+                    entries.push((dict, key, index..index + len));
+                    index += len;
+                }
+                _ => return Err(Error::new(ErrorKind::InvalidData, "Basis archive is malformed")),
+            }
+        }
+        log::info!("importing {} keys into {}", entries.len(), basis_name);
+
+        let mut written = Vec::new();
+        let mut result = Ok(());
+        for (dict, key, range) in entries {
+            if self.dict_attributes(hw, &dict, Some(basis_name)).is_err() {
+                if let Err(e) = self.dict_add(hw, &dict, Some(basis_name)) {
+                    result = Err(e);
+                    break;
+                }
+            }
+            let event = if self.key_attributes(hw, &dict, &key, Some(basis_name)).is_ok() {
+                PddbChangeEvent::KeyUpdated
+            } else {
+                PddbChangeEvent::KeyCreated
+            };
+            let len = range.len();
+            if let Err(e) = self.key_update(hw, &dict, &key, &records[range], Some(0), Some(len), Some(basis_name), true) {
+                result = Err(e);
+                break;
+            }
+            written.push((dict, key, event));
+        }
+        for b in records.iter_mut() {
+            *b = 0;
+        }
+        result?;
+        self.sync(hw, Some(basis_name))?;
+        Ok(written)
+    }
+}
@@ -49,6 +49,7 @@
 /// |------------------------|-------------------------------------------|
 /// | 0x0000_0000_0000_0000  |  Invalid -- VPAGE 0 reserved for Option<> |
 /// | 0x0000_0000_0000_0FE0  |  Basis root page                          |
+/// | 0x0000_0000_0000_1FC0  |  Transaction record page                  |
 /// | 0x0000_0000_00FE_0000  |  Dictionary[0]                            |
 /// |                    +0  |    - Dict header (127 bytes)              |
 /// |                   +7F  |    - Maybe key entry (127 bytes)          |
@@ -216,6 +217,9 @@ pub(crate) struct BasisRoot {
     /* at this point, we are aligned to a 64-bit boundary. All data must stay aligned to this boundary from here out! */
     /// 64-byte name; aligns to 64-bits
     pub(crate) name: BasisRootName,
+    /// storage quota in bytes, or 0 for none. Roots written before quotas existed are zero-padded
+    /// past the name, so they read back as having no quota.
+    pub(crate) quota: u64,
 }
 impl BasisRoot {
     pub(crate) fn aad(&self, dna: u64) -> Vec::<u8> {
@@ -253,13 +257,18 @@ pub(crate) struct BasisCache {
     /// the cache entries themselves
     cache: Vec::<BasisCacheEntry>,
     /// ticktimer reference, for managing atimes
-    pub(crate) tt: ticktimer_server::Ticktimer,
+    pub(crate) tt: EmuTicktimer,
+    /// makes the next `txn_commit()` stop partway through, to test recovery
+    #[cfg(feature="hosted")]
+    pub(crate) txn_crash: Option<TxnCrashPoint>,
 }
 impl BasisCache {
     pub(crate) fn new() -> Self {
         BasisCache {
             cache: Vec::new(),
-            tt: ticktimer_server::Ticktimer::new().unwrap(),
+            tt: EmuTicktimer::new().unwrap(),
+            #[cfg(feature="hosted")]
+            txn_crash: None,
         }
     }
     /// Returns a Vec which is a list of Bases to visit, in order of visitation, to create the union view.
@@ -273,6 +282,54 @@ impl BasisCache {
     pub(crate) fn rekey(&self, hw: &mut PddbOs, op: PddbRekeyOp) -> PddbRekeyOp {
         hw.pddb_rekey(op, &self.cache)
     }
+    /// Reports the space used by a basis, or by a dictionary within it.
+    pub(crate) fn usage(&mut self, hw: &mut PddbOs, basis_name: Option<&str>, dict: Option<&str>) -> Result<PddbUsageReport> {
+        let basis_index = self.select_basis(basis_name)
+            .ok_or(Error::new(ErrorKind::NotFound, "Basis not found"))?;
+        let basis = &mut self.cache[basis_index];
+        let (allocated, used, keys) = basis.usage(hw, dict)?;
+        let (device_total, device_free) = hw.space_usage();
+        Ok(PddbUsageReport {
+            basis_specified: true,
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(&basis.name),
+            dict_specified: dict.is_some(),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict.unwrap_or("")),
+            quota: basis.quota,
+            allocated,
+            used,
+            keys,
+            device_total,
+            device_free,
+            code: PddbRequestCode::NoErr,
+        })
+    }
+    /// Checks every open basis for inconsistencies, and repairs them if `repair` is set.
+    pub(crate) fn fsck(&mut self, hw: &mut PddbOs, repair: bool) -> Result<PddbFsckReport> {
+        let mut report = PddbFsckReport::new(repair);
+        for basis in self.cache.iter_mut() {
+            basis.sync(hw)?;
+            basis.fsck(hw, &mut report);
+        }
+        Ok(report)
+    }
+    /// Maps a page of zeroes at `va` that no dictionary or key refers to, as an interrupted write
+    /// would. Lets the tests check that `fsck` finds it.
+    #[cfg(feature="hosted")]
+    pub(crate) fn orphan_inject(&mut self, hw: &mut PddbOs, basis_name: Option<&str>, va: VirtAddr) -> Result<()> {
+        let basis_index = self.select_basis(basis_name)
+            .ok_or(Error::new(ErrorKind::NotFound, "Basis not found"))?;
+        if !hw.ensure_fast_space_alloc(1, &self.cache) {
+            return Err(Error::new(ErrorKind::OutOfMemory, "No free space for the orphan"));
+        }
+        let mut pp = hw.try_fast_space_alloc().expect("FastSpace empty");
+        pp.set_valid(true);
+        let basis = &mut self.cache[basis_index];
+        let mut block = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
+        hw.data_encrypt_and_patch_page(&basis.cipher, &basis.aad, &mut block, &pp);
+        hw.pt_patch_mapping(va, pp.page_number(), &basis.cipher_ecb);
+        basis.v2p_map.insert(va, pp);
+        Ok(())
+    }
     fn select_basis(&self, basis_name: Option<&str>) -> Option<usize> {
         if self.cache.len() == 0 {
             log::error!("Can't select basis: PDDB is not mounted");
@@ -301,6 +358,7 @@ impl BasisCache {
             if basis.ensure_dict_in_cache(hw, name) {
                 return Err(Error::new(ErrorKind::AlreadyExists, "Dictionary already exists"));
             }
+            basis.quota_check(1)?;
             basis.clean = false;
             // allocate a vpage offset for the dictionary
             let dict_index = basis.dict_get_free_offset(hw);
@@ -364,7 +422,7 @@ impl BasisCache {
                 let basis = &mut self.cache[basis_index];
                 basis.populate_caches(hw);
                 for (key, dcache) in basis.dicts.iter() {
-                    if dcache.flags.valid() {
+                    if dcache.flags.valid() && key != PDDB_META_DICT {
                         dict_set.insert(String::from(key));
                     }
                 }
@@ -373,7 +431,7 @@ impl BasisCache {
             for basis in self.cache.iter_mut() {
                 basis.populate_caches(hw);
                 for (key, dcache) in basis.dicts.iter() {
-                    if dcache.flags.valid() {
+                    if dcache.flags.valid() && key != PDDB_META_DICT {
                         dict_set.insert(String::from(key));
                     }
                 }
@@ -384,22 +442,24 @@ impl BasisCache {
     pub(crate) fn key_list(&mut self, hw: &mut PddbOs, dict: &str, basis_name: Option<&str>) -> Result<BTreeSet::<String>> {
         let mut merge_list = BTreeSet::<String>::new();
         let mut found_dict = false;
-        if basis_name.is_some() {
-            if let Some(basis_index) = self.select_basis(basis_name) {
-                let basis = &mut self.cache[basis_index];
-                basis.populate_caches(hw);
-                if let Some(dcache) = basis.dicts.get_mut(dict) {
-                    dcache.key_list(hw, &basis.v2p_map, &basis.cipher, &mut merge_list);
-                    found_dict = true;
-                }
-            }
+        let basis_indices: Vec<usize> = if basis_name.is_some() {
+            self.select_basis(basis_name).into_iter().collect()
         } else {
-            for basis in self.cache.iter_mut() {
-                basis.populate_caches(hw);
-                if let Some(dcache) = basis.dicts.get_mut(dict) {
-                    dcache.key_list(hw, &basis.v2p_map, &basis.cipher, &mut merge_list);
-                    found_dict = true;
-                }
+            (0..self.cache.len()).collect()
+        };
+        let now = hw.rtc_secs();
+        for basis_index in basis_indices {
+            if now.is_some() {
+                self.expiry_load(hw, basis_index);
+            }
+            let basis = &mut self.cache[basis_index];
+            basis.populate_caches(hw);
+            if let Some(dcache) = basis.dicts.get_mut(dict) {
+                let mut basis_list = BTreeSet::<String>::new();
+                dcache.key_list(hw, &basis.v2p_map, &basis.cipher, &mut basis_list);
+                basis.expiry_filter(dict, now, &mut basis_list);
+                merge_list.append(&mut basis_list);
+                found_dict = true;
             }
         }
         if found_dict {
@@ -409,6 +469,40 @@ impl BasisCache {
         }
     }
 
+    /// Returns the names in `dict` selected by `query`, in order, up to `limit` of them. Expired
+    /// keys are skipped as the names are collected, and no more than `limit` of them are held at
+    /// a time, so the result is bounded by the page size rather than by the size of the
+    /// dictionary. The dictionary's key cache is still filled, as for any other access to it.
+    pub(crate) fn key_query(&mut self, hw: &mut PddbOs, dict: &str, basis_name: Option<&str>, query: &KeyQuery, limit: usize)
+    -> Result<BTreeSet::<String>> {
+        let mut merge_list = BTreeSet::<String>::new();
+        let mut found_dict = false;
+        let basis_indices: Vec<usize> = if basis_name.is_some() {
+            self.select_basis(basis_name).into_iter().collect()
+        } else {
+            (0..self.cache.len()).collect()
+        };
+        let now = hw.rtc_secs();
+        for basis_index in basis_indices {
+            if now.is_some() {
+                self.expiry_load(hw, basis_index);
+            }
+            let basis = &mut self.cache[basis_index];
+            basis.populate_caches(hw);
+            if let Some(dcache) = basis.dicts.get_mut(dict) {
+                let expiry = &basis.expiry;
+                dcache.key_list_filtered(hw, &basis.v2p_map, &basis.cipher,
+                    |key| query.matches(key) && expiry_live(expiry, dict, key, now), limit, &mut merge_list);
+                found_dict = true;
+            }
+        }
+        if found_dict {
+            Ok(merge_list)
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "dictionary not found"))
+        }
+    }
+
     /// This version of the call only removes one instance of a dictionary from the specified basis.
     /// Perhaps there also needs to be a `dict_remove_all` call which iterates through every basis
     /// makes sure the dictionary is removed from all the possible known basis. Anyways, that function
@@ -425,6 +519,7 @@ impl BasisCache {
             basis.dict_delete(hw, dict, paranoid)?;
             basis.basis_sync(hw);
             basis.pt_sync(hw);
+            self.expiry_forget(hw, basis_index, dict, None);
             Ok(())
         } else {
             Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."))
@@ -559,6 +654,7 @@ impl BasisCache {
                         // finally, sync the page tables.
                         basis.pt_sync(hw);
                     }
+                    self.expiry_forget(hw, basis_index, dict, Some(key));
                     return Ok(())
                 } else {
                     return Err(Error::new(ErrorKind::NotFound, "key not found"));
@@ -576,6 +672,20 @@ impl BasisCache {
     pub(crate) fn key_update(&mut self,
         hw: &mut PddbOs, dict: &str, key: &str, data: &[u8], offset: Option<usize>,
         alloc_hint: Option<usize>, basis_name: Option<&str>, truncate: bool) -> Result<()> {
+        self.key_update_with_sync(hw, dict, key, data, offset, alloc_hint, basis_name, truncate, true)
+    }
+    /// Same as `key_update`, but only the key's data is written out immediately. Its descriptor, the
+    /// basis root and the page table are left for the next `sync()`, which saves rewriting the
+    /// dictionary's descriptor page for every chunk of a key that is being streamed. Until then, a
+    /// power loss loses the writes that extended the key.
+    pub(crate) fn key_update_deferred(&mut self,
+        hw: &mut PddbOs, dict: &str, key: &str, data: &[u8], offset: Option<usize>,
+        alloc_hint: Option<usize>, basis_name: Option<&str>) -> Result<()> {
+        self.key_update_with_sync(hw, dict, key, data, offset, alloc_hint, basis_name, false, false)
+    }
+    fn key_update_with_sync(&mut self,
+        hw: &mut PddbOs, dict: &str, key: &str, data: &[u8], offset: Option<usize>,
+        alloc_hint: Option<usize>, basis_name: Option<&str>, truncate: bool, sync: bool) -> Result<()> {
 
         // we have to estimate how many pages are needed *before* we do anything, because we can't
         // mutate the page table to allocate data while we're accessing the page table. This huge gob of code
@@ -655,6 +765,7 @@ impl BasisCache {
                     pages_needed += reserved_pages;
                 }
             }
+            basis.quota_check(pages_needed)?;
         } else {
             return Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."));
         }
@@ -701,12 +812,14 @@ impl BasisCache {
                 // once we do have them.
                 dict_entry.sync_large_pool();
 
-                // encrypt and write the dict entry to disk
-                basis.dict_sync(hw, dict)?;
-                // sync the root basis structure as well, while we're at it...
-                basis.basis_sync(hw);
-                // finally, sync the page tables.
-                basis.pt_sync(hw);
+                if sync {
+                    // encrypt and write the dict entry to disk
+                    basis.dict_sync(hw, dict)?;
+                    // sync the root basis structure as well, while we're at it...
+                    basis.basis_sync(hw);
+                    // finally, sync the page tables.
+                    basis.pt_sync(hw);
+                }
             } else {
                 return Err(Error::new(ErrorKind::NotFound, "Requested dictionary not found, or could not be allocated."));
             }
@@ -717,7 +830,193 @@ impl BasisCache {
         }
     }
 
+    /// Applies `ops` to a basis as a single transaction: after a power loss, either all of them
+    /// are found on disk, or none of them are. See `txn.rs` for how this works.
+    pub(crate) fn txn_commit(&mut self, hw: &mut PddbOs, basis_name: Option<&str>, ops: &[TxnOp]) -> Result<()> {
+        let basis_index = match self.select_basis(basis_name) {
+            Some(index) => index,
+            None => return Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted.")),
+        };
+        let name = self.cache[basis_index].name.clone();
+        // flush any unrelated dirty state first, so that everything written while staging belongs to the transaction
+        self.cache[basis_index].sync(hw)?;
+
+        // every op can rewrite a dictionary page, a key descriptor page and a small pool page, plus its
+        // data if it's in the large pool; the basis root is rewritten once.
+        let mut shadow_pages = 1;
+        for op in ops {
+            shadow_pages += match op {
+                TxnOp::Write { data, .. } => 3 + data.len() / VPAGE_SIZE + 1,
+                TxnOp::Delete { .. } => 3,
+            };
+        }
+        // a delete can also rewrite the expiry table, which is a key of its own. Every rewrite lands
+        // in the same shadow pages, and deletes only shrink the table, so it's counted once.
+        if ops.iter().any(|op| matches!(op, TxnOp::Delete { .. })) {
+            self.expiry_load(hw, basis_index);
+            shadow_pages += 3 + self.cache[basis_index].expiry_encode().len() / VPAGE_SIZE + 1;
+        }
+        if !hw.txn_begin(shadow_pages, &self.cache) {
+            return Err(Error::new(ErrorKind::OutOfMemory, "No free space to stage transaction"));
+        }
+        let mut result = Ok(());
+        for op in ops {
+            result = match op {
+                TxnOp::Write { dict, key, data } =>
+                    self.key_update(hw, dict, key, data, Some(0), None, Some(&name), true),
+                TxnOp::Delete { dict, key } =>
+                    self.key_remove(hw, dict, key, Some(&name), false),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        if result.is_ok() {
+            result = self.cache[basis_index].sync(hw);
+        }
+        let stage = hw.txn_end().expect("transaction stage went missing");
+        if let Err(e) = result {
+            self.txn_rollback(hw, basis_index, stage);
+            return Err(e);
+        }
+        if stage.overflow {
+            self.txn_rollback(hw, basis_index, stage);
+            return Err(Error::new(ErrorKind::OutOfMemory, "Transaction touches too many pages"));
+        }
+
+        // turn the staged changes into a transaction record
+        let basis = &self.cache[basis_index];
+        let mut entries = Vec::<TxnEntry>::new();
+        let mut remaps = Vec::<(VirtAddr, PhysPage)>::new();
+        for (&original, &shadow) in stage.shadows.iter() {
+            let mut released = match basis.v2p_map.iter().find(|(_, pp)| pp.page_number() == original) {
+                Some((&va, &pp)) => {
+                    entries.push(TxnEntry::Map(va, shadow));
+                    remaps.push((va, shadow));
+                    pp
+                }
+                // the page was rewritten, then released within the transaction: the shadow is garbage
+                None => shadow,
+            };
+            released.set_space_state(SpaceState::Dirty);
+            released.set_journal(released.journal() + 1);
+            entries.push(TxnEntry::Release(released));
+        }
+        for (&page, &op) in stage.pt_ops.iter() {
+            if let Some(va) = op {
+                if let Some(&pp) = basis.v2p_map.get(&va) {
+                    if pp.page_number() == page && !stage.shadows.contains_key(&page) {
+                        entries.push(TxnEntry::Map(va, pp));
+                    }
+                }
+            }
+        }
+        for &pp in stage.releases.iter() {
+            // pages that were shadowed are already taken care of above
+            if !stage.shadows.contains_key(&pp.page_number()) {
+                entries.push(TxnEntry::Release(pp));
+            }
+        }
+        if entries.len() > TXN_MAX_ENTRIES {
+            self.txn_rollback(hw, basis_index, stage);
+            return Err(Error::new(ErrorKind::OutOfMemory, "Transaction touches too many pages"));
+        }
+        let mut record = match hw.try_fast_space_alloc() {
+            Some(pp) => pp,
+            None => {
+                self.txn_rollback(hw, basis_index, stage);
+                return Err(Error::new(ErrorKind::OutOfMemory, "No free space for transaction record"));
+            }
+        };
+        record.set_valid(true);
+        let basis = &self.cache[basis_index];
+        let mut block = txn_record_serialize(hw.trng_u32() % JOURNAL_RAND_RANGE, &entries);
+        hw.data_encrypt_and_patch_page(&basis.cipher, &basis.aad, &mut block, &record);
+        #[cfg(feature="hosted")]
+        self.txn_crash_at(TxnCrashPoint::BeforeCommit)?;
+
+        // this is the commit point
+        let basis = &self.cache[basis_index];
+        hw.pt_patch_mapping(VirtAddr::new(TXN_RECORD_VADDR).unwrap(), record.page_number(), &basis.cipher_ecb);
+        log::debug!("committed transaction on {} with {} entries", name, entries.len());
+        #[cfg(feature="hosted")]
+        self.txn_crash_at(TxnCrashPoint::AfterCommit)?;
+
+        #[cfg_attr(not(feature="hosted"), allow(unused_variables))]
+        for (rolled, entry) in entries.iter().enumerate() {
+            self.cache[basis_index].txn_roll_forward(hw, core::slice::from_ref(entry));
+            #[cfg(feature="hosted")]
+            self.txn_crash_at(TxnCrashPoint::RollForward(rolled + 1))?;
+        }
+        #[cfg(feature="hosted")]
+        self.txn_crash_at(TxnCrashPoint::BeforeRetire)?;
+        let basis = &mut self.cache[basis_index];
+        for (va, mut pp) in remaps {
+            pp.set_clean(true);
+            pp.set_valid(true);
+            basis.v2p_map.insert(va, pp);
+        }
+        hw.pt_erase(record.page_number());
+        hw.fast_space_free(&mut record);
+        // the replaced pages still hold the previous contents; clear them as an in-place write would have
+        let mut random = [0u8; PAGE_SIZE];
+        for &original in stage.shadows.keys() {
+            hw.trng_slice(&mut random);
+            hw.patch_data(&random, original * PAGE_SIZE as u32);
+        }
+        Ok(())
+    }
+
+    /// Fails with `Interrupted` if a test asked for `txn_commit()` to stop at `point`. The commit
+    /// is abandoned with the disk in whatever state it was in at that point, as after a power loss.
+    #[cfg(feature="hosted")]
+    fn txn_crash_at(&mut self, point: TxnCrashPoint) -> Result<()> {
+        if self.txn_crash == Some(point) {
+            self.txn_crash = None;
+            return Err(Error::new(ErrorKind::Interrupted, "Simulated power loss during transaction commit"));
+        }
+        Ok(())
+    }
+
+    /// Throws away a staged transaction. The cached copy of the basis has seen the discarded
+    /// writes, so it is reloaded from disk.
+    fn txn_rollback(&mut self, hw: &mut PddbOs, basis_index: usize, stage: TxnStage) {
+        hw.txn_discard(stage);
+        let basis = &self.cache[basis_index];
+        let keys = BasisKeys {
+            pt: basis.pt_key.into(),
+            data: basis.key.into(),
+        };
+        let name = basis.name.clone();
+        let (policy, policy_state) = (basis.policy, basis.policy_state);
+        match BasisCacheEntry::mount(hw, &name, &keys, false, policy) {
+            Some(mut remounted) => {
+                remounted.policy_state = policy_state;
+                self.cache[basis_index] = remounted;
+            }
+            None => {
+                log::error!("Couldn't remount {} after aborting a transaction; unmounting it", name);
+                self.cache.remove(basis_index);
+            }
+        }
+    }
+
+    /// Returns the attributes of a key, or NotFound if it has expired.
     pub(crate) fn key_attributes(&mut self, hw: &mut PddbOs, dict: &str, key: &str, basis_name: Option<&str>) -> Result<KeyAttributes> {
+        let mut attr = self.key_attributes_unfiltered(hw, dict, key, basis_name)?;
+        let basis_index = self.select_basis(Some(&attr.basis)).expect("key was found in a basis that isn't mounted");
+        if let Some(expiry) = self.expiry_get(hw, basis_index, dict, key) {
+            if let Some(now) = hw.rtc_secs() {
+                if expiry <= now {
+                    return Err(Error::new(ErrorKind::NotFound, "key has expired"));
+                }
+                attr.expires_in = Some(expiry - now);
+            }
+        }
+        Ok(attr)
+    }
+    /// Returns the attributes of a key whether or not it has expired. `expires_in` is always None.
+    fn key_attributes_unfiltered(&mut self, hw: &mut PddbOs, dict: &str, key: &str, basis_name: Option<&str>) -> Result<KeyAttributes> {
         if basis_name.is_none() {
             for basis in self.cache.iter_mut().rev() {
                 if !basis.ensure_dict_in_cache(hw, dict) {
@@ -737,6 +1036,7 @@ impl BasisCache {
                             basis: (&basis.name).to_string(),
                             flags: kcache.flags,
                             index: kcache.descriptor_index,
+                            expires_in: None,
                         })
                     } else {
                         return Err(Error::new(ErrorKind::NotFound, "key not found"));
@@ -761,6 +1061,7 @@ impl BasisCache {
                             basis: (&basis.name).to_string(),
                             flags: kcache.flags,
                             index: kcache.descriptor_index,
+                            expires_in: None,
                         })
                     } else {
                         return Err(Error::new(ErrorKind::NotFound, "key not found"));
@@ -772,6 +1073,123 @@ impl BasisCache {
         }
     }
 
+    /// Reads in the expiry table of a basis, if it isn't cached already.
+    fn expiry_load(&mut self, hw: &mut PddbOs, basis_index: usize) {
+        if self.cache[basis_index].expiry.is_some() {
+            return;
+        }
+        let name = self.cache[basis_index].name.clone();
+        let mut data = Vec::<u8>::new();
+        if let Ok(attr) = self.key_attributes_unfiltered(hw, PDDB_META_DICT, PDDB_EXPIRY_KEY, Some(&name)) {
+            data.resize(attr.len, 0);
+            match self.key_read(hw, PDDB_META_DICT, PDDB_EXPIRY_KEY, &mut data, None, Some(&name)) {
+                Ok(len) => data.truncate(len),
+                Err(e) => {
+                    log::error!("couldn't read the expiry table of {}: {:?}", name, e);
+                    data.clear();
+                }
+            }
+        }
+        self.cache[basis_index].expiry_decode(&data);
+    }
+    /// Writes the cached expiry table of a basis back to disk.
+    fn expiry_store(&mut self, hw: &mut PddbOs, basis_index: usize) -> Result<()> {
+        let name = self.cache[basis_index].name.clone();
+        let data = self.cache[basis_index].expiry_encode();
+        if self.dict_attributes(hw, PDDB_META_DICT, Some(&name)).is_err() {
+            if data.len() == 0 {
+                return Ok(());
+            }
+            self.dict_add(hw, PDDB_META_DICT, Some(&name))?;
+        }
+        self.key_update(hw, PDDB_META_DICT, PDDB_EXPIRY_KEY, &data, Some(0), None, Some(&name), true)
+    }
+    fn expiry_get(&mut self, hw: &mut PddbOs, basis_index: usize, dict: &str, key: &str) -> Option<u64> {
+        if dict == PDDB_META_DICT {
+            return None;
+        }
+        self.expiry_load(hw, basis_index);
+        self.cache[basis_index].expiry_get(dict, key)
+    }
+    /// Drops the expiry times of a deleted key, or of every key in a deleted dictionary if `key` is None.
+    fn expiry_forget(&mut self, hw: &mut PddbOs, basis_index: usize, dict: &str, key: Option<&str>) {
+        if dict == PDDB_META_DICT {
+            // the table itself was deleted
+            self.cache[basis_index].expiry = None;
+            return;
+        }
+        self.expiry_load(hw, basis_index);
+        if self.cache[basis_index].expiry_remove(dict, key) {
+            if let Err(e) = self.expiry_store(hw, basis_index) {
+                log::error!("couldn't update the expiry table of {}: {:?}", self.cache[basis_index].name, e);
+            }
+        }
+    }
+    /// Sets a key to expire `ttl` seconds from now, or clears its expiry time if `ttl` is None.
+    pub(crate) fn key_set_expiry(&mut self, hw: &mut PddbOs, dict: &str, key: &str, basis_name: Option<&str>,
+        ttl: Option<u64>) -> Result<()> {
+        if dict == PDDB_META_DICT {
+            return Err(Error::new(ErrorKind::PermissionDenied, "The PDDB's own records can't be given an expiry time"));
+        }
+        let attr = self.key_attributes(hw, dict, key, basis_name)?;
+        let basis_index = self.select_basis(Some(&attr.basis)).expect("key was found in a basis that isn't mounted");
+        self.expiry_load(hw, basis_index);
+        if let Some(ttl) = ttl {
+            let now = hw.rtc_secs().ok_or(Error::new(ErrorKind::Other, "RTC is not available"))?;
+            self.cache[basis_index].expiry.as_mut().unwrap()
+                .insert((dict.to_string(), key.to_string()), now.saturating_add(ttl));
+        } else if !self.cache[basis_index].expiry_remove(dict, Some(key)) {
+            return Ok(());
+        }
+        self.expiry_store(hw, basis_index)
+    }
+    /// Deletes a key right away if it has expired, rather than leaving it for `expire_keys()`, so
+    /// that a key created in its place doesn't inherit its expiry time. Returns true if it was deleted.
+    pub(crate) fn key_expire_now(&mut self, hw: &mut PddbOs, dict: &str, key: &str, basis_name: Option<&str>) -> bool {
+        let basis_index = match self.select_basis(basis_name) {
+            Some(index) => index,
+            None => return false,
+        };
+        let now = match hw.rtc_secs() {
+            Some(now) => now,
+            None => return false,
+        };
+        match self.expiry_get(hw, basis_index, dict, key) {
+            Some(expiry) if expiry <= now => {
+                let name = self.cache[basis_index].name.clone();
+                self.key_remove(hw, dict, key, Some(&name), false).is_ok()
+            }
+            _ => false,
+        }
+    }
+    /// Deletes every key that has expired. Returns the (basis, dict, key) of each key deleted, so
+    /// that handles to them can be invalidated.
+    pub(crate) fn expire_keys(&mut self, hw: &mut PddbOs) -> Vec<(String, String, String)> {
+        let mut expired = Vec::new();
+        let now = match hw.rtc_secs() {
+            Some(now) => now,
+            None => return expired,
+        };
+        for basis_index in 0..self.cache.len() {
+            self.expiry_load(hw, basis_index);
+            let name = self.cache[basis_index].name.clone();
+            for (dict, key) in self.cache[basis_index].expiry_due(now) {
+                // key_remove() also takes the key out of the table
+                match self.key_remove(hw, &dict, &key, Some(&name), false) {
+                    Ok(_) => {
+                        log::info!("{}:{} in {} has expired", dict, key, name);
+                        expired.push((name.clone(), dict, key));
+                    }
+                    Err(e) => {
+                        log::warn!("couldn't delete expired key {}:{} in {}: {:?}", dict, key, name, e);
+                        self.expiry_forget(hw, basis_index, &dict, Some(&key));
+                    }
+                }
+            }
+        }
+        expired
+    }
+
     pub(crate) fn dict_attributes(&mut self, hw: &mut PddbOs, dict: &str, basis_name: Option<&str>) -> Result<DictAttributes> {
         if basis_name.is_none() {
             for basis in self.cache.iter_mut().rev() {
@@ -806,6 +1224,12 @@ impl BasisCache {
     pub(crate) fn basis_unlock(&mut self, hw: &mut PddbOs, name: &str, password: &str,
     policy: BasisRetentionPolicy) -> Option<BasisCacheEntry> {
         let basis_key =  hw.basis_derive_key(name, password);
+        self.basis_unlock_with_keys(hw, name, &basis_key, policy)
+    }
+    /// Same as `basis_unlock`, but for when the keys are already known, e.g. the offline image tool
+    /// reading them from a key file.
+    pub(crate) fn basis_unlock_with_keys(&mut self, hw: &mut PddbOs, name: &str, basis_key: &BasisKeys,
+    policy: BasisRetentionPolicy) -> Option<BasisCacheEntry> {
         if let Some(basis_map) = hw.pt_scan_key(&basis_key.pt, &basis_key.data, name) {
             let aad = hw.data_aad(name);
             if let Some(root_page) = basis_map.get(&VirtAddr::new(VPAGE_SIZE as u64).unwrap()) {
@@ -831,7 +1255,7 @@ impl BasisCache {
                     return None;
                 }
                 log::debug!("Basis {} record found, generating cache entry", name);
-                BasisCacheEntry::mount(hw, &basis_name, &basis_key, false, policy)
+                BasisCacheEntry::mount(hw, &basis_name, basis_key, false, policy)
             } else {
                 None
             }
@@ -842,7 +1266,8 @@ impl BasisCache {
 
     /// this creates a basis entry in the PDDB, which should then be "mounted" to activate it.
     /// similar to how you might use fdisk to create a partition, but you still must call mount to access it.
-    pub(crate) fn basis_create(&mut self, hw: &mut PddbOs, name: &str, password: &str) -> Result<()> {
+    /// `quota` limits the space the basis may occupy, in bytes.
+    pub(crate) fn basis_create(&mut self, hw: &mut PddbOs, name: &str, password: &str, quota: Option<u64>) -> Result<()> {
         if !hw.fast_space_ensure_next_log() {
             return Err(Error::new(ErrorKind::OutOfMemory, "No free space to create basis"));
         };
@@ -862,7 +1287,8 @@ impl BasisCache {
             version: PDDB_VERSION,
             name: BasisRootName::try_from_str(name).unwrap(),
             age: 0,
-            num_dictionaries: 0
+            num_dictionaries: 0,
+            quota: quota.unwrap_or(0),
         };
         // allocate one page for the basis root
         if let Some(alloc) = hw.try_fast_space_alloc() {
@@ -1120,6 +1546,10 @@ pub(crate) struct BasisCacheEntry {
     pub policy: BasisRetentionPolicy,
     // rention state
     pub policy_state: u32,
+    /// storage quota in bytes, if any
+    pub quota: Option<u64>,
+    /// expiry times of keys, loaded on first use; see `expiry.rs`
+    pub expiry: Option<ExpiryTable>,
 }
 impl BasisCacheEntry {
     /// given a pointer to the hardware, name of the basis, and its cryptographic key, try to derive
@@ -1127,9 +1557,13 @@ impl BasisCacheEntry {
     /// If it `lazy` is false, it will populate the dictionary cache and key cache entries, as well as
     /// discover the location of the `large_alloc_ptr`.
     pub(crate) fn mount(hw: &mut PddbOs, name: &str,  key: &BasisKeys, lazy: bool, policy: BasisRetentionPolicy) -> Option<BasisCacheEntry> {
-        if let Some(basis_map) = hw.pt_scan_key(&key.pt, &key.data, name) {
+        if let Some(mut basis_map) = hw.pt_scan_key(&key.pt, &key.data, name) {
             let cipher = Aes256GcmSiv::new(&key.data.into());
             let aad = hw.data_aad(name);
+            // finish any transaction that was interrupted after it was committed
+            if let Some(&record) = basis_map.get(&VirtAddr::new(TXN_RECORD_VADDR).unwrap()) {
+                BasisCacheEntry::txn_recover(hw, &mut basis_map, &cipher, &key.pt, &aad, record, name);
+            }
             // get the first page, where the basis root is guaranteed to be
             if let Some(root_page) = basis_map.get(&VirtAddr::new(VPAGE_SIZE as u64).unwrap()) {
                 let vpage = match hw.data_decrypt_page_with_commit(&key.data, &aad, root_page) {
@@ -1173,6 +1607,8 @@ impl BasisCacheEntry {
                     large_alloc_ptr: None,
                     policy,
                     policy_state: policy.derive_init_state(),
+                    quota: if basis_root.quota == 0 { None } else { Some(basis_root.quota) },
+                    expiry: None,
                 };
                 if !lazy {
                     bcache.populate_caches(hw);
@@ -1189,6 +1625,47 @@ impl BasisCacheEntry {
             None
         }
     }
+    /// Applies the entries of a committed transaction record to the page table on disk.
+    pub(crate) fn txn_roll_forward(&self, hw: &mut PddbOs, entries: &[TxnEntry]) {
+        for entry in entries {
+            match entry {
+                TxnEntry::Map(va, pp) => hw.pt_patch_mapping(*va, pp.page_number(), &self.cipher_ecb),
+                TxnEntry::Release(pp) => hw.txn_release(*pp),
+            }
+        }
+    }
+    /// Rolls forward a transaction record found while mounting, and retires it. `basis_map` is
+    /// updated to match.
+    fn txn_recover(hw: &mut PddbOs, basis_map: &mut HashMap<VirtAddr, PhysPage>, cipher: &Aes256GcmSiv,
+        pt_key: &[u8; AES_KEYSIZE], aad: &[u8], mut record: PhysPage, name: &str
+    ) {
+        let cipher_ecb = Aes256::new(GenericArray::from_slice(pt_key));
+        match hw.data_decrypt_page(cipher, aad, &record).as_deref().and_then(txn_record_deserialize) {
+            Some(entries) => {
+                log::warn!("Basis {} has an interrupted transaction, rolling forward {} entries", name, entries.len());
+                for entry in entries.iter() {
+                    match entry {
+                        TxnEntry::Map(va, pp) => {
+                            hw.pt_patch_mapping(*va, pp.page_number(), &cipher_ecb);
+                            let mut pte = *pp;
+                            pte.set_clean(true);
+                            pte.set_valid(true);
+                            basis_map.insert(*va, pte);
+                        }
+                        TxnEntry::Release(pp) => {
+                            hw.txn_release(*pp);
+                            basis_map.retain(|_, mapped| mapped.page_number() != pp.page_number());
+                        }
+                    }
+                }
+            }
+            // a record that can't be read was never committed, so there is nothing to roll forward
+            None => log::error!("Basis {} has an unreadable transaction record, discarding it", name),
+        }
+        hw.pt_erase(record.page_number());
+        hw.fast_space_free(&mut record);
+        basis_map.remove(&VirtAddr::new(TXN_RECORD_VADDR).unwrap());
+    }
     /// called during the initial basis scan to track where the large allocation pointer end should be.
     /// basically try to find the maximal extent of already allocated data, and start allocating from there.
     pub(crate) fn large_pool_update(&mut self, maybe_end: u64) {
@@ -1615,6 +2092,7 @@ impl BasisCacheEntry {
                 name: BasisRootName::try_from_str(&self.name).unwrap(),
                 age: self.age,
                 num_dictionaries: self.num_dicts,
+                quota: self.quota.unwrap_or(0),
             };
             let pp = self.v2p_map.get(&VirtAddr::new(1 * VPAGE_SIZE as u64).unwrap())
                 .expect("Internal consistency error: Basis exists, but its root map was not allocated!");
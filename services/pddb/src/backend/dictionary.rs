@@ -197,14 +197,30 @@ impl DictCacheEntry {
     /// merges the list of keys in this dict cache entry into a merge_list.
     /// The `merge_list` is used because keys are presented as a union across all open basis.
     pub(crate) fn key_list(&mut self, hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>, cipher: &Aes256GcmSiv, merge_list: &mut BTreeSet<String>) {
+        self.key_list_filtered(hw, v2p_map, cipher, |_| true, usize::MAX, merge_list);
+    }
+    /// Same as `key_list`, but only adds the names that `filter` accepts, and keeps no more than
+    /// `limit` names in `merge_list`: once it is full, a name is only added in place of the last
+    /// one, so what's left are the first `limit` names in order, of the list and the dictionary
+    /// together. A query then never holds more than a page of names, however big the dictionary.
+    pub(crate) fn key_list_filtered<F: Fn(&str) -> bool>(&mut self, hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>,
+        cipher: &Aes256GcmSiv, filter: F, limit: usize, merge_list: &mut BTreeSet<String>) {
         // ensure that the key cache is filled
         if self.keys.len() < self.key_count as usize {
             self.fill(hw, v2p_map, cipher);
         }
         for (key, kcache) in self.keys.iter() {
-            if kcache.flags.valid() {
-                merge_list.insert(key.to_string());
+            if !kcache.flags.valid() || !filter(key) {
+                continue;
+            }
+            if merge_list.len() >= limit {
+                let last = match merge_list.iter().next_back() {
+                    Some(last) if key.as_str() < last.as_str() => last.to_string(),
+                    _ => continue,
+                };
+                merge_list.remove(&last);
             }
+            merge_list.insert(key.to_string());
         }
     }
     /// Simply ensures we have the description of a key in cache. Only tries to load small key data.
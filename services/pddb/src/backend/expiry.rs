@@ -0,0 +1,110 @@
+//! # Key expiry
+//!
+//! A key can be given a time after which it is deleted. There is no room left in the on-disk
+//! `KeyDescriptor` for this, so each basis keeps its expiry times in a table, stored as a single
+//! key in a dictionary reserved for the PDDB's own use. The dictionary is left out of dictionary
+//! listings, and clients can't open, list, delete or subscribe to it. The table is read in the
+//! first time it is needed, and is kept in the basis cache entry after that.
+//!
+//! Times are kept in seconds on the RTC, which keeps counting across reboots and is not moved
+//! when the user sets the clock, so a change of time zone doesn't bring a key's expiry closer.
+//! If the RTC can't be read, expiry times are recorded but not enforced.
+//!
+//! An expired key stops showing up in listings and can't be opened as soon as it expires; the
+//! `PeriodicScrub` pass deletes it from disk some time after that.
+
+use super::*;
+
+use std::collections::{HashMap, BTreeSet};
+use std::convert::TryInto;
+use core::mem::size_of;
+
+/// reserved for the PDDB's own records, and hidden from dictionary listings
+pub(crate) const PDDB_META_DICT: &'static str = "pddb.meta";
+pub(crate) const PDDB_EXPIRY_KEY: &'static str = "expiry";
+
+/// True if `dict` is, or lies below, the dictionary reserved for the PDDB's own records.
+pub(crate) fn dict_reserved(dict: &str) -> bool {
+    dict == PDDB_META_DICT
+        || dict.strip_prefix(PDDB_META_DICT).map_or(false, |rest| rest.starts_with(std::path::MAIN_SEPARATOR))
+}
+
+/// Maps a (dictionary, key) pair to the RTC second at which it expires.
+pub(crate) type ExpiryTable = HashMap<(String, String), u64>;
+
+impl BasisCacheEntry {
+    /// Replaces the cached expiry table with one read from disk. Each record is the expiry time
+    /// as a u64 LE, followed by the dictionary and key names, each preceded by their length in a
+    /// single byte.
+    pub(crate) fn expiry_decode(&mut self, data: &[u8]) {
+        let mut table = ExpiryTable::new();
+        let mut index = 0;
+        let name = |index: &mut usize| -> Option<String> {
+            let len = *data.get(*index)? as usize;
+            let bytes = data.get(*index + 1..*index + 1 + len)?;
+            *index += 1 + len;
+            String::from_utf8(bytes.to_vec()).ok()
+        };
+        while index + size_of::<u64>() <= data.len() {
+            let expiry = u64::from_le_bytes(data[index..index + size_of::<u64>()].try_into().unwrap());
+            index += size_of::<u64>();
+            match (name(&mut index), name(&mut index)) {
+                (Some(dict), Some(key)) => {table.insert((dict, key), expiry);},
+                _ => {
+                    log::error!("{}: expiry table is corrupted; ignoring the rest of it", self.name);
+                    break;
+                }
+            }
+        }
+        self.expiry = Some(table);
+    }
+    /// Serializes the cached expiry table, in the format read by `expiry_decode()`.
+    pub(crate) fn expiry_encode(&self) -> Vec<u8> {
+        let mut data = Vec::<u8>::new();
+        for ((dict, key), expiry) in self.expiry.as_ref().expect("expiry table was not loaded").iter() {
+            data.extend_from_slice(&expiry.to_le_bytes());
+            data.push(dict.len() as u8);
+            data.extend_from_slice(dict.as_bytes());
+            data.push(key.len() as u8);
+            data.extend_from_slice(key.as_bytes());
+        }
+        data
+    }
+    /// Returns the RTC second at which a key expires, if it has an expiry time.
+    pub(crate) fn expiry_get(&self, dict: &str, key: &str) -> Option<u64> {
+        self.expiry.as_ref()?.get(&(dict.to_string(), key.to_string())).copied()
+    }
+    /// Returns the (dict, key) pairs that have expired as of `now`.
+    pub(crate) fn expiry_due(&self, now: u64) -> Vec<(String, String)> {
+        self.expiry.as_ref().expect("expiry table was not loaded").iter()
+            .filter(|(_, &expiry)| expiry <= now)
+            .map(|(entry, _)| entry.clone())
+            .collect()
+    }
+    /// Drops the expiry time of a key, or of every key in `dict` if `key` is None. Returns true if
+    /// the table changed.
+    pub(crate) fn expiry_remove(&mut self, dict: &str, key: Option<&str>) -> bool {
+        let table = self.expiry.as_mut().expect("expiry table was not loaded");
+        let before = table.len();
+        table.retain(|(d, k), _| d != dict || key.map_or(false, |key| k != key));
+        table.len() != before
+    }
+    /// Removes the keys of `dict` from `list` that have expired as of `now`.
+    pub(crate) fn expiry_filter(&self, dict: &str, now: Option<u64>, list: &mut BTreeSet<String>) {
+        if now.is_some() {
+            list.retain(|key| expiry_live(&self.expiry, dict, key, now));
+        }
+    }
+}
+
+/// False if `table` says that `key` in `dict` has expired as of `now`. Takes the table rather than
+/// the basis, so it can be checked while the basis' dictionaries are borrowed.
+pub(crate) fn expiry_live(table: &Option<ExpiryTable>, dict: &str, key: &str, now: Option<u64>) -> bool {
+    match (now, table) {
+        (Some(now), Some(table)) => match table.get(&(dict.to_string(), key.to_string())) {
+            Some(&expiry) => expiry > now,
+            None => true,
+        },
+        _ => true,
+    }
+}
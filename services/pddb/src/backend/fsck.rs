@@ -0,0 +1,155 @@
+use crate::api::*;
+use super::*;
+
+use core::mem::size_of;
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// # Consistency checks
+///
+/// An interrupted write can leave a basis' page table, dictionaries and key descriptors
+/// disagreeing with each other. `fsck` walks a basis and looks for:
+///   - orphaned pages: mapped in the page table, at an address that isn't the basis root, the
+///     transaction record, a dictionary that exists, that dictionary's small pool, or the large
+///     pool extent of one of its keys
+///   - duplicate mappings: more than one physical page mapped to the same virtual page. Mounting
+///     picks the one with the newest journal revision; the others are superseded.
+///   - journal conflicts: duplicate mappings whose journal revisions are the same, so mounting
+///     had to pick one arbitrarily
+///   - dangling keys: key descriptors pointing at data that isn't mapped
+///
+/// Repair returns orphaned and superseded pages to FastSpace. Dangling keys are only reported, as
+/// their data is already gone.
+///
+/// Only pages whose data authenticates under the basis' own key are counted or reclaimed. A
+/// page table entry that merely decrypts to something that looks like a PTE may be a checksum
+/// collision with a page owned by a basis that isn't unlocked, and freeing it would destroy that
+/// basis. For the same reason, pages that aren't mapped by any unlocked basis can never be
+/// identified as orphans: they look exactly like the pages of a locked basis.
+impl BasisCacheEntry {
+    /// Adds this basis' findings to `report`, repairing them if `report.repair` is set. The basis
+    /// must have been synced first, so that the cache agrees with the disk.
+    pub(crate) fn fsck(&mut self, hw: &mut PddbOs, report: &mut PddbFsckReport) {
+        self.populate_caches(hw);
+        report.bases_checked += 1;
+        report.pages_checked += self.v2p_map.len() as u32;
+
+        // 1. duplicate mappings
+        let mut candidates = HashMap::<VirtAddr, Vec<PhysPage>>::new();
+        let pt_key: [u8; AES_KEYSIZE] = self.pt_key.as_slice().try_into().unwrap();
+        for (va, pp) in hw.pt_scan_candidates(&pt_key) {
+            candidates.entry(va).or_insert_with(Vec::new).push(pp);
+        }
+        let mut superseded = Vec::<PhysPage>::new();
+        for (va, pages) in candidates.iter().filter(|(_, pages)| pages.len() > 1) {
+            let current = match self.v2p_map.get(va) {
+                Some(pp) => pp.clone(),
+                None => continue,
+            };
+            let current_journal = self.page_journal(hw, *va, &current);
+            let mut duplicated = false;
+            for pp in pages.iter().filter(|pp| pp.page_number() != current.page_number()) {
+                if let Some(journal) = self.page_journal(hw, *va, pp) {
+                    log::warn!("{}: va {:x} is mapped to p{:x} and p{:x}", self.name, va.get(), current.page_number(), pp.page_number());
+                    duplicated = true;
+                    if Some(journal) == current_journal {
+                        report.journal_conflicts += 1;
+                    }
+                    superseded.push(pp.clone());
+                }
+            }
+            if duplicated {
+                report.duplicate_mappings += 1;
+            }
+        }
+
+        // 2. dangling keys
+        for dict in self.dicts.values().filter(|d| d.flags.valid()) {
+            for (name, key) in dict.keys.iter().filter(|(_, k)| k.flags.valid() && k.len > 0) {
+                let mapped = if key.start >= LARGE_POOL_START {
+                    (key.start..key.start + key.len).step_by(VPAGE_SIZE)
+                        .all(|va| self.v2p_map.contains_key(&VirtAddr::new(va).unwrap()))
+                } else if let Some(pool_index) = small_storage_index_from_key(key, dict.index) {
+                    let va = small_storage_base_vaddr_from_indices(dict.index, pool_index);
+                    self.v2p_map.contains_key(&VirtAddr::new(va).unwrap())
+                } else {
+                    false
+                };
+                if !mapped {
+                    log::warn!("{}: key {} points at unmapped data (start {:x}, len {})", self.name, name, key.start, key.len);
+                    report.dangling_keys += 1;
+                }
+            }
+        }
+
+        // 3. orphaned pages. If a dictionary couldn't be found, the pages it uses would look orphaned.
+        let mut orphans = Vec::<VirtAddr>::new();
+        let valid_dicts = self.dicts.values().filter(|d| d.flags.valid()).count() as u32;
+        if valid_dicts != self.num_dicts {
+            log::warn!("{}: only {} of {} dictionaries were found; not looking for orphans", self.name, valid_dicts, self.num_dicts);
+        } else {
+            let in_use = self.in_use_ranges();
+            for (&va, pp) in self.v2p_map.iter() {
+                // find the last range that starts at or below `va`
+                let i = in_use.partition_point(|&(start, _)| start <= va.get());
+                let covered = i > 0 && va.get() < in_use[i - 1].1;
+                if !covered && self.page_journal(hw, va, pp).is_some() {
+                    log::warn!("{}: va {:x} (p{:x}) is not used by anything", self.name, va.get(), pp.page_number());
+                    orphans.push(va);
+                }
+            }
+            report.orphaned_pages += orphans.len() as u32;
+        }
+
+        if report.repair {
+            for va in orphans {
+                let pp = self.v2p_map.get_mut(&va).unwrap();
+                hw.fast_space_free(pp);
+                report.pages_reclaimed += 1;
+            }
+            // superseded pages aren't in the v2p map, so pt_sync() won't erase their PTEs
+            for mut pp in superseded {
+                hw.pt_erase(pp.page_number());
+                hw.fast_space_free(&mut pp);
+                report.pages_reclaimed += 1;
+            }
+            self.pt_sync(hw);
+        }
+    }
+
+    /// The journal revision of a data page, if it authenticates as belonging to this basis.
+    fn page_journal(&self, hw: &PddbOs, va: VirtAddr, pp: &PhysPage) -> Option<JournalType> {
+        let data = if va.get() == VPAGE_SIZE as u64 {
+            hw.data_decrypt_page_with_commit(self.key.as_slice(), &self.aad, pp)
+        } else {
+            hw.data_decrypt_page(&self.cipher, &self.aad, pp)
+        }?;
+        Some(JournalType::from_le_bytes(data[..size_of::<JournalType>()].try_into().unwrap()))
+    }
+
+    /// Sorted, non-overlapping `[start, end)` ranges of virtual memory that are in use.
+    fn in_use_ranges(&self) -> Vec<(u64, u64)> {
+        let mut ranges = vec![
+            (VPAGE_SIZE as u64, 2 * VPAGE_SIZE as u64),
+            (TXN_RECORD_VADDR, TXN_RECORD_VADDR + VPAGE_SIZE as u64),
+        ];
+        for dict in self.dicts.values().filter(|d| d.flags.valid()) {
+            let dict_base = dict.index.get() as u64 * DICT_VSIZE;
+            ranges.push((dict_base, dict_base + DICT_VSIZE));
+            let pool_base = small_storage_base_vaddr_from_indices(dict.index, 0);
+            ranges.push((pool_base, pool_base + SMALL_POOL_STRIDE));
+            for key in dict.keys.values().filter(|k| k.flags.valid() && k.start >= LARGE_POOL_START) {
+                ranges.push((key.start, key.start + key.reserved));
+            }
+        }
+        ranges.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    }
+}
@@ -18,7 +18,8 @@ use std::io::SeekFrom;
 // Note that this is a concurrently accessed, unsafe, unchecked vector.
 struct FlashSingleton {
     memory: Vec::<u8>,
-    disk: File,
+    /// `None` when the image is opened read-only by the offline tool; patches then stay in memory.
+    disk: Option<File>,
 }
 
 fn flashmem() -> &'static mut FlashSingleton {
@@ -26,6 +27,14 @@ fn flashmem() -> &'static mut FlashSingleton {
     static ONCE: Once = Once::new();
 
     unsafe {
+        #[cfg(feature="offline")]
+        ONCE.call_once(|| {
+            SINGLETON.write(FlashSingleton {
+                memory: offline_image_read(),
+                disk: None,
+            });
+        });
+        #[cfg(not(feature="offline"))]
         ONCE.call_once(|| {
             let mut disk = OpenOptions::new()
             .read(true)
@@ -55,7 +64,7 @@ fn flashmem() -> &'static mut FlashSingleton {
 
             let flashmem = FlashSingleton {
                 memory,
-                disk,
+                disk: Some(disk),
             };
             SINGLETON.write(flashmem);
         });
@@ -63,6 +72,22 @@ fn flashmem() -> &'static mut FlashSingleton {
     }
 }
 
+/// Reads in the image configured for the offline tool.
+#[cfg(feature="offline")]
+fn offline_image_read() -> Vec::<u8> {
+    let (path, offset) = crate::backend::offline_image_path();
+    let mut disk = File::open(&path).expect("Can't open the PDDB image file");
+    disk.seek(SeekFrom::Start(offset)).expect("PDDB image is too short");
+    let mut memory = Vec::<u8>::with_capacity(PDDB_A_LEN);
+    disk.take(PDDB_A_LEN as u64).read_to_end(&mut memory).expect("Can't read the PDDB image file");
+    if memory.len() != PDDB_A_LEN {
+        log::warn!("PDDB image is of an incorrect size: got {}, expected {}", memory.len(), PDDB_A_LEN);
+        // treat the missing part as erased flash
+        memory.resize(PDDB_A_LEN, 0xFF);
+    }
+    memory
+}
+
 #[derive(Copy, Clone)]
 pub struct KeyExport {
     pub basis_name: [u8; 64],
@@ -95,17 +120,18 @@ impl EmuStorage {
             *b = 0xFF;
         }
     }
-    pub fn dump_fs(&self, name: &Option<String>) {
-        let defaultname = String::from("pddb");
-        let rootname = name.as_ref().unwrap_or(&defaultname);
-        let mut f = File::create(format!("../tools/pddb-images/{}.bin", rootname)).unwrap();
+    /// Re-reads the image file, as a fresh run of the offline tool would.
+    #[cfg(all(test, feature="offline"))]
+    pub fn reload(&mut self) {
+        flashmem().memory = offline_image_read();
+    }
+    pub fn dump_fs(&self, path: &str) {
+        let mut f = File::create(path).unwrap();
         f.write_all(flashmem().memory.as_slice()).unwrap();
         f.flush().unwrap();
     }
-    pub fn dump_keys(&self, known_keys: &[KeyExport], name: &Option<String>) {
-        let defaultname = String::from("pddb");
-        let rootname = name.as_ref().unwrap_or(&defaultname);
-        let mut f = File::create(format!("../tools/pddb-images/{}.key", rootname)).unwrap();
+    pub fn dump_keys(&self, known_keys: &[KeyExport], path: &str) {
+        let mut f = File::create(path).unwrap();
         f.write_all(&(known_keys.len() as u32).to_le_bytes()).unwrap();
         for key in known_keys {
             f.write_all(&key.basis_name).unwrap();
@@ -130,20 +156,24 @@ impl HostedSpinor {
         ) {
             *dst = src;
         }
-        flashmem().disk.seek(SeekFrom::Start(offset as u64)).expect("couldn't seek PDDB");
-        flashmem().disk.write(data).expect("couldn't write PDDB");
+        if let Some(disk) = flashmem().disk.as_mut() {
+            disk.seek(SeekFrom::Start(offset as u64)).expect("couldn't seek PDDB");
+            disk.write(data).expect("couldn't write PDDB");
+        }
         Ok(())
     }
     pub fn bulk_erase(&self, start: u32, len: u32) -> Result<(), xous::Error> {
         for b in flashmem().memory.as_mut_slice()[(start - xous::PDDB_LOC) as usize .. (start - xous::PDDB_LOC + len) as usize].iter_mut() {
             *b = 0xFF;
         }
-        flashmem().disk.seek(SeekFrom::Start(start as u64)).expect("couldn't seek PDDB");
-        let mut blank = Vec::<u8>::with_capacity(len as usize);
-        for _ in 0..len {
-            blank.push(0xFF);
+        if let Some(disk) = flashmem().disk.as_mut() {
+            disk.seek(SeekFrom::Start(start as u64)).expect("couldn't seek PDDB");
+            let mut blank = Vec::<u8>::with_capacity(len as usize);
+            for _ in 0..len {
+                blank.push(0xFF);
+            }
+            disk.write(&blank).expect("couldn't write PDDB");
         }
-        flashmem().disk.write(&blank).expect("couldn't write PDDB");
         Ok(())
     }
 }
\ No newline at end of file
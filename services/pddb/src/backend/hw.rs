@@ -44,6 +44,8 @@ pub(crate) const FSCB_PAGES: usize = 16;
 pub const PAGE_SIZE: usize = spinor::SPINOR_ERASE_SIZE as usize;
 /// size of a virtual page -- after the AES encryption and journaling overhead is subtracted
 pub const VPAGE_SIZE: usize = PAGE_SIZE - size_of::<Nonce>() - size_of::<Tag>() - size_of::<JournalType>();
+// Ensure that clients streaming a key agree with us on how much data fits in a page
+const _: () = assert!(VPAGE_SIZE == PDDB_STREAM_CHUNK, "PDDB_STREAM_CHUNK must be one vpage");
 
 /// length of the ciphertext in an AES-GCM-SIV page with key commitments
 /// equal to the total plaintext to be encrypted, including the journal number
@@ -129,10 +131,29 @@ type EmuMemoryRange = xous::MemoryRange;
 #[cfg(any(feature="precursor", feature="renode"))]
 type EmuSpinor = spinor::Spinor;
 
+// services; the offline image tool has no kernel to connect to
+#[cfg(not(feature="offline"))]
+type EmuRootKeys = root_keys::RootKeys;
+#[cfg(not(feature="offline"))]
+pub(crate) type EmuTicktimer = ticktimer_server::Ticktimer;
+#[cfg(not(feature="offline"))]
+type EmuRtc = llio::Llio;
+#[cfg(feature="offline")]
+type EmuRootKeys = OfflineRootKeys;
+#[cfg(feature="offline")]
+pub(crate) type EmuTicktimer = OfflineTicktimer;
+#[cfg(feature="offline")]
+type EmuRtc = OfflineRtc;
+
 pub(crate) struct PddbOs {
     spinor: EmuSpinor,
-    rootkeys: root_keys::RootKeys,
-    tt: ticktimer_server::Ticktimer,
+    rootkeys: EmuRootKeys,
+    tt: EmuTicktimer,
+    /// the real time clock, which keeps counting across reboots; used for key expiry
+    rtc: EmuRtc,
+    /// seconds added to every RTC reading, so tests can let keys expire
+    #[cfg(feature="hosted")]
+    pub(crate) rtc_skew: u64,
     pddb_mr: EmuMemoryRange,
     /// page table base -- location in FLASH, offset from physical bottom of pddb_mr
     pt_phys_base: PageAlignedPa,
@@ -156,6 +177,10 @@ pub(crate) struct PddbOs {
     fspace_log_next_addr: Option<PhysAddr>,
     /// track roughly how big the log has gotten, so we can pre-emptively garbage collect it before we get too full.
     fspace_log_len: usize,
+    /// writes held back while a transaction is being applied; see `txn.rs`
+    txn: RefCell<Option<TxnStage>>,
+    /// pages found in use by the last full free space scan, if there has been one since boot
+    scan_used_pages: Option<usize>,
     /// a cached copy of the FPGA's DNA ID, used in the AAA records.
     dna: u64,
     /// DNA for migrations from restored backups coming from different devices
@@ -172,6 +197,7 @@ pub(crate) struct PddbOs {
 
 impl PddbOs {
     pub fn new(trngpool: Rc<RefCell<TrngPool>>, pw_cid: xous::CID) -> PddbOs {
+        #[cfg(not(feature="offline"))]
         let xns = xous_names::XousNames::new().unwrap();
         #[cfg(any(feature="precursor", feature="renode"))]
         let pddb = xous::syscall::map_memory(
@@ -192,14 +218,21 @@ impl PddbOs {
         let fscb_phys_base = PageAlignedPa::from(mbbb_phys_base.as_u32() + MBBB_PAGES as u32 * PAGE_SIZE as u32);
         log::debug!("fscb_phys_base: {:x?}", fscb_phys_base);
 
+        #[cfg(not(feature="offline"))]
         let llio = llio::Llio::new(&xns);
+        #[cfg(not(feature="offline"))]
         let dna = llio.soc_dna().unwrap();
+        #[cfg(feature="offline")]
+        let llio = OfflineRtc::new();
+        #[cfg(feature="offline")]
+        let dna = offline_dna();
         // native hardware
         #[cfg(any(feature="precursor", feature="renode"))]
         let ret = PddbOs {
             spinor: spinor::Spinor::new(&xns).unwrap(),
             rootkeys: root_keys::RootKeys::new(&xns, Some(AesRootkeyType::User0)).expect("FATAL: couldn't access RootKeys!"),
             tt: ticktimer_server::Ticktimer::new().unwrap(),
+            rtc: llio,
             pddb_mr: pddb,
             pt_phys_base: PageAlignedPa::from(0 as u32),
             key_phys_base,
@@ -212,6 +245,8 @@ impl PddbOs {
             fspace_log_addrs: Vec::<PageAlignedPa>::new(),
             fspace_log_next_addr: None,
             fspace_log_len: 0,
+            txn: RefCell::new(None),
+            scan_used_pages: None,
             dna,
             // default to our own DNA in this case
             migration_dna: dna,
@@ -226,8 +261,14 @@ impl PddbOs {
         let ret = {
             PddbOs {
                 spinor: HostedSpinor::new(),
+                #[cfg(not(feature="offline"))]
                 rootkeys: root_keys::RootKeys::new(&xns, Some(AesRootkeyType::User0)).expect("FATAL: couldn't access RootKeys!"),
-                tt: ticktimer_server::Ticktimer::new().unwrap(),
+                #[cfg(feature="offline")]
+                rootkeys: OfflineRootKeys::new(),
+                tt: EmuTicktimer::new().unwrap(),
+                rtc: llio,
+                #[cfg(feature="hosted")]
+                rtc_skew: 0,
                 pddb_mr: EmuStorage::new(),
                 pt_phys_base: PageAlignedPa::from(0 as u32),
                 key_phys_base,
@@ -240,6 +281,8 @@ impl PddbOs {
                 fspace_log_addrs: Vec::<PageAlignedPa>::new(),
                 fspace_log_next_addr: None,
                 fspace_log_len: 0,
+                txn: RefCell::new(None),
+                scan_used_pages: None,
                 dna,
                 // default to our own DNA in this case
                 migration_dna: dna,
@@ -255,7 +298,17 @@ impl PddbOs {
 
     #[cfg(any(feature="hosted"))]
     pub fn dbg_dump(&self, name: Option<String>, extra_keys: Option<&Vec::<KeyExport>>) {
-        self.pddb_mr.dump_fs(&name);
+        let rootname = name.unwrap_or(String::from("pddb"));
+        self.dbg_dump_to(
+            &format!("../tools/pddb-images/{}.bin", rootname),
+            &format!("../tools/pddb-images/{}.key", rootname),
+            extra_keys
+        );
+    }
+    /// Writes the image, and a key file with the system basis keys and `extra_keys`, to the given paths.
+    #[cfg(any(feature="hosted"))]
+    pub fn dbg_dump_to(&self, image_path: &str, key_path: &str, extra_keys: Option<&Vec::<KeyExport>>) {
+        self.pddb_mr.dump_fs(image_path);
         let mut export = Vec::<KeyExport>::new();
         if let Some(key) = &self.system_basis_key {
             log::info!("(hosted mode debug) written key: {:x?}, {:x?}", key.pt, key.data);
@@ -276,7 +329,7 @@ impl PddbOs {
                 export.push(*key);
             }
         }
-        self.pddb_mr.dump_keys(&export, &name);
+        self.pddb_mr.dump_keys(&export, key_path);
     }
     #[allow(dead_code)]
     #[cfg(any(feature="precursor", feature="renode"))]
@@ -314,6 +367,15 @@ impl PddbOs {
         self.entropy.borrow_mut().get_u8()
     }
     pub(crate) fn timestamp_now(&self) -> u64 {self.tt.elapsed_ms()}
+    /// seconds on the real time clock, or None if it can't be read
+    pub(crate) fn rtc_secs(&self) -> Option<u64> {
+        // lets the tests make keys expire without waiting
+        #[cfg(feature="hosted")]
+        let skew = self.rtc_skew;
+        #[cfg(not(feature="hosted"))]
+        let skew = 0;
+        self.rtc.get_rtc_secs().ok().map(|secs| secs + skew)
+    }
     /// checks if the root keys are initialized, which is a prerequisite to formatting and mounting
     pub(crate) fn rootkeys_initialized(&self) -> bool {
         self.rootkeys.is_initialized().expect("couldn't query initialization state of the rootkeys server")
@@ -322,6 +384,19 @@ impl PddbOs {
     /// exactly to the first entry in the page table
     pub(crate) fn patch_data(&self, data: &[u8], offset: u32) {
         log::trace!("patch offset: {:x} len: {:x}", offset, data.len());
+        // while a transaction is staged, pages that hold data are never overwritten; see `txn.rs`
+        let offset = if let Some(stage) = self.txn.borrow_mut().as_mut() {
+            assert!(offset as usize % PAGE_SIZE == 0 && data.len() == PAGE_SIZE, "transactions can only stage whole-page writes");
+            match stage.write_target(offset / PAGE_SIZE as u32) {
+                Some(page) => page * PAGE_SIZE as u32,
+                None => {
+                    log::warn!("transaction ran out of shadow pages, dropping the write");
+                    return;
+                }
+            }
+        } else {
+            offset
+        };
         assert!(data.len() + offset as usize <= PDDB_A_LEN - self.data_phys_base.as_usize(), "attempt to store past disk boundary");
         self.spinor.patch(
             self.pddb_mr.as_slice(),
@@ -424,6 +499,10 @@ impl PddbOs {
     /// a *page number* (so a physical address divided by the page size). It's a slightly awkward units, but
     /// it saves a bit of math going back and forth between the native storage formats of the records.
     pub(crate) fn pt_patch_mapping(&self, va: VirtAddr, phys_page_num: u32, cipher: &Aes256) {
+        if let Some(stage) = self.txn.borrow_mut().as_mut() {
+            stage.pt_ops.insert(phys_page_num as PhysAddr, Some(va));
+            return;
+        }
         let mut pte = Pte::new(va, PtFlags::CLEAN, Rc::clone(&self.entropy));
         let mut block = Block::from_mut_slice(pte.deref_mut());
         //log::info!("pte pt: {:x?}", block);
@@ -434,6 +513,10 @@ impl PddbOs {
 
     /// erases a page table entry by overwriting it with garbage
     pub(crate) fn pt_erase(&mut self, phys_page_num: u32) {
+        if let Some(stage) = self.txn.borrow_mut().as_mut() {
+            stage.pt_ops.insert(phys_page_num as PhysAddr, None);
+            return;
+        }
         let mut eraseblock = [0u8; aes::BLOCK_SIZE];
         self.trng_slice(&mut eraseblock);
         self.patch_pagetable(&eraseblock, phys_page_num * aes::BLOCK_SIZE as u32);
@@ -572,6 +655,35 @@ impl PddbOs {
             None
         }
     }
+    /// Returns every page table entry that decrypts under `pt_key`, without resolving conflicts the
+    /// way `pt_scan_key()` does. A VA can appear more than once, and an entry may be a checksum
+    /// collision rather than a page that belongs to the basis; the caller has to check the data.
+    pub(crate) fn pt_scan_candidates(&self, pt_key: &[u8; AES_KEYSIZE]) -> Vec::<(VirtAddr, PhysPage)> {
+        let cipher = Aes256::new(&GenericArray::from_slice(pt_key));
+        let mut candidates = Vec::new();
+        let blank = [0xffu8; aes::BLOCK_SIZE];
+        for (page_index, pt_page) in self.pt_as_slice().chunks(PAGE_SIZE).enumerate() {
+            let clean_page = if pt_page[..aes::BLOCK_SIZE] == blank {
+                self.mbbb_retrieve().unwrap_or(pt_page)
+            } else {
+                pt_page
+            };
+            for (index, candidate) in clean_page.chunks(aes::BLOCK_SIZE).enumerate() {
+                let mut block = Block::clone_from_slice(candidate);
+                cipher.decrypt_block(&mut block);
+                if let Some(pte) = Pte::try_from_slice(block.as_slice()) {
+                    let mut pp = PhysPage(0);
+                    pp.set_page_number(((page_index * PAGE_SIZE / aes::BLOCK_SIZE) + index) as PhysAddr);
+                    pp.set_clean(true);
+                    pp.set_valid(true);
+                    pp.set_space_state(SpaceState::Used);
+                    self.resolve_pp_journal(&mut pp);
+                    candidates.push((pte.vaddr(), pp));
+                }
+            }
+        }
+        candidates
+    }
     /// Pages drawn from disk might already have come from the FSCB. We need to make the journal number
     /// of these consistent with those in the FSCB so later on when they are retired we don't have journal conflicts.
     fn resolve_pp_journal(&self, pp: &mut PhysPage) {
@@ -609,6 +721,13 @@ impl PddbOs {
         self.system_basis_key = None;
         self.cipher_ecb = None;
     }
+    /// The offline image tool can't unwrap the system basis key, as the root keys never leave the
+    /// device. It's handed the key instead, e.g. from the key file written by `dbg_dump()`.
+    #[cfg(feature="offline")]
+    pub(crate) fn set_system_key(&mut self, keys: BasisKeys) {
+        self.cipher_ecb = Some(Aes256::new(GenericArray::from_slice(&keys.pt)));
+        self.system_basis_key = Some(keys);
+    }
     pub(crate) fn clear_password(&self) {
         self.rootkeys.clear_password(AesRootkeyType::User0);
     }
@@ -969,6 +1088,7 @@ impl PddbOs {
         let total_pages = (PDDB_A_LEN - self.data_phys_base.as_usize()) / PAGE_SIZE;
         let total_free_pages = total_pages - total_used_pages;
         log::info!("page alloc: {} used; {} free; {} total", total_used_pages, total_free_pages, total_pages);
+        self.scan_used_pages = Some(total_used_pages);
         if total_free_pages == 0 {
             log::warn!("Disk is out of space, no free pages available!");
             // return an empty free_pool vector.
@@ -1195,6 +1315,20 @@ impl PddbOs {
     pub fn fast_space_len(&self) -> usize {
         self.fspace_cache.len()
     }
+    /// Returns the size of the data area in bytes, and an estimate of how much of it is free. The
+    /// estimate starts from the last full free space scan, less the pages handed out of FastSpace
+    /// since, so it's `None` until a scan has been done. Pages of bases that weren't unlocked
+    /// during the scan were counted as free.
+    pub(crate) fn space_usage(&self) -> (u64, Option<u64>) {
+        let total_pages = (PDDB_A_LEN - self.data_phys_base.as_usize()) / PAGE_SIZE;
+        let free_pages = self.scan_used_pages.map(|used| {
+            let allocated = self.fspace_cache.iter()
+                .filter(|pp| pp.space_state() == SpaceState::Used || pp.space_state() == SpaceState::MaybeUsed)
+                .count();
+            total_pages.saturating_sub(used).saturating_sub(allocated)
+        });
+        ((total_pages * PAGE_SIZE) as u64, free_pages.map(|pages| (pages * PAGE_SIZE) as u64))
+    }
     /// Normally, the fspace_log_next_addr is just incremented, but when it hits the end of the
     /// page, it's set to None. This function will do a modestly expensive scan of the FSCB area
     /// to try and either find another partially filled page, or a completely empty page.
@@ -1327,20 +1461,37 @@ impl PddbOs {
             }
             if let Some(alloc) = maybe_alloc {
                 assert!(self.fspace_cache.remove(&alloc), "inconsistent state: we found a free page, but later when we tried to update it, it wasn't there!");
+                if let Some(stage) = self.txn.borrow_mut().as_mut() {
+                    stage.fresh.insert(alloc);
+                }
             }
             maybe_alloc
         }
     }
     pub fn fast_space_free(&mut self, pp: &mut PhysPage) {
+        log::debug!("fast_space_free pp incoming: {:x?}", pp);
+        pp.set_space_state(SpaceState::Dirty);
+        pp.set_journal(pp.journal() + 1);
+        let staged = match self.txn.borrow_mut().as_mut() {
+            Some(stage) => {
+                // the page may still be referenced on disk until the transaction commits
+                stage.releases.push(pp.clone());
+                true
+            }
+            None => false,
+        };
+        if !staged {
+            self.fast_space_log_free(pp.clone());
+        }
+        // mark the page as invalid, so that it will be deleted on the next PT sync
+        pp.set_valid(false);
+    }
+    /// Records a page that has already been marked `Dirty` in the fspace cache and the journal.
+    fn fast_space_log_free(&mut self, pp: PhysPage) {
         self.fast_space_ensure_next_log();
         if !self.fspace_cache.remove(&pp) {
             log::warn!("Freeing a page that's not already in cache: {:x?}", pp);
         }
-        // update the fspace cache
-        log::debug!("fast_space_free pp incoming: {:x?}", pp);
-        pp.set_space_state(SpaceState::Dirty);
-        pp.set_journal(pp.journal() + 1);
-
         // re-cycle the space into the fspace_cache
         self.fspace_cache.insert(pp.clone());
 
@@ -1362,8 +1513,6 @@ impl PddbOs {
             // fspace_log_next_addr is already None because we used "take()". We'll find a free spot for the
             // next journal entry the next time around.
         }
-        // mark the page as invalid, so that it will be deleted on the next PT sync
-        pp.set_valid(false);
     }
     /// This is a "look before you leap" function that will potentially pause all system operations
     /// and do a deep scan for space if the required amount is not available.
@@ -1377,7 +1526,11 @@ impl PddbOs {
         && (self.fspace_log_len < (FSCB_PAGES - FASTSPACE_PAGES - 1) * PAGE_SIZE / aes::BLOCK_SIZE) {
             true
         } else {
-            if !has_pages {
+            if !has_pages && self.txn.borrow().is_some() {
+                // a full-space scan would see the staged pages as free, because they aren't in any page table yet
+                log::warn!("Out of FastSpace while a transaction is staged");
+                false
+            } else if !has_pages {
                 log::warn!("FastSpace alloc forced by lack of free space");
                 // if we're really out of space, do an expensive full-space sweep
                 if let Some(used_pages) = self.pddb_generate_used_map(cache) {
@@ -1420,6 +1573,11 @@ impl PddbOs {
             }
         }
     }
+    /// A snapshot of the pages tracked in the FastSpace cache, for the offline image tool's consistency check
+    #[cfg(feature="offline")]
+    pub(crate) fn fast_space_pages(&self) -> Vec::<PhysPage> {
+        self.fspace_cache.iter().cloned().collect()
+    }
     /// This is a "fast" flush that expires all the PDDB SpaceUpdate journal
     pub(crate) fn fast_space_flush(&mut self) {
         let mut fast_space = FastSpace {
@@ -1444,6 +1602,56 @@ impl PddbOs {
         log::info!("Flush took {}ms", self.timestamp_now() - start);
     }
 
+    /// Starts staging writes for a transaction, reserving `shadow_pages` pages to hold copies of
+    /// the pages it rewrites. Returns false if the space couldn't be reserved.
+    pub(crate) fn txn_begin(&mut self, shadow_pages: usize, cache: &Vec::<BasisCacheEntry>) -> bool {
+        assert!(self.txn.borrow().is_none(), "transactions can't be nested");
+        if !self.ensure_fast_space_alloc(shadow_pages, cache) {
+            return false;
+        }
+        let mut spares = Vec::new();
+        for _ in 0..shadow_pages {
+            match self.try_fast_space_alloc() {
+                Some(pp) => spares.push(pp),
+                None => {
+                    for mut pp in spares {
+                        self.fast_space_free(&mut pp);
+                    }
+                    return false;
+                }
+            }
+        }
+        self.txn.replace(Some(TxnStage::new(spares)));
+        true
+    }
+    /// Stops staging writes, and returns what was staged. Unused spares are returned to FastSpace.
+    pub(crate) fn txn_end(&mut self) -> Option<TxnStage> {
+        let mut stage = self.txn.take()?;
+        for mut pp in stage.spares.drain(..) {
+            self.fast_space_free(&mut pp);
+        }
+        Some(stage)
+    }
+    /// Throws away a transaction that won't be committed. None of the pages it used are referenced
+    /// on disk, so they can go straight back to FastSpace; the pages it released are left alone.
+    pub(crate) fn txn_discard(&mut self, stage: TxnStage) {
+        for mut pp in stage.shadows.into_values().chain(stage.fresh.into_iter()) {
+            self.fast_space_free(&mut pp);
+        }
+    }
+    /// Erases the page table entry of a page that a committed transaction replaced or freed, and
+    /// returns the page to FastSpace. This may be repeated when a transaction is recovered, so pages
+    /// that have already been logged with this journal revision or later are left alone.
+    pub(crate) fn txn_release(&mut self, pp: PhysPage) {
+        self.pt_erase(pp.page_number());
+        if let Some(logged) = self.fspace_cache.get(&pp) {
+            if logged.journal() >= pp.journal() {
+                return;
+            }
+        }
+        self.fast_space_log_free(pp);
+    }
+
     pub(crate) fn data_aad(&self, name: &str) -> Vec::<u8> {
         let mut aad = Vec::<u8>::new();
         aad.extend_from_slice(&name.as_bytes());
@@ -1455,13 +1663,22 @@ impl PddbOs {
         aad
     }
 
+    /// returns the ciphertext of a data page, following any shadow copy made by a staged transaction
+    fn data_page_slice(&self, page: &PhysPage) -> &[u8] {
+        let page_number = match self.txn.borrow().as_ref() {
+            Some(stage) => stage.read_target(page.page_number()),
+            None => page.page_number(),
+        } as usize;
+        &self.pddb_mr.as_slice()[
+            self.data_phys_base.as_usize() + page_number * PAGE_SIZE ..
+            self.data_phys_base.as_usize() + (page_number + 1) * PAGE_SIZE]
+    }
+
     /// returns a decrypted page that still includes the journal number at the very beginning
     /// We don't clip it off because it would require re-allocating a vector, and it's cheaper (although less elegant) to later
     /// just index past it.
     pub(crate) fn data_decrypt_page(&self, cipher: &Aes256GcmSiv, aad: &[u8], page: &PhysPage) -> Option<Vec::<u8>> {
-        let ct_slice = &self.pddb_mr.as_slice()[
-            self.data_phys_base.as_usize() + page.page_number() as usize * PAGE_SIZE ..
-            self.data_phys_base.as_usize() + (page.page_number() as usize + 1) * PAGE_SIZE];
+        let ct_slice = self.data_page_slice(page);
         let nonce = &ct_slice[..size_of::<Nonce>()];
         let ct = &ct_slice[size_of::<Nonce>()..];
         match cipher.decrypt(
@@ -1499,9 +1716,7 @@ impl PddbOs {
         const KCOM_NONCE_LEN: usize = 32;
         const KCOM_LEN: usize = 32;
         const MAC_LEN: usize = 16;
-        let ct_slice = &self.pddb_mr.as_slice()[
-            self.data_phys_base.as_usize() + page.page_number() as usize * PAGE_SIZE ..
-            self.data_phys_base.as_usize() + (page.page_number() as usize + 1) * PAGE_SIZE];
+        let ct_slice = self.data_page_slice(page);
         log::debug!("commit data at 0x{:x}", self.data_phys_base.as_usize() + page.page_number() as usize * PAGE_SIZE);
         let nonce = &ct_slice[..size_of::<Nonce>()];
         let ct_total = &ct_slice[size_of::<Nonce>()..];
@@ -1975,6 +2190,7 @@ impl PddbOs {
             name: BasisRootName::try_from_str(PDDB_DEFAULT_SYSTEM_BASIS).unwrap(),
             age: 0,
             num_dictionaries: 0,
+            quota: 0,
         };
 
         // step 7. Create a hashmap for our reverse PTE, allocate sectors, and add it to the Pddb's cache
@@ -2604,7 +2820,7 @@ impl PddbOs {
                     if maybe_basis.is_some() {
                         cache.basis_add(maybe_basis.unwrap());
                     } else {
-                        cache.basis_create(self, &name, &name).expect("couldn't create basis");
+                        cache.basis_create(self, &name, &name, None).expect("couldn't create basis");
                         let basis = cache.basis_unlock(self, &name, &name, BasisRetentionPolicy::Persist)
                             .expect("couldn't open just created basis");
                         cache.basis_add(basis);
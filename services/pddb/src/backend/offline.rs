@@ -0,0 +1,108 @@
+//! Stand-ins for the services that the backend talks to, used when it is built into the offline
+//! image tool (`--features offline`). There is no kernel to connect to in that case, so these
+//! answer everything locally. They only implement the calls that `PddbOs` and `BasisCache` make.
+use aes::Block;
+use root_keys::api::{AesRootkeyType, KeywrapError};
+use rand::RngCore;
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Where the offline tool found its image, and the device DNA to authenticate it with
+pub struct OfflineImage {
+    pub path: String,
+    /// byte offset of the PDDB within the file; backups carry a header in front of it
+    pub offset: u64,
+    pub dna: u64,
+}
+static OFFLINE_IMAGE: Mutex<Option<OfflineImage>> = Mutex::new(None);
+
+/// Must be called before the `PddbOs` is created, as that is when the image is read in.
+pub fn offline_image_set(image: OfflineImage) {
+    *OFFLINE_IMAGE.lock().unwrap() = Some(image);
+}
+pub(crate) fn offline_image_path() -> (String, u64) {
+    let image = OFFLINE_IMAGE.lock().unwrap();
+    let image = image.as_ref().expect("offline image was not configured");
+    (image.path.clone(), image.offset)
+}
+pub(crate) fn offline_dna() -> u64 {
+    OFFLINE_IMAGE.lock().unwrap().as_ref().expect("offline image was not configured").dna
+}
+
+/// The root keys live in the device's key ROM, so nothing can be wrapped or unwrapped offline.
+/// The system basis key has to be handed to `PddbOs::set_system_key()` instead.
+pub(crate) struct OfflineRootKeys {}
+impl OfflineRootKeys {
+    pub fn new() -> Self {
+        OfflineRootKeys {}
+    }
+    pub fn is_efuse_secured(&self) -> Result<Option<bool>, xous::Error> {
+        Ok(None)
+    }
+    pub fn is_initialized(&self) -> Result<bool, xous::Error> {
+        Ok(true)
+    }
+    pub fn clear_password(&self, _pass_type: AesRootkeyType) {}
+    #[cfg(not(test))]
+    pub fn wrap_key(&self, _input: &[u8]) -> Result<Vec<u8>, KeywrapError> {
+        Err(KeywrapError::IntegrityCheckFailed)
+    }
+    #[cfg(not(test))]
+    pub fn unwrap_key(&self, _wrapped: &[u8], _expected_len: usize) -> Result<Vec<u8>, KeywrapError> {
+        Err(KeywrapError::IntegrityCheckFailed)
+    }
+    /// Tests format their own images, so wrapping is stubbed out: the key is stored in the clear,
+    /// padded to the length of a wrapped key. Reading the image back still goes through a key file.
+    #[cfg(test)]
+    pub fn wrap_key(&self, input: &[u8]) -> Result<Vec<u8>, KeywrapError> {
+        let mut wrapped = input.to_vec();
+        wrapped.resize(input.len() + 8, 0);
+        Ok(wrapped)
+    }
+    #[cfg(test)]
+    pub fn unwrap_key(&self, wrapped: &[u8], expected_len: usize) -> Result<Vec<u8>, KeywrapError> {
+        Ok(wrapped[..expected_len].to_vec())
+    }
+    pub fn decrypt_block(&self, _block: &mut Block) {}
+    pub fn do_reset_dont_ask_init(&self) {}
+}
+
+pub(crate) struct OfflineTicktimer {
+    start: Instant,
+}
+impl OfflineTicktimer {
+    pub fn new() -> Result<Self, xous::Error> {
+        Ok(OfflineTicktimer { start: Instant::now() })
+    }
+    pub fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+    pub fn sleep_ms(&self, ms: usize) -> Result<(), xous::Error> {
+        std::thread::sleep(Duration::from_millis(ms as u64));
+        Ok(())
+    }
+}
+
+/// The device's RTC counts from an arbitrary point that the host can't know, so expiry times can't
+/// be judged offline; keys are left alone until the device itself sees that they have expired.
+pub(crate) struct OfflineRtc {}
+impl OfflineRtc {
+    pub fn new() -> Self {
+        OfflineRtc {}
+    }
+    pub fn get_rtc_secs(&self) -> Result<u64, xous::Error> {
+        Err(xous::Error::ServerNotFound)
+    }
+}
+
+/// Entropy from the host OS, in place of the TRNG server
+pub(crate) struct OfflineTrng {}
+impl OfflineTrng {
+    pub fn new() -> Self {
+        OfflineTrng {}
+    }
+    pub fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand::thread_rng().fill_bytes(dest);
+    }
+}
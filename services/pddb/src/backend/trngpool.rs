@@ -3,17 +3,27 @@ use aes_gcm_siv::Nonce;
 use std::convert::TryInto;
 use core::cell::RefCell;
 
+#[cfg(not(feature="offline"))]
+type EmuTrng = trng::Trng;
+#[cfg(feature="offline")]
+type EmuTrng = crate::backend::OfflineTrng;
+
 /// Crate-shared resource for TRNGs.
 pub(crate) struct TrngPool {
-    trng: RefCell::<trng::Trng>,
+    trng: RefCell::<EmuTrng>,
     /// The PDDB eats a lot of entropy. Keep a local pool of entropy, so we're not wasting a lot of
     /// overhead passing messages to the TRNG hardware server.
     e_cache: RefCell::<Vec::<u8>>,
 }
 impl TrngPool {
     pub fn new() -> Self {
-        let xns = xous_names::XousNames::new().unwrap();
-        let mut trng = trng::Trng::new(&xns).unwrap();
+        #[cfg(not(feature="offline"))]
+        let mut trng = {
+            let xns = xous_names::XousNames::new().unwrap();
+            trng::Trng::new(&xns).unwrap()
+        };
+        #[cfg(feature="offline")]
+        let mut trng = EmuTrng::new();
         let mut cache: [u8; 8192] = [0; 8192];
         trng.fill_bytes(&mut cache);
         TrngPool {
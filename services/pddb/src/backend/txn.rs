@@ -0,0 +1,226 @@
+/// # Transactions
+///
+/// Every write to the PDDB normally goes straight to its page on disk, so a power loss in between
+/// two related writes leaves the basis with only some of them applied. A transaction makes a set of
+/// key writes and deletes within one basis land all-or-nothing.
+///
+/// While a transaction is being applied, `PddbOs` stages the writes instead of performing them:
+///   - Pages that already hold data are never overwritten. The new contents go into a *shadow* page
+///     drawn from FastSpace, and reads of the original page are served from the shadow for the rest
+///     of the transaction. Shadow copies are encrypted with a bumped journal revision, like any
+///     other re-write of the page.
+///   - Pages newly allocated by the transaction are written in place, because nothing on disk
+///     refers to them yet.
+///   - Page table updates and page frees are held back, so the page table on disk still describes
+///     the state from before the transaction.
+///
+/// Once every operation has been applied in RAM, the held-back changes are written into a
+/// transaction record page, which is mapped at `TXN_RECORD_VADDR` in the basis. Writing the page
+/// table entry for the record is the commit point: it is a single 16-byte write. The record is then
+/// rolled forward, which writes the page table entries, erases the entries of the pages the
+/// transaction replaced or released, and logs those pages as `Dirty` in FastSpace. Finally the
+/// record itself is retired.
+///
+/// If power is lost before the commit point, the shadow and newly allocated pages are unreachable,
+/// and the basis mounts in its previous state. The pages are lost to FastSpace until the next full
+/// free space scan, the same as any allocation that is interrupted before it is used. If power is
+/// lost after the commit point, the record is found when the basis is next mounted, and rolled
+/// forward again. Roll-forward is idempotent.
+///
+/// The record is a single VPAGE, which limits how many pages one transaction can touch; see
+/// `TXN_MAX_ENTRIES`. Clients are also limited in how much they can stage, since staged operations
+/// are held in RAM until the commit; see `TXN_MAX_OPS` and `TXN_MAX_DATA`.
+
+use crate::*;
+use core::mem::size_of;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryInto;
+
+/// Virtual address of the transaction record. This is the VPAGE right after the basis root, which
+/// is otherwise unused.
+pub(crate) const TXN_RECORD_VADDR: u64 = 2 * VPAGE_SIZE as u64;
+/// "PTXN", identifies a transaction record after decryption
+const TXN_MAGIC: [u8; 4] = [0x50, 0x54, 0x58, 0x4e];
+/// Length of a serialized `TxnEntry`
+const TXN_ENTRY_LEN: usize = 24;
+/// Length of the record header: the magic number, plus a count of entries
+const TXN_HEADER_LEN: usize = 8;
+/// The most entries that fit in a transaction record. Each page a transaction rewrites costs two
+/// entries (map the shadow, release the original); each page it allocates or frees costs one.
+pub(crate) const TXN_MAX_ENTRIES: usize = (VPAGE_SIZE - TXN_HEADER_LEN) / TXN_ENTRY_LEN;
+/// The most operations that can be staged in one transaction. Every operation rewrites at least
+/// one page of the dictionary it touches, so many more than this would not fit in the record anyway.
+pub(crate) const TXN_MAX_OPS: usize = 64;
+/// The most data that can be staged in one transaction. Each page of data rewritten costs two
+/// record entries, so a transaction carrying more than this can never be committed.
+pub(crate) const TXN_MAX_DATA: usize = TXN_MAX_ENTRIES / 2 * VPAGE_SIZE;
+
+/// A change to the page table, as recorded in a transaction record.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TxnEntry {
+    /// Map the virtual address to the physical page.
+    Map(VirtAddr, PhysPage),
+    /// Erase the page's page table entry, and return it to FastSpace. The `PhysPage` carries
+    /// the journal revision to log it under.
+    Release(PhysPage),
+}
+impl TxnEntry {
+    fn serialize(&self, dest: &mut [u8]) {
+        let (op, va, pp) = match self {
+            TxnEntry::Map(va, pp) => (1u32, va.get(), pp.0 as u64),
+            TxnEntry::Release(pp) => (2u32, 0, pp.0 as u64),
+        };
+        dest[..4].copy_from_slice(&op.to_le_bytes());
+        dest[4..8].copy_from_slice(&[0u8; 4]);
+        dest[8..16].copy_from_slice(&va.to_le_bytes());
+        dest[16..24].copy_from_slice(&pp.to_le_bytes());
+    }
+    fn deserialize(src: &[u8]) -> Option<TxnEntry> {
+        let op = u32::from_le_bytes(src[..4].try_into().unwrap());
+        let va = u64::from_le_bytes(src[8..16].try_into().unwrap());
+        let pp = PhysPage(u64::from_le_bytes(src[16..24].try_into().unwrap()) as PhysAddr);
+        match op {
+            1 => Some(TxnEntry::Map(VirtAddr::new(va)?, pp)),
+            2 => Some(TxnEntry::Release(pp)),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes a transaction record into a page-sized buffer, including the journal revision at
+/// the top, ready to be passed to `data_encrypt_and_patch_page()`.
+pub(crate) fn txn_record_serialize(journal: JournalType, entries: &[TxnEntry]) -> Vec::<u8> {
+    assert!(entries.len() <= TXN_MAX_ENTRIES, "transaction record overflow");
+    let mut page = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
+    page[..size_of::<JournalType>()].copy_from_slice(&journal.to_le_bytes());
+    let record = &mut page[size_of::<JournalType>()..];
+    record[..4].copy_from_slice(&TXN_MAGIC);
+    record[4..8].copy_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (entry, dest) in entries.iter().zip(record[TXN_HEADER_LEN..].chunks_exact_mut(TXN_ENTRY_LEN)) {
+        entry.serialize(dest);
+    }
+    page
+}
+
+/// Recovers the entries from a decrypted transaction record, which still has the journal revision
+/// on top. Returns `None` if the record is not well-formed.
+pub(crate) fn txn_record_deserialize(page: &[u8]) -> Option<Vec::<TxnEntry>> {
+    let record = &page[size_of::<JournalType>()..];
+    if record[..4] != TXN_MAGIC {
+        return None;
+    }
+    let count = u32::from_le_bytes(record[4..8].try_into().unwrap()) as usize;
+    if count > TXN_MAX_ENTRIES {
+        return None;
+    }
+    let mut entries = Vec::new();
+    for src in record[TXN_HEADER_LEN..].chunks_exact(TXN_ENTRY_LEN).take(count) {
+        entries.push(TxnEntry::deserialize(src)?);
+    }
+    Some(entries)
+}
+
+/// The writes held back by `PddbOs` while a transaction is applied.
+pub(crate) struct TxnStage {
+    /// Pages reserved up front to hold shadow copies. They are reserved ahead of time because
+    /// the write path can't allocate from FastSpace.
+    pub(crate) spares: Vec::<PhysPage>,
+    /// Shadow copies, keyed by the page number of the page they stand in for
+    pub(crate) shadows: BTreeMap::<PhysAddr, PhysPage>,
+    /// Pages allocated from FastSpace while the transaction was being applied
+    pub(crate) fresh: BTreeSet::<PhysPage>,
+    /// Page table updates, keyed by page number: `Some` maps the page, `None` erases its entry
+    pub(crate) pt_ops: BTreeMap::<PhysAddr, Option<VirtAddr>>,
+    /// Pages freed while the transaction was being applied, as they should be logged in FastSpace
+    pub(crate) releases: Vec::<PhysPage>,
+    /// Set if the spares ran out. A write was dropped, so the transaction can't be committed.
+    pub(crate) overflow: bool,
+}
+impl TxnStage {
+    pub(crate) fn new(spares: Vec::<PhysPage>) -> Self {
+        TxnStage {
+            spares,
+            shadows: BTreeMap::new(),
+            fresh: BTreeSet::new(),
+            pt_ops: BTreeMap::new(),
+            releases: Vec::new(),
+            overflow: false,
+        }
+    }
+    /// Returns the page that a write to `page_number` should land in, or `None` if there is nowhere
+    /// to put it.
+    pub(crate) fn write_target(&mut self, page_number: PhysAddr) -> Option<PhysAddr> {
+        let mut probe = PhysPage(0);
+        probe.set_page_number(page_number);
+        if self.fresh.contains(&probe) {
+            Some(page_number)
+        } else if let Some(shadow) = self.shadows.get(&page_number) {
+            Some(shadow.page_number())
+        } else if let Some(shadow) = self.spares.pop() {
+            self.shadows.insert(page_number, shadow);
+            Some(shadow.page_number())
+        } else {
+            self.overflow = true;
+            None
+        }
+    }
+    /// Returns the page that holds the current contents of `page_number`.
+    pub(crate) fn read_target(&self, page_number: PhysAddr) -> PhysAddr {
+        self.shadows.get(&page_number).map(|shadow| shadow.page_number()).unwrap_or(page_number)
+    }
+}
+
+/// Points in `BasisCache::txn_commit()` at which a test can have it stop, as if power were lost.
+#[cfg(feature="hosted")]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TxnCrashPoint {
+    /// The record has been written, but not mapped into the page table
+    BeforeCommit,
+    /// The record has been mapped, and nothing has been rolled forward
+    AfterCommit,
+    /// This many entries of the record, counting from the first, have been rolled forward
+    RollForward(usize),
+    /// Every entry has been rolled forward, but the record has not been retired
+    BeforeRetire,
+}
+
+/// An operation to apply as part of a transaction, as staged by a client.
+pub(crate) enum TxnOp {
+    /// Replace the contents of the key, creating the key and its dictionary if needed
+    Write { dict: String, key: String, data: Vec::<u8> },
+    /// Remove the key
+    Delete { dict: String, key: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_txn_record_roundtrip() {
+        let mut pp = PhysPage(0);
+        pp.set_page_number(0x1234);
+        pp.set_journal(3);
+        let entries = [
+            TxnEntry::Map(VirtAddr::new(0xFE_0000).unwrap(), pp),
+            TxnEntry::Release(pp),
+        ];
+        let page = txn_record_serialize(7, &entries);
+        assert!(page.len() == VPAGE_SIZE + size_of::<JournalType>());
+        let recovered = txn_record_deserialize(&page).expect("record did not deserialize");
+        assert!(recovered.len() == entries.len());
+        for (a, b) in recovered.iter().zip(entries.iter()) {
+            // PhysPage only compares page numbers, so check the journal explicitly
+            match (a, b) {
+                (TxnEntry::Map(va, pa), TxnEntry::Map(vb, pb)) => assert!(va == vb && pa.0 == pb.0),
+                (TxnEntry::Release(pa), TxnEntry::Release(pb)) => assert!(pa.0 == pb.0),
+                _ => panic!("entry type changed"),
+            }
+        }
+    }
+    #[test]
+    fn test_txn_record_rejects_garbage() {
+        let page = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
+        assert!(txn_record_deserialize(&page).is_none());
+    }
+}
@@ -0,0 +1,63 @@
+use super::*;
+
+use std::io::{Result, Error, ErrorKind};
+
+/// # Quotas
+///
+/// Every basis allocates out of the same FastSpace pool, so without a limit one basis can use up
+/// the whole disk, and leave the `.System` basis unable to write. A basis can be given a quota when
+/// it is created, which is stored in its root page. It bounds the number of pages the basis maps,
+/// counted in whole physical pages, because that is what it takes away from the other bases.
+///
+/// The check is made against the same page estimates used to reserve FastSpace. These are upper
+/// bounds, so a write can be refused a page or two before the basis actually reaches its quota.
+impl BasisCacheEntry {
+    /// Fails if mapping `pages` more pages would put the basis over its quota.
+    pub(crate) fn quota_check(&self, pages: usize) -> Result<()> {
+        if let Some(quota) = self.quota {
+            if ((self.v2p_map.len() + pages) * PAGE_SIZE) as u64 > quota {
+                log::warn!("{}: {} more pages would exceed the quota of {} bytes", self.name, pages, quota);
+                return Err(Error::new(ErrorKind::OutOfMemory, "Basis quota exceeded"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the bytes allocated to, and the bytes of key data stored in, either the whole basis,
+    /// or just `dict`, along with the number of keys counted.
+    pub(crate) fn usage(&mut self, hw: &mut PddbOs, dict: Option<&str>) -> Result<(u64, u64, u32)> {
+        self.populate_caches(hw);
+        let dicts: Vec<&DictCacheEntry> = if let Some(name) = dict {
+            match self.dicts.get(name) {
+                Some(d) if d.flags.valid() => vec![d],
+                _ => return Err(Error::new(ErrorKind::NotFound, "Dictionary not found")),
+            }
+        } else {
+            self.dicts.values().filter(|d| d.flags.valid()).collect()
+        };
+        let mut used = 0;
+        let mut keys = 0;
+        for d in dicts.iter() {
+            for key in d.keys.values().filter(|k| k.flags.valid()) {
+                used += key.len;
+                keys += 1;
+            }
+        }
+        let pages = if dict.is_some() {
+            // the dictionary's own region and small pool, plus the extents of its large keys
+            let d = dicts[0];
+            let dict_base = d.index.get() as u64 * DICT_VSIZE;
+            let pool_base = small_storage_base_vaddr_from_indices(d.index, 0);
+            let mut extents = vec![(dict_base, dict_base + DICT_VSIZE), (pool_base, pool_base + SMALL_POOL_STRIDE)];
+            for key in d.keys.values().filter(|k| k.flags.valid() && k.start >= LARGE_POOL_START) {
+                extents.push((key.start, key.start + key.reserved));
+            }
+            self.v2p_map.keys()
+                .filter(|va| extents.iter().any(|&(start, end)| va.get() >= start && va.get() < end))
+                .count()
+        } else {
+            self.v2p_map.len()
+        };
+        Ok(((pages * PAGE_SIZE) as u64, used, keys))
+    }
+}
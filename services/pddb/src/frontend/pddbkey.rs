@@ -30,6 +30,32 @@ impl<'a> PddbKey<'a> {
             _ => Err(Error::new(ErrorKind::Other, "Internal error requesting key attributes")),
         }
     }
+    /// Has the key deleted once `ttl` has passed, counted in whole seconds from now. `None` clears
+    /// any expiry time the key already has. The key disappears from listings and can't be opened
+    /// once it has expired, and its storage is reclaimed shortly afterwards.
+    pub fn set_expiry(&self, ttl: Option<std::time::Duration>) -> Result<()> {
+        let secs = match ttl {
+            // 0 is reserved for clearing the expiry
+            Some(ttl) => (ttl.as_secs().max(1)).min(u32::MAX as u64) as usize,
+            None => 0,
+        };
+        let response = send_message(
+            self.conn,
+            Message::new_blocking_scalar(Opcode::KeySetExpiry.to_usize().unwrap(),
+                self.token[0] as usize, self.token[1] as usize, self.token[2] as usize, secs)
+        ).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        if let xous::Result::Scalar1(rcode) = response {
+            match FromPrimitive::from_u8(rcode as u8) {
+                Some(PddbRetcode::Ok) => Ok(()),
+                Some(PddbRetcode::BasisLost) => Err(Error::new(ErrorKind::BrokenPipe, "Basis lost")),
+                Some(PddbRetcode::AccessDenied) => Err(Error::new(ErrorKind::PermissionDenied, "Access denied")),
+                Some(PddbRetcode::DiskFull) => Err(Error::new(ErrorKind::OutOfMemory, "Out of disk space")),
+                _ => Err(Error::new(ErrorKind::Other, "Couldn't set the key's expiry time")),
+            }
+        } else {
+            Err(Error::new(ErrorKind::Other, "Xous internal error"))
+        }
+    }
 }
 
 impl<'a> Seek for PddbKey<'a> {
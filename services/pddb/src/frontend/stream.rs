@@ -0,0 +1,192 @@
+use crate::*;
+use xous_ipc::Buffer;
+
+use num_traits::*;
+use std::io::{Result, Error, ErrorKind};
+use std::io::{Read, Write, Seek, SeekFrom};
+
+/// A `PddbKey` for reading or writing large keys in order, without holding them in RAM.
+///
+/// Data moves between the stream and the server in windows of up to `PDDB_STREAM_CHUNKS` chunks,
+/// aligned to `PDDB_STREAM_CHUNK` boundaries, so that the server writes whole pages without
+/// reading them back and decrypting them first. Reads fetch a full window ahead of the read
+/// position; writes are held back until a window fills, the stream is flushed, or the position
+/// moves elsewhere. Dropping the stream flushes it.
+///
+/// The server defers updating the key's length on disk until the stream is flushed, so data
+/// written since the last flush can be lost to a power failure. The read-ahead window is not
+/// updated by writes made to the same key through other handles.
+///
+/// A key that is meant to be streamed should be created with an `alloc_hint` of at least
+/// `PDDB_STREAM_CHUNK`, so that it is placed in the large pool from the start.
+pub struct PddbKeyStream<'a> {
+    key: PddbKey<'a>,
+    buf: Buffer<'a>,
+    /// position in the key of the first byte of the window
+    window_start: u64,
+    /// number of valid bytes in the window
+    window_len: usize,
+    /// set if the window holds data that has yet to be written
+    dirty: bool,
+    /// set if data has been written since the last flush
+    unsynced: bool,
+}
+
+impl<'a> PddbKey<'a> {
+    /// Converts this key into a stream, starting at the current position.
+    pub fn into_stream(self) -> PddbKeyStream<'a> {
+        PddbKeyStream {
+            window_start: self.pos,
+            key: self,
+            buf: Buffer::new(core::mem::size_of::<PddbStreamBuf>()),
+            window_len: 0,
+            dirty: false,
+            unsynced: false,
+        }
+    }
+}
+
+impl<'a> PddbKeyStream<'a> {
+    pub fn attributes(&self) -> Result<KeyAttributes> {
+        self.key.attributes()
+    }
+    /// Bytes the window can hold if it starts at `start`: it ends on a chunk boundary, so that the
+    /// windows that follow it are aligned.
+    fn window_capacity(start: u64) -> usize {
+        PDDB_STREAM_DATA_LEN - (start % PDDB_STREAM_CHUNK as u64) as usize
+    }
+    /// Writes out the window, if it holds any pending data.
+    fn write_window(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        {
+            let sbuf = PddbStreamBuf::from_slice_mut(self.buf.as_mut());
+            sbuf.token = self.key.token;
+            sbuf.retcode = PddbRetcode::Uninit;
+            sbuf.position = self.window_start;
+            sbuf.len = self.window_len as u32;
+        }
+        self.buf.lend_mut(self.key.conn, Opcode::WriteKeyStream.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        // the window keeps its contents, which now match the disk, so reads can still be served from it
+        self.dirty = false;
+        self.unsynced = true;
+        match PddbStreamBuf::from_slice_mut(self.buf.as_mut()).retcode {
+            PddbRetcode::Ok => Ok(()),
+            PddbRetcode::BasisLost => Err(Error::new(ErrorKind::BrokenPipe, "Basis lost")),
+            PddbRetcode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Access denied")),
+            PddbRetcode::DiskFull => Err(Error::new(ErrorKind::OutOfMemory, "Out of disk space")),
+            _ => Err(Error::new(ErrorKind::Other, "Unhandled error code in PddbKeyStream write")),
+        }
+    }
+    /// Replaces the window with data read from `start`.
+    fn read_window(&mut self, start: u64) -> Result<()> {
+        self.write_window()?;
+        {
+            let sbuf = PddbStreamBuf::from_slice_mut(self.buf.as_mut());
+            sbuf.token = self.key.token;
+            sbuf.retcode = PddbRetcode::Uninit;
+            sbuf.position = start;
+            sbuf.len = Self::window_capacity(start) as u32;
+        }
+        self.window_start = start;
+        self.window_len = 0;
+        self.buf.lend_mut(self.key.conn, Opcode::ReadKeyStream.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let sbuf = PddbStreamBuf::from_slice_mut(self.buf.as_mut());
+        match sbuf.retcode {
+            PddbRetcode::Ok => {
+                self.window_len = (sbuf.len as usize).min(Self::window_capacity(start));
+                Ok(())
+            }
+            PddbRetcode::UnexpectedEof => Ok(()),
+            PddbRetcode::BasisLost => Err(Error::new(ErrorKind::BrokenPipe, "Basis lost")),
+            PddbRetcode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Access denied")),
+            _ => Err(Error::new(ErrorKind::Other, "Unhandled error code in PddbKeyStream read")),
+        }
+    }
+}
+
+impl<'a> Read for PddbKeyStream<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.len() == 0 {
+            return Ok(0);
+        }
+        let pos = self.key.pos;
+        if pos < self.window_start || pos >= self.window_start + self.window_len as u64 {
+            // start the new window on a chunk boundary, so it's read as whole pages
+            self.read_window(pos - pos % PDDB_STREAM_CHUNK as u64)?;
+            if pos >= self.window_start + self.window_len as u64 {
+                return Ok(0);
+            }
+        }
+        let offset = (pos - self.window_start) as usize;
+        let sbuf = PddbStreamBuf::from_slice_mut(self.buf.as_mut());
+        let mut readlen = 0;
+        for (&src, dst) in sbuf.data[offset..self.window_len].iter().zip(buf.iter_mut()) {
+            *dst = src;
+            readlen += 1;
+        }
+        self.key.pos += readlen as u64;
+        Ok(readlen)
+    }
+}
+
+impl<'a> Write for PddbKeyStream<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.len() == 0 {
+            return Ok(0);
+        }
+        let pos = self.key.pos;
+        if !self.dirty || pos != self.window_start + self.window_len as u64 {
+            // not appending to pending data: start a new window here
+            self.write_window()?;
+            self.window_start = pos;
+            self.window_len = 0;
+        }
+        let capacity = Self::window_capacity(self.window_start);
+        let sbuf = PddbStreamBuf::from_slice_mut(self.buf.as_mut());
+        let mut writelen = 0;
+        for (&src, dst) in buf.iter().zip(sbuf.data[self.window_len..capacity].iter_mut()) {
+            *dst = src;
+            writelen += 1;
+        }
+        self.window_len += writelen;
+        self.dirty = true;
+        self.key.pos += writelen as u64;
+        if self.window_len == capacity {
+            self.write_window()?;
+        }
+        Ok(writelen)
+    }
+    /// Writes out any pending data, and has the server commit the key's new length to disk.
+    fn flush(&mut self) -> Result<()> {
+        self.write_window()?;
+        if self.unsynced {
+            self.key.flush()?;
+            self.unsynced = false;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Seek for PddbKeyStream<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        // the window is kept; a read or write at the new position decides whether it's still useful
+        if let SeekFrom::End(_) = pos {
+            // the server only knows the key's length once pending data is written
+            self.write_window()?;
+        }
+        self.key.seek(pos)
+    }
+}
+
+impl<'a> Drop for PddbKeyStream<'a> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::error!("couldn't flush stream on drop: {:?}", e);
+        }
+        self.buf.volatile_clear();
+    }
+}
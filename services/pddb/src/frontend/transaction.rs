@@ -0,0 +1,111 @@
+use crate::*;
+use xous::{CID, send_message, Message};
+use xous_ipc::Buffer;
+
+use num_traits::*;
+use std::io::{Result, Error, ErrorKind};
+
+/// A set of key writes and deletes on one basis that are committed all-or-nothing. Created by
+/// `Pddb::begin_transaction()`.
+///
+/// Nothing is written to disk until `commit()` is called. If the transaction is dropped without
+/// being committed, everything staged in it is discarded. Staging fails with `OutOfMemory` once the
+/// transaction holds 64 operations, or about 330 KiB of data.
+pub struct PddbTransaction {
+    id: u32,
+    conn: CID,
+    /// set once the transaction has been committed or aborted, so `Drop` doesn't abort it again
+    closed: bool,
+}
+impl PddbTransaction {
+    pub(crate) fn new(conn: CID, id: u32) -> Self {
+        PddbTransaction { id, conn, closed: false }
+    }
+    /// Stages a write that replaces the entire contents of `key_name` with `data`. The key, and
+    /// its dictionary, are created if they don't exist.
+    pub fn write(&self, dict_name: &str, key_name: &str, data: &[u8]) -> Result<()> {
+        let mut chunks = data.chunks(PDDB_TXN_DATA_LEN);
+        // an empty write still has to be staged, to create or truncate the key
+        let first = chunks.next().unwrap_or(&[]);
+        self.stage(TxnStageOp::Write, dict_name, key_name, first)?;
+        for chunk in chunks {
+            self.stage(TxnStageOp::Append, dict_name, key_name, chunk)?;
+        }
+        Ok(())
+    }
+    /// Stages the removal of `key_name`. The commit fails with `NotFound` if the key doesn't
+    /// exist at that point.
+    pub fn delete(&self, dict_name: &str, key_name: &str) -> Result<()> {
+        self.stage(TxnStageOp::Delete, dict_name, key_name, &[])
+    }
+    /// Applies every staged operation. On error, none of them have been applied.
+    pub fn commit(mut self) -> Result<()> {
+        self.closed = true;
+        let request = PddbTxnRequest {
+            basis_specified: false,
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::new(),
+            id: self.id,
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::TxnCommit.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+
+        let response = buf.to_original::<PddbTxnRequest, _>().unwrap();
+        match response.code {
+            PddbRequestCode::NoErr => Ok(()),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Transaction, basis, dictionary or key was not found")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "Out of disk space, or transaction too large")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+    /// Discards everything staged in the transaction. This is the same as dropping it.
+    pub fn abort(self) {}
+
+    fn stage(&self, op: TxnStageOp, dict_name: &str, key_name: &str, data: &[u8]) -> Result<()> {
+        if key_name.len() > (KEY_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
+        }
+        if dict_name.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        let mut request = PddbTxnStage {
+            id: self.id,
+            op,
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key_name),
+            data: [0u8; PDDB_TXN_DATA_LEN],
+            len: data.len() as u32,
+            code: PddbRequestCode::Uninit,
+        };
+        request.data[..data.len()].copy_from_slice(data);
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::TxnStage.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+
+        let response = buf.to_original::<PddbTxnStage, _>().unwrap();
+        buf.volatile_clear(); // the staged data may be confidential
+        match response.code {
+            PddbRequestCode::NoErr => Ok(()),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Transaction was not found")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "Transaction is too large")),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Dictionary is reserved")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+}
+
+impl Drop for PddbTransaction {
+    fn drop(&mut self) {
+        if !self.closed {
+            send_message(self.conn, Message::new_blocking_scalar(Opcode::TxnAbort.to_usize().unwrap(),
+            self.id as usize, 0, 0, 0)).expect("couldn't send TxnAbort message");
+        }
+
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
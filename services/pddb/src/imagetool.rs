@@ -0,0 +1,507 @@
+//! Offline PDDB image tool. Built with `--features offline`, the PDDB binary runs on the host and
+//! reads an image with the same backend code the device uses, rather than a separate decoder that
+//! could drift from the on-disk format. The image is never written back: anything the backend
+//! patches while mounting (e.g. recovering an interrupted transaction) only changes the copy in memory.
+//!
+//! The root keys never leave the device, so the system basis keys have to be supplied in a key
+//! file, in the format written next to hosted-mode images by `PddbOs::dbg_dump()`. Secret bases
+//! can be unlocked with their password, or with keys from the same file.
+use crate::*;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use zeroize::Zeroize;
+
+const USAGE: &str = "\
+usage: pddb [options] <command>
+
+options:
+  --image <file>           a raw PDDB image, such as the ones in tools/pddb-images
+  --backup <file>          a backup archive; the PDDB is found after its header
+  --keys <file>            key file with the system basis keys (required), and optionally others
+  --basis <name>:<pass>    unlock a secret basis with its password; may be repeated
+  --dna <hex>              device DNA the image was written with. Defaults to the DNA in
+                           the backup header, or 0 for raw images (as used in hosted mode)
+  --verbose                show the backend's log output
+
+commands:
+  list                     list every key in the unlocked bases, with its attributes
+  export <dir>             write each key to <dir>/<basis>/<dict>/<key>
+  export-json <file>       write the unlocked bases, their attributes and data as JSON (- for stdout)
+  check                    read back every key, and cross-check the page tables against each
+                           other and the FastSpace table. Exits with 1 if problems are found";
+
+/// The PDDB starts after this much header in a backup archive
+const BACKUP_HEADER_LEN: u64 = 4096;
+/// Offset of the device DNA in the plaintext copy of the backup header; see `BackupHeader` in root-keys
+const BACKUP_DNA_OFFSET: usize = 88;
+/// Length of a basis name record in a key file
+const KEYFILE_NAME_LEN: usize = 64;
+
+struct Options {
+    image: String,
+    backup: bool,
+    keys: String,
+    dna: Option<u64>,
+    passwords: Vec<(String, String)>,
+    verbose: bool,
+    command: Vec<String>,
+}
+
+struct StderrLogger;
+impl log::Log for StderrLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool { true }
+    fn log(&self, record: &log::Record) {
+        eprintln!("{}: {}", record.level(), record.args());
+    }
+    fn flush(&self) {}
+}
+static LOGGER: StderrLogger = StderrLogger;
+
+pub(crate) fn main() {
+    let opts = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    log::set_logger(&LOGGER).ok();
+    log::set_max_level(if opts.verbose { log::LevelFilter::Info } else { log::LevelFilter::Error });
+    match run(opts) {
+        Ok(true) => std::process::exit(0),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut image = None;
+    let mut backup = false;
+    let mut keys = None;
+    let mut dna = None;
+    let mut passwords = Vec::new();
+    let mut verbose = false;
+    let mut command = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--image" => image = Some(value()?),
+            "--backup" => {
+                image = Some(value()?);
+                backup = true;
+            }
+            "--keys" => keys = Some(value()?),
+            "--dna" => {
+                let v = value()?;
+                dna = Some(u64::from_str_radix(v.trim_start_matches("0x"), 16)
+                    .map_err(|_| format!("invalid DNA: {}", v))?);
+            }
+            "--basis" => {
+                let v = value()?;
+                match v.split_once(':') {
+                    Some((name, pass)) => passwords.push((name.to_string(), pass.to_string())),
+                    None => return Err(format!("--basis takes <name>:<password>, got {}", v)),
+                }
+            }
+            "--verbose" | "-v" => verbose = true,
+            "--help" | "-h" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => command.push(arg),
+        }
+    }
+    let expected_args = match command.first().map(|c| c.as_str()) {
+        Some("list") | Some("check") => 1,
+        Some("export") | Some("export-json") => 2,
+        Some(c) => return Err(format!("unknown command {}", c)),
+        None => return Err("no command given".to_string()),
+    };
+    if command.len() != expected_args {
+        return Err(format!("wrong number of arguments to {}", command[0]));
+    }
+    Ok(Options {
+        image: image.ok_or("one of --image or --backup is required")?,
+        backup,
+        keys: keys.ok_or("--keys is required")?,
+        dna,
+        passwords,
+        verbose,
+        command,
+    })
+}
+
+/// Returns `false` if a check found problems.
+fn run(opts: Options) -> Result<bool, String> {
+    let (mut hw, mut basis_cache, unlocked) = open(&opts)?;
+    match opts.command[0].as_str() {
+        "list" => {
+            list(&mut hw, &mut basis_cache);
+            Ok(true)
+        }
+        "export" => export_dir(&mut hw, &mut basis_cache, Path::new(&opts.command[1])).map(|_| true),
+        "export-json" => export_json(&mut hw, &mut basis_cache, &opts.command[1]).map(|_| true),
+        "check" => Ok(check(&mut hw, &mut basis_cache, &unlocked)),
+        _ => unreachable!(),
+    }
+}
+
+/// Reads in the image and mounts every basis there are keys or a password for. Also returns the
+/// keys of everything mounted, so the check can scan the page tables on disk.
+fn open(opts: &Options) -> Result<(PddbOs, BasisCache, Vec<(String, BasisKeys)>), String> {
+    let (offset, header_dna) = if opts.backup {
+        let mut header = [0u8; BACKUP_DNA_OFFSET + 8];
+        File::open(&opts.image).and_then(|mut f| f.read_exact(&mut header))
+            .map_err(|e| format!("couldn't read backup header from {}: {}", opts.image, e))?;
+        let mut dna = [0u8; 8];
+        dna.copy_from_slice(&header[BACKUP_DNA_OFFSET..]);
+        (BACKUP_HEADER_LEN, Some(u64::from_le_bytes(dna)))
+    } else {
+        (0, None)
+    };
+    offline_image_set(OfflineImage {
+        path: opts.image.clone(),
+        offset,
+        dna: opts.dna.or(header_dna).unwrap_or(0),
+    });
+
+    let mut keys = read_key_file(&opts.keys)?;
+    let sys_index = keys.iter().position(|(name, _)| name == PDDB_DEFAULT_SYSTEM_BASIS)
+        .ok_or(format!("{} has no keys for the system basis", opts.keys))?;
+    let (_, syskey) = keys.remove(sys_index);
+
+    let entropy = Rc::new(RefCell::new(TrngPool::new()));
+    // there's no password manager to connect to
+    let mut hw = PddbOs::new(entropy, 0);
+    let mut basis_cache = BasisCache::new();
+    hw.set_system_key(BasisKeys { pt: syskey.pt, data: syskey.data });
+    let sys_basis = hw.pddb_mount()
+        .ok_or("couldn't mount the system basis. Check the key file and DNA.")?;
+    basis_cache.basis_add(sys_basis);
+    let mut unlocked = vec![(PDDB_DEFAULT_SYSTEM_BASIS.to_string(), syskey)];
+
+    for (name, key) in keys {
+        match basis_cache.basis_unlock_with_keys(&mut hw, &name, &key, BasisRetentionPolicy::Persist) {
+            Some(basis) => {
+                basis_cache.basis_add(basis);
+                unlocked.push((name, key));
+            }
+            None => eprintln!("warning: basis {} from the key file could not be unlocked", name),
+        }
+    }
+    for (name, password) in opts.passwords.iter() {
+        let key = hw.basis_derive_key(name, password);
+        let basis = basis_cache.basis_unlock_with_keys(&mut hw, name, &key, BasisRetentionPolicy::Persist)
+            .ok_or(format!("couldn't unlock basis {}: wrong password, or it doesn't exist", name))?;
+        basis_cache.basis_add(basis);
+        unlocked.push((name.to_string(), key));
+    }
+    Ok((hw, basis_cache, unlocked))
+}
+
+/// Reads a key file: a little-endian u32 count, then for each basis a zero-padded name and its data and page table keys.
+fn read_key_file(path: &str) -> Result<Vec<(String, BasisKeys)>, String> {
+    let mut raw = Vec::new();
+    File::open(path).and_then(|mut f| f.read_to_end(&mut raw))
+        .map_err(|e| format!("couldn't read key file {}: {}", path, e))?;
+    let record_len = KEYFILE_NAME_LEN + AES_KEYSIZE * 2;
+    let count = raw.get(..4).map(|c| u32::from_le_bytes(c.try_into().unwrap()) as usize).unwrap_or(0);
+    if raw.len() != 4 + count * record_len {
+        return Err(format!("{} is not a valid key file", path));
+    }
+    let mut keys = Vec::new();
+    for record in raw[4..].chunks_exact(record_len) {
+        let name_len = record[..KEYFILE_NAME_LEN].iter().position(|&b| b == 0).unwrap_or(KEYFILE_NAME_LEN);
+        let name = std::str::from_utf8(&record[..name_len])
+            .map_err(|_| format!("{} contains a basis name that isn't utf-8", path))?;
+        let mut key = BasisKeys { pt: [0u8; AES_KEYSIZE], data: [0u8; AES_KEYSIZE] };
+        key.data.copy_from_slice(&record[KEYFILE_NAME_LEN..KEYFILE_NAME_LEN + AES_KEYSIZE]);
+        key.pt.copy_from_slice(&record[KEYFILE_NAME_LEN + AES_KEYSIZE..]);
+        keys.push((name.to_string(), key));
+    }
+    raw.zeroize();
+    Ok(keys)
+}
+
+/// Every (basis, dict, key) in the unlocked bases, in mount order and then by name.
+fn all_keys(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Vec<(String, String, String)> {
+    let mut ret = Vec::new();
+    for basis in basis_cache.basis_list() {
+        let mut dicts: Vec<String> = basis_cache.dict_list(hw, Some(&basis)).into_iter().collect();
+        dicts.sort();
+        for dict in dicts {
+            match basis_cache.key_list(hw, &dict, Some(&basis)) {
+                Ok(keys) => {
+                    for key in keys {
+                        ret.push((basis.to_string(), dict.to_string(), key));
+                    }
+                }
+                Err(e) => log::error!("couldn't list keys in {}:{}: {:?}", basis, dict, e),
+            }
+        }
+    }
+    ret
+}
+
+fn read_key(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis: &str, dict: &str, key: &str
+) -> Result<(KeyAttributes, Vec<u8>), String> {
+    let attr = basis_cache.key_attributes(hw, dict, key, Some(basis)).map_err(|e| e.to_string())?;
+    let mut data = vec![0u8; attr.len];
+    let readlen = basis_cache.key_read(hw, dict, key, &mut data, Some(0), Some(basis)).map_err(|e| e.to_string())?;
+    if readlen != attr.len {
+        return Err(format!("read {} bytes, but the key is {} bytes long", readlen, attr.len));
+    }
+    Ok((attr, data))
+}
+
+fn list(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
+    println!("{:<16} {:<24} {:<32} {:>8} {:>8} {:>6} {:>6} {:>5}", "basis", "dict", "key", "len", "reserved", "age", "index", "flags");
+    for (basis, dict, key) in all_keys(hw, basis_cache) {
+        match basis_cache.key_attributes(hw, &dict, &key, Some(&basis)) {
+            Ok(attr) => println!("{:<16} {:<24} {:<32} {:>8} {:>8} {:>6} {:>6} {:>5}",
+                basis, dict, key, attr.len, attr.reserved, attr.age, attr.index, flags_str(attr.flags)),
+            Err(e) => println!("{:<16} {:<24} {:<32} error: {}", basis, dict, key, e),
+        }
+    }
+}
+
+/// `v` for valid, `u` for unresolved
+fn flags_str(flags: KeyFlags) -> String {
+    let mut ret = String::new();
+    ret.push(if flags.valid() { 'v' } else { '-' });
+    ret.push(if flags.unresolved() { 'u' } else { '-' });
+    ret
+}
+
+/// Makes a PDDB name safe to use as a single path component. Key names often contain `/`, which
+/// must not turn into directories, or `a` and `a/b` couldn't both be exported.
+fn path_component(name: &str) -> String {
+    if name == "." || name == ".." || name.is_empty() {
+        return name.bytes().map(|b| format!("%{:02X}", b)).collect::<String>() + "%";
+    }
+    let mut ret = String::new();
+    for c in name.chars() {
+        match c {
+            '/' | '\\' | '%' | ':' | '\0' => {
+                let mut buf = [0u8; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    ret.push_str(&format!("%{:02X}", b));
+                }
+            }
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+fn export_dir(hw: &mut PddbOs, basis_cache: &mut BasisCache, root: &Path) -> Result<(), String> {
+    let mut errors = 0;
+    for (basis, dict, key) in all_keys(hw, basis_cache) {
+        let (_attr, data) = match read_key(hw, basis_cache, &basis, &dict, &key) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("skipping {}:{}:{}: {}", basis, dict, key, e);
+                errors += 1;
+                continue;
+            }
+        };
+        let dir = root.join(path_component(&basis)).join(path_component(&dict));
+        std::fs::create_dir_all(&dir).map_err(|e| format!("couldn't create {}: {}", dir.display(), e))?;
+        let path = dir.join(path_component(&key));
+        std::fs::write(&path, &data).map_err(|e| format!("couldn't write {}: {}", path.display(), e))?;
+    }
+    if errors != 0 {
+        Err(format!("{} keys could not be read", errors))
+    } else {
+        Ok(())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Writes `{"dna": .., "bases": [{"name": .., "dicts": [{"name": .., "keys": [{..attributes, "data": hex}]}]}]}`
+fn export_json(hw: &mut PddbOs, basis_cache: &mut BasisCache, dest: &str) -> Result<(), String> {
+    let mut out = String::new();
+    out.push_str(&format!("{{\n  \"dna\": \"{:016x}\",\n  \"bases\": [", hw.dna()));
+    let keys = all_keys(hw, basis_cache);
+    let mut errors = 0;
+    for (bi, basis) in basis_cache.basis_list().iter().enumerate() {
+        out.push_str(if bi == 0 { "\n" } else { ",\n" });
+        out.push_str(&format!("    {{\"name\": {}, \"dicts\": [", json_string(basis)));
+        let mut last_dict: Option<&str> = None;
+        for (_, dict, key) in keys.iter().filter(|(b, _, _)| b == basis) {
+            if last_dict != Some(dict.as_str()) {
+                out.push_str(if last_dict.is_none() { "\n" } else { "\n      ]},\n" });
+                out.push_str(&format!("      {{\"name\": {}, \"keys\": [\n", json_string(dict)));
+                last_dict = Some(dict.as_str());
+            } else {
+                out.push_str(",\n");
+            }
+            match read_key(hw, basis_cache, basis, dict, key) {
+                Ok((attr, data)) => {
+                    let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+                    out.push_str(&format!(
+                        "        {{\"name\": {}, \"len\": {}, \"reserved\": {}, \"age\": {}, \"index\": {}, \"flags\": \"{}\", \"data\": \"{}\"}}",
+                        json_string(key), attr.len, attr.reserved, attr.age, attr.index, flags_str(attr.flags), hex));
+                }
+                Err(e) => {
+                    eprintln!("couldn't read {}:{}:{}: {}", basis, dict, key, e);
+                    errors += 1;
+                    out.push_str(&format!("        {{\"name\": {}, \"error\": {}}}", json_string(key), json_string(&e)));
+                }
+            }
+        }
+        out.push_str(if last_dict.is_some() { "\n      ]}\n    ]}" } else { "]}" });
+    }
+    out.push_str("\n  ]\n}\n");
+
+    if dest == "-" {
+        std::io::stdout().write_all(out.as_bytes()).map_err(|e| e.to_string())?;
+    } else {
+        std::fs::write(dest, out.as_bytes()).map_err(|e| format!("couldn't write {}: {}", dest, e))?;
+    }
+    if errors != 0 {
+        Err(format!("{} keys could not be read", errors))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns `true` if no problems were found. Only the unlocked bases can be checked: pages that
+/// belong to a basis we don't have the keys for are indistinguishable from free space.
+fn check(hw: &mut PddbOs, basis_cache: &mut BasisCache, unlocked: &[(String, BasisKeys)]) -> bool {
+    let mut problems = 0;
+
+    // 1. every key should read back in full; this authenticates every data page it touches
+    let keys = all_keys(hw, basis_cache);
+    for (basis, dict, key) in keys.iter() {
+        if let Err(e) = read_key(hw, basis_cache, basis, dict, key) {
+            println!("{}:{}:{}: {}", basis, dict, key, e);
+            problems += 1;
+        }
+    }
+    println!("read back {} keys in {} bases", keys.len(), unlocked.len());
+
+    // 2. no physical page should be mapped twice, whether by one basis or two
+    let mut owners = HashMap::<PhysAddr, (&str, VirtAddr)>::new();
+    for (name, key) in unlocked.iter() {
+        let map = match hw.pt_scan_key(&key.pt, &key.data, name) {
+            Some(map) => map,
+            None => {
+                println!("{}: page table scan found no pages", name);
+                problems += 1;
+                continue;
+            }
+        };
+        for (&va, pp) in map.iter() {
+            if let Some((other, other_va)) = owners.insert(pp.page_number(), (name, va)) {
+                println!("page {:x} is mapped at {}:{:x} and at {}:{:x}", pp.page_number(), other, other_va.get(), name, va.get());
+                problems += 1;
+            }
+        }
+    }
+
+    // 3. FastSpace must not hand out a page that's in use
+    for pp in hw.fast_space_pages() {
+        if pp.space_state() == SpaceState::Free {
+            if let Some((name, va)) = owners.get(&pp.page_number()) {
+                println!("page {:x} is free in FastSpace, but is mapped at {}:{:x}", pp.page_number(), name, va.get());
+                problems += 1;
+            }
+        }
+    }
+
+    // 4. the same checks the running PDDB makes with `pddb fsck`
+    match basis_cache.fsck(hw, false) {
+        Ok(report) => {
+            println!("fsck: {} orphaned pages, {} duplicate mappings, {} journal conflicts, {} dangling keys",
+                report.orphaned_pages, report.duplicate_mappings, report.journal_conflicts, report.dangling_keys);
+            problems += report.orphaned_pages + report.duplicate_mappings + report.dangling_keys;
+        }
+        Err(e) => {
+            println!("fsck: {}", e);
+            problems += 1;
+        }
+    }
+    println!("{} pages in use by the unlocked bases; {} problems found", owners.len(), problems);
+    problems == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every key in the unlocked bases, with its contents.
+    fn contents(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Vec<(String, String, String, Vec<u8>)> {
+        all_keys(hw, basis_cache).into_iter().map(|(basis, dict, key)| {
+            let (_, data) = read_key(hw, basis_cache, &basis, &dict, &key).expect("key can't be read");
+            (basis, dict, key, data)
+        }).collect()
+    }
+
+    /// Builds an image with the backend, dumps it along with a key file the way hosted mode does,
+    /// and checks that the tool reads back every key, from the system basis and a secret one.
+    #[test]
+    fn image_roundtrip() {
+        const SECRET: &str = "roundtrip";
+        const SECRET_PW: &str = "correct horse";
+        let dir = std::env::temp_dir().join(format!("pddb-roundtrip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("pddb.bin").to_string_lossy().into_owned();
+        let keyfile = dir.join("pddb.key").to_string_lossy().into_owned();
+        // an empty image reads as erased flash
+        File::create(&image).unwrap();
+        offline_image_set(OfflineImage { path: image.clone(), offset: 0, dna: 0 });
+
+        let mut hw = PddbOs::new(Rc::new(RefCell::new(TrngPool::new())), 0);
+        let mut basis_cache = BasisCache::new();
+        hw.pddb_format(false, None).expect("couldn't format the image");
+        basis_cache.basis_add(hw.pddb_mount().expect("couldn't mount the new image"));
+        basis_cache.basis_create(&mut hw, SECRET, SECRET_PW, None).expect("couldn't create the secret basis");
+        let secret = basis_cache.basis_unlock(&mut hw, SECRET, SECRET_PW, BasisRetentionPolicy::Persist)
+            .expect("couldn't unlock the secret basis");
+        basis_cache.basis_add(secret);
+        for (salt, basis) in [PDDB_DEFAULT_SYSTEM_BASIS, SECRET].iter().enumerate() {
+            for keynum in 0..8 {
+                // the longer keys go in the large pool
+                let data: Vec<u8> = (0..keynum * 1500 + 10).map(|i| (i + keynum + salt * 17) as u8).collect();
+                basis_cache.key_update(&mut hw, "dict", &format!("key/{}", keynum), &data, None, None, Some(basis), true)
+                    .expect("couldn't write key");
+            }
+        }
+        basis_cache.sync(&mut hw, None).expect("couldn't sync");
+        let written = contents(&mut hw, &mut basis_cache);
+        assert!(written.len() == 16, "wrote {} keys, expected 16", written.len());
+        hw.dbg_dump_to(&image, &keyfile, None);
+
+        // forget the image, and open it again from the files like a fresh run of the tool
+        EmuStorage::new().reset();
+        EmuStorage::new().reload();
+        let args = ["--image", &image, "--keys", &keyfile, "--basis", &format!("{}:{}", SECRET, SECRET_PW), "check"];
+        let opts = parse_args(args.iter().map(|a| a.to_string()).collect()).expect("arguments rejected");
+        let (mut hw, mut basis_cache, unlocked) = open(&opts).expect("couldn't open the dumped image");
+        assert!(contents(&mut hw, &mut basis_cache) == written, "image did not read back the same");
+        assert!(check(&mut hw, &mut basis_cache, &unlocked), "check found problems in the dumped image");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
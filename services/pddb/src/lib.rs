@@ -220,6 +220,7 @@ impl Pddb {
             name: xous_ipc::String::<BASIS_NAME_LEN>::new(),
             code: PddbRequestCode::Uninit,
             policy: None,
+            quota: None,
         };
         let mut buf = Buffer::into_buf(mgmt).expect("Couldn't convert to memory structure");
         buf.lend_mut(self.conn, Opcode::LatestBasis.to_u32().unwrap()).expect("Couldn't execute ListBasis opcode");
@@ -238,6 +239,11 @@ impl Pddb {
         }
     }
     pub fn create_basis(&self, basis_name: &str) -> Result<()> {
+        self.create_basis_with_quota(basis_name, None)
+    }
+    /// Creates a basis that may occupy at most `quota` bytes of the disk. Writes that would take it
+    /// over the quota fail with `ErrorKind::OutOfMemory`, the same as when the disk is full.
+    pub fn create_basis_with_quota(&self, basis_name: &str, quota: Option<u64>) -> Result<()> {
         if basis_name.len() > BASIS_NAME_LEN - 1 {
             return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
         }
@@ -245,6 +251,7 @@ impl Pddb {
             name: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name),
             code: PddbRequestCode::Create,
             policy: None,
+            quota,
         };
         let mut buf = Buffer::into_buf(mgmt).expect("Couldn't convert to memory structure");
         buf.lend_mut(self.conn, Opcode::CreateBasis.to_u32().unwrap()).expect("Couldn't execute CreateBasis opcode");
@@ -268,6 +275,7 @@ impl Pddb {
             name: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name),
             code: PddbRequestCode::Open,
             policy,
+            quota: None,
         };
         let mut buf = Buffer::into_buf(mgmt).expect("Couldn't convert to memory structure");
         buf.lend_mut(self.conn, Opcode::OpenBasis.to_u32().unwrap()).expect("Couldn't execute OpenBasis opcode");
@@ -290,6 +298,7 @@ impl Pddb {
             name: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name),
             code: PddbRequestCode::Close,
             policy: None,
+            quota: None,
         };
         let mut buf = Buffer::into_buf(mgmt).expect("Couldn't convert to memory structure");
         buf.lend_mut(self.conn, Opcode::CloseBasis.to_u32().unwrap()).expect("Couldn't execute CloseBasis opcode");
@@ -312,6 +321,7 @@ impl Pddb {
             name: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name),
             code: PddbRequestCode::Delete,
             policy: None,
+            quota: None,
         };
         let mut buf = Buffer::into_buf(mgmt).expect("Couldn't convert to memory structure");
         buf.lend_mut(self.conn, Opcode::DeleteBasis.to_u32().unwrap()).expect("Couldn't execute DeleteBasis opcode");
@@ -335,6 +345,14 @@ impl Pddb {
     /// goes away due to a basis locking.
     pub fn get(&self, dict_name: &str, key_name: &str, basis_name: Option<&str>,
         create_dict: bool, create_key: bool, alloc_hint: Option<usize>, key_changed_cb: Option<impl Fn() + 'static + Send>) -> Result<PddbKey> {
+        self.get_with_expiry(dict_name, key_name, basis_name, create_dict, create_key, alloc_hint, None, key_changed_cb)
+    }
+    /// Same as `get`, but if `ttl` is given the key is deleted once it has passed, counted in whole seconds
+    /// from now. The expiry time is set before the key is handed back, so a key created by this call never
+    /// exists without one; an existing key has its expiry time replaced. See `PddbKey::set_expiry`.
+    pub fn get_with_expiry(&self, dict_name: &str, key_name: &str, basis_name: Option<&str>,
+        create_dict: bool, create_key: bool, alloc_hint: Option<usize>, ttl: Option<std::time::Duration>,
+        key_changed_cb: Option<impl Fn() + 'static + Send>) -> Result<PddbKey> {
         if key_name.len() > (KEY_NAME_LEN - 1) {
             return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
         }
@@ -373,6 +391,8 @@ impl Pddb {
             result: PddbRequestCode::Uninit,
             cb_sid,
             alloc_hint: if let Some(a) = alloc_hint {Some(a as u64)} else {None},
+            // 0 would expire the key straight away
+            expiry_secs: ttl.map(|ttl| ttl.as_secs().max(1)),
         };
         let mut buf = Buffer::into_buf(request)
             .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
@@ -451,6 +471,7 @@ impl Pddb {
             result: PddbRequestCode::Uninit,
             cb_sid,
             alloc_hint: None,
+            expiry_secs: None,
         };
         let mut buf = Buffer::into_buf(request)
             .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
@@ -461,6 +482,7 @@ impl Pddb {
         match response.result {
             PddbRequestCode::NoErr => Ok(()),
             PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Dictionary or key was not found")),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Dictionary is reserved")),
             _ => Err(Error::new(ErrorKind::Other, "Internal error"))
         }
     }
@@ -497,6 +519,7 @@ impl Pddb {
             result: PddbRequestCode::Uninit,
             cb_sid,
             alloc_hint: None,
+            expiry_secs: None,
         };
         let mut buf = Buffer::into_buf(request)
             .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
@@ -507,10 +530,103 @@ impl Pddb {
         match response.result {
             PddbRequestCode::NoErr => Ok(()),
             PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Dictionary or key was not found")),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Dictionary is reserved")),
             _ => Err(Error::new(ErrorKind::Other, "Internal error"))
         }
     }
 
+    /// Asks to be told about changes to `dict_name`, or only to `key_name` within it, made by any
+    /// process. If `basis_name` is `None`, changes in every basis are reported.
+    ///
+    /// Each change is delivered to the server `cb_sid` as a non-blocking scalar message with
+    /// `cb_opcode` as its ID. `arg1` is a `PddbChangeEvent`, and `arg2` is the subscription ID
+    /// returned by this call. The message doesn't say which key changed, so a subscriber to a whole
+    /// dictionary should list or re-read the keys it cares about. Basis mount and unmount events
+    /// are delivered to every subscription that covers the basis.
+    pub fn subscribe(&self, cb_sid: SID, cb_opcode: u32, basis_name: Option<&str>,
+        dict_name: &str, key_name: Option<&str>
+    ) -> Result<u32> {
+        if dict_name.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        if key_name.map(|k| k.len() > (KEY_NAME_LEN - 1)).unwrap_or(false) {
+            return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
+        }
+        if basis_name.map(|b| b.len() > (BASIS_NAME_LEN - 1)).unwrap_or(false) {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        let request = PddbSubscribeRequest {
+            basis_specified: basis_name.is_some(),
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name.unwrap_or("")),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+            key_specified: key_name.is_some(),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key_name.unwrap_or("")),
+            cb_sid: cb_sid.to_array(),
+            cb_opcode,
+            id: 0,
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::Subscribe.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+
+        let response = buf.to_original::<PddbSubscribeRequest, _>().unwrap();
+        match response.code {
+            PddbRequestCode::NoErr => Ok(response.id),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Too many subscriptions, or the dictionary is reserved")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+    /// Cancels a subscription made with `subscribe()`.
+    pub fn unsubscribe(&self, id: u32) -> Result<()> {
+        let response = send_message(
+            self.conn,
+            Message::new_blocking_scalar(Opcode::Unsubscribe.to_usize().unwrap(), id as usize, 0, 0, 0)
+        ).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        match response {
+            xous::Result::Scalar1(1) => Ok(()),
+            xous::Result::Scalar1(_) => Err(Error::new(ErrorKind::NotFound, "Subscription not found")),
+            _ => Err(Error::new(ErrorKind::Other, "Xous internal error")),
+        }
+    }
+
+    /// Starts a transaction on `basis_name`, or on the most recently unlocked basis if `None`.
+    /// Writes and deletes made through the returned `PddbTransaction` are held by the server,
+    /// and land on disk all together when it is committed. They are not visible through `get()`
+    /// until then.
+    pub fn begin_transaction(&self, basis_name: Option<&str>) -> Result<PddbTransaction> {
+        let bname = if let Some(bname) = basis_name {
+            if bname.len() > BASIS_NAME_LEN - 1 {
+                return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+            }
+            xous_ipc::String::<BASIS_NAME_LEN>::from_str(bname)
+        } else {
+            xous_ipc::String::<BASIS_NAME_LEN>::new()
+        };
+        let request = PddbTxnRequest {
+            basis_specified: basis_name.is_some(),
+            basis: bname,
+            id: 0,
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::TxnBegin.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+
+        let response = buf.to_original::<PddbTxnRequest, _>().unwrap();
+        match response.code {
+            PddbRequestCode::NoErr => {
+                REFCOUNT.fetch_add(1, Ordering::Relaxed);
+                Ok(PddbTransaction::new(self.conn, response.id))
+            }
+            PddbRequestCode::NotMounted => Err(Error::new(ErrorKind::ConnectionReset, "PDDB was unmounted")),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Basis not found")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+
     pub fn sync(&self) -> Result<()> {
         let response = send_message(
             self.conn,
@@ -560,6 +676,7 @@ impl Pddb {
         match response.code {
             PddbRequestCode::NoErr => (),
             PddbRequestCode::NotFound => return Err(Error::new(ErrorKind::NotFound, "dictionary not found")),
+            PddbRequestCode::AccessDenied => return Err(Error::new(ErrorKind::PermissionDenied, "Dictionary is reserved, or a key list is already in progress")),
             _ => return Err(Error::new(ErrorKind::Other, "Internal error")),
         };
         // v2 key listing packs the key list into a larger [u8] field that should cut down on the number of messages
@@ -715,6 +832,242 @@ impl Pddb {
             _ => Err(Error::new(ErrorKind::Unsupported, "Return code was never set")),
         }
     }
+    /// Reports the space used by a basis (the most recently unlocked one if `basis_name` is `None`),
+    /// or by one of its dictionaries, along with the space left in the PDDB.
+    pub fn usage(&self, basis_name: Option<&str>, dict_name: Option<&str>) -> Result<PddbUsageReport> {
+        if basis_name.map(|b| b.len() > BASIS_NAME_LEN - 1).unwrap_or(false) {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        if dict_name.map(|d| d.len() > DICT_NAME_LEN - 1).unwrap_or(false) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        let request = PddbUsageReport {
+            basis_specified: basis_name.is_some(),
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name.unwrap_or("")),
+            dict_specified: dict_name.is_some(),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name.unwrap_or("")),
+            quota: None,
+            allocated: 0,
+            used: 0,
+            keys: 0,
+            device_total: 0,
+            device_free: None,
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::Usage.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let report = buf.to_original::<PddbUsageReport, _>()
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        match report.code {
+            PddbRequestCode::NoErr => Ok(report),
+            PddbRequestCode::NotMounted => Err(Error::new(ErrorKind::ConnectionReset, "PDDB was unmounted")),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Basis or dictionary not found")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error reporting usage")),
+        }
+    }
+    /// Returns a page of the key names in `dict_name` selected by `query`, in sorted order. Unlike
+    /// `list_keys()`, this doesn't copy out every name in the dictionary, so it suits large
+    /// dictionaries: fetch the first page, then pass the returned cursor back in `query` until
+    /// it comes back as `None`. Keys written between pages are returned if they sort after the
+    /// cursor.
+    pub fn query_keys(&self, dict_name: &str, basis_name: Option<&str>, query: &KeyQuery) -> Result<KeyPage> {
+        if dict_name.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        if basis_name.map(|b| b.len() > BASIS_NAME_LEN - 1).unwrap_or(false) {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        let convert = |bound: &Option<String>| -> Result<Option<xous_ipc::String::<KEY_NAME_LEN>>> {
+            match bound {
+                Some(name) if name.len() > KEY_NAME_LEN - 1 => Err(Error::new(ErrorKind::InvalidInput, "key name too long")),
+                Some(name) => Ok(Some(xous_ipc::String::<KEY_NAME_LEN>::from_str(name))),
+                None => Ok(None),
+            }
+        };
+        let request = PddbKeyQuery {
+            basis_specified: basis_name.is_some(),
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name.unwrap_or("")),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+            prefix: convert(&query.prefix)?,
+            start: convert(&query.start)?,
+            end: convert(&query.end)?,
+            cursor: convert(&query.cursor)?,
+            page_size: query.page_size.min(u32::MAX as usize) as u32,
+            data: [0u8; MAX_PDDBKLISTLEN],
+            count: 0,
+            more: false,
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::QueryKeys.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbKeyQuery, _>()
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        match response.code {
+            PddbRequestCode::NoErr => (),
+            PddbRequestCode::NotFound => return Err(Error::new(ErrorKind::NotFound, "dictionary not found")),
+            PddbRequestCode::AccessDenied => return Err(Error::new(ErrorKind::PermissionDenied, "Dictionary is reserved")),
+            _ => return Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+        let mut keys = Vec::<String>::new();
+        let mut index = 0;
+        for _ in 0..response.count {
+            let strlen = response.data[index] as usize;
+            index += 1;
+            if strlen + index >= MAX_PDDBKLISTLEN {
+                log::error!("Logic error in key query, index would be out of bounds. Aborting");
+                break;
+            }
+            keys.push(String::from(std::str::from_utf8(&response.data[index..index + strlen]).unwrap_or("UTF8 error")));
+            index += strlen;
+        }
+        let cursor = if response.more { keys.last().cloned() } else { None };
+        Ok(KeyPage { keys, cursor })
+    }
+    /// Exports every dictionary and key in `basis_name` as an archive encrypted under
+    /// `passphrase`, which can be imported into this or another device with `import_basis()`.
+    /// The basis has to be unlocked, and its archive can be at most `PDDB_ARCHIVE_MAX_LEN` bytes.
+    /// Expiry times are not carried over.
+    pub fn export_basis(&self, basis_name: &str, passphrase: &str) -> Result<Vec<u8>> {
+        if basis_name.len() > BASIS_NAME_LEN - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        if passphrase.len() > ARCHIVE_PASSPHRASE_LEN - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "passphrase too long"));
+        }
+        let request = PddbArchiveRequest {
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name),
+            passphrase: xous_ipc::String::<ARCHIVE_PASSPHRASE_LEN>::from_str(passphrase),
+            id: 0,
+            len: 0,
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::ExportBasis.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbArchiveRequest, _>().unwrap();
+        buf.volatile_clear();
+        match response.code {
+            PddbRequestCode::NoErr => (),
+            PddbRequestCode::NotMounted => return Err(Error::new(ErrorKind::ConnectionReset, "PDDB was unmounted")),
+            PddbRequestCode::NotFound => return Err(Error::new(ErrorKind::NotFound, "Basis not found")),
+            PddbRequestCode::NoFreeSpace => return Err(Error::new(ErrorKind::OutOfMemory, "Basis is too large to export, or no room to stage it")),
+            _ => return Err(Error::new(ErrorKind::Other, "Internal error exporting basis")),
+        }
+
+        let mut archive = Vec::<u8>::with_capacity(response.len as usize);
+        let mut result = Ok(());
+        while (archive.len() as u64) < response.len {
+            let chunk = PddbArchiveChunk {
+                id: response.id,
+                position: archive.len() as u64,
+                data: [0u8; PDDB_ARCHIVE_DATA_LEN],
+                len: 0,
+                code: PddbRequestCode::Uninit,
+            };
+            let mut buf = Buffer::into_buf(chunk)
+                .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+            buf.lend_mut(self.conn, Opcode::ArchiveRead.to_u32().unwrap())
+                .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+            let chunk = buf.to_original::<PddbArchiveChunk, _>().unwrap();
+            if !matches!(chunk.code, PddbRequestCode::NoErr) || chunk.len == 0 {
+                result = Err(Error::new(ErrorKind::Other, "Internal error reading basis archive"));
+                break;
+            }
+            archive.extend_from_slice(&chunk.data[..chunk.len as usize]);
+        }
+        self.archive_close(response.id);
+        result.map(|_| archive)
+    }
+    /// Writes every key in `archive`, which was made by `export_basis()`, into `basis_name`.
+    /// Keys that are already in the basis are overwritten. The basis has to exist and be
+    /// unlocked, so to import into a new basis, create and unlock it first. Returns the number
+    /// of keys written.
+    pub fn import_basis(&self, basis_name: &str, passphrase: &str, archive: &[u8]) -> Result<u64> {
+        if basis_name.len() > BASIS_NAME_LEN - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        if archive.len() > PDDB_ARCHIVE_MAX_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, "archive is too large"));
+        }
+        if passphrase.len() > ARCHIVE_PASSPHRASE_LEN - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "passphrase too long"));
+        }
+        // the server assigns an id to the archive when the first chunk arrives
+        let mut id = 0;
+        for (i, data) in archive.chunks(PDDB_ARCHIVE_DATA_LEN).enumerate() {
+            let mut chunk = PddbArchiveChunk {
+                id,
+                position: (i * PDDB_ARCHIVE_DATA_LEN) as u64,
+                data: [0u8; PDDB_ARCHIVE_DATA_LEN],
+                len: data.len() as u32,
+                code: PddbRequestCode::Uninit,
+            };
+            chunk.data[..data.len()].copy_from_slice(data);
+            let mut buf = Buffer::into_buf(chunk)
+                .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+            buf.lend_mut(self.conn, Opcode::ArchiveWrite.to_u32().unwrap())
+                .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+            let chunk = buf.to_original::<PddbArchiveChunk, _>().unwrap();
+            id = chunk.id;
+            match chunk.code {
+                PddbRequestCode::NoErr => (),
+                PddbRequestCode::NoFreeSpace => {
+                    self.archive_close(id);
+                    return Err(Error::new(ErrorKind::OutOfMemory, "No room to stage the archive; try again later"));
+                }
+                _ => {
+                    self.archive_close(id);
+                    return Err(Error::new(ErrorKind::Other, "Internal error sending basis archive"));
+                }
+            }
+        }
+        let request = PddbArchiveRequest {
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name),
+            passphrase: xous_ipc::String::<ARCHIVE_PASSPHRASE_LEN>::from_str(passphrase),
+            id,
+            len: 0,
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::ImportBasis.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbArchiveRequest, _>().unwrap();
+        buf.volatile_clear();
+        match response.code {
+            PddbRequestCode::NoErr => Ok(response.len),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Basis not found")),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Wrong passphrase, or the archive is corrupted")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "Out of disk space, or over the basis quota; some keys were imported")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error importing basis")),
+        }
+    }
+    fn archive_close(&self, id: u32) {
+        send_message(self.conn, Message::new_blocking_scalar(Opcode::ArchiveClose.to_usize().unwrap(),
+            id as usize, 0, 0, 0)).expect("couldn't send ArchiveClose message");
+    }
+    /// Checks the open bases for orphaned pages, duplicate page mappings, keys that point at
+    /// unmapped data, and journal conflicts. If `repair` is set, orphaned and superseded pages
+    /// are returned to free space. This reads every page of every open basis, so it takes a while.
+    pub fn fsck(&self, repair: bool) -> Result<PddbFsckReport> {
+        let mut buf = Buffer::into_buf(PddbFsckReport::new(repair))
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::Fsck.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let report = buf.to_original::<PddbFsckReport, _>()
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        match report.code {
+            PddbRequestCode::NoErr => Ok(report),
+            PddbRequestCode::NotMounted => Err(Error::new(ErrorKind::ConnectionReset, "PDDB was unmounted")),
+            PddbRequestCode::InternalError => Err(Error::new(ErrorKind::Other, "Internal error checking the PDDB")),
+            _ => Err(Error::new(ErrorKind::Other, "Unhandled return code")),
+        }
+    }
 
     pub fn run_test(&self, args: [u32; 4]) -> Result<(u32, u32)> {
         match send_message(self.conn,
@@ -3,6 +3,9 @@ mod utils;
 
 use crate::backend::BasisCache;
 use crate::backend::PddbOs;
+use crate::backend::TxnOp;
+use crate::notify::Subscriptions;
+use crate::api::PddbChangeEvent;
 use crate::FileHandle;
 
 use senres::{Senres, SenresMut};
@@ -96,9 +99,9 @@ pub(crate) fn stat_path(
         return Ok(());
     }
 
-    // Find all dicts that match this string
+    // A path is a directory if it's a dict, or if any dict lies beneath it
     let dict_list = basis_cache.dict_list(pddb_os, basis.as_deref());
-    let is_dict = dict_list.contains(stripped_path);
+    let is_dict = dict_list.iter().any(|dict| utils::is_within(dict, stripped_path));
     let mut is_key = false;
 
     // Find all keys that are in this dict. Ignore errors, since sometimes
@@ -209,18 +212,18 @@ pub(crate) fn list_path(
         entries_count += 1;
     }
 
-    for dict in dict_list
+    // Subdirectories are listed even if only a dict further down exists
+    let child_dicts: std::collections::BTreeSet<&str> = dict_list
         .iter()
-        .filter(|needle| utils::get_path(&needle, dict).is_some())
-        .filter(|needle| !key_list.contains(needle.as_str()))
-    {
-        if let Some((_, end)) = dict.rsplit_once(':') {
-            // Entry name
-            writer.append(end);
-            // Entry kind
-            writer.append(1u8);
-            entries_count += 1;
-        }
+        .filter_map(|needle| utils::child_of(needle, dict))
+        .filter(|child| !key_list.contains(*child))
+        .collect();
+    for child in child_dicts {
+        // Entry name
+        writer.append(child);
+        // Entry kind
+        writer.append(1u8);
+        entries_count += 1;
     }
 
     // Add the count of entries
@@ -264,6 +267,7 @@ pub(crate) fn open_key(
     mem: &mut xous::MemoryMessage,
     pddb_os: &mut PddbOs,
     basis_cache: &mut BasisCache,
+    subscriptions: &mut Subscriptions,
     fds: &mut Vec<Option<crate::FileHandle>>,
 ) -> Result<(), crate::PddbRetcode> {
     // Convert the memory to a Senres buffer
@@ -356,17 +360,12 @@ pub(crate) fn open_key(
             if !create_path {
                 continue;
             }
-            basis_cache
-                .dict_add(pddb_os, requested_dict, bname)
-                .map_err(|e| {
-                    log::error!(
-                        "unable to add dict {} to basis {}: {:?}",
-                        requested_dict,
-                        bname.unwrap_or("internal_error"),
-                        e
-                    );
-                    crate::PddbRetcode::InternalError
-                })?;
+            dict_add_all(pddb_os, basis_cache, requested_dict, bname)?;
+        }
+
+        // An expired key that hasn't been scrubbed yet is deleted now, so it can't be reopened
+        if basis_cache.key_expire_now(pddb_os, requested_dict, requested_key, bname) {
+            subscriptions.notify_key(bname, requested_dict, requested_key, PddbChangeEvent::KeyDeleted);
         }
 
         let mut len = 0;
@@ -406,6 +405,7 @@ pub(crate) fn open_key(
                     );
                     crate::PddbRetcode::InternalError
                 })?;
+            subscriptions.notify_key(bname, requested_dict, requested_key, PddbChangeEvent::KeyCreated);
             len = 0;
         } else if create_new {
             log::error!(
@@ -439,6 +439,7 @@ pub(crate) fn open_key(
                     );
                     crate::PddbRetcode::InternalError
                 })?;
+            subscriptions.notify_key(bname, requested_dict, requested_key, PddbChangeEvent::KeyUpdated);
         }
 
         // The basis exists for sure.
@@ -541,6 +542,7 @@ pub(crate) fn delete_key(
     mem: &mut xous::MemoryMessage,
     pddb_os: &mut PddbOs,
     basis_cache: &mut BasisCache,
+    subscriptions: &mut Subscriptions,
     all_fds: &mut std::collections::HashMap<Option<xous::PID>, Vec<Option<FileHandle>>>,
 ) -> Result<(), crate::PddbRetcode> {
     // Convert the memory to a Senres buffer
@@ -578,6 +580,7 @@ pub(crate) fn delete_key(
             );
             Err(crate::PddbRetcode::UnexpectedEof)
         })?;
+    subscriptions.notify_key(bname, dict, key, PddbChangeEvent::KeyDeleted);
 
     // Mark the entry as deleted in all remaining file handles in the entire system
     for fds in all_fds.values_mut() {
@@ -599,6 +602,7 @@ pub(crate) fn write_key(
     mem: &mut xous::MemoryMessage,
     pddb_os: &mut PddbOs,
     basis_cache: &mut BasisCache,
+    subscriptions: &mut Subscriptions,
     fds: &mut Vec<Option<crate::FileHandle>>,
     fd: usize,
 ) -> Result<(), crate::PddbRetcode> {
@@ -639,6 +643,7 @@ pub(crate) fn write_key(
         {
             file.offset += length_to_write as u64;
             mem.valid = xous::MemorySize::new(length_to_write);
+            subscriptions.notify_key(file.basis.as_deref(), &file.dict, &file.key, PddbChangeEvent::KeyUpdated);
             return Ok(());
         }
     }
@@ -826,6 +831,7 @@ pub(crate) fn delete_dict(
     mem: &mut xous::MemoryMessage,
     pddb_os: &mut PddbOs,
     basis_cache: &mut BasisCache,
+    subscriptions: &mut Subscriptions,
 ) -> Result<(), crate::PddbRetcode> {
     let backing = senres::Message::from_mut_slice(mem.buf.as_slice_mut())
         .or(Err(crate::PddbRetcode::InternalError))?;
@@ -854,6 +860,14 @@ pub(crate) fn delete_dict(
             return Err(crate::PddbRetcode::DiskFull);
         }
     }
+    if basis_cache
+        .dict_list(pddb_os, bname.as_deref())
+        .iter()
+        .any(|needle| needle != &dict && utils::is_within(needle, &dict))
+    {
+        log::error!("directory {} has subdirectories", dict);
+        return Err(crate::PddbRetcode::DiskFull);
+    }
 
     if basis_cache
         .dict_remove(pddb_os, &dict, bname.as_deref(), false)
@@ -862,6 +876,7 @@ pub(crate) fn delete_dict(
         log::error!("error removing dict {} in basis {:?}", dict, bname);
         return Err(crate::PddbRetcode::InternalError);
     }
+    subscriptions.notify_dict(bname.as_deref(), &dict, PddbChangeEvent::DictDeleted);
 
     Ok(())
 }
@@ -885,17 +900,500 @@ pub(crate) fn create_dict(
             .or(Err(crate::PddbRetcode::InternalError))?;
     let dict = dict.ok_or(crate::PddbRetcode::InternalError)?;
 
-    basis_cache
-        .dict_add(pddb_os, &dict, basis.as_deref())
-        .map_err(|e| {
+    if basis_cache
+        .dict_attributes(pddb_os, &dict, basis.as_deref())
+        .is_ok()
+    {
+        log::error!("dict {} already exists", dict);
+        return Err(crate::PddbRetcode::InternalError);
+    }
+    dict_add_all(pddb_os, basis_cache, &dict, basis.as_deref())
+}
+
+/// Adds `dict`, along with any of its parents that don't exist yet, so that every level
+/// of a nested path is a dict of its own.
+fn dict_add_all(
+    pddb_os: &mut PddbOs,
+    basis_cache: &mut BasisCache,
+    dict: &str,
+    bname: Option<&str>,
+) -> Result<(), crate::PddbRetcode> {
+    let parents = dict
+        .match_indices(utils::MAIN_SEP)
+        .map(|(index, _)| &dict[..index]);
+    for path in parents.chain(core::iter::once(dict)) {
+        if path.is_empty() || basis_cache.dict_attributes(pddb_os, path, bname).is_ok() {
+            continue;
+        }
+        basis_cache.dict_add(pddb_os, path, bname).map_err(|e| {
             log::error!(
                 "unable to add dict {} to basis {}: {:?}",
-                dict,
-                basis.as_deref().unwrap_or("internal_error"),
+                path,
+                bname.unwrap_or("internal_error"),
                 e
             );
             crate::PddbRetcode::InternalError
         })?;
+    }
+    Ok(())
+}
+
+/// Removes a dict, and every dict and key below it.
+pub(crate) fn delete_dict_all(
+    mem: &mut xous::MemoryMessage,
+    pddb_os: &mut PddbOs,
+    basis_cache: &mut BasisCache,
+    subscriptions: &mut Subscriptions,
+    all_fds: &mut std::collections::HashMap<Option<xous::PID>, Vec<Option<FileHandle>>>,
+) -> Result<(), crate::PddbRetcode> {
+    let backing = senres::Message::from_mut_slice(mem.buf.as_slice_mut())
+        .or(Err(crate::PddbRetcode::InternalError))?;
+    let reader = backing
+        .reader(*b"RmAQ")
+        .ok_or(crate::PddbRetcode::InternalError)?;
+
+    let path = reader
+        .try_get_ref_from::<str>()
+        .or(Err(crate::PddbRetcode::InternalError))?;
+    let (basis, dict) =
+        utils::split_basis_and_dict(path, || basis_cache.basis_latest().map(|m| m.to_owned()))
+            .or(Err(crate::PddbRetcode::InternalError))?;
+    let dict = dict.ok_or(crate::PddbRetcode::InternalError)?;
+    let bname = basis.as_deref();
+
+    let doomed: Vec<String> = basis_cache
+        .dict_list(pddb_os, bname)
+        .into_iter()
+        .filter(|needle| utils::is_within(needle, &dict))
+        .collect();
+    if doomed.is_empty() {
+        log::error!("directory {} does not exist in basis {:?}", dict, bname);
+        return Err(crate::PddbRetcode::InternalError);
+    }
+    for doomed_dict in doomed.iter() {
+        basis_cache
+            .dict_remove(pddb_os, doomed_dict, bname, false)
+            .or_else(|e| {
+                log::error!("error removing dict {} in basis {:?}: {:?}", doomed_dict, bname, e);
+                Err(crate::PddbRetcode::InternalError)
+            })?;
+        subscriptions.notify_dict(bname, doomed_dict, PddbChangeEvent::DictDeleted);
+    }
+
+    // Mark every file that was open below this directory as deleted
+    for fds in all_fds.values_mut() {
+        for fd in fds.iter_mut().filter_map(|f| f.as_mut()) {
+            if fd.basis == basis && utils::is_within(&fd.dict, &dict) {
+                fd.deleted = true;
+            }
+        }
+    }
 
     Ok(())
 }
+
+/// Moves a key or a directory to a new path. There is no way to rename an entry in place,
+/// so each key is copied to its new name and the old one is removed. Within a basis, the keys
+/// are moved in one transaction, so a power loss leaves either all of them moved or none;
+/// between bases, they are moved one at a time, and if this fails part way the keys moved so
+/// far stay moved.
+pub(crate) fn rename_path(
+    mem: &mut xous::MemoryMessage,
+    pddb_os: &mut PddbOs,
+    basis_cache: &mut BasisCache,
+    subscriptions: &mut Subscriptions,
+    all_fds: &mut std::collections::HashMap<Option<xous::PID>, Vec<Option<FileHandle>>>,
+) -> Result<(), crate::PddbRetcode> {
+    let backing = senres::Message::from_mut_slice(mem.buf.as_slice_mut())
+        .or(Err(crate::PddbRetcode::InternalError))?;
+    let reader = backing
+        .reader(*b"RnmQ")
+        .ok_or(crate::PddbRetcode::InternalError)?;
+
+    let old_path = reader
+        .try_get_ref_from::<str>()
+        .or(Err(crate::PddbRetcode::AccessDenied))?;
+    let new_path = reader
+        .try_get_ref_from::<str>()
+        .or(Err(crate::PddbRetcode::AccessDenied))?;
+    let (old_basis, old_path) = utils::split_basis_and_dict(old_path, || {
+        basis_cache.basis_latest().map(|m| m.to_owned())
+    })
+    .or(Err(crate::PddbRetcode::AccessDenied))?;
+    let (new_basis, new_path) = utils::split_basis_and_dict(new_path, || {
+        basis_cache.basis_latest().map(|m| m.to_owned())
+    })
+    .or(Err(crate::PddbRetcode::AccessDenied))?;
+    let old_path = old_path.ok_or(crate::PddbRetcode::AccessDenied)?;
+    let new_path = new_path.ok_or(crate::PddbRetcode::AccessDenied)?;
+    if old_basis == new_basis && old_path == new_path {
+        return Ok(());
+    }
+    let old = (old_basis.as_deref(), old_path.as_str());
+    let new = (new_basis.as_deref(), new_path.as_str());
+
+    // A key takes precedence over a dict of the same name, as `stat` reports it as a file
+    if let Some((dict, key)) = old_path.rsplit_once(std::path::MAIN_SEPARATOR) {
+        if basis_cache.key_attributes(pddb_os, dict, key, old.0).is_ok() {
+            let (new_dict, new_key) = new_path
+                .rsplit_once(std::path::MAIN_SEPARATOR)
+                .ok_or(crate::PddbRetcode::AccessDenied)?;
+            dict_add_all(pddb_os, basis_cache, new_dict, new.0)?;
+            let moves = [(dict.to_owned(), key.to_owned(), new_dict.to_owned(), new_key.to_owned())];
+            return move_keys(pddb_os, basis_cache, subscriptions, all_fds, &old_basis, &new_basis, &moves);
+        }
+    }
+
+    let old_dicts: Vec<String> = basis_cache
+        .dict_list(pddb_os, old.0)
+        .into_iter()
+        .filter(|needle| utils::is_within(needle, old.1))
+        .collect();
+    if old_dicts.is_empty() {
+        log::error!("path {} does not exist in basis {:?}", old.1, old.0);
+        return Err(crate::PddbRetcode::BasisLost);
+    }
+    if old.0 == new.0 && utils::is_within(new.1, old.1) {
+        log::error!("can't move directory {} into itself", old.1);
+        return Err(crate::PddbRetcode::AccessDenied);
+    }
+    // As with `rename(2)`, a directory can only replace an empty one
+    let new_dicts = basis_cache.dict_list(pddb_os, new.0);
+    for needle in new_dicts.iter().filter(|needle| utils::is_within(needle, new.1)) {
+        let occupied = needle != new.1
+            || basis_cache
+                .key_list(pddb_os, needle, new.0)
+                .map(|keys| !keys.is_empty())
+                .unwrap_or(false);
+        if occupied {
+            log::error!("directory {} is not empty", new.1);
+            return Err(crate::PddbRetcode::DiskFull);
+        }
+    }
+
+    dict_add_all(pddb_os, basis_cache, new.1, new.0)?;
+    let mut moves = Vec::new();
+    for old_dict in old_dicts.iter() {
+        let new_dict = format!("{}{}", new.1, &old_dict[old.1.len()..]);
+        dict_add_all(pddb_os, basis_cache, &new_dict, new.0)?;
+        let keys = basis_cache
+            .key_list(pddb_os, old_dict, old.0)
+            .or(Err(crate::PddbRetcode::InternalError))?;
+        for key in keys.into_iter() {
+            moves.push((old_dict.to_owned(), key.clone(), new_dict.clone(), key));
+        }
+    }
+    move_keys(pddb_os, basis_cache, subscriptions, all_fds, &old_basis, &new_basis, &moves)?;
+    for old_dict in old_dicts.iter() {
+        basis_cache
+            .dict_remove(pddb_os, old_dict, old.0, false)
+            .or_else(|e| {
+                log::error!("error removing dict {} in basis {:?}: {:?}", old_dict, old.0, e);
+                Err(crate::PddbRetcode::InternalError)
+            })?;
+        subscriptions.notify_dict(old.0, old_dict, PddbChangeEvent::DictDeleted);
+    }
+
+    Ok(())
+}
+
+/// The event subscribers to `key` are sent once something is written to it.
+fn write_event(
+    pddb_os: &mut PddbOs,
+    basis_cache: &mut BasisCache,
+    basis: &Option<String>,
+    dict: &str,
+    key: &str,
+) -> PddbChangeEvent {
+    if basis_cache.key_attributes(pddb_os, dict, key, basis.as_deref()).is_ok() {
+        PddbChangeEvent::KeyUpdated
+    } else {
+        PddbChangeEvent::KeyCreated
+    }
+}
+
+/// Moves each (old dict, old key) of `moves` to its (new dict, new key), along with its expiry
+/// time. Within a basis, this is a single transaction that holds every key in memory at once.
+fn move_keys(
+    pddb_os: &mut PddbOs,
+    basis_cache: &mut BasisCache,
+    subscriptions: &mut Subscriptions,
+    all_fds: &mut std::collections::HashMap<Option<xous::PID>, Vec<Option<FileHandle>>>,
+    old_basis: &Option<String>,
+    new_basis: &Option<String>,
+    moves: &[(String, String, String, String)],
+) -> Result<(), crate::PddbRetcode> {
+    if old_basis != new_basis {
+        for (old_dict, old_key, new_dict, new_key) in moves.iter() {
+            move_key(
+                pddb_os,
+                basis_cache,
+                subscriptions,
+                all_fds,
+                (old_basis, old_dict, old_key),
+                (new_basis, new_dict, new_key),
+            )?;
+        }
+        return Ok(());
+    }
+
+    let mut ops = Vec::new();
+    let mut moved = Vec::new();
+    for (old_dict, old_key, new_dict, new_key) in moves.iter() {
+        let attr = basis_cache
+            .key_attributes(pddb_os, old_dict, old_key, old_basis.as_deref())
+            .or(Err(crate::PddbRetcode::BasisLost))?;
+        let mut data = vec![0u8; attr.len];
+        let len = basis_cache
+            .key_read(pddb_os, old_dict, old_key, &mut data, None, old_basis.as_deref())
+            .or(Err(crate::PddbRetcode::InternalError))?;
+        data.truncate(len);
+        moved.push((attr.expires_in, write_event(pddb_os, basis_cache, new_basis, new_dict, new_key)));
+        ops.push(TxnOp::Write { dict: new_dict.to_owned(), key: new_key.to_owned(), data });
+        ops.push(TxnOp::Delete { dict: old_dict.to_owned(), key: old_key.to_owned() });
+    }
+    basis_cache
+        .txn_commit(pddb_os, old_basis.as_deref(), &ops)
+        .or_else(|e| {
+            log::error!("unable to move {} keys in basis {:?}: {:?}", moves.len(), old_basis, e);
+            Err(crate::PddbRetcode::DiskFull)
+        })?;
+    for ((old_dict, old_key, new_dict, new_key), (expires_in, event)) in moves.iter().zip(moved.into_iter()) {
+        if let Some(ttl) = expires_in {
+            basis_cache
+                .key_set_expiry(pddb_os, new_dict, new_key, new_basis.as_deref(), Some(ttl))
+                .ok();
+        }
+        key_moved(subscriptions, all_fds, (old_basis, old_dict, old_key), (new_basis, new_dict, new_key), event);
+    }
+    Ok(())
+}
+
+/// Copies a key, and its expiry time, to a new (basis, dict, key) a chunk at a time and
+/// removes the original.
+fn move_key(
+    pddb_os: &mut PddbOs,
+    basis_cache: &mut BasisCache,
+    subscriptions: &mut Subscriptions,
+    all_fds: &mut std::collections::HashMap<Option<xous::PID>, Vec<Option<FileHandle>>>,
+    old: (&Option<String>, &str, &str),
+    new: (&Option<String>, &str, &str),
+) -> Result<(), crate::PddbRetcode> {
+    let (old_basis, old_dict, old_key) = old;
+    let (new_basis, new_dict, new_key) = new;
+    let attr = basis_cache
+        .key_attributes(pddb_os, old_dict, old_key, old_basis.as_deref())
+        .or(Err(crate::PddbRetcode::BasisLost))?;
+    let event = write_event(pddb_os, basis_cache, new_basis, new_dict, new_key);
+    let write_failed = |e: std::io::Error| {
+        log::error!("unable to write key {}:{} while renaming: {:?}", new_dict, new_key, e);
+        Err(crate::PddbRetcode::DiskFull)
+    };
+    // reserve the whole length up front, so the key isn't moved as it grows
+    basis_cache
+        .key_update(pddb_os, new_dict, new_key, &[], Some(0), Some(attr.len), new_basis.as_deref(), true)
+        .or_else(write_failed)?;
+    let mut chunk = vec![0u8; crate::backend::VPAGE_SIZE];
+    let mut offset = 0;
+    while offset < attr.len {
+        let len = basis_cache
+            .key_read(pddb_os, old_dict, old_key, &mut chunk, Some(offset), old_basis.as_deref())
+            .or(Err(crate::PddbRetcode::InternalError))?;
+        if len == 0 {
+            break;
+        }
+        basis_cache
+            .key_update_deferred(pddb_os, new_dict, new_key, &chunk[..len], Some(offset), Some(attr.len), new_basis.as_deref())
+            .or_else(write_failed)?;
+        offset += len;
+    }
+    basis_cache.sync(pddb_os, new_basis.as_deref()).or_else(write_failed)?;
+    if let Some(ttl) = attr.expires_in {
+        basis_cache
+            .key_set_expiry(pddb_os, new_dict, new_key, new_basis.as_deref(), Some(ttl))
+            .ok();
+    }
+    basis_cache
+        .key_remove(pddb_os, old_dict, old_key, old_basis.as_deref(), false)
+        .or_else(|e| {
+            log::error!("unable to remove key {}:{} after renaming: {:?}", old_dict, old_key, e);
+            Err(crate::PddbRetcode::InternalError)
+        })?;
+    key_moved(subscriptions, all_fds, old, new, event);
+    Ok(())
+}
+
+/// Tells subscribers a key has moved, and makes open file handles follow it to its new name.
+fn key_moved(
+    subscriptions: &mut Subscriptions,
+    all_fds: &mut std::collections::HashMap<Option<xous::PID>, Vec<Option<FileHandle>>>,
+    old: (&Option<String>, &str, &str),
+    new: (&Option<String>, &str, &str),
+    event: PddbChangeEvent,
+) {
+    let (old_basis, old_dict, old_key) = old;
+    let (new_basis, new_dict, new_key) = new;
+    subscriptions.notify_key(old_basis.as_deref(), old_dict, old_key, PddbChangeEvent::KeyDeleted);
+    subscriptions.notify_key(new_basis.as_deref(), new_dict, new_key, event);
+
+    for fds in all_fds.values_mut() {
+        for fd in fds.iter_mut().filter_map(|f| f.as_mut()) {
+            if &fd.basis == old_basis && fd.dict == old_dict && fd.key == old_key {
+                fd.basis = new_basis.clone();
+                fd.dict = new_dict.to_owned();
+                fd.key = new_key.to_owned();
+            }
+        }
+    }
+}
+
+/// Runs the handlers above on requests laid out the way libstd lays them out.
+#[cfg(all(feature = "hosted", not(feature = "offline")))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::api::PDDB_DEFAULT_SYSTEM_BASIS;
+    use crate::api::BasisRetentionPolicy;
+    use crate::backend::VPAGE_SIZE;
+    use std::collections::{BTreeSet, HashMap};
+
+    const DICT: &str = "libstdtest";
+    const OTHER_BASIS: &str = "LibstdTest";
+    const OTHER_BASIS_PW: &str = "libstd test basis";
+
+    type Fds = HashMap<Option<xous::PID>, Vec<Option<FileHandle>>>;
+
+    /// `dict`, nested below the test dictionary, as a path in `basis`
+    fn path(basis: &str, dict: &str) -> String {
+        if dict.is_empty() {
+            format!(":{}:{}", basis, DICT)
+        } else {
+            format!(":{}:{}{}{}", basis, DICT, utils::MAIN_SEP, dict)
+        }
+    }
+    fn key_path(basis: &str, dict: &str, key: &str) -> String {
+        format!("{}{}{}", path(basis, dict), std::path::MAIN_SEPARATOR, key)
+    }
+    /// `dict`, nested below the test dictionary, as the backend names it
+    fn full(dict: &str) -> String {
+        format!("{}{}{}", DICT, utils::MAIN_SEP, dict)
+    }
+
+    /// A page holding a request with tag `fourcc` and `args`, as libstd lends it
+    fn request(fourcc: [u8; 4], args: &[&str]) -> xous::MemoryMessage {
+        let mut buf = xous::map_memory(None, None, 4096, xous::MemoryFlags::R | xous::MemoryFlags::W)
+            .expect("couldn't allocate a request");
+        {
+            let mut backing = senres::Message::from_mut_slice(buf.as_slice_mut()).unwrap();
+            let mut writer = backing.writer(fourcc).unwrap();
+            for arg in args.iter() {
+                writer.append(*arg);
+            }
+        }
+        xous::MemoryMessage { id: 0, buf, offset: None, valid: None }
+    }
+
+    /// The entries `list_path` returns for `path`, with their kinds
+    fn list(pddb_os: &mut PddbOs, basis_cache: &mut BasisCache, path: &str) -> BTreeSet<(String, u8)> {
+        let mut mem = request(*b"PthQ", &[path]);
+        list_path(&mut mem, pddb_os, basis_cache).expect("couldn't list path");
+        let backing = senres::Message::from_slice(mem.buf.as_slice()).unwrap();
+        let reader = backing.reader(*b"PthR").expect("listing has no response");
+        let count = reader.try_get_from::<u32>().unwrap();
+        (0..count)
+            .map(|_| {
+                let name = reader.try_get_from::<String>().unwrap();
+                (name, reader.try_get_from::<u8>().unwrap())
+            })
+            .collect()
+    }
+
+    fn rename(pddb_os: &mut PddbOs, basis_cache: &mut BasisCache, subscriptions: &mut Subscriptions,
+        fds: &mut Fds, old: &str, new: &str,
+    ) -> Result<(), crate::PddbRetcode> {
+        rename_path(&mut request(*b"RnmQ", &[old, new]), pddb_os, basis_cache, subscriptions, fds)
+    }
+
+    fn read(pddb_os: &mut PddbOs, basis_cache: &mut BasisCache, basis: &str, dict: &str, key: &str) -> Option<Vec<u8>> {
+        let attr = basis_cache.key_attributes(pddb_os, dict, key, Some(basis)).ok()?;
+        let mut data = vec![0u8; attr.len];
+        let len = basis_cache.key_read(pddb_os, dict, key, &mut data, None, Some(basis)).ok()?;
+        data.truncate(len);
+        Some(data)
+    }
+
+    /// Nested directories are created level by level and listed through levels that don't exist
+    /// as dictionaries; keys and directories can be renamed, within a basis and to another, but
+    /// not into themselves or onto a directory with something in it; and a directory can be
+    /// removed along with everything below it.
+    pub(crate) fn libstd_test(pddb_os: &mut PddbOs) -> std::io::Result<()> {
+        let system = PDDB_DEFAULT_SYSTEM_BASIS;
+        let mut basis_cache = crate::tests::remount_system(pddb_os);
+        let mut subscriptions = Subscriptions::new();
+        let mut fds = Fds::new();
+        let sep = utils::MAIN_SEP;
+
+        // every level of a nested path becomes a dictionary
+        let nested = format!("a{0}b{0}c", sep);
+        create_dict(&mut request(*b"NuDQ", &[&path(system, &nested)]), pddb_os, &mut basis_cache)
+            .expect("couldn't create nested directory");
+        for dict in [DICT.to_owned(), full("a"), full(&format!("a{}b", sep)), full(&nested)].iter() {
+            assert!(basis_cache.dict_attributes(pddb_os, dict, Some(system)).is_ok(), "{} was not created", dict);
+        }
+
+        // a dictionary whose parents were never created is still reachable by listing
+        basis_cache.dict_add(pddb_os, &full(&format!("x{0}y{0}z", sep)), Some(system))?;
+        let top = list(pddb_os, &mut basis_cache, &path(system, ""));
+        assert!(top.contains(&("a".to_owned(), 1)) && top.contains(&("x".to_owned(), 1)), "top level lists {:?}", top);
+        let x = list(pddb_os, &mut basis_cache, &path(system, "x"));
+        assert!(x == [("y".to_owned(), 1)].iter().cloned().collect::<BTreeSet<_>>(), "missing level lists {:?}", x);
+
+        // renaming a key, into a directory that doesn't exist yet
+        let data: Vec<u8> = (0..3 * VPAGE_SIZE + 77).map(|i| (i * 7) as u8).collect();
+        basis_cache.key_update(pddb_os, &full("a"), "k1", &data, None, None, Some(system), true)?;
+        rename(pddb_os, &mut basis_cache, &mut subscriptions, &mut fds,
+            &key_path(system, "a", "k1"), &key_path(system, "new", "k2"))
+            .expect("couldn't rename key");
+        assert!(read(pddb_os, &mut basis_cache, system, &full("a"), "k1").is_none(), "renamed key is still there");
+        assert!(read(pddb_os, &mut basis_cache, system, &full("new"), "k2") == Some(data.clone()), "renamed key differs");
+
+        // renaming a directory moves everything below it
+        basis_cache.key_update(pddb_os, &full(&nested), "deep", b"deep key", None, None, Some(system), true)?;
+        rename(pddb_os, &mut basis_cache, &mut subscriptions, &mut fds, &path(system, "a"), &path(system, "moved"))
+            .expect("couldn't rename directory");
+        let moved_nested = full(&format!("moved{0}b{0}c", sep));
+        assert!(read(pddb_os, &mut basis_cache, system, &moved_nested, "deep") == Some(b"deep key".to_vec()),
+            "key below a renamed directory didn't move with it");
+        assert!(!basis_cache.dict_list(pddb_os, Some(system)).iter().any(|d| utils::is_within(d, &full("a"))),
+            "renamed directory is still there");
+
+        // a directory can't go into itself, or replace one that isn't empty
+        let e = rename(pddb_os, &mut basis_cache, &mut subscriptions, &mut fds,
+            &path(system, "moved"), &path(system, &format!("moved{}inner", sep)))
+            .expect_err("directory was moved into itself");
+        assert!(matches!(e, crate::PddbRetcode::AccessDenied), "moving a directory into itself gave {:?}", e);
+        let e = rename(pddb_os, &mut basis_cache, &mut subscriptions, &mut fds, &path(system, "x"), &path(system, "moved"))
+            .expect_err("directory replaced one that isn't empty");
+        assert!(matches!(e, crate::PddbRetcode::DiskFull), "replacing a directory that isn't empty gave {:?}", e);
+        assert!(read(pddb_os, &mut basis_cache, system, &moved_nested, "deep").is_some(), "refused rename changed the target");
+
+        // a key moved to another basis is copied a chunk at a time
+        basis_cache.basis_create(pddb_os, OTHER_BASIS, OTHER_BASIS_PW, None)?;
+        let basis = basis_cache.basis_unlock(pddb_os, OTHER_BASIS, OTHER_BASIS_PW, BasisRetentionPolicy::Persist)
+            .expect("couldn't unlock the second basis");
+        basis_cache.basis_add(basis);
+        rename(pddb_os, &mut basis_cache, &mut subscriptions, &mut fds,
+            &key_path(system, "new", "k2"), &key_path(OTHER_BASIS, "new", "k2"))
+            .expect("couldn't move key to another basis");
+        assert!(read(pddb_os, &mut basis_cache, system, &full("new"), "k2").is_none(), "moved key is still in the first basis");
+        assert!(read(pddb_os, &mut basis_cache, OTHER_BASIS, &full("new"), "k2") == Some(data),
+            "key moved to another basis differs");
+        basis_cache.basis_delete(pddb_os, OTHER_BASIS)?;
+
+        // removing a directory removes everything below it
+        delete_dict_all(&mut request(*b"RmAQ", &[&path(system, "")]), pddb_os, &mut basis_cache, &mut subscriptions, &mut fds)
+            .expect("couldn't remove directory tree");
+        assert!(!basis_cache.dict_list(pddb_os, Some(system)).iter().any(|d| utils::is_within(d, DICT)),
+            "dictionaries are left below a removed directory");
+        Ok(())
+    }
+}
@@ -26,6 +26,23 @@ pub fn get_path<'a>(s: &'a str, prefix: &'a str) -> Option<&'a str> {
     }
 }
 
+/// Returns the name of the directory directly below `prefix` that `s` is in, or is. Unlike
+/// `get_path`, `s` can be any depth below `prefix`, as a dict's parents don't have to exist as
+/// dicts themselves.
+pub fn child_of<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let without_prefix = if prefix.is_empty() {
+        s
+    } else {
+        s.strip_prefix(prefix)?.strip_prefix(MAIN_SEP)?
+    };
+    without_prefix.split(MAIN_SEP).next().filter(|child| !child.is_empty())
+}
+
+/// Returns true if `s` is the same as `prefix`, or lies anywhere below it.
+pub fn is_within(s: &str, prefix: &str) -> bool {
+    prefix.is_empty() || s == prefix || s.strip_prefix(prefix).map_or(false, |rest| rest.starts_with(MAIN_SEP))
+}
+
 /// Split a path into its constituant Basis and Dict, if the path is legal.
 pub fn split_basis_and_dict<F: FnMut() -> Option<String>>(src: &str, mut default: F) -> io::Result<(Option<String>, Option<String>)> {
     let mut basis = None;
@@ -65,6 +82,9 @@ pub fn split_basis_and_dict<F: FnMut() -> Option<String>>(src: &str, mut default
         if dict.ends_with(MAIN_SEP) {
             return Err(io::Error::new(io::ErrorKind::Other, "invalid path"));
         }
+        if crate::backend::dict_reserved(dict) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "dictionary is reserved"));
+        }
     }
     Ok((basis, dict))
 }
@@ -171,4 +191,36 @@ fn dict_with_two_keys_two_trailing_colons() {
 #[should_panic]
 fn dict_with_two_keys_three_trailing_colons() {
     split_basis_and_dict("foo:bar:::", default_path).unwrap();
-}
\ No newline at end of file
+}
+#[test]
+fn child_of_nested() {
+    assert_eq!(child_of("one:two:three", "one"), Some("two"));
+    assert_eq!(child_of("one:two:three", ""), Some("one"));
+    assert_eq!(child_of("one:two", "one:two"), None);
+    assert_eq!(child_of("onetwo:three", "one"), None);
+}
+
+#[test]
+fn within() {
+    assert!(is_within("one:two", "one"));
+    assert!(is_within("one", "one"));
+    assert!(!is_within("onetwo", "one"));
+    assert!(is_within("one", ""));
+}
+
+#[test]
+fn reserved_dict() {
+    assert_eq!(split_basis_and_dict("pddb.meta", default_path).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(split_basis_and_dict(":one:pddb.meta", default_path).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn reserved_dict_key() {
+    let path = format!("pddb.meta{}expiry", std::path::MAIN_SEPARATOR);
+    assert_eq!(split_basis_and_dict(&path, default_path).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn dict_named_like_reserved() {
+    assert_eq!(split_basis_and_dict("pddb.metadata", default_path).unwrap(), (None, Some("pddb.metadata".to_owned())));
+}
@@ -366,34 +366,53 @@ extern crate bitfield;
 
 mod api;
 use api::*;
+// the offline image tool uses only part of the backend's API
+#[cfg_attr(feature="offline", allow(dead_code))]
 mod backend;
 use backend::*;
 mod ux;
 use ux::*;
+// the offline image tool only uses the backend, so it leaves out the server
+#[cfg(not(feature="offline"))]
 mod menu;
+#[cfg(not(feature="offline"))]
 use menu::*;
 
+#[cfg(not(feature="offline"))]
 mod libstd;
+#[cfg(not(feature="offline"))]
+mod notify;
+#[cfg(not(feature="offline"))]
+use notify::*;
 
-#[cfg(any(feature="hosted"))]
+#[cfg(all(feature="hosted", not(feature="offline")))]
 mod tests;
-#[cfg(any(feature="hosted"))]
+#[cfg(all(feature="hosted", not(feature="offline")))]
 #[allow(unused_imports)]
 use tests::*;
 
 #[cfg(feature="pddb-flamegraph")]
 mod profiling;
+#[cfg(feature="offline")]
+mod imagetool;
 
 use num_traits::*;
-use xous::{send_message, Message, msg_blocking_scalar_unpack};
 use xous_ipc::Buffer;
 use core::cell::RefCell;
 use std::rc::Rc;
+#[cfg(not(feature="offline"))]
+use xous::{send_message, Message, msg_blocking_scalar_unpack};
+#[cfg(not(feature="offline"))]
 use std::thread;
-use std::collections::{HashMap, BTreeSet};
+#[cfg(not(feature="offline"))]
+use std::collections::{HashMap, HashSet, BTreeSet};
+#[cfg(not(feature="offline"))]
 use std::io::ErrorKind;
+#[cfg(not(feature="offline"))]
 use core::fmt::Write;
+#[cfg(not(feature="offline"))]
 use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(feature="offline"))]
 use std::sync::Arc;
 
 use locales::t;
@@ -411,6 +430,7 @@ pub(crate) enum PasswordState {
 }
 
 #[derive(Debug)]
+#[cfg(not(feature="offline"))]
 struct TokenRecord {
     pub dict: String,
     pub key: String,
@@ -419,6 +439,34 @@ struct TokenRecord {
     pub conn: Option<xous::CID>, // callback connection, if one was specified
 }
 
+/// Returns false if `pid` refers to a process that has exited.
+#[cfg(not(feature="offline"))]
+fn process_alive(pid: Option<xous::PID>) -> bool {
+    match pid {
+        Some(pid) => !matches!(xous::process_name(pid, &mut []), Err(xous::Error::ProcessNotFound)),
+        None => true,
+    }
+}
+
+/// Operations staged in an open transaction, waiting for `TxnCommit`
+#[cfg(not(feature="offline"))]
+struct StagedTxn {
+    pub basis: Option<String>,
+    pub ops: Vec<TxnOp>,
+    /// only the process that began the transaction may stage into it, commit it, or abort it
+    pub owner: Option<xous::PID>,
+}
+
+/// An archive held for a client: between `ExportBasis` and the `ArchiveRead`s that fetch it, or
+/// between the `ArchiveWrite`s that send it and `ImportBasis`
+#[cfg(not(feature="offline"))]
+struct StagedArchive {
+    pub data: Vec<u8>,
+    /// only the process that created the archive may read, write, import or close it
+    pub owner: Option<xous::PID>,
+}
+
+#[cfg(not(feature="offline"))]
 struct FileHandle {
     pub dict: String,
     pub key: String,
@@ -433,6 +481,12 @@ struct FileHandle {
     pub deleted: bool,
 }
 
+#[cfg(feature="offline")]
+fn main() {
+    imagetool::main()
+}
+
+#[cfg(not(feature="offline"))]
 fn main () -> ! {
     let stack_size = 1024 * 1024;
     std::thread::Builder::new()
@@ -443,6 +497,7 @@ fn main () -> ! {
         .unwrap()
 }
 
+#[cfg(not(feature="offline"))]
 fn wrapped_main() -> ! {
     log_server::init_wait().unwrap();
     log::set_max_level(log::LevelFilter::Info);
@@ -478,6 +533,16 @@ fn wrapped_main() -> ! {
     // storage for the token lookup: given an ApiToken, return a dict/key/basis set. Basis can be None or specified.
     let mut token_dict = HashMap::<ApiToken, TokenRecord>::new();
 
+    // callbacks registered for changes to dictionaries and keys
+    let mut subscriptions = Subscriptions::new();
+    // keys written through a stream since the last flush; subscribers hear about them once, on the flush
+    let mut stream_updates = HashSet::<ApiToken>::new();
+    // open transactions, by ID
+    let mut txn_dict = HashMap::<u32, StagedTxn>::new();
+    let mut next_txn_id: u32 = 1;
+    let mut archive_dict = HashMap::<u32, StagedArchive>::new();
+    let mut next_archive_id: u32 = 1;
+
     // Process-indexed map of file descriptors to token records
     let mut fd_mapping = HashMap::<Option<xous::PID>, Vec<Option<FileHandle>>>::new();
 
@@ -583,6 +648,7 @@ fn wrapped_main() -> ! {
         xous::Limits::HeapMaximum as usize,
         0,
         new_limit,
+        None,
     ));
 
     if let Ok(xous::Result::Scalar2(1, current_limit)) = result {
@@ -590,6 +656,7 @@ fn wrapped_main() -> ! {
             xous::Limits::HeapMaximum as usize,
             current_limit,
             new_limit,
+            None,
         ))
         .unwrap();
         log::info!("Heap limit increased to: {}", new_limit);
@@ -607,7 +674,12 @@ fn wrapped_main() -> ! {
         // log::error!("got msg: {:x?}", msg);
         match FromPrimitive::from_usize(msg.body.id() & 0xffff).unwrap_or(Opcode::InvalidOpcode) {
             Opcode::SuspendResume => xous::msg_scalar_unpack!(msg, token, _, _, _, {
+                let mounted = basis_cache.basis_list();
                 basis_cache.suspend(&mut pddb_os);
+                let still_mounted = basis_cache.basis_list();
+                for basis in mounted.iter().filter(|b| !still_mounted.contains(b)) {
+                    subscriptions.notify_basis(basis, PddbChangeEvent::BasisUnmounted);
+                }
                 susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
             }),
             Opcode::IsEfuseSecured => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
@@ -642,6 +714,9 @@ fn wrapped_main() -> ! {
                                     }
                                     assert!(mount_notifications.len() == 0, "apparently I don't understand what drain() does");
                                     log::info!("{}PDDB.MOUNTED,{}", xous::BOOKEND_START, xous::BOOKEND_END);
+                                    for basis in basis_cache.basis_list() {
+                                        subscriptions.notify_basis(&basis, PddbChangeEvent::BasisMounted);
+                                    }
                                     xous::return_scalar(msg.sender, 1).expect("couldn't return scalar");
                                 } else {
                                     xous::return_scalar(msg.sender, 0).expect("couldn't return scalar");
@@ -655,6 +730,9 @@ fn wrapped_main() -> ! {
                                     }
                                     assert!(mount_notifications.len() == 0, "apparently I don't understand what drain() does");
                                     log::info!("{}PDDB.MOUNTED,{}", xous::BOOKEND_START, xous::BOOKEND_END);
+                                    for basis in basis_cache.basis_list() {
+                                        subscriptions.notify_basis(&basis, PddbChangeEvent::BasisMounted);
+                                    }
                                     xous::return_scalar(msg.sender, 1).expect("couldn't return scalar");
                                 } else {
                                     xous::return_scalar(msg.sender, 0).expect("couldn't return scalar");
@@ -691,6 +769,10 @@ fn wrapped_main() -> ! {
                     latest_heap = heap_usage();
                     log::info!("{} pruned, now: {} heap, {} cache", pruned, latest_heap, basis_cache.cache_size())
                 }
+                for (basis, dict, key) in basis_cache.expire_keys(&mut pddb_os) {
+                    evict_key_tokens(&mut token_dict, &dict, &key, Some(&basis));
+                    subscriptions.notify_key(Some(&basis), &dict, &key, PddbChangeEvent::KeyDeleted);
+                }
             }
             Opcode::ListBasis => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
@@ -766,7 +848,7 @@ fn wrapped_main() -> ! {
                         buf.lend_mut(pw_cid, PwManagerOpcode::RequestPassword.to_u32().unwrap()).unwrap();
                         let ret = buf.to_original::<BasisRequestPassword, _>().unwrap();
                         if let Some(pw) = ret.plaintext_pw {
-                            match basis_cache.basis_create(&mut pddb_os, mgmt.name.as_str().expect("name is not valid utf-8"), pw.as_str().expect("password was not valid utf-8")) {
+                            match basis_cache.basis_create(&mut pddb_os, mgmt.name.as_str().expect("name is not valid utf-8"), pw.as_str().expect("password was not valid utf-8"), mgmt.quota) {
                                 Ok(_) => {
                                     log::info!("{}PDDB.CREATEOK,{},{}", xous::BOOKEND_START, mgmt.name.as_str().unwrap(), xous::BOOKEND_END);
                                     mgmt.code = PddbRequestCode::NoErr
@@ -808,6 +890,7 @@ fn wrapped_main() -> ! {
                                     mgmt.policy.unwrap_or(BasisRetentionPolicy::Persist)
                                 ) {
                                     basis_cache.basis_add(basis);
+                                    subscriptions.notify_basis(mgmt.name.as_str().unwrap(), PddbChangeEvent::BasisMounted);
                                     finished = true;
                                     log::info!("{}PDDB.UNLOCKOK,{},{}", xous::BOOKEND_START, mgmt.name.as_str().unwrap(), xous::BOOKEND_END);
                                     mgmt.code = PddbRequestCode::NoErr;
@@ -850,7 +933,10 @@ fn wrapped_main() -> ! {
                 match mgmt.code {
                     PddbRequestCode::Close => {
                         match basis_cache.basis_unmount(&mut pddb_os, mgmt.name.as_str().expect("name is not valid utf-8")) {
-                            Ok(_) => mgmt.code = PddbRequestCode::NoErr,
+                            Ok(_) => {
+                                subscriptions.notify_basis(mgmt.name.as_str().unwrap(), PddbChangeEvent::BasisUnmounted);
+                                mgmt.code = PddbRequestCode::NoErr;
+                            }
                             Err(e) => match e.kind() {
                                 ErrorKind::NotFound => mgmt.code = PddbRequestCode::NotFound,
                                 _ => mgmt.code = PddbRequestCode::InternalError,
@@ -895,6 +981,11 @@ fn wrapped_main() -> ! {
                     let dict = req.dict.as_str().expect("dict utf-8 decode error");
                     let key = req.key.as_str().expect("key utf-8 decode error");
                     log::debug!("get: {:?} {}", bname, key);
+                    if dict_reserved(dict) {
+                        req.result = PddbRequestCode::AccessDenied;
+                        buffer.replace(req).unwrap();
+                        break;
+                    }
                     if basis_cache.dict_attributes(&mut pddb_os, dict, bname).is_err() {
                         if req.create_dict {
                             match basis_cache.dict_add(&mut pddb_os, dict, bname) {
@@ -912,7 +1003,13 @@ fn wrapped_main() -> ! {
                             buffer.replace(req).unwrap(); continue
                         }
                     }
+                    // an expired key that hasn't been scrubbed yet is deleted now, and treated as absent
+                    if basis_cache.key_expire_now(&mut pddb_os, dict, key, bname) {
+                        evict_key_tokens(&mut token_dict, dict, key, bname);
+                        subscriptions.notify_key(bname, dict, key, PddbChangeEvent::KeyDeleted);
+                    }
                     let alloc_hint = if let Some(hint) = req.alloc_hint {Some(hint as usize)} else {None};
+                    let mut created = false;
                     if basis_cache.key_attributes(&mut pddb_os, dict, key, bname).is_err() {
                         if !req.create_key {
                             req.result = PddbRequestCode::NotFound;
@@ -925,7 +1022,7 @@ fn wrapped_main() -> ! {
                                 // don't truncate if we've been given an explicit size hint.
                                 alloc_hint.is_none()
                             ) {
-                                Ok(_) => {},
+                                Ok(_) => created = true,
                                 Err(e) => {
                                     log::error!("Couldn't allocate key: {:?}", e);
                                     match e.kind() {
@@ -938,6 +1035,24 @@ fn wrapped_main() -> ! {
                             }
                         }
                     }
+                    // the expiry time is set before a handle is given out, so a new key never exists without one
+                    if let Some(ttl) = req.expiry_secs {
+                        if let Err(e) = basis_cache.key_set_expiry(&mut pddb_os, dict, key, bname, Some(ttl)) {
+                            log::error!("Couldn't set the expiry time of {}: {:?}", key, e);
+                            if created {
+                                basis_cache.key_remove(&mut pddb_os, dict, key, bname, false).ok();
+                            }
+                            match e.kind() {
+                                std::io::ErrorKind::OutOfMemory => req.result = PddbRequestCode::NoFreeSpace,
+                                _ => req.result = PddbRequestCode::InternalError,
+                            }
+                            buffer.replace(req).unwrap();
+                            break;
+                        }
+                    }
+                    if created {
+                        subscriptions.notify_key(bname, dict, key, PddbChangeEvent::KeyCreated);
+                    }
                     // at this point, we have established a basis/dict/key tuple.
                     let token: ApiToken = [pddb_os.trng_u32(), pddb_os.trng_u32(), pddb_os.trng_u32()];
                     let cid = if let Some(cb_sid) = req.cb_sid {
@@ -962,7 +1077,7 @@ fn wrapped_main() -> ! {
             Opcode::OpenKeyStd => {
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
-                    if let Err(e) = libstd::open_key(mem, &mut pddb_os, &mut basis_cache, fd_mapping.entry(msg.sender.pid()).or_default()) {
+                    if let Err(e) = libstd::open_key(mem, &mut pddb_os, &mut basis_cache, &mut subscriptions, fd_mapping.entry(msg.sender.pid()).or_default()) {
                         mem.offset = xous::MemoryAddress::new(e as usize);
                     }
                 }
@@ -970,6 +1085,10 @@ fn wrapped_main() -> ! {
 
             Opcode::KeyDrop => msg_blocking_scalar_unpack!(msg, t0, t1, t2, _, {
                 let token: ApiToken = [t0 as u32, t1 as u32, t2 as u32];
+                // a stream that was never flushed still changed the key
+                if stream_updates.contains(&token) {
+                    notify_stream_updates(&mut stream_updates, &token_dict, &mut subscriptions);
+                }
                 if let Some(rec) = token_dict.remove(&token) {
                     // now check if we can safely disconnect and recycle our connection number.
                     // This is important because we can only have 32 outgoing connections...
@@ -984,6 +1103,8 @@ fn wrapped_main() -> ! {
                                 }
                             }
                         }
+                        // subscriptions can share the connection, too
+                        still_needs_cid |= subscriptions.uses_cid(conn_to_remove);
                         // if nobody else had my connection number, disconnect it.
                         if !still_needs_cid {
                             unsafe{xous::disconnect(conn_to_remove).expect("couldn't disconnect from callback server")};
@@ -1021,35 +1142,16 @@ fn wrapped_main() -> ! {
                 };
                 let dict = req.dict.as_str().expect("dict utf-8 decode error");
                 let key = req.key.as_str().expect("key utf-8 decode error");
+                if dict_reserved(dict) {
+                    req.result = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
                 match basis_cache.key_remove(&mut pddb_os, dict, key, bname, false) {
                     Ok(_) => {
-                        let mut evict_list = Vec::<ApiToken>::new();
                         // check to see if we need to eliminate any ApiTokens as a result of this.
-                        for (token, rec) in token_dict.iter() {
-                            if (rec.dict == dict) && (rec.key == key) {
-                                // check the basis union rules
-                                let mut matching = false;
-                                if rec.basis.is_none() && bname.is_none() {
-                                    matching = true;
-                                }
-                                if let Some(breq) = bname {
-                                    if rec.basis.is_none() {
-                                        matching = true;
-                                    }
-                                    if let Some(brec) = &rec.basis {
-                                        if brec == breq {
-                                            matching = true;
-                                        }
-                                    }
-                                }
-                                if matching {
-                                    evict_list.push(*token);
-                                }
-                            }
-                        }
-                        for token in evict_list {
-                            token_dict.remove(&token);
-                        }
+                        evict_key_tokens(&mut token_dict, dict, key, bname);
+                        subscriptions.notify_key(bname, dict, key, PddbChangeEvent::KeyDeleted);
                         req.result = PddbRequestCode::NoErr;
                     }
                     Err(e) => {
@@ -1064,7 +1166,7 @@ fn wrapped_main() -> ! {
             Opcode::DeleteKeyStd => {
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
-                    if let Err(err) = libstd::delete_key(mem, &mut pddb_os, &mut basis_cache, &mut fd_mapping) {
+                    if let Err(err) = libstd::delete_key(mem, &mut pddb_os, &mut basis_cache, &mut subscriptions, &mut fd_mapping) {
                         mem.offset = xous::MemoryAddress::new(err as usize);
                     }
                 }
@@ -1079,6 +1181,11 @@ fn wrapped_main() -> ! {
                 };
                 let dict = req.dict.as_str().expect("dict utf-8 decode error");
                 log::debug!("attempting to remove dict {} basis {:?}", dict, bname);
+                if dict_reserved(dict) {
+                    req.result = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
                 match basis_cache.dict_remove(&mut pddb_os, dict, bname, false) {
                     Ok(_) => {
                         let mut evict_list = Vec::<ApiToken>::new();
@@ -1108,6 +1215,7 @@ fn wrapped_main() -> ! {
                         for token in evict_list {
                             token_dict.remove(&token);
                         }
+                        subscriptions.notify_dict(bname, dict, PddbChangeEvent::DictDeleted);
                         req.result = PddbRequestCode::NoErr;
                     }
                     Err(e) => {
@@ -1122,7 +1230,23 @@ fn wrapped_main() -> ! {
             Opcode::DeleteDictStd => {
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
-                    if let Err(err) = libstd::delete_dict(mem, &mut pddb_os, &mut basis_cache) {
+                    if let Err(err) = libstd::delete_dict(mem, &mut pddb_os, &mut basis_cache, &mut subscriptions) {
+                        mem.offset = xous::MemoryAddress::new(err as usize);
+                    }
+                }
+            }
+            Opcode::DeleteDictAllStd => {
+                if let Some(mem) = msg.body.memory_message_mut() {
+                    mem.offset = None;
+                    if let Err(err) = libstd::delete_dict_all(mem, &mut pddb_os, &mut basis_cache, &mut subscriptions, &mut fd_mapping) {
+                        mem.offset = xous::MemoryAddress::new(err as usize);
+                    }
+                }
+            }
+            Opcode::RenamePathStd => {
+                if let Some(mem) = msg.body.memory_message_mut() {
+                    mem.offset = None;
+                    if let Err(err) = libstd::rename_path(mem, &mut pddb_os, &mut basis_cache, &mut subscriptions, &mut fd_mapping) {
                         mem.offset = xous::MemoryAddress::new(err as usize);
                     }
                 }
@@ -1155,6 +1279,24 @@ fn wrapped_main() -> ! {
                     }
                 }
             }
+            Opcode::KeySetExpiry => msg_blocking_scalar_unpack!(msg, t0, t1, t2, secs, {
+                let token: ApiToken = [t0 as u32, t1 as u32, t2 as u32];
+                let code = if let Some(rec) = token_dict.get(&token) {
+                    let ttl = if secs == 0 { None } else { Some(secs as u64) };
+                    match basis_cache.key_set_expiry(&mut pddb_os, &rec.dict, &rec.key, rec.basis.as_deref(), ttl) {
+                        Ok(_) => PddbRetcode::Ok,
+                        Err(e) => match e.kind() {
+                            std::io::ErrorKind::NotFound => PddbRetcode::BasisLost,
+                            std::io::ErrorKind::PermissionDenied => PddbRetcode::AccessDenied,
+                            std::io::ErrorKind::OutOfMemory => PddbRetcode::DiskFull,
+                            _ => PddbRetcode::InternalError,
+                        }
+                    }
+                } else {
+                    PddbRetcode::BasisLost
+                };
+                xous::return_scalar(msg.sender, code.to_usize().unwrap()).expect("couldn't return KeySetExpiry");
+            }),
             Opcode::KeyCountInDict => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut req = buffer.to_original::<PddbDictRequest, _>().unwrap();
@@ -1172,6 +1314,13 @@ fn wrapped_main() -> ! {
                 };
                 let dict = req.dict.as_str().expect("dict utf-8 decode error");
                 log::debug!("counting keys in dict {} basis {:?}", dict, bname);
+                if dict_reserved(dict) {
+                    key_token = None;
+                    req.code = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                // expired keys are left out of the list, and so out of the count
                 key_list = match basis_cache.key_list(&mut pddb_os, dict, bname) {
                     Ok(list) => {
                         log::debug!("count: {}", list.len());
@@ -1234,6 +1383,53 @@ fn wrapped_main() -> ! {
                 }
                 buffer.replace(req).unwrap();
             }
+            Opcode::QueryKeys => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbKeyQuery, _>().unwrap();
+                let bname = if req.basis_specified {
+                    Some(req.basis.as_str().unwrap())
+                } else {
+                    None
+                };
+                let dict = req.dict.as_str().expect("dict utf-8 decode error");
+                if dict_reserved(dict) {
+                    req.code = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                let query = req.to_query();
+                // every name takes at least two bytes in the packed list
+                let limit = if query.page_size == 0 {
+                    MAX_PDDBKLISTLEN / 2
+                } else {
+                    query.page_size.min(MAX_PDDBKLISTLEN / 2)
+                };
+                // ask for one more than the page holds, to find out whether there are more to come
+                match basis_cache.key_query(&mut pddb_os, dict, bname, &query, limit + 1) {
+                    Ok(list) => {
+                        let mut index = 0;
+                        let mut count = 0;
+                        for keyname in list.iter().take(limit) {
+                            if keyname.len() + 1 + index >= MAX_PDDBKLISTLEN {
+                                break;
+                            }
+                            req.data[index] = keyname.len() as u8;
+                            index += 1;
+                            req.data[index..index + keyname.len()].copy_from_slice(keyname.as_bytes());
+                            index += keyname.len();
+                            count += 1;
+                        }
+                        req.count = count as u32;
+                        req.more = count < list.len();
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    Err(e) => match e.kind() {
+                        std::io::ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                        _ => req.code = PddbRequestCode::InternalError,
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
             Opcode::ListKeyStd => {
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
@@ -1376,6 +1572,7 @@ fn wrapped_main() -> ! {
                             false
                         ) {
                             Ok(_) => {
+                                subscriptions.notify_key(rec.basis.as_deref(), &rec.dict, &rec.key, PddbChangeEvent::KeyUpdated);
                                 pbuf.retcode = PddbRetcode::Ok;
                                 break;
                             }
@@ -1396,18 +1593,63 @@ fn wrapped_main() -> ! {
                 basis_cache.sync(&mut pddb_os, None).expect("couldn't sync basis");
             }
 
+            Opcode::ReadKeyStream => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let sbuf = PddbStreamBuf::from_slice_mut(buffer.as_mut());
+                let token = sbuf.token;
+                let len = (sbuf.len as usize).min(sbuf.data.len());
+                sbuf.retcode = PddbRetcode::BasisLost;
+                if let Some(rec) = token_dict.get(&token) {
+                    for basis in basis_cache.access_list().iter() {
+                        match basis_cache.key_read(&mut pddb_os,
+                            &rec.dict, &rec.key,
+                            &mut sbuf.data[..len], Some(sbuf.position as usize),
+                            if let Some (name) = &rec.basis {Some(&name)} else {Some(basis)}) {
+                            Ok(readlen) => {
+                                sbuf.len = readlen as u32;
+                                sbuf.retcode = PddbRetcode::Ok;
+                                break;
+                            }
+                            Err(e) => match e.kind() {
+                                std::io::ErrorKind::NotFound => sbuf.retcode = PddbRetcode::BasisLost,
+                                std::io::ErrorKind::UnexpectedEof => sbuf.retcode = PddbRetcode::UnexpectedEof,
+                                _ => sbuf.retcode = PddbRetcode::InternalError,
+                            }
+                        }
+                    }
+                }
+            }
+            Opcode::WriteKeyStream => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let sbuf = PddbStreamBuf::from_slice_mut(buffer.as_mut());
+                let token = sbuf.token;
+                let len = (sbuf.len as usize).min(sbuf.data.len());
+                sbuf.retcode = PddbRetcode::BasisLost;
+                if let Some(rec) = token_dict.get(&token) {
+                    sbuf.retcode = match write_key_stream(&mut pddb_os, &mut basis_cache, &mut stream_updates,
+                        token, rec, &sbuf.data[..len], sbuf.position as usize
+                    ) {
+                        Ok(_) => PddbRetcode::Ok,
+                        Err(e) => match e.kind() {
+                            std::io::ErrorKind::NotFound => PddbRetcode::BasisLost,
+                            std::io::ErrorKind::OutOfMemory => PddbRetcode::DiskFull,
+                            _ => PddbRetcode::InternalError,
+                        }
+                    };
+                }
+            }
             Opcode::WriteKeyStd => {
                 let fd = (msg.body.id() >> 16) & 0xffff;
                 if let Some(mem) = msg.body.memory_message_mut() {
                     mem.offset = None;
-                    if let Err(e) = libstd::write_key(mem, &mut pddb_os, &mut basis_cache, fd_mapping.entry(msg.sender.pid()).or_default(), fd) {
+                    if let Err(e) = libstd::write_key(mem, &mut pddb_os, &mut basis_cache, &mut subscriptions, fd_mapping.entry(msg.sender.pid()).or_default(), fd) {
                         mem.offset = xous::MemoryAddress::new(e as usize);
                     }
                 }
             }
 
             Opcode::WriteKeyFlush => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                match basis_cache.sync(&mut pddb_os, None) {
+                match flush_key_streams(&mut pddb_os, &mut basis_cache, &mut stream_updates, &token_dict, &mut subscriptions) {
                     Ok(_) => xous::return_scalar(msg.sender, PddbRetcode::Ok.to_usize().unwrap()).unwrap(),
                     Err(e) => match e.kind() {
                         std::io::ErrorKind::OutOfMemory => xous::return_scalar(msg.sender, PddbRetcode::DiskFull.to_usize().unwrap()).unwrap(),
@@ -1417,6 +1659,304 @@ fn wrapped_main() -> ! {
                 };
             }),
 
+            Opcode::TxnBegin => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbTxnRequest = buffer.to_original::<PddbTxnRequest, _>().unwrap();
+                let bname = if req.basis_specified {
+                    Some(req.basis.as_str().unwrap().to_string())
+                } else {
+                    None
+                };
+                if basis_cache.basis_count() == 0 {
+                    req.code = PddbRequestCode::NotMounted;
+                } else if bname.is_some() && !basis_cache.basis_list().contains(bname.as_ref().unwrap()) {
+                    req.code = PddbRequestCode::NotFound;
+                } else {
+                    // nobody is left to commit or abort the transactions of processes that have exited
+                    txn_dict.retain(|_, txn| process_alive(txn.owner));
+                    req.id = next_txn_id;
+                    next_txn_id = next_txn_id.wrapping_add(1).max(1);
+                    txn_dict.insert(req.id, StagedTxn {
+                        basis: bname,
+                        ops: Vec::new(),
+                        owner: msg.sender.pid(),
+                    });
+                    req.code = PddbRequestCode::NoErr;
+                }
+                buffer.replace(req).unwrap();
+            }
+            Opcode::TxnStage => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbTxnStage = buffer.to_original::<PddbTxnStage, _>().unwrap();
+                match txn_dict.get_mut(&req.id) {
+                    Some(txn) if txn.owner == msg.sender.pid() => {
+                        let dict = req.dict.as_str().expect("dict utf-8 decode error").to_string();
+                        let key = req.key.as_str().expect("key utf-8 decode error").to_string();
+                        let data = &req.data[..(req.len as usize).min(PDDB_TXN_DATA_LEN)];
+                        let staged_data: usize = txn.ops.iter().map(|op| match op {
+                            TxnOp::Write { data, .. } => data.len(),
+                            TxnOp::Delete { .. } => 0,
+                        }).sum();
+                        let mut code = PddbRequestCode::NoErr;
+                        match req.op {
+                            _ if dict_reserved(&dict) => code = PddbRequestCode::AccessDenied,
+                            _ if staged_data + data.len() > TXN_MAX_DATA => code = PddbRequestCode::NoFreeSpace,
+                            TxnStageOp::Write | TxnStageOp::Delete if txn.ops.len() >= TXN_MAX_OPS => code = PddbRequestCode::NoFreeSpace,
+                            TxnStageOp::Write => txn.ops.push(TxnOp::Write { dict, key, data: data.to_vec() }),
+                            // an append continues a write that was too big for one message
+                            TxnStageOp::Append => match txn.ops.last_mut() {
+                                Some(TxnOp::Write { dict: d, key: k, data: staged }) if *d == dict && *k == key => {
+                                    staged.extend_from_slice(data);
+                                }
+                                _ => code = PddbRequestCode::InternalError,
+                            },
+                            TxnStageOp::Delete => txn.ops.push(TxnOp::Delete { dict, key }),
+                        }
+                        req.code = code;
+                    }
+                    _ => req.code = PddbRequestCode::NotFound,
+                }
+                buffer.replace(req).unwrap();
+            }
+            Opcode::TxnCommit => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbTxnRequest = buffer.to_original::<PddbTxnRequest, _>().unwrap();
+                let owned = txn_dict.get(&req.id).map(|txn| txn.owner == msg.sender.pid()).unwrap_or(false);
+                match if owned { txn_dict.remove(&req.id) } else { None } {
+                    Some(txn) => {
+                        let bname = txn.basis.as_deref();
+                        // work out which writes create keys, so subscribers can be told after the commit
+                        let mut events = Vec::<PddbChangeEvent>::new();
+                        for (i, op) in txn.ops.iter().enumerate() {
+                            events.push(match op {
+                                TxnOp::Write { dict, key, .. } => {
+                                    let written_before = txn.ops[..i].iter().any(|prev|
+                                        matches!(prev, TxnOp::Write { dict: d, key: k, .. } if d == dict && k == key)
+                                    );
+                                    if written_before || basis_cache.key_attributes(&mut pddb_os, dict, key, bname).is_ok() {
+                                        PddbChangeEvent::KeyUpdated
+                                    } else {
+                                        PddbChangeEvent::KeyCreated
+                                    }
+                                }
+                                TxnOp::Delete { .. } => PddbChangeEvent::KeyDeleted,
+                            });
+                        }
+                        match basis_cache.txn_commit(&mut pddb_os, bname, &txn.ops) {
+                            Ok(_) => {
+                                for (op, event) in txn.ops.iter().zip(events.into_iter()) {
+                                    match op {
+                                        TxnOp::Write { dict, key, .. } => subscriptions.notify_key(bname, dict, key, event),
+                                        TxnOp::Delete { dict, key } => {
+                                            evict_key_tokens(&mut token_dict, dict, key, bname);
+                                            subscriptions.notify_key(bname, dict, key, event);
+                                        }
+                                    }
+                                }
+                                req.code = PddbRequestCode::NoErr;
+                            }
+                            Err(e) => {
+                                log::warn!("transaction commit failed: {:?}", e);
+                                match e.kind() {
+                                    std::io::ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                                    std::io::ErrorKind::OutOfMemory => req.code = PddbRequestCode::NoFreeSpace,
+                                    _ => req.code = PddbRequestCode::InternalError,
+                                }
+                            }
+                        }
+                    }
+                    None => req.code = PddbRequestCode::NotFound,
+                }
+                buffer.replace(req).unwrap();
+            }
+            Opcode::TxnAbort => msg_blocking_scalar_unpack!(msg, id, _, _, _, {
+                let id = id as u32;
+                if txn_dict.get(&id).map(|txn| txn.owner == msg.sender.pid()).unwrap_or(false) {
+                    txn_dict.remove(&id);
+                }
+                xous::return_scalar(msg.sender, 0).expect("couldn't return scalar");
+            }),
+            Opcode::ExportBasis => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbArchiveRequest = buffer.to_original::<PddbArchiveRequest, _>().unwrap();
+                if basis_cache.basis_count() == 0 {
+                    req.code = PddbRequestCode::NotMounted;
+                } else {
+                    let bname = req.basis.as_str().expect("basis utf-8 decode error");
+                    let passphrase = req.passphrase.as_str().expect("passphrase utf-8 decode error");
+                    // archives left behind by processes that have since exited count against the limit
+                    archive_dict.retain(|_, archive| process_alive(archive.owner));
+                    let staged: usize = archive_dict.values().map(|archive| archive.data.len()).sum();
+                    match basis_cache.basis_export(&mut pddb_os, bname, passphrase) {
+                        Ok(data) if staged + data.len() > PDDB_ARCHIVE_MAX_LEN => {
+                            log::warn!("no room to stage a {} byte archive; {} bytes are staged already", data.len(), staged);
+                            req.code = PddbRequestCode::NoFreeSpace;
+                        }
+                        Ok(data) => {
+                            req.id = next_archive_id;
+                            next_archive_id = next_archive_id.wrapping_add(1).max(1);
+                            req.len = data.len() as u64;
+                            archive_dict.insert(req.id, StagedArchive {
+                                data,
+                                owner: msg.sender.pid(),
+                            });
+                            req.code = PddbRequestCode::NoErr;
+                        }
+                        Err(e) => {
+                            log::warn!("basis export failed: {:?}", e);
+                            match e.kind() {
+                                std::io::ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                                std::io::ErrorKind::OutOfMemory => req.code = PddbRequestCode::NoFreeSpace,
+                                _ => req.code = PddbRequestCode::InternalError,
+                            }
+                        }
+                    }
+                }
+                req.passphrase.volatile_clear();
+                buffer.replace(req).unwrap();
+            }
+            Opcode::ImportBasis => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbArchiveRequest = buffer.to_original::<PddbArchiveRequest, _>().unwrap();
+                let owned = archive_dict.get(&req.id).map(|a| a.owner == msg.sender.pid()).unwrap_or(false);
+                match if owned { archive_dict.remove(&req.id) } else { None } {
+                    Some(mut archive) => {
+                        let bname = req.basis.as_str().expect("basis utf-8 decode error").to_string();
+                        let passphrase = req.passphrase.as_str().expect("passphrase utf-8 decode error");
+                        match basis_cache.basis_import(&mut pddb_os, &bname, passphrase, &archive.data) {
+                            Ok(written) => {
+                                req.len = written.len() as u64;
+                                for (dict, key, event) in written {
+                                    subscriptions.notify_key(Some(&bname), &dict, &key, event);
+                                }
+                                req.code = PddbRequestCode::NoErr;
+                            }
+                            Err(e) => {
+                                log::warn!("basis import failed: {:?}", e);
+                                match e.kind() {
+                                    std::io::ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                                    std::io::ErrorKind::PermissionDenied => req.code = PddbRequestCode::AccessDenied,
+                                    std::io::ErrorKind::OutOfMemory => req.code = PddbRequestCode::NoFreeSpace,
+                                    _ => req.code = PddbRequestCode::InternalError,
+                                }
+                            }
+                        }
+                        for b in archive.data.iter_mut() {
+                            *b = 0;
+                        }
+                    }
+                    None => req.code = PddbRequestCode::NotFound,
+                }
+                req.passphrase.volatile_clear();
+                buffer.replace(req).unwrap();
+            }
+            Opcode::ArchiveRead => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbArchiveChunk = buffer.to_original::<PddbArchiveChunk, _>().unwrap();
+                match archive_dict.get(&req.id) {
+                    Some(archive) if archive.owner == msg.sender.pid() => {
+                        let start = (req.position as usize).min(archive.data.len());
+                        let end = (start + PDDB_ARCHIVE_DATA_LEN).min(archive.data.len());
+                        req.data[..end - start].copy_from_slice(&archive.data[start..end]);
+                        req.len = (end - start) as u32;
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    _ => req.code = PddbRequestCode::NotFound,
+                }
+                buffer.replace(req).unwrap();
+            }
+            Opcode::ArchiveWrite => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbArchiveChunk = buffer.to_original::<PddbArchiveChunk, _>().unwrap();
+                let len = (req.len as usize).min(PDDB_ARCHIVE_DATA_LEN);
+                if req.id == 0 {
+                    archive_dict.retain(|_, archive| process_alive(archive.owner));
+                    req.id = next_archive_id;
+                    next_archive_id = next_archive_id.wrapping_add(1).max(1);
+                    archive_dict.insert(req.id, StagedArchive {
+                        data: Vec::new(),
+                        owner: msg.sender.pid(),
+                    });
+                }
+                // bounds the archive being sent, and everything staged for other clients with it
+                let staged: usize = archive_dict.values().map(|archive| archive.data.len()).sum();
+                match archive_dict.get_mut(&req.id) {
+                    Some(archive) if archive.owner == msg.sender.pid() && staged + len > PDDB_ARCHIVE_MAX_LEN => {
+                        req.code = PddbRequestCode::NoFreeSpace;
+                    }
+                    // chunks have to arrive in order
+                    Some(archive) if archive.owner == msg.sender.pid() && req.position == archive.data.len() as u64 => {
+                        archive.data.extend_from_slice(&req.data[..len]);
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    Some(archive) if archive.owner == msg.sender.pid() => req.code = PddbRequestCode::InternalError,
+                    _ => req.code = PddbRequestCode::NotFound,
+                }
+                buffer.replace(req).unwrap();
+            }
+            Opcode::ArchiveClose => msg_blocking_scalar_unpack!(msg, id, _, _, _, {
+                let id = id as u32;
+                if archive_dict.get(&id).map(|a| a.owner == msg.sender.pid()).unwrap_or(false) {
+                    if let Some(mut archive) = archive_dict.remove(&id) {
+                        for b in archive.data.iter_mut() {
+                            *b = 0;
+                        }
+                    }
+                }
+                xous::return_scalar(msg.sender, 0).expect("couldn't return scalar");
+            }),
+            Opcode::Subscribe => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req: PddbSubscribeRequest = buffer.to_original::<PddbSubscribeRequest, _>().unwrap();
+                let bname = if req.basis_specified {
+                    Some(req.basis.as_str().unwrap().to_string())
+                } else {
+                    None
+                };
+                let dict = req.dict.as_str().expect("dict utf-8 decode error").to_string();
+                let key = if req.key_specified {
+                    Some(req.key.as_str().expect("key utf-8 decode error").to_string())
+                } else {
+                    None
+                };
+                if dict_reserved(&dict) {
+                    req.code = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                match xous::connect(xous::SID::from_array(req.cb_sid)) {
+                    Ok(cid) => {
+                        match subscriptions.add(bname.as_deref(), &dict, key.as_deref(), cid, req.cb_opcode, msg.sender.pid()) {
+                            Some(id) => {
+                                req.id = id;
+                                req.code = PddbRequestCode::NoErr;
+                            }
+                            None => {
+                                log::warn!("too many subscriptions from {:?}", msg.sender.pid());
+                                if !subscriptions.uses_cid(cid) && !token_dict.values().any(|r| r.conn == Some(cid)) {
+                                    unsafe{xous::disconnect(cid).ok();}
+                                }
+                                req.code = PddbRequestCode::AccessDenied;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("couldn't connect to subscriber: {:?}", e);
+                        req.code = PddbRequestCode::InternalError;
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Opcode::Unsubscribe => msg_blocking_scalar_unpack!(msg, id, _, _, _, {
+                if let Some(cid) = subscriptions.remove(id as u32, msg.sender.pid()) {
+                    if !subscriptions.uses_cid(cid) && !token_dict.values().any(|r| r.conn == Some(cid)) {
+                        unsafe{xous::disconnect(cid).ok();}
+                    }
+                    xous::return_scalar(msg.sender, 1).expect("couldn't return scalar");
+                } else {
+                    xous::return_scalar(msg.sender, 0).expect("couldn't return scalar");
+                }
+            }),
             Opcode::MenuListBasis => {
                 let bases = basis_cache.basis_list();
                 let mut note = String::from(t!("pddb.menu.listbasis_response", xous::LANG));
@@ -1448,6 +1988,47 @@ fn wrapped_main() -> ! {
                 let result = basis_cache.rekey(&mut pddb_os, rekey_op);
                 buffer.replace(result).unwrap();
             }
+            Opcode::Fsck => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let request = buffer.to_original::<PddbFsckReport, _>().unwrap();
+                let mut report = PddbFsckReport::new(request.repair);
+                if basis_cache.basis_count() == 0 {
+                    report.code = PddbRequestCode::NotMounted;
+                } else {
+                    match basis_cache.fsck(&mut pddb_os, request.repair) {
+                        Ok(r) => {
+                            report = r;
+                            report.code = PddbRequestCode::NoErr;
+                        }
+                        Err(e) => {
+                            log::error!("fsck failed: {:?}", e);
+                            report.code = PddbRequestCode::InternalError;
+                        }
+                    }
+                }
+                buffer.replace(report).unwrap();
+            }
+            Opcode::Usage => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbUsageReport, _>().unwrap();
+                if basis_cache.basis_count() == 0 {
+                    req.code = PddbRequestCode::NotMounted;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                let bname = if req.basis_specified { Some(req.basis.as_str().unwrap()) } else { None };
+                let dname = if req.dict_specified { Some(req.dict.as_str().unwrap()) } else { None };
+                match basis_cache.usage(&mut pddb_os, bname, dname) {
+                    Ok(report) => buffer.replace(report).unwrap(),
+                    Err(e) => {
+                        req.code = match e.kind() {
+                            ErrorKind::NotFound => PddbRequestCode::NotFound,
+                            _ => PddbRequestCode::InternalError,
+                        };
+                        buffer.replace(req).unwrap();
+                    }
+                }
+            }
             Opcode::FlushSpaceUpdate => {
                 pddb_os.fast_space_flush();
                 xous::return_scalar(msg.sender, 1).ok();
@@ -1512,6 +2093,7 @@ fn wrapped_main() -> ! {
                 mounted_bases.retain(|x| x != PDDB_DEFAULT_SYSTEM_BASIS);
                 for basis in mounted_bases {
                     basis_cache.basis_unmount(&mut pddb_os, &basis).expect("can't unmount extra bases");
+                    subscriptions.notify_basis(&basis, PddbChangeEvent::BasisUnmounted);
                 }
                 if basis_cache.basis_list().len() != 1 {
                     log::warn!("Couldn't unmount extra bases before unmounting the system basis. Failing unmount operation!");
@@ -1519,6 +2101,7 @@ fn wrapped_main() -> ! {
                 }
                 // finally, unmount the system basis
                 basis_cache.basis_unmount(&mut pddb_os, PDDB_DEFAULT_SYSTEM_BASIS).expect("can't unmount system basis");
+                subscriptions.notify_basis(PDDB_DEFAULT_SYSTEM_BASIS, PddbChangeEvent::BasisUnmounted);
                 if basis_cache.basis_list().len() == 0 {
                     log::info!(".System basis is unmounted.");
                     xous::return_scalar(msg.sender, 1).unwrap();
@@ -1565,6 +2148,7 @@ fn wrapped_main() -> ! {
     xous::terminate_process(0)
 }
 
+#[cfg(not(feature="offline"))]
 fn ensure_password(modals: &modals::Modals, pddb_os: &mut PddbOs, _pw_cid: xous::CID) -> PasswordState {
     log::info!("Requesting login password");
     loop {
@@ -1609,6 +2193,7 @@ fn ensure_password(modals: &modals::Modals, pddb_os: &mut PddbOs, _pw_cid: xous:
         }
     }
 }
+#[cfg(not(feature="offline"))]
 fn try_mount_or_format(modals: &modals::Modals, pddb_os: &mut PddbOs, basis_cache: &mut BasisCache, pw_state: PasswordState, time_resetter: xous::CID) -> bool {
     log::info!("Attempting to mount the PDDB");
     if pw_state == PasswordState::Correct {
@@ -1719,6 +2304,7 @@ fn try_mount_or_format(modals: &modals::Modals, pddb_os: &mut PddbOs, basis_cach
 
 // Test cases that have been coded to run directly on hardware (that is, they do not require host-OS debug features)
 #[allow(dead_code)]
+#[cfg(not(feature="offline"))]
 pub(crate) fn manual_testcase(hw: &mut PddbOs) {
     log::info!("Initializing disk...");
     hw.pddb_format(true, None).unwrap();
@@ -1777,6 +2363,7 @@ pub(crate) fn manual_testcase(hw: &mut PddbOs) {
 }
 
 #[allow(dead_code)]
+#[cfg(not(feature="offline"))]
 pub(crate) fn hw_testcase(pddb_os: &mut PddbOs) {
     log::info!("Running `hw` test case");
     #[cfg(any(feature="hosted"))]
@@ -1808,6 +2395,81 @@ pub(crate) fn hw_testcase(pddb_os: &mut PddbOs) {
     pddb_os.dbg_dump(Some("manual".to_string()), None);
 }
 
+/// Drops the tokens that refer to a key that has been deleted. A token with no basis specified
+/// matches a deletion from any basis.
+#[cfg(not(feature="offline"))]
+fn evict_key_tokens(token_dict: &mut HashMap::<ApiToken, TokenRecord>, dict: &str, key: &str, bname: Option<&str>) {
+    let mut evict_list = Vec::<ApiToken>::new();
+    for (token, rec) in token_dict.iter() {
+        if (rec.dict == dict) && (rec.key == key) {
+            // check the basis union rules
+            let mut matching = false;
+            if rec.basis.is_none() && bname.is_none() {
+                matching = true;
+            }
+            if let Some(breq) = bname {
+                if rec.basis.is_none() {
+                    matching = true;
+                }
+                if let Some(brec) = &rec.basis {
+                    if brec == breq {
+                        matching = true;
+                    }
+                }
+            }
+            if matching {
+                evict_list.push(*token);
+            }
+        }
+    }
+    for token in evict_list {
+        token_dict.remove(&token);
+    }
+}
+
+/// Writes one window of a stream to the key behind `token`, in its own basis or else the first
+/// open one that takes it. Unlike `WriteKey`, there's no sync after the write: the stream asks for
+/// one when it's flushed, and that is when the key is reported as changed.
+#[cfg(not(feature="offline"))]
+fn write_key_stream(pddb_os: &mut PddbOs, basis_cache: &mut BasisCache, stream_updates: &mut HashSet::<ApiToken>,
+    token: ApiToken, rec: &TokenRecord, data: &[u8], position: usize
+) -> std::io::Result<()> {
+    let mut result = Err(std::io::Error::new(ErrorKind::NotFound, "no basis is open"));
+    for basis in basis_cache.access_list().iter() {
+        result = basis_cache.key_update_deferred(pddb_os, &rec.dict, &rec.key, data, Some(position),
+            rec.alloc_hint, Some(rec.basis.as_deref().unwrap_or(basis.as_str())));
+        if result.is_ok() {
+            stream_updates.insert(token);
+            break;
+        }
+    }
+    result
+}
+
+/// Handles `WriteKeyFlush`: syncs what streams have written, then reports the keys they changed.
+#[cfg(not(feature="offline"))]
+fn flush_key_streams(pddb_os: &mut PddbOs, basis_cache: &mut BasisCache, stream_updates: &mut HashSet::<ApiToken>,
+    token_dict: &HashMap::<ApiToken, TokenRecord>, subscriptions: &mut Subscriptions
+) -> std::io::Result<()> {
+    let result = basis_cache.sync(pddb_os, None);
+    notify_stream_updates(stream_updates, token_dict, subscriptions);
+    result
+}
+
+/// Tells subscribers about the keys that were written through streams since the last time this
+/// was called. A stream writes a key in many chunks, but each key is only reported once.
+#[cfg(not(feature="offline"))]
+fn notify_stream_updates(stream_updates: &mut HashSet::<ApiToken>, token_dict: &HashMap::<ApiToken, TokenRecord>,
+    subscriptions: &mut Subscriptions
+) {
+    for token in stream_updates.drain() {
+        if let Some(rec) = token_dict.get(&token) {
+            subscriptions.notify_key(rec.basis.as_deref(), &rec.dict, &rec.key, PddbChangeEvent::KeyUpdated);
+        }
+    }
+}
+
+#[cfg(not(feature="offline"))]
 fn notify_of_disconnect(pddb_os: &mut PddbOs, token_dict: &HashMap::<ApiToken, TokenRecord>, basis_cache: &mut BasisCache) {
     // 1. search to see if any of the active tokens are are in our token_dict
     // 2. notify them of the disconnect, if there is a callback set.
@@ -1840,6 +2502,7 @@ fn notify_of_disconnect(pddb_os: &mut PddbOs, token_dict: &HashMap::<ApiToken, T
     }
 }
 
+#[cfg(not(feature="offline"))]
 pub(crate) fn heap_usage() -> usize {
     match xous::rsyscall(xous::SysCall::IncreaseHeap(0, xous::MemoryFlags::R)).expect("couldn't get heap size") {
         xous::Result::MemoryRange(m) => {
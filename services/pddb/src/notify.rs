@@ -0,0 +1,105 @@
+use crate::api::*;
+use num_traits::*;
+use std::collections::HashMap;
+use xous::{Message, CID, PID};
+
+/// Limit on subscriptions held by any one process, so a misbehaving client can't make every
+/// write fan out into an unbounded number of messages.
+const MAX_SUBSCRIPTIONS_PER_PROCESS: usize = 32;
+
+struct Subscription {
+    /// `None` covers every basis
+    basis: Option<String>,
+    dict: String,
+    /// `None` covers every key in the dictionary
+    key: Option<String>,
+    cid: CID,
+    opcode: u32,
+    owner: Option<PID>,
+}
+impl Subscription {
+    /// A change that names no basis went to the union of open bases, so it could have touched any
+    /// of them. These are the same rules used to evict tokens when a key is deleted.
+    fn covers_basis(&self, basis: Option<&str>) -> bool {
+        match (&self.basis, basis) {
+            (Some(mine), Some(theirs)) => mine == theirs,
+            _ => true,
+        }
+    }
+}
+
+/// Subscriptions to changes in dictionaries and keys. Subscribers are sent a non-blocking scalar
+/// message with their chosen opcode, with a `PddbChangeEvent` and the subscription ID as arguments.
+pub(crate) struct Subscriptions {
+    subs: HashMap<u32, Subscription>,
+    next_id: u32,
+}
+impl Subscriptions {
+    pub(crate) fn new() -> Self {
+        Subscriptions { subs: HashMap::new(), next_id: 1 }
+    }
+    /// Returns the new subscription's ID, or `None` if `owner` already has too many.
+    pub(crate) fn add(&mut self, basis: Option<&str>, dict: &str, key: Option<&str>,
+        cid: CID, opcode: u32, owner: Option<PID>
+    ) -> Option<u32> {
+        if self.subs.values().filter(|s| s.owner == owner).count() >= MAX_SUBSCRIPTIONS_PER_PROCESS {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.subs.insert(id, Subscription {
+            basis: basis.map(|b| b.to_string()),
+            dict: dict.to_string(),
+            key: key.map(|k| k.to_string()),
+            cid,
+            opcode,
+            owner,
+        });
+        Some(id)
+    }
+    /// Removes a subscription, if `owner` is the process that made it. Returns the connection it
+    /// was using, which the caller may disconnect if nothing else needs it.
+    pub(crate) fn remove(&mut self, id: u32, owner: Option<PID>) -> Option<CID> {
+        if self.subs.get(&id)?.owner != owner {
+            return None;
+        }
+        self.subs.remove(&id).map(|s| s.cid)
+    }
+    pub(crate) fn uses_cid(&self, cid: CID) -> bool {
+        self.subs.values().any(|s| s.cid == cid)
+    }
+
+    pub(crate) fn notify_key(&mut self, basis: Option<&str>, dict: &str, key: &str, event: PddbChangeEvent) {
+        self.notify(event, |s| {
+            s.covers_basis(basis) && s.dict == dict && s.key.as_deref().map(|k| k == key).unwrap_or(true)
+        });
+    }
+    pub(crate) fn notify_dict(&mut self, basis: Option<&str>, dict: &str, event: PddbChangeEvent) {
+        self.notify(event, |s| s.covers_basis(basis) && s.dict == dict);
+    }
+    pub(crate) fn notify_basis(&mut self, basis: &str, event: PddbChangeEvent) {
+        self.notify(event, |s| s.covers_basis(Some(basis)));
+    }
+
+    fn notify<F: Fn(&Subscription) -> bool>(&mut self, event: PddbChangeEvent, matches: F) {
+        let mut dead = Vec::<u32>::new();
+        for (&id, sub) in self.subs.iter().filter(|(_, s)| matches(s)) {
+            match xous::send_message(sub.cid,
+                Message::new_scalar(sub.opcode as usize, event.to_usize().unwrap(), id as usize, 0, 0)
+            ) {
+                Ok(_) => {}
+                // the subscriber's server is gone
+                Err(xous::Error::ServerNotFound) | Err(xous::Error::ProcessNotFound) => {
+                    log::warn!("dropping subscription {}: couldn't deliver {:?}", id, event);
+                    dead.push(id);
+                }
+                // the subscriber is behind; it misses this event, but not the ones after it
+                Err(xous::Error::ServerQueueFull) => log::warn!("subscription {} missed {:?}: queue is full", id, event),
+                Err(e) => log::warn!("subscription {} missed {:?}: {:?}", id, event, e),
+            }
+        }
+        for id in dead {
+            self.subs.remove(&id);
+        }
+    }
+}
@@ -3,7 +3,7 @@ use rand_chacha::rand_core::RngCore;
 use rand_chacha::rand_core::SeedableRng;
 use crate::*;
 use core::sync::atomic::{AtomicU64, Ordering};
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::io::Result;
 
 const UPPER_BOUND: usize = 9000;
@@ -422,7 +422,7 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
 
         log::info!("Building a second basis");
         basis_cache.basis_create(pddb_os,
-            EXTRA_BASIS, EXTRA_BASIS_PW).expect("couldn't build test basis");
+            EXTRA_BASIS, EXTRA_BASIS_PW, None).expect("couldn't build test basis");
 
         log::info!("heap usage: {}", heap_usage());
         test_prune(pddb_os, &mut basis_cache);
@@ -553,6 +553,33 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         assert!(merge2_list.difference(&merge_list).count() == 0, "merged list is different from the original list after remount");
         list_all(pddb_os, &mut basis_cache);
 
+        log::info!("Doing transaction test");
+        txn_test(pddb_os)?;
+
+        log::info!("Doing stream test");
+        stream_test(pddb_os)?;
+
+        log::info!("Doing expiry test");
+        expiry_test(pddb_os)?;
+
+        log::info!("Doing archive test");
+        archive_test(pddb_os)?;
+
+        log::info!("Doing quota test");
+        quota_test(pddb_os)?;
+
+        log::info!("Doing libstd path test");
+        crate::libstd::tests::libstd_test(pddb_os)?;
+
+        log::info!("Doing key query test");
+        query_test(pddb_os)?;
+
+        log::info!("Doing consistency check test");
+        fsck_test(pddb_os)?;
+
+        log::info!("Doing subscription test");
+        notify_test(pddb_os)?;
+
         log::info!("CI done");
 
         Ok(())
@@ -567,4 +594,504 @@ fn test_prune(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
         log::info!("pruned {}", basis_cache.cache_prune(hw, cache_size - TARGET_SIZE));
     }
     log::info!("size is now {}", basis_cache.cache_size());
-}
\ No newline at end of file
+}
+const SYSTEM_BASIS: Option<&'static str> = Some(PDDB_DEFAULT_SYSTEM_BASIS);
+
+/// Throws away everything cached in RAM and mounts the system basis again, as after a reboot.
+pub(crate) fn remount_system(hw: &mut PddbOs) -> BasisCache {
+    let mut basis_cache = BasisCache::new();
+    basis_cache.basis_add(hw.pddb_mount().expect("couldn't remount the system basis"));
+    basis_cache
+}
+
+const TXN_DICT: &'static str = "txntest";
+
+/// Contents of key `keynum` in generation `gen`. Key 0 is big enough to go in the large pool.
+fn txn_data(keynum: usize, gen: usize) -> Vec::<u8> {
+    let len = if keynum == 0 { 2 * VPAGE_SIZE + 100 } else { 64 + keynum };
+    (0..len).map(|i| (i + keynum * 31 + gen * 7) as u8).collect()
+}
+
+/// Every key in the test dictionary, with its contents.
+fn txn_snapshot(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> BTreeMap::<String, Vec::<u8>> {
+    let mut snapshot = BTreeMap::new();
+    let keys = basis_cache.key_list(hw, TXN_DICT, SYSTEM_BASIS).unwrap_or_default();
+    for key in keys.iter() {
+        let attr = basis_cache.key_attributes(hw, TXN_DICT, key, SYSTEM_BASIS).expect("listed key has no attributes");
+        let mut data = vec![0u8; attr.len];
+        let len = basis_cache.key_read(hw, TXN_DICT, key, &mut data, None, SYSTEM_BASIS).expect("listed key can't be read");
+        data.truncate(len);
+        snapshot.insert(key.to_string(), data);
+    }
+    snapshot
+}
+
+/// Puts keys 0-3 back to generation 0, outside of any transaction, and removes key 4.
+fn txn_baseline(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<BTreeMap::<String, Vec::<u8>>> {
+    for keynum in 0..4 {
+        basis_cache.key_update(hw, TXN_DICT, &format!("key{}", keynum), &txn_data(keynum, 0), Some(0), None, SYSTEM_BASIS, true)?;
+    }
+    basis_cache.key_remove(hw, TXN_DICT, "key4", SYSTEM_BASIS, false).ok();
+    basis_cache.sync(hw, SYSTEM_BASIS)?;
+    Ok(txn_snapshot(hw, basis_cache))
+}
+
+/// Rewrites keys 0 and 1, deletes key 2 and creates key 4; key 3 is left alone. Returns the
+/// operations, and what the dictionary should hold once they have been applied to `baseline`.
+fn txn_ops(baseline: &BTreeMap::<String, Vec::<u8>>, gen: usize) -> (Vec::<TxnOp>, BTreeMap::<String, Vec::<u8>>) {
+    let ops = vec![
+        TxnOp::Write { dict: TXN_DICT.to_string(), key: "key0".to_string(), data: txn_data(0, gen) },
+        TxnOp::Write { dict: TXN_DICT.to_string(), key: "key1".to_string(), data: txn_data(1, gen) },
+        TxnOp::Delete { dict: TXN_DICT.to_string(), key: "key2".to_string() },
+        TxnOp::Write { dict: TXN_DICT.to_string(), key: "key4".to_string(), data: txn_data(4, gen) },
+    ];
+    let mut expected = baseline.clone();
+    for op in ops.iter() {
+        match op {
+            TxnOp::Write { key, data, .. } => { expected.insert(key.to_string(), data.to_vec()); }
+            TxnOp::Delete { key, .. } => { expected.remove(key); }
+        }
+    }
+    (ops, expected)
+}
+
+/// Stops a commit at `point` and reboots. The dictionary must come back as it was before the
+/// commit if the record wasn't mapped yet, and with every operation applied otherwise. Returns
+/// false if the commit finished without reaching `point`.
+fn txn_crash_round(hw: &mut PddbOs, basis_cache: &mut BasisCache, point: TxnCrashPoint, gen: usize) -> Result<bool> {
+    let baseline = txn_baseline(hw, basis_cache)?;
+    let (ops, expected) = txn_ops(&baseline, gen);
+    basis_cache.txn_crash = Some(point);
+    match basis_cache.txn_commit(hw, SYSTEM_BASIS, &ops) {
+        Ok(_) => {
+            basis_cache.txn_crash = None;
+            return Ok(false);
+        }
+        Err(e) => assert!(e.kind() == std::io::ErrorKind::Interrupted, "commit failed before reaching {:?}: {:?}", point, e),
+    }
+    *basis_cache = remount_system(hw);
+    let recovered = txn_snapshot(hw, basis_cache);
+    if point == TxnCrashPoint::BeforeCommit {
+        assert!(recovered == baseline, "transaction is visible after a crash before its commit point");
+    } else {
+        assert!(recovered == expected, "transaction was not rolled forward after a crash at {:?}", point);
+    }
+    // the record was retired by the recovery, so mounting again must not change anything
+    *basis_cache = remount_system(hw);
+    assert!(txn_snapshot(hw, basis_cache) == recovered, "recovered state changed on the next mount ({:?})", point);
+    Ok(true)
+}
+
+/// Transactions: a failed commit leaves no trace, a good commit lands all-or-nothing, and a commit
+/// that is interrupted at any point is either fully undone or fully redone on the next mount.
+pub(crate) fn txn_test(hw: &mut PddbOs) -> Result<()> {
+    let mut basis_cache = remount_system(hw);
+    basis_cache.dict_add(hw, TXN_DICT, SYSTEM_BASIS)?;
+
+    log::info!("Transaction rollback");
+    let baseline = txn_baseline(hw, &mut basis_cache)?;
+    let (mut ops, _) = txn_ops(&baseline, 1);
+    // the last operation fails, so none of the others may stick
+    ops.push(TxnOp::Delete { dict: TXN_DICT.to_string(), key: "no such key".to_string() });
+    let free_before = hw.fast_space_len();
+    assert!(basis_cache.txn_commit(hw, SYSTEM_BASIS, &ops).is_err(), "commit with a failing operation succeeded");
+    assert!(txn_snapshot(hw, &mut basis_cache) == baseline, "rolled back transaction changed the cached basis");
+    assert!(hw.fast_space_len() >= free_before, "rolled back transaction leaked FastSpace pages");
+    let mut basis_cache = remount_system(hw);
+    assert!(txn_snapshot(hw, &mut basis_cache) == baseline, "rolled back transaction reached the disk");
+
+    log::info!("Transaction commit");
+    let (ops, expected) = txn_ops(&baseline, 2);
+    basis_cache.txn_commit(hw, SYSTEM_BASIS, &ops)?;
+    assert!(txn_snapshot(hw, &mut basis_cache) == expected, "committed transaction is incomplete in the cache");
+    let mut basis_cache = remount_system(hw);
+    assert!(txn_snapshot(hw, &mut basis_cache) == expected, "committed transaction is incomplete on disk");
+
+    log::info!("Transaction recovery");
+    let mut gen = 3;
+    for &point in [TxnCrashPoint::BeforeCommit, TxnCrashPoint::AfterCommit, TxnCrashPoint::BeforeRetire].iter() {
+        assert!(txn_crash_round(hw, &mut basis_cache, point, gen)?, "commit never reached {:?}", point);
+        gen += 1;
+    }
+    // crash after each entry of the record in turn, until the commit runs out of entries
+    for rolled in 1..=TXN_MAX_ENTRIES {
+        if !txn_crash_round(hw, &mut basis_cache, TxnCrashPoint::RollForward(rolled), gen)? {
+            log::info!("Recovered from a crash after each of {} record entries", rolled - 1);
+            break;
+        }
+        gen += 1;
+    }
+    basis_cache.dict_remove(hw, TXN_DICT, SYSTEM_BASIS, false)?;
+    Ok(())
+}
+
+const STREAM_DICT: &'static str = "streamtest";
+const STREAM_KEY: &'static str = "stream";
+
+/// Reads the streamed key back the way `ReadKeyStream` serves it: a window at a time, from chunk
+/// aligned positions, until a read comes back empty.
+fn stream_read(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<Vec::<u8>> {
+    let mut data = Vec::new();
+    let mut window = vec![0u8; PDDB_STREAM_DATA_LEN];
+    loop {
+        let len = basis_cache.key_read(hw, STREAM_DICT, STREAM_KEY, &mut window, Some(data.len()), SYSTEM_BASIS)?;
+        if len == 0 {
+            break;
+        }
+        data.extend_from_slice(&window[..len]);
+    }
+    Ok(data)
+}
+
+/// Streaming: a key written a window at a time the way `WriteKeyStream` does it, with the length
+/// only committed by the sync on flush, reads back the same from the cache and after a remount.
+pub(crate) fn stream_test(hw: &mut PddbOs) -> Result<()> {
+    // several full windows, and a last one that ends part way into a chunk
+    let data: Vec::<u8> = (0..2 * PDDB_STREAM_DATA_LEN + 3 * PDDB_STREAM_CHUNK + 100)
+        .map(|i| (i ^ (i / PDDB_STREAM_CHUNK)) as u8).collect();
+    let mut basis_cache = remount_system(hw);
+    basis_cache.dict_add(hw, STREAM_DICT, SYSTEM_BASIS)?;
+    // opening a stream creates the key with a chunk sized hint, so it starts out in the large pool
+    basis_cache.key_update(hw, STREAM_DICT, STREAM_KEY, &[], None, Some(PDDB_STREAM_CHUNK), SYSTEM_BASIS, false)?;
+    for (i, window) in data.chunks(PDDB_STREAM_DATA_LEN).enumerate() {
+        basis_cache.key_update_deferred(hw, STREAM_DICT, STREAM_KEY, window,
+            Some(i * PDDB_STREAM_DATA_LEN), Some(PDDB_STREAM_CHUNK), SYSTEM_BASIS)?;
+    }
+    basis_cache.sync(hw, SYSTEM_BASIS)?;
+    let attr = basis_cache.key_attributes(hw, STREAM_DICT, STREAM_KEY, SYSTEM_BASIS)?;
+    assert!(attr.len == data.len(), "streamed key is {} bytes, expected {}", attr.len, data.len());
+    assert!(stream_read(hw, &mut basis_cache)? == data, "streamed key reads back wrong from the cache");
+
+    let mut basis_cache = remount_system(hw);
+    assert!(stream_read(hw, &mut basis_cache)? == data, "streamed key reads back wrong after a remount");
+    basis_cache.dict_remove(hw, STREAM_DICT, SYSTEM_BASIS, false)?;
+    Ok(())
+}
+
+const EXPIRY_DICT: &'static str = "expirytest";
+
+/// Names of the keys in the expiry test dictionary that haven't expired.
+fn expiry_list(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<Vec::<String>> {
+    Ok(basis_cache.key_list(hw, EXPIRY_DICT, SYSTEM_BASIS)?.into_iter().collect())
+}
+
+/// Key expiry: an expired key drops out of listings, counts and queries and can't be opened, it is
+/// deleted by the scrub, and expiry times survive a remount. The PDDB's own dictionary is hidden.
+pub(crate) fn expiry_test(hw: &mut PddbOs) -> Result<()> {
+    if hw.rtc_secs().is_none() {
+        log::warn!("RTC can't be read, so expiry times aren't enforced; skipping expiry test");
+        return Ok(());
+    }
+    let mut basis_cache = remount_system(hw);
+    basis_cache.dict_add(hw, EXPIRY_DICT, SYSTEM_BASIS)?;
+    for keynum in 0..4 {
+        basis_cache.key_update(hw, EXPIRY_DICT, &format!("key{}", keynum), &[keynum as u8; 16], None, None, SYSTEM_BASIS, true)?;
+    }
+    // keys 0 and 1 expire in a minute, key 2 in an hour, and key 3 never
+    basis_cache.key_set_expiry(hw, EXPIRY_DICT, "key0", SYSTEM_BASIS, Some(60))?;
+    basis_cache.key_set_expiry(hw, EXPIRY_DICT, "key1", SYSTEM_BASIS, Some(60))?;
+    basis_cache.key_set_expiry(hw, EXPIRY_DICT, "key2", SYSTEM_BASIS, Some(3600))?;
+    assert!(expiry_list(hw, &mut basis_cache)?.len() == 4, "keys expired early");
+    let attr = basis_cache.key_attributes(hw, EXPIRY_DICT, "key0", SYSTEM_BASIS)?;
+    assert!(attr.expires_in.map_or(false, |secs| secs <= 60), "expiry time is not reported: {:?}", attr.expires_in);
+    assert!(basis_cache.key_set_expiry(hw, PDDB_META_DICT, PDDB_EXPIRY_KEY, SYSTEM_BASIS, Some(60)).is_err(),
+        "the expiry table was given an expiry time");
+    assert!(!basis_cache.dict_list(hw, SYSTEM_BASIS).contains(PDDB_META_DICT), "the reserved dictionary is listed");
+
+    // the times have to come back from disk, not just from the cache
+    let mut basis_cache = remount_system(hw);
+    hw.rtc_skew += 120;
+    let remaining = expiry_list(hw, &mut basis_cache)?;
+    assert!(remaining == vec!["key2".to_string(), "key3".to_string()], "expired keys are listed: {:?}", remaining);
+    let query = KeyQuery { prefix: Some("key".to_string()), ..Default::default() };
+    let queried: Vec::<String> = basis_cache.key_query(hw, EXPIRY_DICT, SYSTEM_BASIS, &query, 16)?.into_iter().collect();
+    assert!(queried == remaining, "expired keys are returned by a query: {:?}", queried);
+    assert!(basis_cache.key_attributes(hw, EXPIRY_DICT, "key0", SYSTEM_BASIS).is_err(), "an expired key can be opened");
+
+    // opening an expired key deletes it there and then; the scrub gets the rest
+    assert!(basis_cache.key_expire_now(hw, EXPIRY_DICT, "key1", SYSTEM_BASIS), "expired key was not deleted when opened");
+    assert!(!basis_cache.key_expire_now(hw, EXPIRY_DICT, "key2", SYSTEM_BASIS), "a live key was deleted when opened");
+    let expired = basis_cache.expire_keys(hw);
+    assert!(expired.len() == 1 && expired[0].2 == "key0", "scrub deleted the wrong keys: {:?}", expired);
+
+    // with the clock put back, the deleted keys stay gone
+    hw.rtc_skew -= 120;
+    let mut basis_cache = remount_system(hw);
+    let remaining = expiry_list(hw, &mut basis_cache)?;
+    assert!(remaining == vec!["key2".to_string(), "key3".to_string()], "expired keys came back: {:?}", remaining);
+    basis_cache.key_set_expiry(hw, EXPIRY_DICT, "key2", SYSTEM_BASIS, None)?;
+    hw.rtc_skew += 7200;
+    assert!(expiry_list(hw, &mut basis_cache)?.len() == 2, "a key expired after its expiry time was cleared");
+    hw.rtc_skew -= 7200;
+    basis_cache.dict_remove(hw, EXPIRY_DICT, SYSTEM_BASIS, false)?;
+    Ok(())
+}
+
+const ARCHIVE_SRC_BASIS: &'static str = "archivesrc";
+const ARCHIVE_DST_BASIS: &'static str = "archivedst";
+const ARCHIVE_BASIS_PW: &'static str = "archive basis";
+const ARCHIVE_PASSPHRASE: &'static str = "archive passphrase";
+
+/// Every key in a basis, with its contents.
+fn archive_contents(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis: &str) -> BTreeMap::<(String, String), Vec::<u8>> {
+    let mut contents = BTreeMap::new();
+    for dict in basis_cache.dict_list(hw, Some(basis)).iter() {
+        for key in basis_cache.key_list(hw, dict, Some(basis)).unwrap().iter() {
+            let attr = basis_cache.key_attributes(hw, dict, key, Some(basis)).expect("listed key has no attributes");
+            let mut data = vec![0u8; attr.len];
+            let len = basis_cache.key_read(hw, dict, key, &mut data, None, Some(basis)).expect("listed key can't be read");
+            data.truncate(len);
+            contents.insert((dict.to_string(), key.to_string()), data);
+        }
+    }
+    contents
+}
+
+/// Archives: a basis exported under a passphrase imports into another basis with the same keys and
+/// contents, and an import with the wrong passphrase, or of an altered archive, writes nothing.
+pub(crate) fn archive_test(hw: &mut PddbOs) -> Result<()> {
+    let mut basis_cache = remount_system(hw);
+    for &name in [ARCHIVE_SRC_BASIS, ARCHIVE_DST_BASIS].iter() {
+        basis_cache.basis_create(hw, name, ARCHIVE_BASIS_PW, None)?;
+        let basis = basis_cache.basis_unlock(hw, name, ARCHIVE_BASIS_PW, BasisRetentionPolicy::Persist)
+            .expect("couldn't unlock a new basis");
+        basis_cache.basis_add(basis);
+    }
+    for dictnum in 0..2 {
+        let dict = format!("archive{}", dictnum);
+        basis_cache.dict_add(hw, &dict, Some(ARCHIVE_SRC_BASIS))?;
+        for keynum in 0..5 {
+            // key 4 is big enough to go in the large pool
+            let len = if keynum == 4 { 3 * VPAGE_SIZE + 10 } else { 20 + keynum };
+            let data: Vec::<u8> = (0..len).map(|i| (i + keynum * 13 + dictnum * 101) as u8).collect();
+            basis_cache.key_update(hw, &dict, &format!("key{}", keynum), &data, None, None, Some(ARCHIVE_SRC_BASIS), true)?;
+        }
+    }
+    basis_cache.sync(hw, Some(ARCHIVE_SRC_BASIS))?;
+    let exported = archive_contents(hw, &mut basis_cache, ARCHIVE_SRC_BASIS);
+    assert!(exported.len() == 10, "source basis has {} keys, expected 10", exported.len());
+    let mut archive = basis_cache.basis_export(hw, ARCHIVE_SRC_BASIS, ARCHIVE_PASSPHRASE)?;
+
+    let e = basis_cache.basis_import(hw, ARCHIVE_DST_BASIS, "wrong passphrase", &archive)
+        .expect_err("archive imported with the wrong passphrase");
+    assert!(e.kind() == std::io::ErrorKind::PermissionDenied, "wrong passphrase gave {:?}", e);
+    let last = archive.len() - 1;
+    archive[last] ^= 1;
+    let e = basis_cache.basis_import(hw, ARCHIVE_DST_BASIS, ARCHIVE_PASSPHRASE, &archive)
+        .expect_err("altered archive was imported");
+    assert!(e.kind() == std::io::ErrorKind::PermissionDenied, "altered archive gave {:?}", e);
+    archive[last] ^= 1;
+    assert!(archive_contents(hw, &mut basis_cache, ARCHIVE_DST_BASIS).is_empty(), "a rejected import wrote keys");
+
+    let written = basis_cache.basis_import(hw, ARCHIVE_DST_BASIS, ARCHIVE_PASSPHRASE, &archive)?;
+    assert!(written.len() == 10 && written.iter().all(|(_, _, event)| *event == PddbChangeEvent::KeyCreated),
+        "import did not create every key");
+    assert!(archive_contents(hw, &mut basis_cache, ARCHIVE_DST_BASIS) == exported, "imported basis differs from the exported one");
+    // importing again overwrites the same keys
+    let written = basis_cache.basis_import(hw, ARCHIVE_DST_BASIS, ARCHIVE_PASSPHRASE, &archive)?;
+    assert!(written.iter().all(|(_, _, event)| *event == PddbChangeEvent::KeyUpdated), "reimport created keys");
+
+    for &name in [ARCHIVE_SRC_BASIS, ARCHIVE_DST_BASIS].iter() {
+        basis_cache.basis_delete(hw, name)?;
+    }
+    Ok(())
+}
+
+const QUOTA_BASIS: &'static str = "QuotaTest";
+const QUOTA_BASIS_PW: &'static str = "some quota password";
+const QUOTA_DICT: &'static str = "quotatest";
+/// Room for the basis root, the dictionary and a handful of large keys
+const QUOTA_BYTES: u64 = 24 * PAGE_SIZE as u64;
+
+/// Mounts the quota test basis on top of the system basis.
+fn quota_mount(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
+    let basis = basis_cache.basis_unlock(hw, QUOTA_BASIS, QUOTA_BASIS_PW, BasisRetentionPolicy::Persist)
+        .expect("couldn't unlock the quota test basis");
+    basis_cache.basis_add(basis);
+}
+
+/// Quotas: writes to a basis fail with `OutOfMemory` once it would grow past its quota, without
+/// leaving anything behind, and the quota and what was written are the same after a remount.
+pub(crate) fn quota_test(hw: &mut PddbOs) -> Result<()> {
+    let mut basis_cache = remount_system(hw);
+    basis_cache.basis_create(hw, QUOTA_BASIS, QUOTA_BASIS_PW, Some(QUOTA_BYTES))?;
+    quota_mount(hw, &mut basis_cache);
+    basis_cache.dict_add(hw, QUOTA_DICT, Some(QUOTA_BASIS))?;
+
+    // keys big enough to go in the large pool, so each one takes pages of its own
+    let data: Vec::<u8> = (0..VPAGE_SIZE + 100).map(|i| i as u8).collect();
+    let mut written = 0;
+    let e = loop {
+        match basis_cache.key_update(hw, QUOTA_DICT, &format!("key{}", written), &data, None, None, Some(QUOTA_BASIS), true) {
+            Ok(_) => written += 1,
+            Err(e) => break e,
+        }
+        assert!(written < QUOTA_BYTES as usize / VPAGE_SIZE, "wrote {} keys without reaching the quota", written);
+    };
+    assert!(e.kind() == std::io::ErrorKind::OutOfMemory, "write past the quota gave {:?}", e);
+    assert!(written > 0, "quota left no room for a single key");
+    // a dictionary that is already there is reported as such, even with no room for a new one
+    let e = basis_cache.dict_add(hw, QUOTA_DICT, Some(QUOTA_BASIS)).expect_err("existing dictionary added again");
+    assert!(e.kind() == std::io::ErrorKind::AlreadyExists, "adding an existing dictionary gave {:?}", e);
+    basis_cache.sync(hw, Some(QUOTA_BASIS))?;
+
+    let before = basis_cache.usage(hw, Some(QUOTA_BASIS), None)?;
+    assert!(before.quota == QUOTA_BYTES, "quota is {}, expected {}", before.quota, QUOTA_BYTES);
+    assert!(before.allocated > 0 && before.allocated <= QUOTA_BYTES,
+        "{} bytes allocated under a quota of {}", before.allocated, QUOTA_BYTES);
+    assert!(before.used == (written * data.len()) as u64, "{} bytes used, expected {}", before.used, written * data.len());
+    assert!(before.keys == written as u32, "{} keys counted, expected {}", before.keys, written);
+
+    let mut basis_cache = remount_system(hw);
+    quota_mount(hw, &mut basis_cache);
+    let after = basis_cache.usage(hw, Some(QUOTA_BASIS), None)?;
+    assert!(after.quota == QUOTA_BYTES, "quota is {} after a remount, expected {}", after.quota, QUOTA_BYTES);
+    assert!(after.allocated == before.allocated && after.used == before.used && after.keys == before.keys,
+        "usage changed across a remount: {}/{}/{} became {}/{}/{}",
+        before.allocated, before.used, before.keys, after.allocated, after.used, after.keys);
+    let e = basis_cache.key_update(hw, QUOTA_DICT, "onemore", &data, None, None, Some(QUOTA_BASIS), true)
+        .expect_err("quota was not enforced after a remount");
+    assert!(e.kind() == std::io::ErrorKind::OutOfMemory, "write past the quota after a remount gave {:?}", e);
+
+    basis_cache.basis_delete(hw, QUOTA_BASIS)?;
+    Ok(())
+}
+
+const QUERY_DICT: &'static str = "querytest";
+
+/// Key queries: a dictionary with many more keys than fit in a page is returned a page at a time,
+/// in order and without gaps, and expired keys don't take up room in a page.
+pub(crate) fn query_test(hw: &mut PddbOs) -> Result<()> {
+    const PAGE: usize = 8;
+    let mut basis_cache = remount_system(hw);
+    basis_cache.dict_add(hw, QUERY_DICT, SYSTEM_BASIS)?;
+    let mut expected = Vec::<String>::new();
+    for keynum in 0..5 * PAGE + 3 {
+        let key = format!("key{:02}", keynum);
+        basis_cache.key_update(hw, QUERY_DICT, &key, &[keynum as u8; 4], None, None, SYSTEM_BASIS, true)?;
+        expected.push(key);
+    }
+    // expire a few keys in the first pages, if expiry is enforced here
+    if hw.rtc_secs().is_some() {
+        for &keynum in [1, 2, PAGE + 3].iter() {
+            basis_cache.key_set_expiry(hw, QUERY_DICT, &expected[keynum], SYSTEM_BASIS, Some(60))?;
+        }
+        hw.rtc_skew += 120;
+        expected.retain(|key| key != "key01" && key != "key02" && *key != format!("key{:02}", PAGE + 3));
+    }
+
+    let mut query = KeyQuery::default();
+    let mut paged = Vec::<String>::new();
+    loop {
+        // one more than a page, the way `QueryKeys` asks for it
+        let page: Vec::<String> = basis_cache.key_query(hw, QUERY_DICT, SYSTEM_BASIS, &query, PAGE + 1)?.into_iter().collect();
+        assert!(page.len() <= PAGE + 1, "query returned {} names, more than the limit", page.len());
+        let more = page.len() > PAGE;
+        paged.extend(page.into_iter().take(PAGE));
+        if !more {
+            break;
+        }
+        query.cursor = paged.last().cloned();
+    }
+    assert!(paged == expected, "paged query returned {:?}, expected {:?}", paged, expected);
+
+    let query = KeyQuery { prefix: Some("key1".to_string()), ..Default::default() };
+    let page: Vec::<String> = basis_cache.key_query(hw, QUERY_DICT, SYSTEM_BASIS, &query, 4)?.into_iter().collect();
+    let first: Vec::<String> = expected.iter().filter(|key| key.starts_with("key1")).take(4).cloned().collect();
+    assert!(page == first, "prefix query returned {:?}, expected {:?}", page, first);
+
+    hw.rtc_skew = 0;
+    basis_cache.dict_remove(hw, QUERY_DICT, SYSTEM_BASIS, false)?;
+    Ok(())
+}
+
+/// Consistency check: a mapped page that nothing refers to is reported as an orphan, both from the
+/// cache and after a remount, and repair unmaps it.
+pub(crate) fn fsck_test(hw: &mut PddbOs) -> Result<()> {
+    let mut basis_cache = remount_system(hw);
+    let before = basis_cache.fsck(hw, false)?;
+    // far above anything the large pool has handed out
+    let va = VirtAddr::new(LARGE_POOL_START + 0x100_0000_0000).unwrap();
+    basis_cache.orphan_inject(hw, SYSTEM_BASIS, va)?;
+    let found = basis_cache.fsck(hw, false)?;
+    assert!(found.orphaned_pages == before.orphaned_pages + 1, "injected orphan was not found in the cache");
+    assert!(found.pages_checked == before.pages_checked + 1, "injected orphan was not checked");
+
+    let mut basis_cache = remount_system(hw);
+    let found = basis_cache.fsck(hw, false)?;
+    assert!(found.orphaned_pages == before.orphaned_pages + 1, "injected orphan was not found on disk");
+    assert!(found.pages_reclaimed == 0, "fsck reclaimed pages without being asked to repair");
+
+    let repaired = basis_cache.fsck(hw, true)?;
+    assert!(repaired.pages_reclaimed >= 1, "repair did not reclaim the orphan");
+    let mut basis_cache = remount_system(hw);
+    let after = basis_cache.fsck(hw, false)?;
+    assert!(after.orphaned_pages == 0, "{} orphans are left after repair", after.orphaned_pages);
+    assert!(after.pages_checked == found.pages_checked - repaired.orphaned_pages, "repaired pages are still mapped");
+    Ok(())
+}
+
+/// Waits for the next notification sent to `sid`, and returns its event and subscription ID.
+fn notify_receive(sid: xous::SID, opcode: u32) -> (PddbChangeEvent, u32) {
+    let envelope = xous::receive_message(sid).expect("couldn't receive notification");
+    match envelope.body {
+        xous::Message::Scalar(scalar) => {
+            assert!(scalar.id == opcode as usize, "notification has the wrong opcode");
+            (FromPrimitive::from_usize(scalar.arg1).expect("notification has an unknown event"), scalar.arg2 as u32)
+        }
+        _ => panic!("notification is not a scalar message"),
+    }
+}
+
+/// Subscriptions: a subscriber is told about writes to the key it watches and no others, and a key
+/// written through a stream is reported once per flush, however many chunks it was written in.
+pub(crate) fn notify_test(hw: &mut PddbOs) -> Result<()> {
+    const OPCODE: u32 = 7;
+    const DICT: &'static str = "notifytest";
+    let sid = xous::create_server().expect("couldn't create subscriber server");
+    let cid = xous::connect(sid).expect("couldn't connect to subscriber server");
+    let mut subscriptions = Subscriptions::new();
+    let id = subscriptions.add(None, DICT, Some("watched"), cid, OPCODE, None).expect("couldn't subscribe");
+
+    // messages arrive in order, so if the first one is let through it is received instead
+    subscriptions.notify_key(SYSTEM_BASIS, DICT, "unwatched", PddbChangeEvent::KeyUpdated);
+    subscriptions.notify_key(SYSTEM_BASIS, DICT, "watched", PddbChangeEvent::KeyUpdated);
+    assert!(notify_receive(sid, OPCODE) == (PddbChangeEvent::KeyUpdated, id), "wrong notification for a key update");
+
+    // open a stream on the key the way `OpenKey` does, then write and flush it as the opcodes do
+    let mut basis_cache = remount_system(hw);
+    basis_cache.dict_add(hw, DICT, SYSTEM_BASIS)?;
+    basis_cache.key_update(hw, DICT, "watched", &[], None, Some(PDDB_STREAM_CHUNK), SYSTEM_BASIS, false)?;
+    let token: ApiToken = [1, 2, 3];
+    let mut token_dict = HashMap::<ApiToken, TokenRecord>::new();
+    token_dict.insert(token, TokenRecord {
+        dict: DICT.to_string(),
+        key: "watched".to_string(),
+        basis: SYSTEM_BASIS.map(|b| b.to_string()),
+        alloc_hint: Some(PDDB_STREAM_CHUNK),
+        conn: None,
+    });
+    let mut stream_updates = HashSet::<ApiToken>::new();
+    let chunk = vec![0x5a_u8; PDDB_STREAM_CHUNK];
+    let mut position = 0;
+    for flush in 0..3 {
+        for _chunk in 0..3 {
+            write_key_stream(hw, &mut basis_cache, &mut stream_updates, token, &token_dict[&token], &chunk, position)?;
+            position += chunk.len();
+        }
+        flush_key_streams(hw, &mut basis_cache, &mut stream_updates, &token_dict, &mut subscriptions)?;
+        assert!(notify_receive(sid, OPCODE) == (PddbChangeEvent::KeyUpdated, id), "flush {} did not report the streamed key", flush);
+    }
+    // nothing was written since, so this must not report the key again
+    flush_key_streams(hw, &mut basis_cache, &mut stream_updates, &token_dict, &mut subscriptions)?;
+    subscriptions.notify_key(SYSTEM_BASIS, DICT, "watched", PddbChangeEvent::KeyDeleted);
+    assert!(notify_receive(sid, OPCODE) == (PddbChangeEvent::KeyDeleted, id), "streamed key was reported more than once per flush");
+    let attr = basis_cache.key_attributes(hw, DICT, "watched", SYSTEM_BASIS)?;
+    assert!(attr.len == position, "streamed key is {} bytes, expected {}", attr.len, position);
+    basis_cache.dict_remove(hw, DICT, SYSTEM_BASIS, false)?;
+
+    subscriptions.remove(id, None);
+    unsafe { xous::disconnect(cid).ok(); }
+    xous::destroy_server(sid).ok();
+    Ok(())
+}
@@ -1,13 +1,21 @@
+// only the opcodes are needed by the offline image tool, which has no UX
+#[cfg(not(feature="offline"))]
 use xous::{SID, CID};
 
+#[cfg(not(feature="offline"))]
 use xous::msg_scalar_unpack;
+#[cfg(not(feature="offline"))]
 use xous_ipc::Buffer;
 
+#[cfg(not(feature="offline"))]
 use num_traits::*;
 
+#[cfg(not(feature="offline"))]
 use gam::modal::*;
 
+#[cfg(not(feature="offline"))]
 use locales::t;
+#[cfg(not(feature="offline"))]
 use crate::BasisRequestPassword;
 /*
 Conclusions:
@@ -55,6 +63,7 @@ pub(crate) enum PwManagerOpcode {
     Quit,
 }
 
+#[cfg(not(feature="offline"))]
 pub(crate) fn password_ux_manager(
     // the CID of the main loop, as a backchannel for async callbacks.
     _main_cid: CID,
@@ -94,6 +94,7 @@ mod rtc_cmd;  use rtc_cmd::*;
 mod vibe;     use vibe::*;
 mod ssid;     use ssid::*;
 mod ver;      use ver::*;
+mod ps;       use ps::*;
 //mod audio;    use audio::*; // this command is currently contra-indicated with PDDB, as the test audio currently overlaps the PDDB space. We'll fix this eventually, but for now, let's switch to PDDB mode.
 mod backlight; use backlight::*;
 mod accel;    use accel::*;
@@ -263,6 +264,7 @@ impl CmdEnv {
 
         let mut echo_cmd = Echo {}; // this command has no persistent storage, so we can "create" it every time we call dispatch (but it's a zero-cost absraction so this doesn't actually create any instructions)
         let mut ver_cmd = Ver{};
+        let mut ps_cmd = Ps{};
         let mut backlight_cmd = Backlight{};
         let mut accel_cmd = Accel{};
         let mut console_cmd = Console{};
@@ -277,6 +279,7 @@ impl CmdEnv {
             &mut self.vibe_cmd,
             &mut self.ssid_cmd,
             &mut ver_cmd,
+            &mut ps_cmd,
             //&mut self.audio_cmd,
             &mut backlight_cmd,
             &mut accel_cmd,
@@ -78,10 +78,10 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
         use core::fmt::Write;
         let mut ret = String::<1024>::new();
         #[cfg(any(feature="precursor", feature="renode"))]
-        let helpstring = "net [udp [rx socket] [tx dest socket]] [ping [host] [count]] [tcpget host/path]";
+        let helpstring = "net [udp [rx socket] [tx dest socket]] [ping [host] [count]] [tcpget host/path] [pcap] [apps] [policy]";
         // no ping in hosted mode -- why would you need it? we're using the host's network connection.
         #[cfg(any(feature="hosted"))]
-        let helpstring = "net [udp [port]] [count]] [tcpget host/path]";
+        let helpstring = "net [udp [port]] [count]] [tcpget host/path] [pcap] [apps] [policy]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
 
@@ -674,6 +674,131 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                         write!(ret, "Missing host: net ping [host] [count]").unwrap();
                     }
                 }
+                "pcap" => {
+                    let netmgr = net::NetManager::new();
+                    let usage = "net pcap [start [tcp|udp|icmp|arp] [port]] [stop] [status] [save name] [dump]";
+                    match tokens.next() {
+                        Some("start") => {
+                            let mut filter = net::CaptureFilter::default();
+                            for token in tokens {
+                                filter.protocol = match token {
+                                    "tcp" => net::CaptureProtocol::Tcp,
+                                    "udp" => net::CaptureProtocol::Udp,
+                                    "icmp" => net::CaptureProtocol::Icmp,
+                                    "arp" => net::CaptureProtocol::Arp,
+                                    _ => match token.parse::<u16>() {
+                                        Ok(port) => {
+                                            filter.port = Some(port);
+                                            continue;
+                                        }
+                                        Err(_) => {
+                                            write!(ret, "{}", usage).unwrap();
+                                            return Ok(Some(ret));
+                                        }
+                                    },
+                                };
+                            }
+                            match netmgr.capture_start(filter, NET_MTU as u32, net::CAPTURE_MAX_BYTES) {
+                                Ok(_) => write!(ret, "Capturing {:?}", filter).unwrap(),
+                                Err(e) => write!(ret, "Couldn't start capture: {:?}", e).unwrap(),
+                            }
+                        }
+                        Some("stop") => match netmgr.capture_stop() {
+                            Ok(frames) => write!(ret, "Capture stopped, {} frames held", frames).unwrap(),
+                            Err(e) => write!(ret, "Couldn't stop capture: {:?}", e).unwrap(),
+                        },
+                        Some("status") => match netmgr.capture_status() {
+                            Ok(status) => write!(ret, "{}: {} frames, {} bytes, {} dropped",
+                                if status.capturing { "Capturing" } else { "Stopped" },
+                                status.frames, status.bytes, status.dropped
+                            ).unwrap(),
+                            Err(e) => write!(ret, "Couldn't get capture status: {:?}", e).unwrap(),
+                        },
+                        Some("save") => match tokens.next() {
+                            Some(name) => match netmgr.capture_save(name) {
+                                Ok(len) => write!(ret, "Saved {} bytes to {}:{}", len, net::CAPTURE_DICT, name).unwrap(),
+                                Err(e) => write!(ret, "Couldn't save capture: {:?}", e).unwrap(),
+                            },
+                            None => write!(ret, "{}", usage).unwrap(),
+                        },
+                        Some("dump") => match netmgr.capture_dump() {
+                            Ok(len) => write!(ret, "Wrote {} bytes of pcap to the log", len).unwrap(),
+                            Err(e) => write!(ret, "Couldn't dump capture: {:?}", e).unwrap(),
+                        },
+                        _ => write!(ret, "{}", usage).unwrap(),
+                    }
+                }
+                "apps" => {
+                    let netmgr = net::NetManager::new();
+                    match tokens.next() {
+                        Some("reset") => match netmgr.usage_reset() {
+                            Ok(_) => write!(ret, "Network usage counters reset").unwrap(),
+                            Err(e) => write!(ret, "Couldn't reset counters: {:?}", e).unwrap(),
+                        },
+                        Some(_) => write!(ret, "net apps [reset]").unwrap(),
+                        None => match netmgr.usage_list() {
+                            Ok(usage) if usage.is_empty() => write!(ret, "No app has used the network").unwrap(),
+                            Ok(usage) => {
+                                for app in usage.iter() {
+                                    write!(ret, "{} ({}): {} open, {} conn, tx {}, rx {}",
+                                        app.process.as_str().unwrap_or("?"), app.pid, app.sockets, app.connections,
+                                        human_bytes(app.tx_bytes), human_bytes(app.rx_bytes),
+                                    ).ok();
+                                    if app.denied != 0 {
+                                        write!(ret, ", {} denied", app.denied).ok();
+                                    }
+                                    write!(ret, ", {}s ago\n", app.idle_ms / 1000).ok();
+                                }
+                            }
+                            Err(e) => write!(ret, "Couldn't get network usage: {:?}", e).unwrap(),
+                        },
+                    }
+                }
+                "policy" => {
+                    let netmgr = net::NetManager::new();
+                    let usage = "net policy [set <app|*> deny|listed|lan|any [host[/prefix][:port] ...]] [rm <app|*>]";
+                    match tokens.next() {
+                        None => match netmgr.policy_list() {
+                            Ok(rules) if rules.is_empty() => write!(ret, "No network rules; every app may reach any host").unwrap(),
+                            Ok(rules) => {
+                                for rule in rules.iter() {
+                                    write!(ret, "{}: {:?}", rule.process.as_str().unwrap_or("?"), rule.access).ok();
+                                    for host in rule.hosts() {
+                                        write!(ret, " {}", host).ok();
+                                    }
+                                    write!(ret, "\n").ok();
+                                }
+                            }
+                            Err(e) => write!(ret, "Couldn't get network rules: {:?}", e).unwrap(),
+                        },
+                        Some("set") => {
+                            let process = tokens.next();
+                            let access = match tokens.next() {
+                                Some("deny") => Some(net::NetAccess::Deny),
+                                Some("listed") => Some(net::NetAccess::Listed),
+                                Some("lan") => Some(net::NetAccess::Lan),
+                                Some("any") => Some(net::NetAccess::Any),
+                                _ => None,
+                            };
+                            let hosts: Result<Vec<net::HostRule>, ()> = tokens.map(|h| h.parse::<net::HostRule>()).collect();
+                            match (process, access, hosts) {
+                                (Some(process), Some(access), Ok(hosts)) => match netmgr.policy_set(process, access, &hosts) {
+                                    Ok(_) => write!(ret, "{} may now reach {:?} {:?}", process, access, hosts).unwrap(),
+                                    Err(e) => write!(ret, "Couldn't set rule: {:?}", e).unwrap(),
+                                },
+                                _ => write!(ret, "{}", usage).unwrap(),
+                            }
+                        }
+                        Some("rm") => match tokens.next() {
+                            Some(process) => match netmgr.policy_remove(process) {
+                                Ok(_) => write!(ret, "Removed rule for {}", process).unwrap(),
+                                Err(e) => write!(ret, "Couldn't remove rule: {:?}", e).unwrap(),
+                            },
+                            None => write!(ret, "{}", usage).unwrap(),
+                        },
+                        _ => write!(ret, "{}", usage).unwrap(),
+                    }
+                }
                 _ => {
                     write!(ret, "{}", helpstring).unwrap();
                 }
@@ -945,3 +1070,13 @@ fn join_tokens<'a>(buf: &mut String<1024>, tokens: impl Iterator<Item = &'a str>
         }
     }
 }
+
+fn human_bytes(bytes: u64) -> std::string::String {
+    if bytes < 1024 {
+        format!("{}B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1}KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1}MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
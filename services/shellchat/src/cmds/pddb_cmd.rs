@@ -23,9 +23,9 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
     fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
         let mut ret = String::<1024>::new();
         #[cfg(not(feature="pddbtest"))]
-        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete] [churn] [flush] [sync]";
+        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete] [churn] [flush] [sync] [fsck] [usage]";
         #[cfg(feature="pddbtest")]
-        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete] [churn] [flush] [sync]\n[test]";
+        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete] [churn] [flush] [sync] [fsck] [usage]\n[test]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
         if let Some(sub_cmd) = tokens.next() {
@@ -53,12 +53,13 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
                 }
                 "basiscreate" => {
                     if let Some(bname) = tokens.next() {
-                        match self.pddb.create_basis(bname) {
+                        let quota = tokens.next().and_then(|q| q.parse::<u64>().ok());
+                        match self.pddb.create_basis_with_quota(bname, quota) {
                             Ok(_) => write!(ret, "basis {} created successfully", bname).unwrap(),
                             Err(e) => write!(ret, "basis {} could not be created: {:?}", bname, e).unwrap(),
                         }
                     } else {
-                        write!(ret, "usage: pddb basiscreate [basis name]").unwrap()
+                        write!(ret, "usage: pddb basiscreate [basis name] [quota in bytes]").unwrap()
                     }
                 }
                 "basisunlock" => {
@@ -259,6 +260,49 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
                     write!(ret, "Sync result code: {:?}\n", self.pddb.sync()).ok();
                     log::info!("{}PDDB.SYNCDONE,{}", xous::BOOKEND_START, xous::BOOKEND_END);
                 }
+                "usage" => {
+                    let bname = tokens.next();
+                    let dname = tokens.next();
+                    match self.pddb.usage(bname, dname) {
+                        Ok(report) => {
+                            write!(ret, "{}{}{}: {} keys, {} bytes in {} bytes allocated",
+                                report.basis.as_str().unwrap_or("UTF8-error"),
+                                if dname.is_some() { ":" } else { "" },
+                                dname.unwrap_or(""),
+                                report.keys, report.used, report.allocated,
+                            ).ok();
+                            if let Some(quota) = report.quota {
+                                write!(ret, " of a {} byte quota", quota).ok();
+                            }
+                            if let Some(free) = report.device_free {
+                                write!(ret, "\nPDDB: {} of {} bytes free", free, report.device_total).ok();
+                            } else {
+                                write!(ret, "\nPDDB: {} bytes, no free space scan yet", report.device_total).ok();
+                            }
+                        }
+                        Err(e) => {
+                            write!(ret, "usage failed: {:?}", e).ok();
+                        }
+                    }
+                }
+                "fsck" => {
+                    let repair = tokens.next() == Some("repair");
+                    match self.pddb.fsck(repair) {
+                        Ok(report) => {
+                            write!(ret, "Checked {} bases, {} pages\n", report.bases_checked, report.pages_checked).ok();
+                            write!(ret, "Orphaned pages: {}\n", report.orphaned_pages).ok();
+                            write!(ret, "Duplicate mappings: {}\n", report.duplicate_mappings).ok();
+                            write!(ret, "Journal conflicts: {}\n", report.journal_conflicts).ok();
+                            write!(ret, "Dangling keys: {}\n", report.dangling_keys).ok();
+                            if repair {
+                                write!(ret, "Reclaimed {} pages", report.pages_reclaimed).ok();
+                            }
+                        }
+                        Err(e) => {
+                            write!(ret, "fsck failed: {:?}", e).ok();
+                        }
+                    }
+                }
                 "hwtest" => {
                     let mut args = [0u32; 4];
                     for (index, token) in tokens.enumerate() {
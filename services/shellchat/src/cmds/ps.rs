@@ -0,0 +1,132 @@
+use crate::{ShellCmdApi, CommonEnv};
+use xous_ipc::String;
+
+#[derive(Debug)]
+pub struct Ps {
+}
+
+/// The hosted kernel doesn't manage memory, so it always reports 0 bytes of RAM
+const RAM_TRACKED: bool = cfg!(target_os = "none");
+
+fn status_str(status: &xous::ProcessStatus) -> &'static str {
+    match status {
+        xous::ProcessStatus::Allocated => "alloc",
+        xous::ProcessStatus::Setup => "setup",
+        xous::ProcessStatus::Ready(_) => "ready",
+        xous::ProcessStatus::Running(_) => "run",
+        xous::ProcessStatus::Sleeping => "sleep",
+        xous::ProcessStatus::Debug(_) => "debug",
+        xous::ProcessStatus::Exception(_) | xous::ProcessStatus::BlockedException(_) => "exc",
+    }
+}
+
+impl<'a> ShellCmdApi<'a> for Ps {
+    cmd_api!(ps); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "ps [pid] [cpu]";
+
+        let mut tokens = args.as_str().unwrap().split(' ');
+
+        if let Some(sub_cmd) = tokens.next() {
+            if sub_cmd == "cpu" {
+                // breakdown of the CPU time consumed by each process since boot
+                let mut times = [(0usize, 0u64); 64];
+                let mut count = 0;
+                let mut total: u64 = 0;
+                for index in 1..=times.len() {
+                    let pid = match xous::pid_from_usize(index) {
+                        Ok(pid) => pid,
+                        Err(_) => break,
+                    };
+                    match xous::process_run_time(pid) {
+                        Ok(time) => {
+                            times[count] = (index, time);
+                            count += 1;
+                            total += time;
+                        }
+                        Err(xous::Error::ProcessNotFound) => continue,
+                        Err(_) => break,
+                    }
+                }
+                write!(ret, "PID     CPU ms    % NAME\n").unwrap();
+                for &(index, time) in times[..count].iter() {
+                    let pid = xous::pid_from_usize(index).unwrap();
+                    let mut name = [0u8; 16];
+                    let len = xous::process_name(pid, &mut name).unwrap_or(0).min(name.len());
+                    write!(ret, "{:>3} {:>10} {:>4} {}\n",
+                        index, time / 1000, if total > 0 { time * 100 / total } else { 0 },
+                        core::str::from_utf8(&name[..len]).unwrap_or("?"),
+                    ).unwrap();
+                }
+                write!(ret, "{} ms total", total / 1000).unwrap();
+                return Ok(Some(ret));
+            }
+            if sub_cmd.len() > 0 {
+                // detailed view of a single process
+                let pid = match sub_cmd.parse::<usize>().ok().and_then(|p| xous::pid_from_usize(p).ok()) {
+                    Some(pid) => pid,
+                    None => {
+                        write!(ret, "{}", helpstring).unwrap();
+                        return Ok(Some(ret));
+                    }
+                };
+                match xous::process_info(pid) {
+                    Ok(info) => {
+                        let mut name = [0u8; 64];
+                        let len = xous::process_name(pid, &mut name).unwrap_or(0).min(name.len());
+                        write!(ret, "PID {}: {}\n", info.pid, core::str::from_utf8(&name[..len]).unwrap_or("?")).unwrap();
+                        write!(ret, "  parent: {}\n", info.ppid).unwrap();
+                        write!(ret, "  state: {:?}\n", info.status).unwrap();
+                        write!(ret, "  threads: {}\n", info.threads).unwrap();
+                        write!(ret, "  servers: {}\n", info.servers).unwrap();
+                        if RAM_TRACKED {
+                            write!(ret, "  RAM: {} kiB\n", info.ram_bytes / 1024).unwrap();
+                        } else {
+                            write!(ret, "  RAM: n/a\n").unwrap();
+                        }
+                        write!(ret, "  CPU: {} ms", xous::process_run_time(pid).unwrap_or(0) / 1000).unwrap();
+                    }
+                    Err(e) => {
+                        write!(ret, "PID {}: {:?}", pid, e).unwrap();
+                    }
+                }
+                return Ok(Some(ret));
+            }
+        }
+
+        // summary of every process. The output buffer is fixed-size, so very long lists are truncated.
+        write!(ret, "PID THR SRV   RAM STATE NAME\n").unwrap();
+        let mut total_ram = 0;
+        for index in 1.. {
+            let pid = match xous::pid_from_usize(index) {
+                Ok(pid) => pid,
+                Err(_) => break,
+            };
+            let info = match xous::process_info(pid) {
+                Ok(info) => info,
+                Err(xous::Error::ProcessNotFound) => continue,
+                Err(_) => break,
+            };
+            total_ram += info.ram_bytes;
+            let mut name = [0u8; 16];
+            let len = xous::process_name(pid, &mut name).unwrap_or(0).min(name.len());
+            write!(ret, "{:>3} {:>3} {:>3} ", info.pid, info.threads, info.servers).unwrap();
+            if RAM_TRACKED {
+                write!(ret, "{:>4}k ", info.ram_bytes / 1024).unwrap();
+            } else {
+                write!(ret, "{:>5} ", "n/a").unwrap();
+            }
+            write!(ret, "{:5} {}\n",
+                status_str(&info.status), core::str::from_utf8(&name[..len]).unwrap_or("?"),
+            ).unwrap();
+        }
+        if RAM_TRACKED {
+            write!(ret, "{}k total", total_ram / 1024).unwrap();
+        }
+
+        Ok(Some(ret))
+    }
+}
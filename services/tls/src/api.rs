@@ -0,0 +1,245 @@
+pub(crate) const SERVER_NAME_TLS: &str = "_TLS Service_";
+use rkyv::{Archive, Deserialize, Serialize};
+
+/// Host names handed to `Connect`
+#[allow(dead_code)]
+pub(crate) const TLS_HOST_LEN: usize = 256;
+/// CA names and pinned host names are PDDB key names, so they share its limit
+#[allow(dead_code)]
+pub(crate) const TLS_NAME_LEN: usize = 95;
+/// Largest DER certificate that can be added as a trust anchor
+pub(crate) const TLS_CERT_MAX_LEN: usize = 3072;
+/// Plaintext moved per `Read` or `Write` on a session
+pub(crate) const TLS_DATA_LEN: usize = 3072;
+/// Space for the packed listing of the trust store
+pub(crate) const TLS_LIST_LEN: usize = 3584;
+
+/// Extra trust anchors added by the user. Each key is the name given to the CA, and
+/// holds its certificate in DER form.
+pub const TLS_TRUST_DICT: &str = "tls.trust";
+/// Certificates pinned to a host. Each key is a host name, and holds the SHA-256 of
+/// the DER of the only end-entity certificate accepted for that host.
+pub const TLS_PIN_DICT: &str = "tls.pins";
+
+/// These opcodes can be called by anyone at any time. Changes to the trust store are shown to
+/// the user, who must confirm them before they are made.
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone)]
+pub(crate) enum Opcode {
+    /// Open a TCP connection, run the handshake, and hand back a private server for the session
+    Connect = 0,
+    /// Show the custom CAs and pins to the user, and remove the ones they select
+    Review = 1,
+    /// List the custom CAs and pins
+    ListTrust = 2,
+    /// Add a CA to the trust store
+    AddCa = 3,
+    /// Remove a CA from the trust store
+    RemoveCa = 4,
+    /// Pin a certificate to a host
+    Pin = 5,
+    /// Remove the pin on a host
+    Unpin = 6,
+    /// Exits the server
+    Quit = 7,
+}
+
+/// Opcodes understood by the private server of each session
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum SessionOpcode {
+    /// Read up to `len` bytes of plaintext. A `len` of 0 on return means the peer closed the session.
+    Read = 0,
+    /// Write `len` bytes of plaintext
+    Write = 1,
+    /// Send a close_notify, and tear down the session
+    Close = 2,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub enum TlsRetcode {
+    Ok,
+    /// The TCP connection couldn't be made
+    ConnectFailed,
+    /// The handshake failed for a reason other than the server's certificate
+    HandshakeFailed,
+    /// The server's certificate isn't signed by a trusted CA, or doesn't match the pin for the host
+    Untrusted,
+    /// The user was shown the server's certificate, or a change to the trust store, and didn't accept it
+    UserRejected,
+    /// A certificate given to the trust store couldn't be parsed
+    InvalidCertificate,
+    /// A name or host given was not found in the trust store
+    NotFound,
+    /// The trust store couldn't be read or written; usually, the PDDB isn't mounted
+    StoreError,
+    /// The session was closed, or the connection broke
+    Closed,
+    /// The network rule for the client doesn't let it reach the host
+    AccessDenied,
+    InternalError,
+}
+
+#[derive(Debug, Copy, Clone, Archive, Serialize, Deserialize)]
+pub(crate) struct TlsConnect {
+    pub host: xous_ipc::String<TLS_HOST_LEN>,
+    pub port: u16,
+    /// If true, a certificate that can't be verified is shown to the user, who can accept it
+    pub interactive: bool,
+    /// The private server of the session, on return
+    pub session: [u32; 4],
+    /// SHA-256 of the server's end-entity certificate, on return
+    pub fingerprint: [u8; 32],
+    pub code: TlsRetcode,
+}
+
+#[derive(Debug, Copy, Clone, Archive, Serialize, Deserialize)]
+pub(crate) struct TlsChunk {
+    pub data: [u8; TLS_DATA_LEN],
+    pub len: u32,
+    pub code: TlsRetcode,
+}
+
+/// Used by `AddCa`, `RemoveCa`, `Pin` and `Unpin`. `data` holds the DER of a CA, or the
+/// 32-byte fingerprint of a pin.
+#[derive(Debug, Copy, Clone, Archive, Serialize, Deserialize)]
+pub(crate) struct TlsTrustEdit {
+    pub name: xous_ipc::String<TLS_NAME_LEN>,
+    pub data: [u8; TLS_CERT_MAX_LEN],
+    pub len: u32,
+    pub code: TlsRetcode,
+}
+
+/// Each entry in `data` is a kind byte (0 for a CA, 1 for a pin), a 32-byte fingerprint, and the
+/// name preceded by its length in one byte.
+#[derive(Debug, Copy, Clone, Archive, Serialize, Deserialize)]
+pub(crate) struct TlsTrustList {
+    pub data: [u8; TLS_LIST_LEN],
+    pub len: u32,
+    /// Set if there were more entries than would fit
+    pub more: bool,
+    pub code: TlsRetcode,
+}
+impl TlsTrustList {
+    /// Packs as many entries as fit into `data`, and sets `more` if some didn't
+    #[allow(dead_code)]
+    pub(crate) fn pack<'a>(&mut self, entries: impl Iterator<Item = (TrustKind, &'a str, [u8; 32])>) {
+        let mut index = 0;
+        for (kind, name, fingerprint) in entries {
+            let entry_len = 34 + name.len();
+            if index + entry_len > TLS_LIST_LEN || name.len() > u8::MAX as usize {
+                self.more = true;
+                break;
+            }
+            self.data[index] = match kind {
+                TrustKind::Ca => 0,
+                TrustKind::Pin => 1,
+            };
+            self.data[index + 1..index + 33].copy_from_slice(&fingerprint);
+            self.data[index + 33] = name.len() as u8;
+            self.data[index + 34..index + entry_len].copy_from_slice(name.as_bytes());
+            index += entry_len;
+        }
+        self.len = index as u32;
+    }
+    /// The entries packed into `data`, or None if they are malformed
+    #[allow(dead_code)]
+    pub(crate) fn unpack(&self) -> Option<Vec<TrustEntry>> {
+        let data = self.data.get(..self.len as usize)?;
+        let mut entries = Vec::new();
+        let mut index = 0;
+        while index < data.len() {
+            let header = data.get(index..index + 34)?;
+            let kind = if header[0] == 0 { TrustKind::Ca } else { TrustKind::Pin };
+            let mut fingerprint = [0u8; 32];
+            fingerprint.copy_from_slice(&header[1..33]);
+            let name_len = header[33] as usize;
+            let name = data.get(index + 34..index + 34 + name_len)?;
+            entries.push(TrustEntry {
+                kind,
+                name: String::from_utf8_lossy(name).to_string(),
+                fingerprint,
+            });
+            index += 34 + name_len;
+        }
+        Some(entries)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TrustKind {
+    /// A CA added to the built-in roots
+    Ca,
+    /// A certificate pinned to a host
+    Pin,
+}
+
+/// One entry of the trust store. `fingerprint` is the SHA-256 of the CA's certificate, or the
+/// pinned end-entity certificate.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct TrustEntry {
+    pub kind: TrustKind,
+    pub name: String,
+    pub fingerprint: [u8; 32],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_list() -> TlsTrustList {
+        TlsTrustList {
+            data: [0; TLS_LIST_LEN],
+            len: 0,
+            more: false,
+            code: TlsRetcode::Ok,
+        }
+    }
+
+    #[test]
+    fn trust_list_roundtrip() {
+        let mut list = empty_list();
+        let entries = [
+            (TrustKind::Ca, "Example Root CA", [0x11u8; 32]),
+            (TrustKind::Pin, "bunniefoo.com", [0x22u8; 32]),
+            (TrustKind::Pin, "", [0x33u8; 32]),
+        ];
+        list.pack(entries.iter().map(|(kind, name, fp)| (*kind, *name, *fp)));
+        assert!(!list.more);
+        assert_eq!(list.len as usize, 3 * 34 + "Example Root CA".len() + "bunniefoo.com".len());
+        let unpacked = list.unpack().unwrap();
+        assert_eq!(unpacked.len(), entries.len());
+        for (entry, (kind, name, fp)) in unpacked.iter().zip(entries.iter()) {
+            assert_eq!(entry.kind, *kind);
+            assert_eq!(entry.name, *name);
+            assert_eq!(entry.fingerprint, *fp);
+        }
+    }
+
+    #[test]
+    fn trust_list_overflow() {
+        let mut list = empty_list();
+        let name = "x".repeat(TLS_NAME_LEN - 1);
+        let entry_len = 34 + name.len();
+        let fits = TLS_LIST_LEN / entry_len;
+        list.pack((0..fits + 5).map(|i| (TrustKind::Pin, name.as_str(), [i as u8; 32])));
+        assert!(list.more);
+        assert_eq!(list.len as usize, fits * entry_len);
+        let unpacked = list.unpack().unwrap();
+        assert_eq!(unpacked.len(), fits);
+        assert_eq!(unpacked[fits - 1].fingerprint, [(fits - 1) as u8; 32]);
+    }
+
+    #[test]
+    fn trust_list_malformed() {
+        let mut list = empty_list();
+        list.pack(core::iter::once((TrustKind::Ca, "Example Root CA", [0x11u8; 32])));
+        // cut the entry off part way through its name
+        list.len -= 1;
+        assert!(list.unpack().is_none());
+        // and part way through its header
+        list.len = 20;
+        assert!(list.unpack().is_none());
+        list.len = TLS_LIST_LEN as u32 + 1;
+        assert!(list.unpack().is_none());
+    }
+}
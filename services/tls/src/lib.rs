@@ -0,0 +1,211 @@
+pub mod api;
+pub use api::*;
+use xous::{CID, send_message, Message};
+use xous_ipc::Buffer;
+use num_traits::*;
+use std::io::{Read, Write, Result, Error, ErrorKind};
+
+fn to_io_error(code: TlsRetcode) -> Error {
+    match code {
+        TlsRetcode::ConnectFailed => Error::new(ErrorKind::ConnectionRefused, "TCP connection failed"),
+        TlsRetcode::HandshakeFailed => Error::new(ErrorKind::ConnectionAborted, "TLS handshake failed"),
+        TlsRetcode::Untrusted => Error::new(ErrorKind::PermissionDenied, "Server certificate is not trusted"),
+        TlsRetcode::UserRejected => Error::new(ErrorKind::PermissionDenied, "Rejected by the user"),
+        TlsRetcode::InvalidCertificate => Error::new(ErrorKind::InvalidData, "Certificate could not be parsed"),
+        TlsRetcode::NotFound => Error::new(ErrorKind::NotFound, "Not in the trust store"),
+        TlsRetcode::StoreError => Error::new(ErrorKind::Other, "Trust store is not available"),
+        TlsRetcode::Closed => Error::new(ErrorKind::BrokenPipe, "TLS session is closed"),
+        TlsRetcode::AccessDenied => Error::new(ErrorKind::PermissionDenied, "Network rule does not allow this host"),
+        _ => Error::new(ErrorKind::Other, "TLS service internal error"),
+    }
+}
+
+pub struct Tls {
+    conn: CID,
+}
+impl Tls {
+    pub fn new(xns: &xous_names::XousNames) -> std::result::Result<Self, xous::Error> {
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_TLS).expect("Can't connect to TLS server");
+        Ok(Tls {
+            conn
+        })
+    }
+
+    /// Connects to `host` and runs the handshake. The server's certificate must chain to one of
+    /// the built-in roots or a CA added to the trust store, or match the pin for `host`.
+    pub fn connect(&self, host: &str, port: u16) -> Result<TlsStream> {
+        self.connect_inner(host, port, false)
+    }
+    /// As `connect`, but if the server's certificate can't be verified it is shown to the user,
+    /// who can accept it for this session or pin it to the host.
+    pub fn connect_interactive(&self, host: &str, port: u16) -> Result<TlsStream> {
+        self.connect_inner(host, port, true)
+    }
+    fn connect_inner(&self, host: &str, port: u16, interactive: bool) -> Result<TlsStream> {
+        if host.len() > TLS_HOST_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, "host name too long"));
+        }
+        let request = TlsConnect {
+            host: xous_ipc::String::from_str(host),
+            port,
+            interactive,
+            session: [0; 4],
+            fingerprint: [0; 32],
+            code: TlsRetcode::InternalError,
+        };
+        let mut buf = Buffer::into_buf(request).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::Connect.to_u32().unwrap()).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<TlsConnect, _>().unwrap();
+        if response.code != TlsRetcode::Ok {
+            return Err(to_io_error(response.code));
+        }
+        let conn = xous::connect(xous::SID::from_array(response.session))
+            .or(Err(Error::new(ErrorKind::Other, "couldn't connect to the TLS session")))?;
+        Ok(TlsStream {
+            conn,
+            fingerprint: response.fingerprint,
+        })
+    }
+
+    /// Lists the CAs added to the trust store, and the pinned hosts.
+    pub fn trust_list(&self) -> Result<Vec<TrustEntry>> {
+        let request = TlsTrustList {
+            data: [0; TLS_LIST_LEN],
+            len: 0,
+            more: false,
+            code: TlsRetcode::InternalError,
+        };
+        let mut buf = Buffer::into_buf(request).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::ListTrust.to_u32().unwrap()).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<TlsTrustList, _>().unwrap();
+        if response.code != TlsRetcode::Ok {
+            return Err(to_io_error(response.code));
+        }
+        if response.more {
+            log::warn!("trust store has more entries than fit in one listing; the list is truncated");
+        }
+        response.unpack().ok_or(Error::new(ErrorKind::InvalidData, "trust listing is malformed"))
+    }
+    /// Adds a CA to the trust store, under `name`. `der` is the CA's certificate.
+    ///
+    /// This, and the other calls that change the trust store, show the change to the user along
+    /// with the certificate's SHA-256, and return once they have confirmed or rejected it.
+    pub fn add_ca(&self, name: &str, der: &[u8]) -> Result<()> {
+        if der.len() > TLS_CERT_MAX_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, "certificate is too large"));
+        }
+        self.trust_edit(Opcode::AddCa, name, der)
+    }
+    pub fn remove_ca(&self, name: &str) -> Result<()> {
+        self.trust_edit(Opcode::RemoveCa, name, &[])
+    }
+    /// Accepts only the end-entity certificate whose DER hashes to `fingerprint` for `host`,
+    /// whether or not it chains to a trusted CA.
+    pub fn pin(&self, host: &str, fingerprint: &[u8; 32]) -> Result<()> {
+        self.trust_edit(Opcode::Pin, host, fingerprint)
+    }
+    pub fn unpin(&self, host: &str) -> Result<()> {
+        self.trust_edit(Opcode::Unpin, host, &[])
+    }
+    fn trust_edit(&self, op: Opcode, name: &str, data: &[u8]) -> Result<()> {
+        if name.len() > TLS_NAME_LEN - 1 || name.len() == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "name is empty or too long"));
+        }
+        let mut request = TlsTrustEdit {
+            name: xous_ipc::String::from_str(name),
+            data: [0; TLS_CERT_MAX_LEN],
+            len: data.len() as u32,
+            code: TlsRetcode::InternalError,
+        };
+        request.data[..data.len()].copy_from_slice(data);
+        let mut buf = Buffer::into_buf(request).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, op.to_u32().unwrap()).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<TlsTrustEdit, _>().unwrap();
+        match response.code {
+            TlsRetcode::Ok => Ok(()),
+            code => Err(to_io_error(code)),
+        }
+    }
+    /// Shows the custom CAs and pins to the user, and removes the ones they select.
+    /// Returns once the user is done.
+    pub fn review(&self) -> std::result::Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::Review.to_usize().unwrap(), 0, 0, 0, 0)
+        ).map(|_| ())
+    }
+}
+
+use core::sync::atomic::{AtomicU32, Ordering};
+static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl Drop for Tls {
+    fn drop(&mut self) {
+        // the connection to the server side must be reference counted, so that multiple instances of this object within
+        // a single process do not end up de-allocating the CID on other threads before they go out of scope.
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
+
+/// An open TLS session. Reads and writes carry plaintext; the service does the encryption, and
+/// owns the TCP connection underneath. Dropping the stream closes the session.
+pub struct TlsStream {
+    conn: CID,
+    fingerprint: [u8; 32],
+}
+impl TlsStream {
+    /// SHA-256 of the server's end-entity certificate. Pass it to `Tls::pin` to pin the certificate
+    /// to the host.
+    pub fn fingerprint(&self) -> [u8; 32] {
+        self.fingerprint
+    }
+}
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let request = TlsChunk {
+            data: [0; TLS_DATA_LEN],
+            len: buf.len().min(TLS_DATA_LEN) as u32,
+            code: TlsRetcode::InternalError,
+        };
+        let mut ipc = Buffer::into_buf(request).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        ipc.lend_mut(self.conn, SessionOpcode::Read.to_u32().unwrap()).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = ipc.to_original::<TlsChunk, _>().unwrap();
+        if response.code != TlsRetcode::Ok {
+            return Err(to_io_error(response.code));
+        }
+        let len = response.len as usize;
+        buf[..len].copy_from_slice(&response.data[..len]);
+        Ok(len)
+    }
+}
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let len = buf.len().min(TLS_DATA_LEN);
+        let mut request = TlsChunk {
+            data: [0; TLS_DATA_LEN],
+            len: len as u32,
+            code: TlsRetcode::InternalError,
+        };
+        request.data[..len].copy_from_slice(&buf[..len]);
+        let mut ipc = Buffer::into_buf(request).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        ipc.lend_mut(self.conn, SessionOpcode::Write.to_u32().unwrap()).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = ipc.to_original::<TlsChunk, _>().unwrap();
+        match response.code {
+            TlsRetcode::Ok => Ok(len),
+            code => Err(to_io_error(code)),
+        }
+    }
+    /// Writes are flushed to the socket by the service as they are made
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        send_message(self.conn,
+            Message::new_blocking_scalar(SessionOpcode::Close.to_usize().unwrap(), 0, 0, 0, 0)
+        ).ok();
+        unsafe{xous::disconnect(self.conn).ok();}
+    }
+}
@@ -0,0 +1,395 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod api;
+use api::*;
+mod store;
+use store::*;
+
+use locales::t;
+use num_traits::*;
+use rustls::{ClientConfig, ClientConnection, ServerName};
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use xous_ipc::Buffer;
+
+/// The addresses of `host` that the rule for `client` lets it reach. The connection is made by
+/// this service, so `net` checks it against the rule for `tls`; the client's is checked here.
+fn reachable(client: Option<xous::PID>, host: &str, port: u16) -> Result<Vec<SocketAddr>, TlsRetcode> {
+    let client = client.ok_or(TlsRetcode::AccessDenied)?;
+    let addrs = (host, port).to_socket_addrs().map_err(|e| {
+        log::info!("couldn't resolve {}: {:?}", host, e);
+        TlsRetcode::ConnectFailed
+    })?;
+    let netmgr = net::NetManager::new();
+    let allowed: Vec<SocketAddr> = addrs.filter(|addr| netmgr.policy_check(client, Some(*addr)).is_ok()).collect();
+    if allowed.is_empty() {
+        log::warn!("PID {} may not reach {}:{}", client, host, port);
+        return Err(TlsRetcode::AccessDenied);
+    }
+    Ok(allowed)
+}
+
+/// Opens a TCP connection to `host` at one of `addrs` and runs the handshake, checking the server's certificate
+/// against `pin` if there is one, and against `roots` otherwise. On failure, returns the
+/// fingerprint of the certificate presented, if it was the certificate that was refused.
+fn handshake(roots: rustls::RootCertStore, host: &str, addrs: &[SocketAddr], pin: Option<[u8; 32]>)
+-> Result<(ClientConnection, TcpStream, [u8; 32]), (TlsRetcode, Option<[u8; 32]>)> {
+    let server_name = ServerName::try_from(host).or(Err((TlsRetcode::HandshakeFailed, None)))?;
+    let verifier = Arc::new(TrustVerifier::new(roots, pin));
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+    let mut sock = TcpStream::connect(addrs).map_err(|e| {
+        log::info!("couldn't connect to {}: {:?}", host, e);
+        (TlsRetcode::ConnectFailed, None)
+    })?;
+    let mut conn = ClientConnection::new(Arc::new(config), server_name).or(Err((TlsRetcode::InternalError, None)))?;
+    while conn.is_handshaking() {
+        if let Err(e) = conn.complete_io(&mut sock) {
+            log::info!("handshake with {} failed: {:?}", host, e);
+            return match verifier.presented() {
+                Some((fingerprint, false)) => Err((TlsRetcode::Untrusted, Some(fingerprint))),
+                _ => Err((TlsRetcode::HandshakeFailed, None)),
+            };
+        }
+    }
+    let (fingerprint, _) = verifier.presented().expect("handshake completed without a certificate");
+    Ok((conn, sock, fingerprint))
+}
+
+enum Decision {
+    Reject,
+    AcceptOnce,
+    Pin,
+}
+
+/// Shows a certificate that couldn't be verified to the user, and asks what to do with it.
+fn review_certificate(modals: &modals::Modals, host: &str, fingerprint: &[u8; 32], pinned: bool) -> Decision {
+    let reason = if pinned {
+        t!("tls.pin_mismatch", xous::LANG)
+    } else {
+        t!("tls.untrusted", xous::LANG)
+    };
+    modals.show_notification(
+        &format!("{}\n\n{}\n\nSHA-256:\n{}", host, reason, hex(fingerprint)),
+        None
+    ).expect("couldn't show certificate");
+    modals.add_list_item(t!("tls.reject", xous::LANG)).expect("couldn't build radio item list");
+    modals.add_list_item(t!("tls.accept_once", xous::LANG)).expect("couldn't build radio item list");
+    modals.add_list_item(t!("tls.accept_pin", xous::LANG)).expect("couldn't build radio item list");
+    match modals.get_radiobutton(t!("tls.review_prompt", xous::LANG)) {
+        Ok(choice) if choice == t!("tls.accept_once", xous::LANG) => Decision::AcceptOnce,
+        Ok(choice) if choice == t!("tls.accept_pin", xous::LANG) => Decision::Pin,
+        _ => Decision::Reject,
+    }
+}
+
+/// Lets the user pick entries to remove from the trust store. The store isn't locked while the
+/// user is choosing, so connections can still be made.
+fn review_store(modals: &modals::Modals, store: &Mutex<TrustStore>) {
+    // modal list items are limited in length, so long names are shortened for display
+    let mut entries = Vec::<(TrustKind, String, String)>::new();
+    {
+        let mut store = store.lock().unwrap();
+        if !store.ensure_loaded() {
+            modals.show_notification(t!("tls.store_locked", xous::LANG), None).expect("couldn't show notification");
+            return;
+        }
+        for (name, _) in store.cas() {
+            entries.push((TrustKind::Ca, name.to_string(), format!("CA: {}", name)));
+        }
+        for (host, _) in store.pins() {
+            entries.push((TrustKind::Pin, host.to_string(), format!("Pin: {}", host)));
+        }
+    }
+    if entries.len() == 0 {
+        modals.show_notification(t!("tls.store_empty", xous::LANG), None).expect("couldn't show notification");
+        return;
+    }
+    for (_, _, label) in entries.iter_mut() {
+        if label.len() > 60 {
+            let mut end = 60;
+            while !label.is_char_boundary(end) {
+                end -= 1;
+            }
+            label.truncate(end);
+            label.push_str("...");
+        }
+    }
+    for (_, _, label) in entries.iter() {
+        modals.add_list_item(label).expect("couldn't build checkbox list");
+    }
+    modals.get_checkbox(t!("tls.review_remove", xous::LANG)).ok();
+    let selected = modals.get_check_index().unwrap_or_default();
+    let mut store = store.lock().unwrap();
+    for index in selected {
+        if let Some((kind, name, _)) = entries.get(index) {
+            let code = match kind {
+                TrustKind::Ca => store.remove_ca(name),
+                TrustKind::Pin => store.unpin(name),
+            };
+            log::info!("removed {:?} {} from the trust store: {:?}", kind, name, code);
+        }
+    }
+}
+
+/// What the user is shown to confirm a change to the trust store: the subject of the CA, if
+/// it has one, and the SHA-256 of the certificate.
+fn describe_edit(op: Opcode, name: &str, data: &[u8], store: &Mutex<TrustStore>)
+-> Result<(Option<String>, [u8; 32]), TlsRetcode> {
+    if name.len() == 0 {
+        return Err(TlsRetcode::NotFound);
+    }
+    match op {
+        Opcode::AddCa => {
+            if webpki::TrustAnchor::try_from_cert_der(data).is_err() {
+                return Err(TlsRetcode::InvalidCertificate);
+            }
+            Ok((subject_cn(data), fingerprint(data)))
+        }
+        Opcode::Pin => <[u8; 32]>::try_from(data).map(|pin| (None, pin)).or(Err(TlsRetcode::InvalidCertificate)),
+        _ => {
+            let mut store = store.lock().unwrap();
+            if !store.ensure_loaded() {
+                return Err(TlsRetcode::StoreError);
+            }
+            match op {
+                Opcode::RemoveCa => store.ca(name).map(|der| (subject_cn(der), fingerprint(der))),
+                _ => store.pin_for(name).map(|pin| (None, pin)),
+            }.ok_or(TlsRetcode::NotFound)
+        }
+    }
+}
+
+/// Asks the user to confirm a change to the trust store made by another process.
+fn confirm_edit(op: Opcode, name: &str, subject: Option<&str>, fingerprint: &[u8; 32]) -> bool {
+    let xns = xous_names::XousNames::new().unwrap();
+    let modals = modals::Modals::new(&xns).expect("can't connect to Modals server");
+    let question = match op {
+        Opcode::AddCa => t!("tls.confirm_add_ca", xous::LANG),
+        Opcode::RemoveCa => t!("tls.confirm_remove_ca", xous::LANG),
+        Opcode::Pin => t!("tls.confirm_pin", xous::LANG),
+        _ => t!("tls.confirm_unpin", xous::LANG),
+    };
+    let mut prompt = format!("{}\n\n{}", question, name);
+    if let Some(subject) = subject {
+        prompt.push_str(&format!("\n\n{} {}", t!("tls.subject", xous::LANG), subject));
+    }
+    prompt.push_str(&format!("\n\nSHA-256:\n{}", hex(fingerprint)));
+    modals.add_list_item(t!("tls.yes", xous::LANG)).expect("couldn't build radio item list");
+    modals.add_list_item(t!("tls.no", xous::LANG)).expect("couldn't build radio item list");
+    match modals.get_radiobutton(&prompt) {
+        Ok(choice) => choice == t!("tls.yes", xous::LANG),
+        _ => false,
+    }
+}
+
+/// Handles `AddCa`, `RemoveCa`, `Pin` and `Unpin`, on a thread of its own so the main loop isn't
+/// held up while the user decides. The change is only made if the user confirms it.
+fn trust_edit(mut msg: xous::MessageEnvelope, op: Opcode, store: Arc<Mutex<TrustStore>>) {
+    let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+    let mut edit = buffer.to_original::<TlsTrustEdit, _>().unwrap();
+    let name = edit.name.as_str().unwrap_or("").to_string();
+    let data = &edit.data[..(edit.len as usize).min(TLS_CERT_MAX_LEN)];
+    edit.code = match describe_edit(op, &name, data, &store) {
+        Ok((subject, fingerprint)) => {
+            if confirm_edit(op, &name, subject.as_deref(), &fingerprint) {
+                let mut store = store.lock().unwrap();
+                match op {
+                    Opcode::AddCa => store.add_ca(&name, data),
+                    Opcode::RemoveCa => store.remove_ca(&name),
+                    Opcode::Pin => store.pin(&name, &fingerprint),
+                    _ => store.unpin(&name),
+                }
+            } else {
+                TlsRetcode::UserRejected
+            }
+        }
+        Err(code) => code,
+    };
+    log::info!("{:?} {} (PID {:?}): {:?}", op, name, msg.sender.pid(), edit.code);
+    buffer.replace(edit).unwrap();
+}
+
+/// Handles `Connect` on a thread of its own: checks the client may reach the host, makes the TCP
+/// connection, runs the handshake, shows
+/// the certificate to the user if it can't be verified and the client asked for that, and replies
+/// once it's done. If a session was opened, the thread goes on to serve it.
+fn connect(mut msg: xous::MessageEnvelope, store: Arc<Mutex<TrustStore>>) {
+    let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+    let mut request = buffer.to_original::<TlsConnect, _>().unwrap();
+    let host = request.host.as_str().unwrap_or("").to_string();
+    let (roots, stored_pin) = {
+        let mut store = store.lock().unwrap();
+        store.ensure_loaded();
+        (store.roots(), store.pin_for(&host))
+    };
+    let addrs = match reachable(msg.sender.pid(), &host, request.port) {
+        Ok(addrs) => addrs,
+        Err(code) => {
+            request.code = code;
+            buffer.replace(request).unwrap();
+            return;
+        }
+    };
+    let mut pin = stored_pin;
+    let result = loop {
+        match handshake(roots.clone(), &host, &addrs, pin) {
+            Err((TlsRetcode::Untrusted, Some(presented))) if request.interactive => {
+                let xns = xous_names::XousNames::new().unwrap();
+                let modals = modals::Modals::new(&xns).expect("can't connect to Modals server");
+                match review_certificate(&modals, &host, &presented, stored_pin.is_some()) {
+                    Decision::Reject => break Err(TlsRetcode::UserRejected),
+                    Decision::AcceptOnce => pin = Some(presented),
+                    Decision::Pin => {
+                        let code = store.lock().unwrap().pin(&host, &presented);
+                        if code != TlsRetcode::Ok {
+                            log::warn!("couldn't pin certificate for {}: {:?}", host, code);
+                        }
+                        pin = Some(presented);
+                    }
+                }
+            }
+            Err((code, _)) => break Err(code),
+            Ok(session) => break Ok(session),
+        }
+    };
+    match result {
+        Ok((conn, sock, fingerprint)) => {
+            let session_sid = xous::create_server().unwrap();
+            log::info!("TLS session opened to {}:{}", host, request.port);
+            request.session = session_sid.to_array();
+            request.fingerprint = fingerprint;
+            request.code = TlsRetcode::Ok;
+            buffer.replace(request).unwrap();
+            // reply to the client before serving the session, so it can connect to it
+            drop(buffer);
+            drop(msg);
+            session_server(session_sid, conn, sock);
+        }
+        Err(code) => {
+            request.code = code;
+            buffer.replace(request).unwrap();
+        }
+    }
+}
+
+/// Serves the private server of one session, until the client closes it.
+fn session_server(sid: xous::SID, mut conn: ClientConnection, mut sock: TcpStream) {
+    loop {
+        let mut msg = xous::receive_message(sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(SessionOpcode::Read) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut chunk = buffer.to_original::<TlsChunk, _>().unwrap();
+                let len = (chunk.len as usize).min(TLS_DATA_LEN);
+                let mut stream = rustls::Stream::new(&mut conn, &mut sock);
+                match stream.read(&mut chunk.data[..len]) {
+                    Ok(read) => {
+                        chunk.len = read as u32;
+                        chunk.code = TlsRetcode::Ok;
+                    }
+                    Err(e) => {
+                        log::info!("session read failed: {:?}", e);
+                        chunk.len = 0;
+                        chunk.code = TlsRetcode::Closed;
+                    }
+                }
+                buffer.replace(chunk).unwrap();
+            }
+            Some(SessionOpcode::Write) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut chunk = buffer.to_original::<TlsChunk, _>().unwrap();
+                let len = (chunk.len as usize).min(TLS_DATA_LEN);
+                let mut stream = rustls::Stream::new(&mut conn, &mut sock);
+                chunk.code = match stream.write_all(&chunk.data[..len]).and_then(|_| stream.flush()) {
+                    Ok(_) => TlsRetcode::Ok,
+                    Err(e) => {
+                        log::info!("session write failed: {:?}", e);
+                        TlsRetcode::Closed
+                    }
+                };
+                buffer.replace(chunk).unwrap();
+            }
+            Some(SessionOpcode::Close) => {
+                conn.send_close_notify();
+                conn.complete_io(&mut sock).ok();
+                xous::return_scalar(msg.sender, 0).ok();
+                break;
+            }
+            None => {
+                log::error!("couldn't convert session opcode: {:?}", msg);
+            }
+        }
+    }
+    xous::destroy_server(sid).unwrap();
+}
+
+fn main() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    log::info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    let tls_sid = xns.register_name(api::SERVER_NAME_TLS, None).expect("can't register server");
+    log::trace!("registered with NS -- {:?}", tls_sid);
+
+    // the store is shared with the threads that serve connections and edits
+    let store = Arc::new(Mutex::new(TrustStore::new()));
+
+    log::trace!("ready to accept requests");
+    loop {
+        let mut msg = xous::receive_message(tls_sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::Connect) => {
+                let store = store.clone();
+                std::thread::spawn(move || connect(msg, store));
+            }
+            Some(Opcode::Review) => {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    let xns = xous_names::XousNames::new().unwrap();
+                    let modals = modals::Modals::new(&xns).expect("can't connect to Modals server");
+                    review_store(&modals, &store);
+                    xous::return_scalar(msg.sender, 0).ok();
+                });
+            }
+            Some(Opcode::ListTrust) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut list = buffer.to_original::<TlsTrustList, _>().unwrap();
+                let mut store = store.lock().unwrap();
+                if store.ensure_loaded() {
+                    let cas = store.cas().map(|(name, der)| (TrustKind::Ca, name.as_str(), fingerprint(der)));
+                    let pins = store.pins().map(|(host, pin)| (TrustKind::Pin, host.as_str(), *pin));
+                    list.pack(cas.chain(pins));
+                    list.code = TlsRetcode::Ok;
+                } else {
+                    list.code = TlsRetcode::StoreError;
+                }
+                buffer.replace(list).unwrap();
+            }
+            Some(Opcode::AddCa) | Some(Opcode::RemoveCa) | Some(Opcode::Pin) | Some(Opcode::Unpin) => {
+                let op = FromPrimitive::from_usize(msg.body.id()).unwrap();
+                let store = store.clone();
+                std::thread::spawn(move || trust_edit(msg, op, store));
+            }
+            Some(Opcode::Quit) => {
+                log::warn!("Quit received, goodbye world!");
+                break;
+            }
+            None => {
+                log::error!("couldn't convert opcode: {:?}", msg);
+            }
+        }
+    }
+    // clean up our program
+    log::trace!("main loop exit, destroying servers");
+    xns.unregister_server(tls_sid).unwrap();
+    xous::destroy_server(tls_sid).unwrap();
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
@@ -0,0 +1,302 @@
+//! # Trust store
+//!
+//! The built-in roots come from `webpki-roots`. On top of those, the user can add CAs of their
+//! own, and pin certificates to hosts; both are kept in the PDDB, in `TLS_TRUST_DICT` and
+//! `TLS_PIN_DICT`. A pin takes the place of chain validation for its host: the server must
+//! present exactly the pinned end-entity certificate, and no other, even one from a trusted CA.
+//!
+//! The store is read from the PDDB the first time a connection is made after it is mounted, and
+//! is kept in memory after that. Every change goes through this service, so the copy in memory
+//! is updated as the PDDB is written.
+
+use crate::api::*;
+
+use digest::Digest;
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, OwnedTrustAnchor, RootCertStore, ServerName};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+pub(crate) fn fingerprint(der: &[u8]) -> [u8; 32] {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(der);
+    let mut fingerprint = [0u8; 32];
+    fingerprint.copy_from_slice(hasher.finalize().as_slice());
+    fingerprint
+}
+
+pub(crate) fn hex(fingerprint: &[u8; 32]) -> String {
+    let mut s = String::with_capacity(64);
+    for b in fingerprint.iter() {
+        s.push_str(&format!("{:02x}", b));
+    }
+    s
+}
+
+/// The common name in the subject of a CA's certificate, for showing to the user
+pub(crate) fn subject_cn(der: &[u8]) -> Option<String> {
+    const CN_OID: [u8; 5] = [0x06, 0x03, 0x55, 0x04, 0x03];
+    let subject = webpki::TrustAnchor::try_from_cert_der(der).ok()?.subject;
+    let at = subject.windows(CN_OID.len()).position(|w| w == CN_OID)? + CN_OID.len();
+    // the value is a string of one of several types, short enough to have a one-byte length
+    let len = *subject.get(at + 1)? as usize;
+    if len >= 0x80 {
+        return None;
+    }
+    subject.get(at + 2..at + 2 + len).map(|cn| String::from_utf8_lossy(cn).to_string())
+}
+
+pub(crate) struct TrustStore {
+    pddb: pddb::Pddb,
+    /// None until the store has been read from the PDDB
+    loaded: Option<(BTreeMap<String, Vec<u8>>, BTreeMap<String, [u8; 32]>)>,
+}
+impl TrustStore {
+    pub(crate) fn new() -> Self {
+        TrustStore {
+            pddb: pddb::Pddb::new(),
+            loaded: None,
+        }
+    }
+    /// Reads the store in, if it hasn't been and the PDDB is mounted. Until then, only the
+    /// built-in roots are trusted.
+    pub(crate) fn ensure_loaded(&mut self) -> bool {
+        if self.loaded.is_some() {
+            return true;
+        }
+        if !pddb::PddbMountPoller::new().is_mounted_nonblocking() {
+            return false;
+        }
+        let mut cas = BTreeMap::new();
+        for name in self.pddb.list_keys(TLS_TRUST_DICT, None).unwrap_or_default() {
+            if let Ok(mut key) = self.pddb.get(TLS_TRUST_DICT, &name, None, false, false, None, None::<fn()>) {
+                let mut der = Vec::new();
+                if key.read_to_end(&mut der).is_ok() {
+                    cas.insert(name, der);
+                }
+            }
+        }
+        let mut pins = BTreeMap::new();
+        for host in self.pddb.list_keys(TLS_PIN_DICT, None).unwrap_or_default() {
+            if let Ok(mut key) = self.pddb.get(TLS_PIN_DICT, &host, None, false, false, None, None::<fn()>) {
+                let mut pin = [0u8; 32];
+                if key.read_exact(&mut pin).is_ok() {
+                    pins.insert(host, pin);
+                }
+            }
+        }
+        log::info!("loaded {} CAs and {} pins", cas.len(), pins.len());
+        self.loaded = Some((cas, pins));
+        true
+    }
+
+    pub(crate) fn cas(&self) -> impl Iterator<Item = (&String, &Vec<u8>)> {
+        self.loaded.iter().flat_map(|(cas, _)| cas.iter())
+    }
+    pub(crate) fn pins(&self) -> impl Iterator<Item = (&String, &[u8; 32])> {
+        self.loaded.iter().flat_map(|(_, pins)| pins.iter())
+    }
+    pub(crate) fn ca(&self, name: &str) -> Option<&Vec<u8>> {
+        self.loaded.as_ref()?.0.get(name)
+    }
+    pub(crate) fn pin_for(&self, host: &str) -> Option<[u8; 32]> {
+        self.loaded.as_ref()?.1.get(host).copied()
+    }
+
+    /// The built-in roots, plus the CAs added by the user
+    pub(crate) fn roots(&self) -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots.add_server_trust_anchors(
+            webpki_roots::TLS_SERVER_ROOTS
+                .0
+                .iter()
+                .map(|ta| {
+                    OwnedTrustAnchor::from_subject_spki_name_constraints(
+                        ta.subject,
+                        ta.spki,
+                        ta.name_constraints,
+                    )
+                })
+        );
+        for (name, der) in self.cas() {
+            match webpki::TrustAnchor::try_from_cert_der(der) {
+                Ok(ta) => roots.add_server_trust_anchors(core::iter::once(
+                    OwnedTrustAnchor::from_subject_spki_name_constraints(
+                        ta.subject,
+                        ta.spki,
+                        ta.name_constraints,
+                    )
+                )),
+                Err(e) => log::warn!("CA {} in the trust store can't be used: {:?}", name, e),
+            }
+        }
+        roots
+    }
+
+    pub(crate) fn add_ca(&mut self, name: &str, der: &[u8]) -> TlsRetcode {
+        if webpki::TrustAnchor::try_from_cert_der(der).is_err() {
+            return TlsRetcode::InvalidCertificate;
+        }
+        if !self.ensure_loaded() {
+            return TlsRetcode::StoreError;
+        }
+        if !self.write(TLS_TRUST_DICT, name, der) {
+            return TlsRetcode::StoreError;
+        }
+        self.loaded.as_mut().unwrap().0.insert(name.to_string(), der.to_vec());
+        TlsRetcode::Ok
+    }
+    pub(crate) fn remove_ca(&mut self, name: &str) -> TlsRetcode {
+        if !self.ensure_loaded() {
+            return TlsRetcode::StoreError;
+        }
+        if self.loaded.as_mut().unwrap().0.remove(name).is_none() {
+            return TlsRetcode::NotFound;
+        }
+        self.remove(TLS_TRUST_DICT, name)
+    }
+    pub(crate) fn pin(&mut self, host: &str, fingerprint: &[u8; 32]) -> TlsRetcode {
+        if !self.ensure_loaded() {
+            return TlsRetcode::StoreError;
+        }
+        if !self.write(TLS_PIN_DICT, host, fingerprint) {
+            return TlsRetcode::StoreError;
+        }
+        self.loaded.as_mut().unwrap().1.insert(host.to_string(), *fingerprint);
+        TlsRetcode::Ok
+    }
+    pub(crate) fn unpin(&mut self, host: &str) -> TlsRetcode {
+        if !self.ensure_loaded() {
+            return TlsRetcode::StoreError;
+        }
+        if self.loaded.as_mut().unwrap().1.remove(host).is_none() {
+            return TlsRetcode::NotFound;
+        }
+        self.remove(TLS_PIN_DICT, host)
+    }
+
+    fn write(&self, dict: &str, name: &str, data: &[u8]) -> bool {
+        // remove any previous record first, so a shorter one doesn't leave a tail behind
+        self.pddb.delete_key(dict, name, None).ok();
+        match self.pddb.get(dict, name, None, true, true, Some(data.len()), None::<fn()>) {
+            Ok(mut key) => {
+                if let Err(e) = key.write_all(data) {
+                    log::error!("couldn't write {}:{}: {:?}", dict, name, e);
+                    return false;
+                }
+                self.pddb.sync().ok();
+                true
+            }
+            Err(e) => {
+                log::error!("couldn't open {}:{}: {:?}", dict, name, e);
+                false
+            }
+        }
+    }
+    fn remove(&self, dict: &str, name: &str) -> TlsRetcode {
+        match self.pddb.delete_key(dict, name, None) {
+            Ok(_) => {
+                self.pddb.sync().ok();
+                TlsRetcode::Ok
+            }
+            Err(e) => {
+                log::error!("couldn't remove {}:{}: {:?}", dict, name, e);
+                TlsRetcode::StoreError
+            }
+        }
+    }
+}
+
+/// Checks the server's certificate against the pin for the host if there is one, and against
+/// the roots otherwise. The fingerprint of the certificate presented is kept, along with whether
+/// it passed, so that one that fails can be shown to the user.
+pub(crate) struct TrustVerifier {
+    webpki: WebPkiVerifier,
+    pin: Option<[u8; 32]>,
+    presented: Mutex<Option<([u8; 32], bool)>>,
+}
+impl TrustVerifier {
+    pub(crate) fn new(roots: RootCertStore, pin: Option<[u8; 32]>) -> Self {
+        TrustVerifier {
+            webpki: WebPkiVerifier::new(roots, None),
+            pin,
+            presented: Mutex::new(None),
+        }
+    }
+    pub(crate) fn presented(&self) -> Option<([u8; 32], bool)> {
+        *self.presented.lock().unwrap()
+    }
+}
+impl ServerCertVerifier for TrustVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let presented = fingerprint(&end_entity.0);
+        let result = match self.pin {
+            Some(pin) if pin == presented => Ok(ServerCertVerified::assertion()),
+            Some(_) => Err(rustls::Error::General("certificate does not match the pin for this host".into())),
+            None => self.webpki.verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now),
+        };
+        *self.presented.lock().unwrap() = Some((presented, result.is_ok()));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn verify(verifier: &TrustVerifier, der: &[u8]) -> Result<ServerCertVerified, rustls::Error> {
+        verifier.verify_server_cert(
+            &Certificate(der.to_vec()),
+            &[],
+            &ServerName::try_from("bunniefoo.com").unwrap(),
+            &mut core::iter::empty(),
+            &[],
+            SystemTime::now(),
+        )
+    }
+
+    #[test]
+    fn pin_match() {
+        // with a pin, the certificate isn't parsed at all, so any bytes will do
+        let der = b"not really a certificate";
+        let verifier = TrustVerifier::new(RootCertStore::empty(), Some(fingerprint(der)));
+        assert!(verifier.presented().is_none());
+        assert!(verify(&verifier, der).is_ok());
+        assert_eq!(verifier.presented(), Some((fingerprint(der), true)));
+    }
+
+    #[test]
+    fn pin_mismatch() {
+        let pinned = b"the pinned certificate";
+        let der = b"some other certificate";
+        let verifier = TrustVerifier::new(RootCertStore::empty(), Some(fingerprint(pinned)));
+        assert!(verify(&verifier, der).is_err());
+        assert_eq!(verifier.presented(), Some((fingerprint(der), false)));
+    }
+
+    #[test]
+    fn unpinned_untrusted() {
+        // without a pin, the certificate has to chain to a root; there are none here
+        let der = b"not really a certificate";
+        let verifier = TrustVerifier::new(RootCertStore::empty(), None);
+        assert!(verify(&verifier, der).is_err());
+        assert_eq!(verifier.presented(), Some((fingerprint(der), false)));
+    }
+
+    #[test]
+    fn fingerprint_hex() {
+        let fp = fingerprint(b"");
+        assert_eq!(hex(&fp), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+}
@@ -5,13 +5,18 @@ use xous_api_names::*;
 use xous_api_names::api::*;
 
 use num_traits::FromPrimitive;
-use xous::{msg_blocking_scalar_unpack, MessageEnvelope};
+use xous::{msg_blocking_scalar_unpack, msg_scalar_unpack, MessageEnvelope};
 use xous_ipc::{Buffer, String};
 
 use log::{error, info};
 
 use std::collections::HashMap;
 
+mod policy;
+mod policy_autogen;
+mod restart_autogen;
+mod supervisor;
+
 #[derive(PartialEq)]
 #[repr(C)]
 enum ConnectError {
@@ -26,6 +31,9 @@ enum ConnectError {
 
     /// The message was not a mutable memory message
     InvalidMessageType = 4,
+
+    /// The connection policy does not allow the sender to connect to this server
+    AccessDenied = 5,
 }
 
 #[derive(PartialEq)]
@@ -79,6 +87,9 @@ mod implementation {
     }
 }
 
+    /// Server names are only traced in hosted mode.
+    pub fn trace_registration(_name: &str, _sid: xous::SID, _pid: Option<xous::PID>) {}
+
 #[cfg(any(feature="hosted",
     not(any(feature="precursor", feature="renode", feature="hosted")) // default to pass crates.io build
 ))]
@@ -95,6 +106,28 @@ mod implementation {
             std::thread::sleep(std::time::Duration::from_millis(10));
         }
     }
+
+    /// Append a `server` record to the kernel's IPC trace, if there is one, so
+    /// that `tools/ipc_trace.py` can show the name of each registered server
+    /// rather than its SID. The kernel opens the trace in append mode, and
+    /// each record is written in one go, so lines don't interleave.
+    pub fn trace_registration(name: &str, sid: xous::SID, pid: Option<xous::PID>) {
+        use std::io::Write;
+        let path = match std::env::var("XOUS_TRACE_FILE") {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        let (a, b, c, d) = sid.to_u32();
+        let record = format!(
+            "{{\"event\":\"server\",\"pid\":{},\"server\":\"{:08x}-{:08x}-{:08x}-{:08x}\",\"name\":\"{}\"}}\n",
+            pid.map(|p| p.get() as usize).unwrap_or(0),
+            a, b, c, d,
+            name.replace('\\', "\\\\").replace('"', "\\\""),
+        );
+        if let Ok(mut file) = std::fs::OpenOptions::new().append(true).open(path) {
+            file.write_all(record.as_bytes()).ok();
+        }
+    }
 }
 
 /*
@@ -114,6 +147,7 @@ struct Connection {
     pub _allow_authenticate: bool,
     pub _auth_conns: u32,        // number of authenticated connections
     pub token: Option<[u32; 4]>, // a random number that must be presented to allow for disconnection
+    pub pid: Option<xous::PID>,  // the process that registered the server
 }
 #[derive(Debug)]
 struct CheckedHashMap {
@@ -130,6 +164,7 @@ impl CheckedHashMap {
         name: XousServerName,
         sid: xous::SID,
         max_conns: Option<u32>,
+        pid: Option<xous::PID>,
     ) -> Result<(), xous::Error> {
         let token =
             // for use with 1-connection servers, provision a one-time use token for disconnects
@@ -151,6 +186,7 @@ impl CheckedHashMap {
                 _allow_authenticate: false, // for now, we don't support authenticated connections
                 _auth_conns: 0,
                 token,
+                pid,
             },
         );
         Ok(())
@@ -173,6 +209,20 @@ impl CheckedHashMap {
         removed_name
     }
 
+    /// Remove every server registered by `pid`, returning their names.
+    pub fn remove_process(&mut self, pid: xous::PID) -> Vec<XousServerName> {
+        let names: Vec<XousServerName> = self
+            .map
+            .iter()
+            .filter(|(_, mapping)| mapping.pid == Some(pid))
+            .map(|(name, _)| *name)
+            .collect();
+        for name in names.iter() {
+            self.map.remove(name);
+        }
+        names
+    }
+
     pub fn contains_key(&self, name: &XousServerName) -> bool {
         self.map.contains_key(name)
     }
@@ -308,6 +358,12 @@ fn blocking_connect(
         sender_pid
     );
 
+    // Check the policy before touching the table, so that a denied request never
+    // consumes one of the server's limited connection slots.
+    if !policy::connection_permitted(&name, sender_pid) {
+        return Err(ConnectError::AccessDenied);
+    }
+
     // If the server already exists, attempt to make the connection. The connection can
     // only succeed if the
     if let (Some(server_sid), token) = name_table.connect(&name) {
@@ -375,6 +431,12 @@ fn main() -> ! {
     let name_server = xous::create_server_with_address(b"xous-name-server")
         .expect("Couldn't create xousnames-server");
 
+    // Ask the kernel to tell us when processes terminate, so that their servers can be
+    // removed from the table and they can be restarted according to `manifest.json`.
+    xous::set_supervisor(name_server, api::Opcode::ProcessTerminated as usize)
+        .expect("couldn't register as the process supervisor");
+    let mut supervisor = supervisor::Supervisor::new();
+
     let d11ctimeout = D11cTimeout::new();
 
     // When a connection is requested but the serevr does not yet exist, it gets
@@ -408,8 +470,13 @@ fn main() -> ! {
                 if !name_table.contains_key(&name) {
                     let new_sid =
                         xous::create_server_id().expect("create server failed, maybe OOM?");
+                    let sender_pid = msg.sender.pid();
+                    if let Some(pid) = sender_pid {
+                        supervisor.track(pid);
+                    }
+                    trace_registration(name.to_str(), new_sid, sender_pid);
                     name_table
-                        .insert(name, new_sid, registration.conn_limit)
+                        .insert(name, new_sid, registration.conn_limit, sender_pid)
                         .expect("register name failure, maybe out of HashMap capacity?");
                     log::trace!("request successful, SID is {:?}", new_sid);
                     should_connect = true;
@@ -495,12 +562,14 @@ fn main() -> ! {
                         .expect("couldn't convert server name to string"),
                 );
                 log::trace!("Lookup request for '{}'", name);
+                let sender_pid = msg
+                    .sender
+                    .pid()
+                    .expect("can't extract sender PID on Lookup");
                 let response: api::Return;
-                if let (Some(server_sid), token) = name_table.connect(&name) {
-                    let sender_pid = msg
-                        .sender
-                        .pid()
-                        .expect("can't extract sender PID on Lookup");
+                if !policy::connection_permitted(&name, sender_pid) {
+                    response = api::Return::AccessDenied
+                } else if let (Some(server_sid), token) = name_table.connect(&name) {
                     match xous::connect_for_process(sender_pid, server_sid)
                         .expect("can't broker connection")
                     {
@@ -567,6 +636,22 @@ fn main() -> ! {
                 };
                 buffer.replace(response).expect("Can't return buffer");
             }
+            Some(api::Opcode::ProcessTerminated) => msg_scalar_unpack!(msg, pid, code, faulted, _, {
+                // Only the kernel may report terminations
+                if msg.sender.pid().map(|p| p.get()) != Some(1) {
+                    log::error!("ignoring ProcessTerminated from {:?}", msg.sender.pid());
+                    continue;
+                }
+                let pid = match xous::PID::new(pid as u8) {
+                    Some(pid) => pid,
+                    None => continue,
+                };
+                for name in name_table.remove_process(pid) {
+                    info!("{} server removed, its process has terminated", name);
+                }
+                waiting_connections.retain(|env| env.sender.pid() != Some(pid));
+                supervisor.process_terminated(pid, code, faulted != 0);
+            }),
             None => {
                 error!("couldn't decode message: {:?}", msg);
                 break;
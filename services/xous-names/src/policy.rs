@@ -0,0 +1,39 @@
+use crate::policy_autogen::CONN_POLICY;
+use xous_api_names::api::XousServerName;
+
+/// Length of the longest process name that can match a policy entry. Longer names
+/// are truncated by the kernel query and will never match.
+const MAX_PROCESS_NAME: usize = 64;
+
+/// Checks whether process `pid` may be connected to the server registered as `name`.
+///
+/// The policy is generated by xtask from `policy.json`. Servers that are not listed
+/// there accept connections from anyone, subject to their `max_conns` limit. Servers
+/// that are listed only accept the named processes.
+///
+/// The hosted kernel only knows the names of the processes it launched, so the policy
+/// can't be applied there and every connection is permitted.
+pub(crate) fn connection_permitted(name: &XousServerName, pid: xous::PID) -> bool {
+    let clients = match CONN_POLICY.iter().find(|(server, _)| name.to_str() == *server) {
+        Some((_, clients)) => clients,
+        None => return true,
+    };
+    if cfg!(feature = "hosted") {
+        return true;
+    }
+    let mut process_name = [0u8; MAX_PROCESS_NAME];
+    let len = match xous::process_name(pid, &mut process_name) {
+        Ok(len) if len <= process_name.len() => len,
+        _ => {
+            log::warn!("denied connection to '{}' for PID {}: can't resolve process name", name, pid);
+            return false;
+        }
+    };
+    let process_name = core::str::from_utf8(&process_name[..len]).unwrap_or("");
+    if clients.iter().any(|client| *client == process_name) {
+        true
+    } else {
+        log::warn!("denied connection to '{}' for PID {} ({}): not in policy", name, pid, process_name);
+        false
+    }
+}
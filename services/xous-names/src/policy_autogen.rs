@@ -0,0 +1,17 @@
+// This file is auto-generated by xtask/main.rs generate_conn_policy()
+// Edit services/xous-names/policy.json instead.
+
+pub(crate) const CONN_POLICY: &[(&'static str, &[&'static str])] = &[
+    ("_JTAG Server_", &[
+        "jtag",
+        "root-keys",
+        "shellchat",
+    ]),
+    ("_SPINOR Hardware Interface Server_", &[
+        "spinor",
+        "pddb",
+        "root-keys",
+        "keyboard",
+        "shellchat",
+    ]),
+];
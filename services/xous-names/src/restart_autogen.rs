@@ -0,0 +1,9 @@
+// This file is auto-generated by xtask/main.rs generate_restart_policy()
+// Edit services/manifest.json instead.
+
+use crate::supervisor::RestartPolicy;
+
+pub(crate) const RESTART_POLICY: &[(&'static str, RestartPolicy, u32)] = &[
+    ("ime-plugin-shell", RestartPolicy::OnFailure, 500),
+    ("shellchat", RestartPolicy::OnFailure, 1000),
+];
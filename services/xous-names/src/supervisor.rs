@@ -0,0 +1,151 @@
+use crate::restart_autogen::RESTART_POLICY;
+use std::collections::HashMap;
+
+/// Length of the longest process name that can have a restart policy.
+const MAX_PROCESS_NAME: usize = 64;
+
+/// The longest a restart will be put off, as a multiple of the service's backoff.
+#[cfg(feature = "hosted")]
+const MAX_BACKOFF_SHIFT: u32 = 6;
+
+/// What to do when a service terminates. This is set per service in
+/// `services/manifest.json`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[allow(dead_code)]
+pub(crate) enum RestartPolicy {
+    /// Leave the service stopped
+    Never,
+
+    /// Restart the service if it faulted or exited with a nonzero code
+    OnFailure,
+
+    /// Restart the service however it stopped
+    Always,
+}
+
+/// Tracks the processes that register servers so they can be cleaned up, and
+/// possibly restarted, when the kernel reports that they have terminated.
+///
+/// Restarting is only implemented in hosted mode. On a device the restart policy
+/// is still evaluated, but a service that should be restarted is only reported.
+pub(crate) struct Supervisor {
+    /// Process names, captured while the process is still alive
+    names: HashMap<xous::PID, std::string::String>,
+
+    /// How many times each service has been restarted
+    #[cfg(feature = "hosted")]
+    restarts: HashMap<std::string::String, u32>,
+
+    /// Processes that have been restarted, reported once they are running
+    #[cfg(feature = "hosted")]
+    started: std::sync::mpsc::Receiver<(xous::PID, std::string::String)>,
+    #[cfg(feature = "hosted")]
+    started_sender: std::sync::mpsc::Sender<(xous::PID, std::string::String)>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        #[cfg(feature = "hosted")]
+        let (started_sender, started) = std::sync::mpsc::channel();
+        Supervisor {
+            names: HashMap::new(),
+            #[cfg(feature = "hosted")]
+            restarts: HashMap::new(),
+            #[cfg(feature = "hosted")]
+            started,
+            #[cfg(feature = "hosted")]
+            started_sender,
+        }
+    }
+
+    /// Remember the name of `pid`, if the kernel knows it, so that the process
+    /// can still be identified after it terminates.
+    pub fn track(&mut self, pid: xous::PID) {
+        self.collect_started();
+        if self.names.contains_key(&pid) {
+            return;
+        }
+        let mut name = [0u8; MAX_PROCESS_NAME];
+        if let Ok(len) = xous::process_name(pid, &mut name) {
+            if len > 0 && len <= name.len() {
+                if let Ok(name) = core::str::from_utf8(&name[..len]) {
+                    self.names.insert(pid, name.to_owned());
+                }
+            }
+        }
+    }
+
+    /// Apply the restart policy to a process that has terminated. `code` is the
+    /// exit code, or the exception type if the process `faulted`.
+    pub fn process_terminated(&mut self, pid: xous::PID, code: usize, faulted: bool) {
+        self.collect_started();
+        let name = match self.names.remove(&pid) {
+            Some(name) => name,
+            None => {
+                log::info!("PID {} terminated (code {}, faulted: {})", pid, code, faulted);
+                return;
+            }
+        };
+        log::warn!("{} (PID {}) terminated (code {}, faulted: {})", name, pid, code, faulted);
+
+        let (policy, backoff_ms) = RESTART_POLICY
+            .iter()
+            .find(|(service, _, _)| *service == name)
+            .map(|(_, policy, backoff_ms)| (*policy, *backoff_ms))
+            .unwrap_or((RestartPolicy::Never, 0));
+        let restart = match policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => faulted || code != 0,
+            RestartPolicy::Always => true,
+        };
+        if !restart {
+            return;
+        }
+
+        self.restart(name, backoff_ms);
+    }
+
+    /// Launch a fresh copy of the service once its backoff has elapsed. The
+    /// service sits next to this program, and is started from a separate thread
+    /// so that name lookups carry on in the meantime.
+    #[cfg(feature = "hosted")]
+    fn restart(&mut self, name: std::string::String, backoff_ms: u32) {
+        let count = self.restarts.entry(name.clone()).or_insert(0);
+        let delay_ms = backoff_ms << (*count).min(MAX_BACKOFF_SHIFT);
+        *count += 1;
+        log::info!("restarting {} in {} ms (restart #{})", name, delay_ms, count);
+        let started = self.started_sender.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(delay_ms as u64));
+            let program = match std::env::current_exe() {
+                Ok(exe) => exe.with_file_name(format!("{}{}", name, std::env::consts::EXE_SUFFIX)),
+                Err(e) => {
+                    log::error!("can't locate {} to restart it: {}", name, e);
+                    return;
+                }
+            };
+            let args = xous::ProcessArgs::new(&name, program.to_string_lossy().into_owned());
+            match xous::create_process(args) {
+                Ok(handle) => {
+                    started.send((handle.pid, name)).ok();
+                }
+                Err(e) => log::error!("couldn't restart {}: {:?}", name, e),
+            }
+        });
+    }
+
+    /// Processes in the boot image can't be loaded again once they have exited.
+    #[cfg(not(feature = "hosted"))]
+    fn restart(&mut self, name: std::string::String, _backoff_ms: u32) {
+        log::error!("can't restart {}: restarting services is only supported in hosted mode", name);
+    }
+
+    /// Record the names of processes that were restarted, since the kernel only
+    /// knows the names of the processes it started itself.
+    fn collect_started(&mut self) {
+        #[cfg(feature = "hosted")]
+        while let Ok((pid, name)) = self.started.try_recv() {
+            self.names.insert(pid, name);
+        }
+    }
+}
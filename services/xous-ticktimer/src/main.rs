@@ -15,14 +15,22 @@ type TimeoutExpiry = i64;
 pub enum RequestKind {
     Sleep = 0,
     Timeout = 1,
+    IpcTimeout = 2,
 }
 
+/// The number of times an IPC timeout is retried while its thread has not yet
+/// blocked. The delay doubles after each attempt, so this gives up after
+/// roughly half a second.
+const IPC_TIMEOUT_RETRIES: usize = 9;
+
 #[derive(Eq)]
 pub struct TimerRequest {
     msec: TimeoutExpiry,
     sender: xous::MessageSender,
     kind: RequestKind,
     data: usize,
+    /// How many times delivery of this request has already been retried
+    retries: usize,
 }
 
 impl core::fmt::Display for TimerRequest {
@@ -61,6 +69,26 @@ impl core::cmp::PartialEq for TimerRequest {
     }
 }
 
+/// Deliver the response for a request whose timer has fired. Returns `true` if the
+/// request could not be delivered yet and should be retried shortly.
+fn respond(request: &TimerRequest) -> bool {
+    match request.kind {
+        RequestKind::IpcTimeout => match request.sender.pid() {
+            // The thread has not blocked on its IPC call yet, so try again in a moment.
+            Some(pid) => matches!(
+                xous::expire_ipc_timeout(pid, request.data),
+                Err(xous::Error::ThreadNotAvailable)
+            ),
+            None => false,
+        },
+        _ => {
+            xous::return_scalar(request.sender, request.kind as usize)
+                .expect("couldn't send response");
+            false
+        }
+    }
+}
+
 #[cfg(any(feature="precursor", feature="renode"))]
 mod implementation {
     const TICKS_PER_MS: u64 = 1;
@@ -84,8 +112,7 @@ mod implementation {
         // Safe because we're in an interrupt, and this interrupt is only
         // enabled when this value is not None.
         let response = xtt.current_response.take().unwrap();
-        xous::return_scalar(response.sender, response.kind as usize)
-            .expect("couldn't send response");
+        let retry = crate::respond(&response);
 
         // Disable the timer
         xtt.csr.wfo(utra::ticktimer::EV_ENABLE_ALARM, 0);
@@ -101,7 +128,7 @@ mod implementation {
                 arg1: response.sender.to_usize(),
                 arg2: response.kind as usize,
                 arg3: response.data,
-                arg4: 0,
+                arg4: if retry { response.retries + 1 } else { 0 },
             }),
         )
         .ok();
@@ -287,8 +314,6 @@ mod implementation {
   not(any(feature="precursor", feature="renode", feature="hosted"))
 ))]
 mod implementation {
-    use crate::RequestKind;
-
     use super::TimerRequest;
     use num_traits::ToPrimitive;
     use std::convert::TryInto;
@@ -297,8 +322,7 @@ mod implementation {
     enum SleepComms {
         InterruptSleep,
         StartSleep(
-            xous::MessageSender,
-            i64, /* ms */
+            TimerRequest,
             u64, /* elapsed */
         ),
     }
@@ -327,8 +351,7 @@ mod implementation {
                             let response = current_response.take().unwrap();
                             #[cfg(feature = "debug-print")]
                             log::info!("Returning scalar to {}", response.sender);
-                            xous::return_scalar(response.sender, response.kind as usize)
-                                .expect("couldn't send response");
+                            let retry = crate::respond(&response);
 
                             // This is dangerous and may panic if the queue is full.
                             xous::try_send_message(
@@ -338,7 +361,7 @@ mod implementation {
                                     arg1: response.sender.to_usize(),
                                     arg2: response.kind as usize,
                                     arg3: response.data,
-                                    arg4: 0,
+                                    arg4: if retry { response.retries + 1 } else { 0 },
                                 }),
                             )
                             .unwrap();
@@ -351,33 +374,28 @@ mod implementation {
                             timeout = None;
                             time_remaining_sender.send(current_response.take()).unwrap()
                         }
-                        Ok(SleepComms::StartSleep(new_sender, expiry, elapsed)) => {
-                            let mut duration = expiry - (elapsed as i64);
+                        Ok(SleepComms::StartSleep(request, elapsed)) => {
+                            let mut duration = request.msec - (elapsed as i64);
                             if duration > 0 {
                                 #[cfg(feature = "debug-print")]
                                 log::info!(
                                     "Starting sleep for {} ms, returning to {}",
                                     duration,
-                                    new_sender
+                                    request.sender
                                 );
                             } else {
                                 #[cfg(feature = "debug-print")]
                                 log::info!(
                                     "Clamping duration to 0 (was: {})m returning to {}",
                                     duration,
-                                    new_sender
+                                    request.sender
                                 );
                                 duration = 0;
                             }
                             timeout = Some(std::time::Duration::from_millis(
                                 duration.try_into().unwrap(),
                             ));
-                            current_response = Some(TimerRequest {
-                                sender: new_sender,
-                                msec: expiry,
-                                kind: RequestKind::Sleep,
-                                data: 0,
-                            });
+                            current_response = Some(request);
                         }
                     }
                 }
@@ -413,11 +431,7 @@ mod implementation {
                 request.sender
             );
             self.sleep_comms
-                .send(SleepComms::StartSleep(
-                    request.sender,
-                    request.msec as i64,
-                    self.elapsed_ms(),
-                ))
+                .send(SleepComms::StartSleep(request, self.elapsed_ms()))
                 .unwrap();
         }
 
@@ -616,6 +630,7 @@ fn main() -> ! {
                         sender: msg.sender,
                         kind: RequestKind::Sleep,
                         data: 0,
+                        retries: 0,
                     }),
                 )
             }),
@@ -654,9 +669,59 @@ fn main() -> ! {
                         }
                         // log::trace!("new entries for PID {:?}/condvar {:08x}: {:?}", sender_pid, condvar, notify_hash.get(&sender_pid).unwrap().get(&condvar));
                     }
+
+                    // An IPC timeout fired before its thread had blocked. Try again shortly,
+                    // backing off each time, and give up if the thread never blocks.
+                    if (msg.sender.pid().map(|p| p.get()).unwrap_or_default() as u32) == xous::process::id()
+                        && (request_kind == RequestKind::IpcTimeout as usize)
+                        && (args.arg4 != 0)
+                    {
+                        let retries = args.arg4;
+                        if retries <= IPC_TIMEOUT_RETRIES {
+                            recalculate_sleep(
+                                &mut ticktimer,
+                                &mut sleep_heap,
+                                Some(TimerRequest {
+                                    msec: 1 << (retries - 1),
+                                    sender: xous::MessageSender::from_usize(sender),
+                                    kind: RequestKind::IpcTimeout,
+                                    data: condvar,
+                                    retries,
+                                }),
+                            );
+                            continue;
+                        }
+                        log::warn!(
+                            "dropping IPC timeout for {:?}: thread never blocked after {} retries",
+                            sender_pid, IPC_TIMEOUT_RETRIES
+                        );
+                    }
                 }
                 recalculate_sleep(&mut ticktimer, &mut sleep_heap, None);
             }
+            Some(api::Opcode::IpcTimeout) => xous::msg_scalar_unpack!(msg, token, ms, _, _, {
+                recalculate_sleep(
+                    &mut ticktimer,
+                    &mut sleep_heap,
+                    Some(TimerRequest {
+                        msec: ms as i64,
+                        sender: msg.sender,
+                        kind: RequestKind::IpcTimeout,
+                        data: token,
+                        retries: 0,
+                    }),
+                )
+            }),
+            Some(api::Opcode::CancelIpcTimeout) => xous::msg_scalar_unpack!(msg, token, _, _, _, {
+                // The call returned before the timeout fired, so drop the request, along with
+                // any retry of it, rather than keeping it until it expires.
+                let pid = msg.sender.pid();
+                stop_sleep(&mut ticktimer, &mut sleep_heap);
+                sleep_heap.retain(|_, v| {
+                    !(v.kind == RequestKind::IpcTimeout && v.data == token && v.sender.pid() == pid)
+                });
+                start_sleep(&mut ticktimer, &mut sleep_heap);
+            }),
             Some(api::Opcode::SuspendResume) => xous::msg_scalar_unpack!(msg, token, _, _, _, {
                 ticktimer.suspend();
                 susres
@@ -764,6 +829,7 @@ fn main() -> ! {
                                 sender: msg.sender,
                                 kind: RequestKind::Timeout,
                                 data: condvar,
+                                retries: 0,
                             }),
                         )
                     }
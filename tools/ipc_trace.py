@@ -0,0 +1,120 @@
+#! /usr/bin/env python3
+import argparse
+import json
+import sys
+
+# Renders the IPC trace written by the hosted kernel when XOUS_TRACE_FILE is set.
+# Each line of the trace is a JSON object; see kernel/src/arch/hosted/trace.rs.
+
+def load_trace(filename):
+    names = {}
+    servers = {}
+    messages = []
+    with open(filename, "r") as f:
+        for lineno, line in enumerate(f, 1):
+            line = line.strip()
+            if len(line) == 0:
+                continue
+            try:
+                record = json.loads(line)
+            except json.JSONDecodeError:
+                # the kernel may have been killed in the middle of writing a line
+                print("skipping malformed line {}".format(lineno), file=sys.stderr)
+                continue
+            if record["event"] == "process":
+                names[record["pid"]] = record["name"]
+            elif record["event"] == "server":
+                # written by xous-names as each name is registered
+                servers[record["server"]] = record["name"]
+            elif record["event"] == "message":
+                messages.append(record)
+    for m in messages:
+        m["server"] = servers.get(m["server"], m["server"])
+    messages.sort(key=lambda m: m["t_us"])
+    return names, messages
+
+def process_name(names, pid):
+    if pid in names:
+        return "{}({})".format(names[pid], pid)
+    return "pid{}".format(pid)
+
+def filter_messages(names, messages, process):
+    if process is None:
+        return messages
+    def matches(pid):
+        return str(pid) == process or names.get(pid) == process
+    return [m for m in messages if matches(m["pid"]) or matches(m["server_pid"])]
+
+def print_summary(names, messages):
+    # aggregate by (client, server, opcode)
+    stats = {}
+    for m in messages:
+        key = (m["pid"], m["server_pid"], m["server"], m["opcode"], m["kind"])
+        entry = stats.setdefault(key, {"count": 0, "bytes": 0, "latencies": [], "errors": 0})
+        entry["count"] += 1
+        entry["bytes"] += m["size"]
+        if m["latency_us"] is not None:
+            entry["latencies"].append(m["latency_us"])
+        if m.get("error") is not None:
+            entry["errors"] += 1
+
+    print("{:<24} {:<24} {:<20} {:>6} {:<16} {:>7} {:>9} {:>9} {:>9} {:>5}".format(
+        "client", "server", "server id", "opcode", "kind", "count", "bytes", "avg us", "max us", "err"))
+    for key in sorted(stats, key=lambda k: -stats[k]["count"]):
+        (pid, server_pid, server, opcode, kind) = key
+        entry = stats[key]
+        lat = entry["latencies"]
+        avg = "{:.0f}".format(sum(lat) / len(lat)) if len(lat) > 0 else "-"
+        worst = "{}".format(max(lat)) if len(lat) > 0 else "-"
+        print("{:<24} {:<24} {:<20} {:>6} {:<16} {:>7} {:>9} {:>9} {:>9} {:>5}".format(
+            process_name(names, pid), process_name(names, server_pid), server[:20],
+            opcode, kind, entry["count"], entry["bytes"], avg, worst, entry["errors"]))
+
+def print_sequence(names, messages):
+    # emits a mermaid sequence diagram, which renders directly in GitHub markdown
+    print("sequenceDiagram")
+    participants = []
+    for m in messages:
+        for pid in (m["pid"], m["server_pid"]):
+            if pid not in participants:
+                participants.append(pid)
+    for pid in participants:
+        print("    participant p{} as {}".format(pid, process_name(names, pid)))
+    for m in messages:
+        label = "{} {} op {}".format(m["server"], m["kind"], m["opcode"])
+        if m["size"] > 0:
+            label += " ({} bytes)".format(m["size"])
+        if m["latency_us"] is None:
+            print("    p{}-)p{}: {}".format(m["pid"], m["server_pid"], label))
+        else:
+            print("    p{}->>p{}: {}".format(m["pid"], m["server_pid"], label))
+            reply = "{} us".format(m["latency_us"])
+            if m.get("error") is not None:
+                reply += " {}".format(m["error"])
+            print("    p{}-->>p{}: {}".format(m["server_pid"], m["pid"], reply))
+
+def main():
+    parser = argparse.ArgumentParser(description="Render hosted-mode IPC traces")
+    parser.add_argument(
+        "file", help="trace file written by the kernel via XOUS_TRACE_FILE", type=str
+    )
+    parser.add_argument(
+        "--sequence", help="emit a mermaid sequence diagram instead of a summary table", action="store_true"
+    )
+    parser.add_argument(
+        "--process", help="only show messages to or from this process (PID or name)", type=str
+    )
+    parser.add_argument(
+        "--limit", help="maximum number of messages in a sequence diagram", type=int, default=200
+    )
+    args = parser.parse_args()
+
+    names, messages = load_trace(args.file)
+    messages = filter_messages(names, messages, args.process)
+    if args.sequence:
+        print_sequence(names, messages[:args.limit])
+    else:
+        print_summary(names, messages)
+
+if __name__ == "__main__":
+    main()
@@ -37,11 +37,15 @@ impl From<&str> for ProcessKey {
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ProcessInit {
     pub key: ProcessKey,
+    pub priority: crate::ThreadPriority,
+    pub memory_quota: usize,
 }
 
 pub struct ProcessArgs {
     command: String,
     name: String,
+    priority: crate::ThreadPriority,
+    memory_quota: usize,
 }
 
 impl ProcessArgs {
@@ -49,8 +53,24 @@ impl ProcessArgs {
         ProcessArgs {
             command,
             name: name.to_owned(),
+            priority: crate::ThreadPriority::Normal,
+            memory_quota: usize::MAX,
         }
     }
+
+    /// The priority of the new process, which its threads will inherit. It is
+    /// lowered to the priority of the process that creates it, if that is lower.
+    pub fn priority(mut self, priority: crate::ThreadPriority) -> ProcessArgs {
+        self.priority = priority;
+        self
+    }
+
+    /// The number of pages of RAM the new process may own. It is lowered to the
+    /// number of pages the process that creates it has left, if that is fewer.
+    pub fn memory_quota(mut self, pages: usize) -> ProcessArgs {
+        self.memory_quota = pages;
+        self
+    }
 }
 
 impl Into<[usize; 7]> for &ProcessInit {
@@ -60,8 +80,9 @@ impl Into<[usize; 7]> for &ProcessInit {
             u32::from_le_bytes(self.key.0[4..8].try_into().unwrap()) as _,
             u32::from_le_bytes(self.key.0[8..12].try_into().unwrap()) as _,
             u32::from_le_bytes(self.key.0[12..16].try_into().unwrap()) as _,
-            0,
-            0,
+            self.priority.to_init_arg(),
+            // offset by one, so that a zeroed slot means there's no quota
+            self.memory_quota.wrapping_add(1),
             0,
         ]
     }
@@ -78,6 +99,9 @@ impl TryFrom<[usize; 7]> for ProcessInit {
         key.copy_from_slice(&exploded);
         Ok(ProcessInit {
             key: ProcessKey(key),
+            priority: crate::ThreadPriority::from_init_arg(src[4])
+                .ok_or(crate::Error::InvalidSyscall)?,
+            memory_quota: src[5].wrapping_sub(1),
         })
     }
 }
@@ -127,12 +151,33 @@ impl Into<[usize; 7]> for &ProcessStartup {
 }
 
 #[derive(Debug)]
-pub struct ProcessHandle(std::process::Child);
-
-/// If no connection exists, create a new connection to the server. This means
-/// our parent PID will be PID1. Otherwise, reuse the same connection.
-pub fn create_process_pre(_args: &ProcessArgs) -> core::result::Result<ProcessInit, crate::Error> {
-    unimplemented!()
+pub struct ProcessHandle {
+    pub pid: crate::PID,
+    child: std::process::Child,
+}
+
+/// Pick a fresh key that the kernel will use to recognise the new process when
+/// it connects. Processes that were not launched by the kernel itself point
+/// their children at the kernel they are connected to.
+pub fn create_process_pre(args: &ProcessArgs) -> core::result::Result<ProcessInit, crate::Error> {
+    {
+        let mut address = CHILD_PROCESS_ADDRESS.lock().unwrap();
+        if address.port() == 0 {
+            *address = *super::NETWORK_CONNECT_ADDRESS;
+        }
+    }
+    let mut key = [0u8; 16];
+    for (bytes, word) in key
+        .chunks_exact_mut(4)
+        .zip(crate::create_server_id()?.to_array().iter())
+    {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    Ok(ProcessInit {
+        key: ProcessKey::new(key),
+        priority: args.priority,
+        memory_quota: args.memory_quota,
+    })
 }
 
 /// Launch a new process with the current PID as the parent.
@@ -162,7 +207,10 @@ pub fn create_process_post(
         .env("XOUS_PROCESS_NAME", process_name_env)
         .env("XOUS_PROCESS_KEY", process_key_env)
         .spawn()
-        .map(ProcessHandle)
+        .map(|child| ProcessHandle {
+            pid: startup.pid,
+            child,
+        })
         .map_err(|_| {
             // eprintln!("couldn't start command: {}", e);
             crate::Error::InternalError
@@ -171,7 +219,7 @@ pub fn create_process_post(
 
 pub fn wait_process(mut joiner: ProcessHandle) -> crate::SysCallResult {
     joiner
-        .0
+        .child
         .wait()
         .or(Err(crate::Error::InternalError))
         .and_then(|e| {
@@ -9,6 +9,8 @@ pub struct ProcessArgs<'a> {
     stack: crate::MemoryRange,
     load_address: crate::MemoryAddress,
     entrypoint: crate::MemoryAddress,
+    priority: crate::ThreadPriority,
+    memory_quota: usize,
 }
 
 impl<'a> ProcessArgs<'a> {
@@ -22,6 +24,8 @@ impl<'a> ProcessArgs<'a> {
             entrypoint,
             stub,
             stack: unsafe { crate::MemoryRange::new(0x8000_0000 - 131072, 131072).unwrap() },
+            priority: crate::ThreadPriority::Normal,
+            memory_quota: usize::MAX,
         }
     }
 
@@ -34,6 +38,20 @@ impl<'a> ProcessArgs<'a> {
         self.stack.size = length;
         self
     }
+
+    /// The priority of the new process, which its threads will inherit. It is
+    /// lowered to the priority of the process that creates it, if that is lower.
+    pub fn priority(mut self, priority: crate::ThreadPriority) -> ProcessArgs<'a> {
+        self.priority = priority;
+        self
+    }
+
+    /// The number of pages of RAM the new process may own. It is lowered to the
+    /// number of pages the process that creates it has left, if that is fewer.
+    pub fn memory_quota(mut self, pages: usize) -> ProcessArgs<'a> {
+        self.memory_quota = pages;
+        self
+    }
 }
 
 /// ProcessInit describes the values that are passed to the
@@ -49,7 +67,10 @@ pub struct ProcessInit {
     pub text_destination: crate::MemoryAddress,
     // 5 -- Entrypoint
     pub start: crate::MemoryAddress,
-    // 6
+    // 6 -- Priority in the low byte, RAM page quota in the rest. Both are
+    // encoded so that a zeroed slot means a `Normal` process with no quota.
+    pub priority: crate::ThreadPriority,
+    pub memory_quota: usize,
 }
 
 impl Into<[usize; 7]> for &ProcessInit {
@@ -61,7 +82,7 @@ impl Into<[usize; 7]> for &ProcessInit {
             self.text.size.get(),
             self.text_destination.get(),
             self.start.get(),
-            0,
+            self.priority.to_init_arg() | (quota_to_arg(self.memory_quota) << 8),
         ]
     }
 }
@@ -78,10 +99,28 @@ impl TryFrom<[usize; 7]> for ProcessInit {
             },
             text_destination: crate::MemoryAddress::new(src[4]).ok_or(crate::Error::OutOfMemory)?,
             start: crate::MemoryAddress::new(src[5]).ok_or(crate::Error::OutOfMemory)?,
+            priority: crate::ThreadPriority::from_init_arg(src[6] & 0xff)
+                .ok_or(crate::Error::InvalidSyscall)?,
+            memory_quota: quota_from_arg(src[6] >> 8),
         })
     }
 }
 
+/// Encode a RAM page quota into the 24 bits it has in a `ProcessInit`. The
+/// quota is offset by one so that 0 means there's no quota, and any quota too
+/// big to fit is treated as no quota.
+fn quota_to_arg(quota: usize) -> usize {
+    if quota >= (usize::MAX >> 8) {
+        0
+    } else {
+        quota + 1
+    }
+}
+
+fn quota_from_arg(arg: usize) -> usize {
+    arg.wrapping_sub(1)
+}
+
 /// When a new process is created, this platform-specific structure is returned.
 #[derive(Debug, PartialEq)]
 pub struct ProcessStartup {
@@ -162,6 +201,9 @@ pub fn create_process_pre(args: &ProcessArgs) -> core::result::Result<ProcessIni
         text_destination: args.load_address,
         // 5 -- Entrypoint
         start: args.entrypoint,
+        // 6 -- Priority and RAM page quota
+        priority: args.priority,
+        memory_quota: args.memory_quota,
     })
 }
 
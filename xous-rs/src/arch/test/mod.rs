@@ -46,11 +46,15 @@ pub struct ThreadInit {}
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ProcessInit {
     pub key: ProcessKey,
+    pub priority: crate::ThreadPriority,
+    pub memory_quota: usize,
 }
 
 pub struct ProcessArgsAsThread<F: FnOnce()> {
     main: F,
     name: String,
+    priority: crate::ThreadPriority,
+    memory_quota: usize,
 }
 
 impl<F> ProcessArgsAsThread<F>
@@ -61,15 +65,31 @@ where
         ProcessArgsAsThread {
             main,
             name: name.to_owned(),
+            priority: crate::ThreadPriority::Normal,
+            memory_quota: usize::MAX,
         }
     }
+
+    /// The priority of the new process, which its threads will inherit. It is
+    /// lowered to the priority of the process that creates it, if that is lower.
+    pub fn priority(mut self, priority: crate::ThreadPriority) -> ProcessArgsAsThread<F> {
+        self.priority = priority;
+        self
+    }
+
+    /// The number of pages of RAM the new process may own. It is lowered to the
+    /// number of pages the process that creates it has left, if that is fewer.
+    pub fn memory_quota(mut self, pages: usize) -> ProcessArgsAsThread<F> {
+        self.memory_quota = pages;
+        self
+    }
 }
 pub struct ProcessHandleAsThread(std::thread::JoinHandle<()>);
 
 /// If no connection exists, create a new connection to the server. This means
 /// our parent PID will be PID1. Otherwise, reuse the same connection.
 pub fn create_process_pre_as_thread<F>(
-    _args: &ProcessArgsAsThread<F>,
+    args: &ProcessArgsAsThread<F>,
 ) -> core::result::Result<ProcessInit, crate::Error>
 where
     F: FnOnce(),
@@ -83,6 +103,8 @@ where
         key: PROCESS_KEY
             .with(|pk| *pk.borrow())
             .unwrap_or_else(default_process_key),
+        priority: args.priority,
+        memory_quota: args.memory_quota,
     })
 }
 
@@ -135,6 +157,8 @@ pub fn wait_process_as_thread(joiner: ProcessHandleAsThread) -> crate::SysCallRe
 pub struct ProcessArgs {
     command: String,
     name: String,
+    priority: crate::ThreadPriority,
+    memory_quota: usize,
 }
 
 impl ProcessArgs {
@@ -142,8 +166,24 @@ impl ProcessArgs {
         ProcessArgs {
             command,
             name: name.to_owned(),
+            priority: crate::ThreadPriority::Normal,
+            memory_quota: usize::MAX,
         }
     }
+
+    /// The priority of the new process, which its threads will inherit. It is
+    /// lowered to the priority of the process that creates it, if that is lower.
+    pub fn priority(mut self, priority: crate::ThreadPriority) -> ProcessArgs {
+        self.priority = priority;
+        self
+    }
+
+    /// The number of pages of RAM the new process may own. It is lowered to the
+    /// number of pages the process that creates it has left, if that is fewer.
+    pub fn memory_quota(mut self, pages: usize) -> ProcessArgs {
+        self.memory_quota = pages;
+        self
+    }
 }
 
 /// This is returned when a process is created
@@ -197,8 +237,9 @@ impl Into<[usize; 7]> for &ProcessInit {
             u32::from_le_bytes(self.key.0[4..8].try_into().unwrap()) as _,
             u32::from_le_bytes(self.key.0[8..12].try_into().unwrap()) as _,
             u32::from_le_bytes(self.key.0[12..16].try_into().unwrap()) as _,
-            0,
-            0,
+            self.priority.to_init_arg(),
+            // offset by one, so that a zeroed slot means there's no quota
+            self.memory_quota.wrapping_add(1),
             0,
         ]
     }
@@ -215,6 +256,9 @@ impl TryFrom<[usize; 7]> for ProcessInit {
         key.copy_from_slice(&exploded);
         Ok(ProcessInit {
             key: ProcessKey(key),
+            priority: crate::ThreadPriority::from_init_arg(src[4])
+                .ok_or(crate::Error::InvalidSyscall)?,
+            memory_quota: src[5].wrapping_sub(1),
         })
     }
 }
@@ -224,7 +268,7 @@ pub struct ProcessHandle(std::process::Child);
 
 /// If no connection exists, create a new connection to the server. This means
 /// our parent PID will be PID1. Otherwise, reuse the same connection.
-pub fn create_process_pre(_args: &ProcessArgs) -> core::result::Result<ProcessInit, crate::Error> {
+pub fn create_process_pre(args: &ProcessArgs) -> core::result::Result<ProcessInit, crate::Error> {
     ensure_connection()?;
 
     // Ensure there is a connection, because after this function returns
@@ -234,6 +278,8 @@ pub fn create_process_pre(_args: &ProcessArgs) -> core::result::Result<ProcessIn
         key: PROCESS_KEY
             .with(|pk| *pk.borrow())
             .unwrap_or_else(default_process_key),
+        priority: args.priority,
+        memory_quota: args.memory_quota,
     })
 }
 
@@ -304,8 +350,8 @@ pub fn process_to_args(call: usize, init: &ProcessInit) -> [usize; 8] {
         u32::from_le_bytes(init.key.0[4..8].try_into().unwrap()) as _,
         u32::from_le_bytes(init.key.0[8..12].try_into().unwrap()) as _,
         u32::from_le_bytes(init.key.0[12..16].try_into().unwrap()) as _,
-        0,
-        0,
+        init.priority.to_init_arg(),
+        init.memory_quota.wrapping_add(1),
         0,
     ]
 }
@@ -327,8 +373,8 @@ pub fn args_to_process(
     a2: usize,
     a3: usize,
     a4: usize,
-    _a5: usize,
-    _a6: usize,
+    a5: usize,
+    a6: usize,
     _a7: usize,
 ) -> core::result::Result<ProcessInit, crate::Error> {
     let mut v = vec![];
@@ -340,6 +386,8 @@ pub fn args_to_process(
     key.copy_from_slice(&v);
     Ok(ProcessInit {
         key: ProcessKey(key),
+        priority: crate::ThreadPriority::from_init_arg(a5).ok_or(crate::Error::InvalidSyscall)?,
+        memory_quota: a6.wrapping_sub(1),
     })
 }
 
@@ -83,6 +83,12 @@ pub use messages::*;
 pub mod limits;
 pub use limits::*;
 
+pub mod priority;
+pub use priority::*;
+
+pub mod process_info;
+pub use process_info::*;
+
 use crate::arch::ProcessStartup;
 
 /// Server ID
@@ -464,6 +470,12 @@ pub enum Result {
     /// the caller.
     NewProcess(ProcessStartup),
 
+    /// 20: A scalar with five values
+    Scalar5(usize, usize, usize, usize, usize),
+
+    /// 22: A snapshot of one entry in the process table
+    ProcessInfo(ProcessInfo),
+
     UnknownResult(usize, usize, usize, usize, usize, usize, usize),
 }
 
@@ -527,6 +539,8 @@ impl Result {
                 0,
             ],
             Result::NewProcess(p) => Self::add_opcode(19, p.into()),
+            Result::Scalar5(a, b, c, d, e) => [20, *a, *b, *c, *d, *e, 0, 0],
+            Result::ProcessInfo(info) => Self::add_opcode(22, info.to_usize()),
             Result::UnknownResult(arg1, arg2, arg3, arg4, arg5, arg6, arg7) => {
                 [usize::MAX, *arg1, *arg2, *arg3, *arg4, *arg5, *arg6, *arg7]
             }
@@ -604,6 +618,13 @@ impl Result {
             17 => Result::None,
             18 => Result::MemoryReturned(MemorySize::new(src[1]), MemorySize::new(src[2])),
             19 => Result::NewProcess(src.into()),
+            20 => Result::Scalar5(src[1], src[2], src[3], src[4], src[5]),
+            22 => match ProcessInfo::from_usize([
+                src[1], src[2], src[3], src[4], src[5], src[6], src[7],
+            ]) {
+                Ok(info) => Result::ProcessInfo(info),
+                Err(e) => Result::Error(e),
+            },
             _ => Result::UnknownResult(src[0], src[1], src[2], src[3], src[4], src[5], src[6]),
         }
     }
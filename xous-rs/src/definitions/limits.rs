@@ -1,5 +1,10 @@
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(usize)]
 pub enum Limits {
     HeapMaximum = 1,
     HeapSize = 2,
-}
\ No newline at end of file
+    /// The maximum number of pages of RAM that a process may own
+    MemoryQuota = 3,
+    /// The number of pages of RAM a process currently owns. This is read-only.
+    MemoryUsage = 4,
+}
@@ -0,0 +1,55 @@
+/// Scheduling priority of a thread. The kernel always runs the
+/// highest-priority context that is ready, and round-robins between
+/// contexts that share the same priority.
+///
+/// Priorities are strict and never age, so a context that spins without
+/// blocking will starve everything below it. Raised priorities are meant for
+/// services that spend most of their time waiting for messages.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[repr(usize)]
+pub enum ThreadPriority {
+    /// Only runs when nothing else in the system is ready
+    Idle = 0,
+    /// Background work such as scrubbing or indexing
+    Low = 1,
+    /// The default priority for all processes and threads
+    #[default]
+    Normal = 2,
+    /// Latency-sensitive services such as input and display
+    High = 3,
+    /// Reserved for threads that must never be starved
+    Realtime = 4,
+}
+
+impl ThreadPriority {
+    pub fn from_usize(arg: usize) -> Option<Self> {
+        use ThreadPriority::*;
+        match arg {
+            0 => Some(Idle),
+            1 => Some(Low),
+            2 => Some(Normal),
+            3 => Some(High),
+            4 => Some(Realtime),
+            _ => None,
+        }
+    }
+
+    pub fn to_usize(&self) -> usize {
+        *self as usize
+    }
+
+    /// Decode the priority slot of a `ProcessInit`. The slot is offset by one
+    /// so that a zeroed slot, as sent by callers that don't set a priority,
+    /// means `Normal` rather than `Idle`.
+    pub fn from_init_arg(arg: usize) -> Option<Self> {
+        match arg {
+            0 => Some(ThreadPriority::Normal),
+            arg => Self::from_usize(arg - 1),
+        }
+    }
+
+    /// Encode this priority for the priority slot of a `ProcessInit`
+    pub fn to_init_arg(&self) -> usize {
+        *self as usize + 1
+    }
+}
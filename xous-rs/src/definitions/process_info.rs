@@ -0,0 +1,110 @@
+use crate::{pid_from_usize, Error, PID};
+
+/// The scheduling state of a process, as reported by `GetProcessInfo`.
+/// Thread bitmasks have bit `n` set if thread `n` is in the given state.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ProcessStatus {
+    /// The process has been allocated, but has no threads yet
+    Allocated,
+
+    /// The process has been created but has not yet run
+    Setup,
+
+    /// The process has threads that are ready to run
+    Ready(usize /* thread bitmask */),
+
+    /// The process is currently running. The bitmask describes threads that
+    /// are ready, excluding the currently-executing thread.
+    Running(usize /* thread bitmask */),
+
+    /// Every thread in the process is waiting for an event
+    Sleeping,
+
+    /// The process is stopped in the debugger
+    Debug(usize /* thread bitmask */),
+
+    /// The process is handling an exception
+    Exception(usize /* thread bitmask */),
+
+    /// The process is handling an exception and is waiting on a Server
+    BlockedException(usize /* thread bitmask */),
+}
+
+impl ProcessStatus {
+    pub fn to_usize(&self) -> (usize, usize) {
+        match *self {
+            ProcessStatus::Allocated => (1, 0),
+            ProcessStatus::Setup => (2, 0),
+            ProcessStatus::Ready(x) => (3, x),
+            ProcessStatus::Running(x) => (4, x),
+            ProcessStatus::Sleeping => (5, 0),
+            ProcessStatus::Debug(x) => (6, x),
+            ProcessStatus::Exception(x) => (7, x),
+            ProcessStatus::BlockedException(x) => (8, x),
+        }
+    }
+
+    pub fn from_usize(kind: usize, threads: usize) -> Option<Self> {
+        Some(match kind {
+            1 => ProcessStatus::Allocated,
+            2 => ProcessStatus::Setup,
+            3 => ProcessStatus::Ready(threads),
+            4 => ProcessStatus::Running(threads),
+            5 => ProcessStatus::Sleeping,
+            6 => ProcessStatus::Debug(threads),
+            7 => ProcessStatus::Exception(threads),
+            8 => ProcessStatus::BlockedException(threads),
+            _ => return None,
+        })
+    }
+}
+
+/// A snapshot of a single entry in the kernel's process table.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ProcessInfo {
+    /// The ID of this process
+    pub pid: PID,
+
+    /// The ID of the process that created this process
+    pub ppid: PID,
+
+    /// What the process is doing right now
+    pub status: ProcessStatus,
+
+    /// The number of threads that have been created and not yet exited
+    pub threads: usize,
+
+    /// The number of Servers owned by this process
+    pub servers: usize,
+
+    /// The number of bytes of RAM owned by this process, including its
+    /// heap, its stacks, and any memory it has been lent. This is `0`
+    /// in hosted mode, where the kernel does not manage memory.
+    pub ram_bytes: usize,
+}
+
+impl ProcessInfo {
+    pub fn to_usize(&self) -> [usize; 7] {
+        let (status, thread_mask) = self.status.to_usize();
+        [
+            self.pid.get() as usize,
+            self.ppid.get() as usize,
+            status,
+            thread_mask,
+            self.threads,
+            self.servers,
+            self.ram_bytes,
+        ]
+    }
+
+    pub fn from_usize(src: [usize; 7]) -> core::result::Result<Self, Error> {
+        Ok(ProcessInfo {
+            pid: pid_from_usize(src[0])?,
+            ppid: pid_from_usize(src[1])?,
+            status: ProcessStatus::from_usize(src[2], src[3]).ok_or(Error::InternalError)?,
+            threads: src[4],
+            servers: src[5],
+            ram_bytes: src[6],
+        })
+    }
+}
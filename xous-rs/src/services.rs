@@ -1 +1,2 @@
-pub mod nameserver;
\ No newline at end of file
+pub mod nameserver;
+pub mod ticktimer;
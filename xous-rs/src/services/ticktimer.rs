@@ -0,0 +1,35 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Ask the ticktimer to expire the IPC timeout identified by `token` once
+/// `timeout_ms` milliseconds have passed.
+pub(crate) fn request_ipc_timeout(token: usize, timeout_ms: usize) -> Result<(), crate::Error> {
+    crate::send_message(
+        ticktimer(),
+        crate::Message::new_scalar(10 /* IpcTimeout */, token, timeout_ms, 0, 0),
+    )
+    .map(|_| ())
+}
+
+/// Tell the ticktimer that the IPC timeout identified by `token` is no longer
+/// needed, so it can stop waiting for it.
+pub(crate) fn cancel_ipc_timeout(token: usize) -> Result<(), crate::Error> {
+    crate::send_message(
+        ticktimer(),
+        crate::Message::new_scalar(11 /* CancelIpcTimeout */, token, 0, 0, 0),
+    )
+    .map(|_| ())
+}
+
+/// Return the connection ID of the ticktimer
+fn ticktimer() -> crate::CID {
+    static TICKTIMER_CID: AtomicU32 = AtomicU32::new(0);
+
+    let cid = TICKTIMER_CID.load(Ordering::Relaxed);
+    if cid != 0 {
+        return cid;
+    }
+
+    let cid = crate::connect(crate::SID::from_bytes(b"ticktimer-server").unwrap()).unwrap();
+    TICKTIMER_CID.store(cid, Ordering::Relaxed);
+    cid
+}
@@ -1,7 +1,8 @@
 use crate::{
-    pid_from_usize, CpuID, Error, MemoryAddress, MemoryFlags, MemoryMessage, MemoryRange,
-    MemorySize, MemoryType, Message, MessageEnvelope, MessageSender, ProcessArgs, ProcessInit,
-    Result, ScalarMessage, SysCallResult, ThreadInit, CID, PID, SID, TID,
+    pid_from_usize, CpuID, Error, Limits, MemoryAddress, MemoryFlags, MemoryMessage,
+    MemoryRange, MemorySize, MemoryType, Message, MessageEnvelope, MessageSender, ProcessArgs,
+    ProcessInfo, ProcessInit, Result, ScalarMessage, SysCallResult, ThreadInit, ThreadPriority,
+    CID, PID, SID, TID,
 };
 use core::convert::{TryFrom, TryInto};
 /* https://github.com/betrusted-io/xous-core/issues/90
@@ -427,7 +428,7 @@ pub enum SysCall {
         usize, /* stack pointer */
     ),
 
-    /// Adjust one of the limits within this process. Note that you must pass
+    /// Adjust one of the limits within a process. Note that you must pass
     /// the current limit value in order to set the new limit. The current limit
     /// value is always returned, so this function may need to be called twice --
     /// once in order to get the current limit, and again to set the new limit.
@@ -438,11 +439,21 @@ pub enum SysCall {
     ///                  are supported:
     ///                         1: Maximum heap size
     ///                         2: Current heap size
+    ///                         3: Maximum number of RAM pages
+    ///                         4: Number of RAM pages in use (read-only)
     ///     * **Current Limit**: Pass the current limit value here. The current
     ///                 limit must match in order for the new limit to take
     ///                 effect. This is used to avoid a race condition if two
     ///                 threads try to set the same limit.
     ///     * **Proposed Limit**: The new value that you would like to use.
+    ///     * **PID**: The process to adjust, or `None` for the current process.
+    ///                Heap limits may only be adjusted on the current process.
+    ///                The RAM page quota may be set by the process that created
+    ///                the target or by PID 1, and a process may lower but not
+    ///                raise its own quota. No process but PID 1 may give
+    ///                another more pages than it has left itself. The quota
+    ///                may also be given when the process is spawned, through
+    ///                its `ProcessArgs`.
     ///
     /// ## Returns
     ///
@@ -451,10 +462,14 @@ pub enum SysCall {
     /// ## Errors
     ///
     ///     * **InvalidLimit**: The specified index was not valid
+    ///     * **ProcessNotChild**: The caller may not adjust the given process
+    ///     * **AccessDenied**: A process tried to raise its own RAM page quota,
+    ///                         or to grant more pages than it has left
     AdjustProcessLimit(
-        usize, /* process limit index */
-        usize, /* expected current limit */
-        usize, /* proposed new limit */
+        usize,       /* process limit index */
+        usize,       /* expected current limit */
+        usize,       /* proposed new limit */
+        Option<PID>, /* process to adjust */
     ),
 
     /// Returns the physical address corresponding to a virtual address, if such a mapping exists.
@@ -472,6 +487,211 @@ pub enum SysCall {
         usize, /* virtual address */
     ),
 
+    /// Set the scheduling priority of a process or of a single thread within
+    /// a process. The scheduler always runs the highest-priority context that
+    /// is ready, so this can be used to keep latency-sensitive services
+    /// responsive while long-running jobs are active.
+    ///
+    /// Threads created with `CreateThread` inherit the default priority of
+    /// their process, which may be changed with `SetDefaultPriority` in order
+    /// to pick the priority of a thread at creation time. Processes pick up
+    /// the priority given in their `ProcessInit`.
+    ///
+    /// There is no aging: a context that is always ready will starve every
+    /// context of a lower priority for as long as it stays ready. Anything
+    /// above `Normal` should block regularly. For the same reason, no process
+    /// but PID 1 may set a priority above its own default, and a new process
+    /// runs no higher than the process that created it.
+    ///
+    /// ## Arguments
+    ///
+    ///     * **PID**: The process to adjust, or `None` for the current process.
+    ///                Only the process itself or its parent may adjust it.
+    ///     * **TID**: The thread to adjust, or `None` to adjust the process
+    ///                default along with every thread in the process.
+    ///     * **Priority**: The new priority.
+    ///
+    /// ## Returns
+    ///
+    /// Returns a Scalar1 containing the previous priority.
+    ///
+    /// ## Errors
+    ///
+    ///     * **ProcessNotFound**: The specified process does not exist
+    ///     * **ProcessNotChild**: The caller may not adjust the given process
+    ///     * **AccessDenied**: The priority is above the caller's own
+    ///     * **InvalidThread**: The thread ID was not valid
+    SetPriority(
+        Option<PID>,    /* process to adjust */
+        Option<TID>,    /* thread to adjust */
+        ThreadPriority, /* new priority */
+    ),
+
+    /// Return a snapshot of one entry in the process table. Processes may be
+    /// enumerated by querying PIDs in order, starting from 1, until
+    /// `InvalidPID` is returned.
+    ///
+    /// ## Returns
+    ///
+    /// Returns a `Result::ProcessInfo` describing the process.
+    ///
+    /// ## Errors
+    ///
+    ///     * **InvalidPID**: The PID is beyond the end of the process table
+    ///     * **ProcessNotFound**: No process is allocated to this PID
+    GetProcessInfo(PID),
+
+    /// Return part of the name of the given process. Names are returned in
+    /// chunks of `4 * size_of::<usize>()` bytes starting at the given byte
+    /// offset, so a name is read by calling this repeatedly until the offset
+    /// reaches the total length.
+    ///
+    /// ## Returns
+    ///
+    /// Returns a Scalar5 containing the total length of the name in bytes,
+    /// followed by four words containing the name bytes in little-endian order.
+    /// Processes without a name report a length of `0`.
+    ///
+    /// ## Errors
+    ///
+    ///     * **InvalidPID**: The PID is beyond the end of the process table
+    ///     * **ProcessNotFound**: No process is allocated to this PID
+    GetProcessName(PID, usize /* offset */),
+
+    /// Return the cumulative amount of time that a thread has spent running,
+    /// in microseconds. If no thread is specified, the total for every thread
+    /// in the process is returned. On hardware this is measured with the
+    /// machine timer, while in hosted mode it is the wall-clock time between
+    /// a thread being resumed and that thread making its next syscall.
+    ///
+    /// ## Returns
+    ///
+    /// Returns a Scalar2 containing the lower and upper 32 bits of the time.
+    ///
+    /// ## Errors
+    ///
+    ///     * **InvalidPID**: The PID is beyond the end of the process table
+    ///     * **ProcessNotFound**: No process is allocated to this PID
+    ///     * **InvalidThread**: The thread ID is out of range
+    GetRunTime(PID, Option<TID>),
+
+    /// OR `flags` into the event flags of the server behind the given
+    /// connection. Unlike sending a message, this never allocates a queue
+    /// slot, so repeated notifications coalesce into a single bit. If a
+    /// server thread is waiting on any of the bits that are now set, it is
+    /// woken up. This may be called from an interrupt handler.
+    ///
+    /// ## Returns
+    ///
+    /// Returns `Ok` once the flags have been set.
+    ///
+    /// ## Errors
+    ///
+    ///     * **ServerNotFound**: The connection does not refer to a server
+    SetEventFlags(CID, usize /* flags */),
+
+    /// Wait until at least one of the event flags in `mask` is set on the
+    /// given server. The flags that were set in `mask` are cleared and
+    /// returned. Only one thread per server may wait on event flags at a time.
+    ///
+    /// ## Returns
+    ///
+    /// Returns a Scalar1 containing the flags that were set and cleared.
+    ///
+    /// ## Errors
+    ///
+    ///     * **ServerNotFound**: The server does not exist or is not owned by this process
+    ///     * **InvalidSyscall**: The mask was empty
+    ///     * **ThreadNotAvailable**: Another thread is already waiting on this server's flags
+    WaitEventFlags(SID, usize /* mask */),
+
+    /// Like `WaitEventFlags`, but returns a Scalar1 of `0` immediately if
+    /// none of the flags in `mask` are set. This may be called from an
+    /// interrupt handler.
+    ///
+    /// ## Errors
+    ///
+    ///     * **ServerNotFound**: The server does not exist or is not owned by this process
+    TryWaitEventFlags(SID, usize /* mask */),
+
+    /// Arm an IPC timeout for the calling thread. The returned token is
+    /// handed to the ticktimer, which passes it to `ExpireIpcTimeout` once
+    /// the timeout has elapsed. Arming a new timeout replaces any previous
+    /// one.
+    ///
+    /// ## Returns
+    ///
+    /// Returns a Scalar1 containing the token.
+    ArmIpcTimeout,
+
+    /// Disarm the calling thread's IPC timeout, if one is armed. This is done
+    /// once the call the timeout was guarding has returned, so that a late
+    /// `ExpireIpcTimeout` has no effect.
+    ///
+    /// ## Returns
+    ///
+    /// Returns `Ok` whether or not a timeout was armed.
+    DisarmIpcTimeout,
+
+    /// Cancel the blocking send or receive of the thread in the given process
+    /// that armed the IPC timeout with the given token. The thread returns
+    /// `Error::Timeout`, and any memory it lent to a server that has not yet
+    /// received the message is returned to it. Memory that the server is
+    /// already working on can't be taken back, so in that case the thread
+    /// continues to wait for the server. Nothing happens if the token is no
+    /// longer armed. Only the ticktimer may make this call, and it may be
+    /// made from an interrupt handler.
+    ///
+    /// ## Returns
+    ///
+    /// Returns `Ok` once the timeout has been handled.
+    ///
+    /// ## Errors
+    ///
+    ///     * **AccessDenied**: The caller is not the ticktimer
+    ///     * **ProcessNotFound**: The process does not exist
+    ///     * **ThreadNotAvailable**: The thread has not started waiting yet,
+    ///       so the call should be retried shortly
+    ExpireIpcTimeout(PID, usize /* token */),
+
+    /// Make the given server, which must belong to the calling process, the
+    /// supervisor that is told whenever another process terminates. The
+    /// notification is a `Scalar` message sent by PID 1 with the given `id`:
+    ///
+    ///     * **arg1**: The PID of the process that terminated
+    ///     * **arg2**: The exit code, or the `ExceptionType` if it faulted
+    ///     * **arg3**: `1` if the process faulted, `0` if it exited
+    ///
+    /// ## Returns
+    ///
+    /// Returns `Ok` once the supervisor has been set.
+    ///
+    /// ## Errors
+    ///
+    ///     * **ServerNotFound**: The calling process does not own the server
+    ///     * **AccessDenied**: Another supervisor is already running
+    SetSupervisor(SID, usize /* id */),
+
+    /// Set the priority that new threads in a process will inherit, without
+    /// changing the priority of any existing thread.
+    ///
+    /// ## Arguments
+    ///
+    ///     * **PID**: The process to adjust, or `None` for the current process.
+    ///                Only the process itself or its parent may adjust it.
+    ///     * **Priority**: The new default priority.
+    ///
+    /// ## Returns
+    ///
+    /// Returns a Scalar1 containing the previous default priority.
+    ///
+    /// ## Errors
+    ///
+    ///     * **ProcessNotFound**: The specified process does not exist
+    ///     * **ProcessNotChild**: The caller may not adjust the given process
+    ///     * **AccessDenied**: The priority is above the caller's own
+    SetDefaultPriority(Option<PID>, ThreadPriority),
+
     /// This syscall does not exist. It captures all possible
     /// arguments so detailed analysis can be performed.
     Invalid(usize, usize, usize, usize, usize, usize, usize),
@@ -518,6 +738,18 @@ pub enum SysCallNumber {
     AdjustProcessLimit = 38,
     #[cfg(feature="v2p")]
     VirtToPhys = 39,
+    SetPriority = 40,
+    GetProcessInfo = 41,
+    GetProcessName = 42,
+    GetRunTime = 43,
+    SetEventFlags = 44,
+    WaitEventFlags = 45,
+    TryWaitEventFlags = 46,
+    ArmIpcTimeout = 47,
+    DisarmIpcTimeout = 48,
+    ExpireIpcTimeout = 49,
+    SetSupervisor = 50,
+    SetDefaultPriority = 51,
     Invalid,
 }
 
@@ -564,6 +796,18 @@ impl SysCallNumber {
             38 => AdjustProcessLimit,
             #[cfg(feature="v2p")]
             39 => VirtToPhys,
+            40 => SetPriority,
+            41 => GetProcessInfo,
+            42 => GetProcessName,
+            43 => GetRunTime,
+            44 => SetEventFlags,
+            45 => WaitEventFlags,
+            46 => TryWaitEventFlags,
+            47 => ArmIpcTimeout,
+            48 => DisarmIpcTimeout,
+            49 => ExpireIpcTimeout,
+            50 => SetSupervisor,
+            51 => SetDefaultPriority,
             _ => Invalid,
         }
     }
@@ -918,12 +1162,12 @@ impl SysCall {
                 0,
                 0,
             ],
-            SysCall::AdjustProcessLimit(index, current, new) => [
+            SysCall::AdjustProcessLimit(index, current, new, pid) => [
                 SysCallNumber::AdjustProcessLimit as usize,
                 *index,
                 *current,
                 *new,
-                0,
+                pid.map(|p| p.get() as usize).unwrap_or(0),
                 0,
                 0,
                 0,
@@ -939,6 +1183,135 @@ impl SysCall {
                 0,
                 0,
             ],
+            SysCall::SetPriority(pid, tid, priority) => [
+                SysCallNumber::SetPriority as usize,
+                pid.map(|p| p.get() as usize).unwrap_or(0),
+                tid.unwrap_or(0),
+                priority.to_usize(),
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::GetProcessInfo(pid) => [
+                SysCallNumber::GetProcessInfo as usize,
+                pid.get() as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::GetProcessName(pid, offset) => [
+                SysCallNumber::GetProcessName as usize,
+                pid.get() as usize,
+                *offset,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::GetRunTime(pid, tid) => [
+                SysCallNumber::GetRunTime as usize,
+                pid.get() as usize,
+                tid.map(|t| t + 1).unwrap_or(0),
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::SetEventFlags(cid, flags) => [
+                SysCallNumber::SetEventFlags as usize,
+                *cid as usize,
+                *flags,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::WaitEventFlags(sid, mask) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::WaitEventFlags as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    *mask,
+                    0,
+                    0,
+                ]
+            }
+            SysCall::TryWaitEventFlags(sid, mask) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::TryWaitEventFlags as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    *mask,
+                    0,
+                    0,
+                ]
+            }
+            SysCall::ArmIpcTimeout => [
+                SysCallNumber::ArmIpcTimeout as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::DisarmIpcTimeout => [
+                SysCallNumber::DisarmIpcTimeout as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::ExpireIpcTimeout(pid, token) => [
+                SysCallNumber::ExpireIpcTimeout as usize,
+                pid.get() as usize,
+                *token,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::SetSupervisor(sid, id) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::SetSupervisor as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    *id,
+                    0,
+                    0,
+                ]
+            }
+            SysCall::SetDefaultPriority(pid, priority) => [
+                SysCallNumber::SetDefaultPriority as usize,
+                pid.map(|p| p.get() as usize).unwrap_or(0),
+                priority.to_usize(),
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
             SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7) => [
                 SysCallNumber::Invalid as usize,
                 *a1,
@@ -1102,9 +1475,44 @@ impl SysCall {
             SysCallNumber::Disconnect => SysCall::Disconnect(a1 as _),
             SysCallNumber::JoinThread => SysCall::JoinThread(a1 as _),
             SysCallNumber::SetExceptionHandler => SysCall::SetExceptionHandler(a1 as _, a2 as _),
-            SysCallNumber::AdjustProcessLimit => SysCall::AdjustProcessLimit(a1, a2, a3),
+            SysCallNumber::AdjustProcessLimit => SysCall::AdjustProcessLimit(
+                a1,
+                a2,
+                a3,
+                if a4 == 0 { None } else { Some(pid_from_usize(a4)?) },
+            ),
             #[cfg(feature="v2p")]
             SysCallNumber::VirtToPhys => SysCall::VirtToPhys(a1 as _),
+            SysCallNumber::SetPriority => SysCall::SetPriority(
+                if a1 == 0 { None } else { Some(pid_from_usize(a1)?) },
+                if a2 == 0 { None } else { Some(a2 as TID) },
+                ThreadPriority::from_usize(a3).ok_or(Error::InvalidSyscall)?,
+            ),
+            SysCallNumber::GetProcessInfo => SysCall::GetProcessInfo(pid_from_usize(a1)?),
+            SysCallNumber::GetProcessName => SysCall::GetProcessName(pid_from_usize(a1)?, a2),
+            SysCallNumber::GetRunTime => SysCall::GetRunTime(
+                pid_from_usize(a1)?,
+                if a2 == 0 { None } else { Some(a2 - 1) },
+            ),
+            SysCallNumber::SetEventFlags => SysCall::SetEventFlags(a1 as _, a2),
+            SysCallNumber::WaitEventFlags => {
+                SysCall::WaitEventFlags(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _), a5)
+            }
+            SysCallNumber::TryWaitEventFlags => {
+                SysCall::TryWaitEventFlags(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _), a5)
+            }
+            SysCallNumber::ArmIpcTimeout => SysCall::ArmIpcTimeout,
+            SysCallNumber::DisarmIpcTimeout => SysCall::DisarmIpcTimeout,
+            SysCallNumber::ExpireIpcTimeout => {
+                SysCall::ExpireIpcTimeout(pid_from_usize(a1)?, a2)
+            }
+            SysCallNumber::SetSupervisor => {
+                SysCall::SetSupervisor(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _), a5)
+            }
+            SysCallNumber::SetDefaultPriority => SysCall::SetDefaultPriority(
+                if a1 == 0 { None } else { Some(pid_from_usize(a1)?) },
+                ThreadPriority::from_usize(a2).ok_or(Error::InvalidSyscall)?,
+            ),
             SysCallNumber::Invalid => SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7),
         })
     }
@@ -1204,6 +1612,9 @@ impl SysCall {
                 | SysCall::ReturnScalar2(_, _, _)
                 | SysCall::ReturnScalar1(_, _)
                 | SysCall::ReturnMemory(_, _, _, _)
+                | SysCall::SetEventFlags(_, _)
+                | SysCall::TryWaitEventFlags(_, _)
+                | SysCall::ExpireIpcTimeout(_, _)
         )
     }
 }
@@ -1883,6 +2294,310 @@ pub fn virt_to_phys(va: usize) -> core::result::Result<usize, Error> {
     })
 }
 
+/// Set the scheduling priority of the calling thread. Returns the previous
+/// priority of the thread.
+pub fn set_thread_priority(priority: ThreadPriority) -> core::result::Result<ThreadPriority, Error> {
+    let tid = current_tid()?;
+    set_priority(None, Some(tid), priority)
+}
+
+/// Set the scheduling priority of every thread in the given process, as well
+/// as the priority that new threads in that process will inherit. Pass `None`
+/// to adjust the current process. Returns the previous default priority.
+///
+/// # Errors
+///
+/// * **ProcessNotChild**: The given process is not a child of this process
+pub fn set_process_priority(
+    pid: Option<PID>,
+    priority: ThreadPriority,
+) -> core::result::Result<ThreadPriority, Error> {
+    set_priority(pid, None, priority)
+}
+
+/// Set the priority that threads created from now on in the given process
+/// will inherit, leaving existing threads alone. Pass `None` to adjust the
+/// current process. Returns the previous default priority.
+///
+/// # Errors
+///
+/// * **ProcessNotChild**: The given process is not a child of this process
+pub fn set_default_priority(
+    pid: Option<PID>,
+    priority: ThreadPriority,
+) -> core::result::Result<ThreadPriority, Error> {
+    rsyscall(SysCall::SetDefaultPriority(pid, priority)).and_then(|result| {
+        if let Result::Scalar1(previous) = result {
+            ThreadPriority::from_usize(previous).ok_or(Error::InternalError)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+fn set_priority(
+    pid: Option<PID>,
+    tid: Option<TID>,
+    priority: ThreadPriority,
+) -> core::result::Result<ThreadPriority, Error> {
+    rsyscall(SysCall::SetPriority(pid, tid, priority)).and_then(|result| {
+        if let Result::Scalar1(previous) = result {
+            ThreadPriority::from_usize(previous).ok_or(Error::InternalError)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+/// Return a snapshot of the process table entry for the given PID.
+///
+/// # Errors
+///
+/// * **InvalidPID**: The PID is beyond the end of the process table
+/// * **ProcessNotFound**: No process is allocated to this PID
+pub fn process_info(pid: PID) -> core::result::Result<ProcessInfo, Error> {
+    rsyscall(SysCall::GetProcessInfo(pid)).and_then(|result| {
+        if let Result::ProcessInfo(info) = result {
+            Ok(info)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+/// Copy the name of the given process into `name`, truncating it if `name`
+/// is too short. Returns the total length of the name in bytes.
+///
+/// # Errors
+///
+/// * **InvalidPID**: The PID is beyond the end of the process table
+/// * **ProcessNotFound**: No process is allocated to this PID
+pub fn process_name(pid: PID, name: &mut [u8]) -> core::result::Result<usize, Error> {
+    let mut offset = 0;
+    loop {
+        let result = rsyscall(SysCall::GetProcessName(pid, offset))?;
+        let (total, words) = if let Result::Scalar5(total, w0, w1, w2, w3) = result {
+            (total, [w0, w1, w2, w3])
+        } else {
+            return Err(Error::InternalError);
+        };
+        for word in words.iter() {
+            for byte in word.to_le_bytes().iter() {
+                if offset >= total || offset >= name.len() {
+                    return Ok(total);
+                }
+                name[offset] = *byte;
+                offset += 1;
+            }
+        }
+    }
+}
+
+/// Return the total time, in microseconds, that every thread in the given
+/// process has spent running.
+pub fn process_run_time(pid: PID) -> core::result::Result<u64, Error> {
+    run_time(pid, None)
+}
+
+/// Return the total time, in microseconds, that the given thread has spent
+/// running.
+pub fn thread_run_time(pid: PID, tid: TID) -> core::result::Result<u64, Error> {
+    run_time(pid, Some(tid))
+}
+
+fn run_time(pid: PID, tid: Option<TID>) -> core::result::Result<u64, Error> {
+    rsyscall(SysCall::GetRunTime(pid, tid)).and_then(|result| {
+        if let Result::Scalar2(lo, hi) = result {
+            Ok((lo as u64 & 0xffff_ffff) | ((hi as u64) << 32))
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+/// Set `flags` in the event flags of the server behind `connection`, waking
+/// the server if it is waiting on any of them.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The connection does not refer to a server
+pub fn set_event_flags(connection: CID, flags: usize) -> core::result::Result<(), Error> {
+    rsyscall(SysCall::SetEventFlags(connection, flags)).map(|_| ())
+}
+
+/// Block until at least one of the event flags in `mask` is set on `server`,
+/// then clear and return those flags.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist or is not owned by this process
+/// * **InvalidSyscall**: The mask was empty
+/// * **ThreadNotAvailable**: Another thread is already waiting on this server's flags
+pub fn wait_event_flags(server: SID, mask: usize) -> core::result::Result<usize, Error> {
+    event_flags_result(rsyscall(SysCall::WaitEventFlags(server, mask)))
+}
+
+/// Clear and return whichever event flags in `mask` are set on `server`,
+/// without blocking. Returns `0` if none are set.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist or is not owned by this process
+pub fn try_wait_event_flags(server: SID, mask: usize) -> core::result::Result<usize, Error> {
+    event_flags_result(rsyscall(SysCall::TryWaitEventFlags(server, mask)))
+}
+
+fn event_flags_result(result: SysCallResult) -> core::result::Result<usize, Error> {
+    result.and_then(|result| {
+        if let Result::Scalar1(flags) = result {
+            Ok(flags)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+/// Like `send_message()`, but give up if a blocking message has not been
+/// answered within `timeout_ms` milliseconds. The timeout is tracked by the
+/// ticktimer, which must be running.
+///
+/// If the server has not yet received the message, it is withdrawn and any
+/// lent memory is returned. If the server is already handling a scalar
+/// message, its eventual response is discarded. A server that has already
+/// received lent memory must return it before this call can complete.
+///
+/// # Errors
+///
+/// * **Timeout**: The message was not answered in time
+/// * Any error that `send_message()` may return
+pub fn send_message_timeout(
+    connection: CID,
+    message: Message,
+    timeout_ms: usize,
+) -> core::result::Result<Result, Error> {
+    with_ipc_timeout(timeout_ms, || send_message(connection, message))
+}
+
+/// Like `receive_message()`, but give up if no message has arrived within
+/// `timeout_ms` milliseconds. The timeout is tracked by the ticktimer, which
+/// must be running.
+///
+/// # Errors
+///
+/// * **Timeout**: No message arrived in time
+/// * **ServerNotFound**: The server does not exist or is not owned by this process
+pub fn receive_message_timeout(
+    server: SID,
+    timeout_ms: usize,
+) -> core::result::Result<MessageEnvelope, Error> {
+    with_ipc_timeout(timeout_ms, || {
+        if let Result::Message(envelope) = rsyscall(SysCall::ReceiveMessage(server))? {
+            Ok(envelope)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+/// Cancel the call guarded by the IPC timeout `token` in process `pid`. This
+/// is used by the ticktimer once a timeout requested by `send_message_timeout()`
+/// or `receive_message_timeout()` has elapsed.
+///
+/// # Errors
+///
+/// * **AccessDenied**: The caller is not the ticktimer
+/// * **ProcessNotFound**: The process does not exist
+/// * **ThreadNotAvailable**: The thread has not started waiting yet, so the
+///   call should be retried shortly
+pub fn expire_ipc_timeout(pid: PID, token: usize) -> core::result::Result<(), Error> {
+    rsyscall(SysCall::ExpireIpcTimeout(pid, token)).map(|_| ())
+}
+
+/// Make `sid`, a server owned by the calling process, the supervisor that is
+/// sent a `Scalar` message with the given `id` whenever another process
+/// terminates. See `SysCall::SetSupervisor` for the message layout.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The calling process does not own the server
+/// * **AccessDenied**: Another supervisor is already running
+pub fn set_supervisor(sid: SID, id: usize) -> core::result::Result<(), Error> {
+    rsyscall(SysCall::SetSupervisor(sid, id)).map(|_| ())
+}
+
+/// Arm an IPC timeout, ask the ticktimer to expire it after `timeout_ms`, and
+/// run `f`. The timeout is disarmed once `f` returns, and the ticktimer is
+/// told to drop its request so it doesn't pile up behind long timeouts.
+fn with_ipc_timeout<F, R>(timeout_ms: usize, f: F) -> core::result::Result<R, Error>
+where
+    F: FnOnce() -> core::result::Result<R, Error>,
+{
+    let token = match rsyscall(SysCall::ArmIpcTimeout)? {
+        Result::Scalar1(token) => token,
+        _ => return Err(Error::InternalError),
+    };
+    let requested = crate::services::ticktimer::request_ipc_timeout(token, timeout_ms);
+    let sent = requested.is_ok();
+    let result = requested.and_then(|_| f());
+    rsyscall(SysCall::DisarmIpcTimeout).ok();
+    if sent {
+        crate::services::ticktimer::cancel_ipc_timeout(token).ok();
+    }
+    result
+}
+
+/// Adjust a limit of the given process, or of the current process if `pid`
+/// is `None`. The new value only takes effect if `current` matches the
+/// existing value. Returns the value of the limit after the call.
+///
+/// # Errors
+///
+/// * **InvalidLimit**: The limit may not be adjusted on the given process
+/// * **ProcessNotChild**: The caller may not adjust the given process
+/// * **AccessDenied**: A process tried to raise its own RAM page quota, or to
+///   grant more pages than it has left
+pub fn adjust_process_limit(
+    pid: Option<PID>,
+    limit: Limits,
+    current: usize,
+    new: usize,
+) -> core::result::Result<usize, Error> {
+    rsyscall(SysCall::AdjustProcessLimit(limit as usize, current, new, pid)).and_then(
+        |result| {
+            if let Result::Scalar2(index, value) = result {
+                if index == limit as usize {
+                    return Ok(value);
+                }
+            }
+            Err(Error::InternalError)
+        },
+    )
+}
+
+/// Return the maximum number of pages of RAM the given process may own, or
+/// the current process if `pid` is `None`.
+pub fn memory_quota(pid: Option<PID>) -> core::result::Result<usize, Error> {
+    adjust_process_limit(pid, Limits::MemoryQuota, usize::MAX, usize::MAX)
+}
+
+/// Limit the number of pages of RAM the given process may own. This is
+/// normally done by the parent immediately after it creates a process.
+pub fn set_memory_quota(pid: Option<PID>, pages: usize) -> core::result::Result<(), Error> {
+    let current = memory_quota(pid)?;
+    if adjust_process_limit(pid, Limits::MemoryQuota, current, pages)? == pages {
+        Ok(())
+    } else {
+        // Someone else changed the quota at the same time
+        Err(Error::InternalError)
+    }
+}
+
+/// Return the number of pages of RAM the given process currently owns, or the
+/// current process if `pid` is `None`.
+pub fn memory_usage(pid: Option<PID>) -> core::result::Result<usize, Error> {
+    adjust_process_limit(pid, Limits::MemoryUsage, 0, 0)
+}
+
 /// Perform a raw syscall and return the result. This will transform
 /// `xous::Result::Error(e)` into an `Err(e)`.
 pub fn rsyscall(call: SysCall) -> SysCallResult {
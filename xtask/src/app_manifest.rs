@@ -218,7 +218,7 @@ pub(crate) fn app_menu_items(menu_items: &mut Vec::<MenuItem>, status_conn: u32)
     overwrite_if_changed(&menu, "services/status/src/app_autogen.rs");
 }
 
-fn overwrite_if_changed(new_string: &String, old_file: &str) {
+pub(crate) fn overwrite_if_changed(new_string: &String, old_file: &str) {
     let original = match OpenOptions::new().read(true).open(old_file) {
         Ok(mut ref_file) => {
             let mut buf = String::new();
@@ -5,7 +5,7 @@ use std::{
     path::{Path, PathBuf},
     process::Command,
 };
-use crate::{DynError, TARGET_TRIPLE, app_manifest::generate_app_menus, MemorySpec};
+use crate::{DynError, TARGET_TRIPLE, app_manifest::generate_app_menus, conn_policy::generate_conn_policy, service_manifest::generate_restart_policy, MemorySpec};
 
 #[allow(dead_code)]
 #[derive(Copy, Clone, Debug)]
@@ -467,6 +467,8 @@ impl Builder {
             }
         }
         generate_app_menus(&app_names);
+        generate_conn_policy();
+        generate_restart_policy();
         let mut services_path = self.builder(
             &[&self.services[..], &self.apps[..]].concat(),
             &self.features,
@@ -0,0 +1,51 @@
+// This module generates the xous-names connection policy from the JSON file in services/xous-names/.
+
+use std::{
+    fs::File,
+    io::Read,
+    string::String,
+    fmt::Write as StdWrite,
+};
+use std::collections::BTreeMap;
+use crate::app_manifest::overwrite_if_changed;
+
+/// Maps a server name to the list of process names that may connect to it.
+/// Servers that are not listed remain open to every process.
+type ConnPolicy = BTreeMap<String, Vec<String>>;
+
+pub(crate) fn generate_conn_policy() {
+    let file = File::open("services/xous-names/policy.json").expect("Failed to open the connection policy file");
+    let mut reader = std::io::BufReader::new(file);
+    let mut content = String::new();
+    reader
+        .read_to_string(&mut content)
+        .expect("Failed to read the file");
+    let policy: ConnPolicy =
+        serde_json::from_str(&content).expect("Cannot parse connection policy file");
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// This file is auto-generated by xtask/main.rs generate_conn_policy()"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "// Edit services/xous-names/policy.json instead.\n"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "pub(crate) const CONN_POLICY: &[(&'static str, &[&'static str])] = &["
+    )
+    .unwrap();
+    for (server, clients) in policy.iter() {
+        writeln!(out, "    ({:?}, &[", server).unwrap();
+        for client in clients.iter() {
+            writeln!(out, "        {:?},", client).unwrap();
+        }
+        writeln!(out, "    ]),").unwrap();
+    }
+    writeln!(out, "];").unwrap();
+    overwrite_if_changed(&out, "services/xous-names/src/policy_autogen.rs");
+}
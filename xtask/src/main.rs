@@ -1,4 +1,6 @@
 mod app_manifest;
+mod conn_policy;
+mod service_manifest;
 mod versioning;
 use versioning::*;
 mod utils;
@@ -78,6 +80,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "com",
             "net",
             "dns",
+            "tls",
             // UX abstractions
             "gam",
             "ime-frontend",
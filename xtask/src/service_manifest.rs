@@ -0,0 +1,66 @@
+// This module generates the xous-names restart policy from the service manifest in services/.
+
+use std::{
+    fs::File,
+    io::Read,
+    string::String,
+    fmt::Write as StdWrite,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use crate::app_manifest::overwrite_if_changed;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+enum Restart {
+    Never,
+    OnFailure,
+    Always,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct ServiceManifest {
+    restart: Restart,
+    /// Delay before the first restart. It doubles with each further restart.
+    #[serde(default)]
+    backoff_ms: u32,
+}
+
+pub(crate) fn generate_restart_policy() {
+    let file = File::open("services/manifest.json").expect("Failed to open the service manifest file");
+    let mut reader = std::io::BufReader::new(file);
+    let mut content = String::new();
+    reader
+        .read_to_string(&mut content)
+        .expect("Failed to read the file");
+    let manifest: BTreeMap<String, ServiceManifest> =
+        serde_json::from_str(&content).expect("Cannot parse service manifest file");
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// This file is auto-generated by xtask/main.rs generate_restart_policy()"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "// Edit services/manifest.json instead.\n"
+    )
+    .unwrap();
+    writeln!(out, "use crate::supervisor::RestartPolicy;\n").unwrap();
+    writeln!(
+        out,
+        "pub(crate) const RESTART_POLICY: &[(&'static str, RestartPolicy, u32)] = &["
+    )
+    .unwrap();
+    for (service, entry) in manifest.iter() {
+        writeln!(
+            out,
+            "    ({:?}, RestartPolicy::{:?}, {}),",
+            service, entry.restart, entry.backoff_ms
+        )
+        .unwrap();
+    }
+    writeln!(out, "];").unwrap();
+    overwrite_if_changed(&out, "services/xous-names/src/restart_autogen.rs");
+}